@@ -1,2 +0,0 @@
-mod des;
-pub use self::des::encrypt as des;
@@ -0,0 +1,2109 @@
+pub mod gii;
+
+use crate::framebuffer::Framebuffer;
+use crate::record::Recorder;
+use crate::screenshot::Screenshot;
+#[cfg(feature = "sasl")]
+use crate::security::sasl;
+#[cfg(feature = "websocket")]
+use crate::websocket::WebSocket;
+use crate::ServerKind;
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use log::{debug, trace, warn};
+use protocol::Message;
+use std::io::{self, BufWriter, Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream, ToSocketAddrs};
+use std::ops::{Deref, DerefMut};
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, Receiver, RecvError, RecvTimeoutError, Sender, TryRecvError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+#[cfg(feature = "png")]
+use vnc_proto::tightpng;
+#[cfg(feature = "lzo")]
+use vnc_proto::ultra;
+use vnc_proto::{
+    des, h264, hextile, pixels, protocol, trle, zlib, zlibhex, zrle, Colour, Error, Fence, Rect,
+    Result, Screen,
+};
+
+/// A way of authenticating that the server offers and the client supports.
+///
+/// Within VeNCrypt, only the sub-types without TLS are offered: `None`,
+/// `Password` and `Plain`. The TLS and X509 sub-types (TLSNone, TLSVnc,
+/// X509Vnc, X509Plain and the like) need a TLS library the client does not
+/// have, so servers that insist on them cannot be connected to. VeNCrypt is
+/// also only picked when the server offers none of the plain security types.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum AuthMethod {
+    None,
+    Password,
+    AppleRemoteDesktop,
+    Sasl,
+    /// A username and password within VeNCrypt, without TLS.
+    Plain,
+    /* more to come */
+}
+
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum AuthChoice {
+    None,
+    Password([u8; 8]),
+    AppleRemoteDesktop(String, String),
+    /// A username and password, sent with the SASL PLAIN mechanism. This needs
+    /// the `sasl` feature.
+    Sasl(String, String),
+    /// A username and password, sent with the VeNCrypt Plain sub-type. Without
+    /// TLS, they travel in the clear.
+    Plain(String, String),
+    /* more to come */
+}
+
+#[derive(Debug)]
+pub enum DisconnectReason {
+    /// The server closed the connection cleanly, between two messages.
+    Closed,
+    /// The server ended the session and said why.
+    Server(String),
+    /// Reading from the connection failed, including the server closing it
+    /// in the middle of a message.
+    Io(std::io::Error),
+    /// The server went quiet in the middle of a message for longer than the
+    /// read timeout; see `Client::set_read_timeout`.
+    Timeout,
+    /// The server sent something that violates the protocol. `message_type` is
+    /// the type of the offending server message, if it got that far.
+    Protocol {
+        message_type: Option<u8>,
+        error: Error,
+    },
+}
+
+impl DisconnectReason {
+    fn from_error(error: Error, message_type: Option<u8>) -> DisconnectReason {
+        match error {
+            Error::Disconnected => DisconnectReason::Closed,
+            Error::Io(error) => DisconnectReason::Io(error),
+            Error::Timeout => DisconnectReason::Timeout,
+            Error::Server(reason) | Error::AuthenticationFailure(reason) => {
+                DisconnectReason::Server(reason)
+            }
+            Error::UnexpectedMessageType(message_type) => DisconnectReason::Protocol {
+                message_type: Some(message_type),
+                error,
+            },
+            error => DisconnectReason::Protocol {
+                message_type,
+                error,
+            },
+        }
+    }
+}
+
+impl std::fmt::Display for DisconnectReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            DisconnectReason::Closed => write!(f, "connection closed"),
+            DisconnectReason::Server(ref reason) => write!(f, "server: {}", reason),
+            DisconnectReason::Io(ref error) => write!(f, "I/O error: {}", error),
+            DisconnectReason::Timeout => write!(f, "timed out in the middle of a message"),
+            DisconnectReason::Protocol {
+                message_type: Some(message_type),
+                ref error,
+            } => write!(
+                f,
+                "protocol error in message type {}: {}",
+                message_type, error
+            ),
+            DisconnectReason::Protocol {
+                message_type: None,
+                ref error,
+            } => write!(f, "protocol error: {}", error),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Event {
+    Disconnected(DisconnectReason),
+    Resize(u16, u16),
+    SetColourMap {
+        first_colour: u16,
+        colours: Vec<Colour>,
+    },
+    PutPixels(Rect, Vec<u8>),
+    CopyPixels {
+        src: Rect,
+        dst: Rect,
+    },
+    EndOfFrame,
+    SetCursor {
+        size: (u16, u16),
+        hotspot: (u16, u16),
+        pixels: Vec<u8>,
+        mask_bits: Vec<u8>,
+    },
+    Clipboard(String),
+    Bell,
+    /// A cursor image from `Encoding::CursorWithAlpha`: 8-bit red, green,
+    /// blue and alpha per pixel, with straight rather than premultiplied alpha.
+    SetCursorAlpha {
+        size: (u16, u16),
+        hotspot: (u16, u16),
+        rgba: Vec<u8>,
+    },
+    LedState {
+        scroll_lock: bool,
+        num_lock: bool,
+        caps_lock: bool,
+    },
+    /// The server accepts `Client::send_key_event_ext`.
+    QemuExtendedKeyEvent,
+    /// The server switched between absolute and relative pointer motion; see
+    /// `Client::send_relative_pointer`.
+    PointerMotionMode(PointerMotionMode),
+    /// The server described its screens, or changed them: the id, area of the
+    /// framebuffer and flags of each monitor. See `Client::screens`.
+    ScreenLayout(Vec<Screen>),
+    /// The server did not honour `Client::set_desktop_size`: 1 if it does not
+    /// allow clients to resize, 2 if it ran out of resources, 3 if the layout
+    /// was invalid.
+    DesktopSizeRefused(u16),
+    /// The server renamed the desktop; see `Client::name`.
+    NameChange(String),
+    /// A gii message; see the `gii` module.
+    Gii(gii::ServerMessage),
+    /// The server sent a fence, either answering `Client::send_fence` or, with
+    /// `Fence::REQUEST` set, asking for an answer through it.
+    Fence(Fence),
+    /// Nothing arrived from the server within the read timeout; see
+    /// `Client::set_read_timeout`. The connection is still usable.
+    Timeout,
+    /// A keepalive probe went unanswered; see `Client::set_keepalive`. The
+    /// server may be gone without the connection having been closed.
+    ConnectionStale,
+    /// Only from `ReconnectingClient`: the connection was lost and has been
+    /// established again. A full update follows.
+    Reconnected,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PointerMotionMode {
+    Absolute,
+    Relative,
+}
+
+/// When an event was received, and which framebuffer update it was part of.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timestamp {
+    /// The moment the data for the event had been read from the socket.
+    pub received: Instant,
+    /// The sequence number of the framebuffer update the event belongs to;
+    /// `None` for events that are not part of an update. `EndOfFrame` carries
+    /// the number of the update it terminates.
+    pub frame: Option<u64>,
+}
+
+impl Timestamp {
+    fn now(frame: Option<u64>) -> Timestamp {
+        Timestamp {
+            received: Instant::now(),
+            frame,
+        }
+    }
+}
+
+/// The longest clipboard text a client exchanges unless told otherwise.
+pub const DEFAULT_MAX_CLIPBOARD_SIZE: usize = 1024 * 1024;
+
+/// Settings the caller can change while the pump is running.
+struct Settings {
+    max_clipboard_size: AtomicUsize,
+    // Taken by the pump when the first Open H.264 rectangle arrives.
+    h264_backend: Mutex<Option<h264::Backend>>,
+    keepalive: Mutex<Keepalive>,
+    recorder: Mutex<Recorder>,
+}
+
+/// Hands what the pump reads to the recorder; see `Client::record`.
+struct Tee<R> {
+    inner: R,
+    settings: Arc<Settings>,
+}
+
+impl<R: Read> Read for Tee<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let length = self.inner.read(buf)?;
+        self.settings
+            .recorder
+            .lock()
+            .unwrap()
+            .capture(&buf[..length]);
+        Ok(length)
+    }
+}
+
+/// Whether the server still answers, for `Client::set_keepalive`.
+struct Keepalive {
+    interval: Option<Duration>,
+    // Updated by the pump with every message.
+    last_received: Instant,
+    // When the probe that is still waiting for an answer went out.
+    probe_sent: Option<Instant>,
+    stale_reported: bool,
+}
+
+impl Keepalive {
+    fn received(&mut self) {
+        self.last_received = Instant::now();
+        self.probe_sent = None;
+        self.stale_reported = false;
+    }
+
+    fn probe_due(&self) -> bool {
+        match self.interval {
+            Some(interval) => self.probe_sent.is_none() && self.last_received.elapsed() >= interval,
+            None => false,
+        }
+    }
+
+    fn next_probe(&self) -> Option<Instant> {
+        match self.probe_sent {
+            None => Some(self.last_received + self.interval?),
+            Some(_) => None,
+        }
+    }
+
+    /// How long to wait for events before checking for a stale connection again.
+    fn time_to_stale(&self) -> Option<Duration> {
+        let interval = self.interval?;
+        match self.probe_sent {
+            Some(sent) if !self.stale_reported => Some(interval.saturating_sub(sent.elapsed())),
+            _ => Some(interval),
+        }
+    }
+
+    /// Returns true, once, when the probe has gone unanswered for the interval.
+    fn take_stale(&mut self) -> bool {
+        match (self.interval, self.probe_sent) {
+            (Some(interval), Some(sent)) if !self.stale_reported && sent.elapsed() >= interval => {
+                self.stale_reported = true;
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Agrees on VeNCrypt 0.2 with the server and returns the sub-types it offers.
+fn vencrypt_handshake<S: Read + Write>(stream: &mut S) -> Result<Vec<protocol::VeNCryptSubType>> {
+    let version = protocol::VeNCryptVersion::read_from(stream)?;
+    debug!("<- {:?}", version);
+    if version < protocol::VeNCryptVersion::V0_2 {
+        return Err(Error::Unexpected("VeNCrypt version"));
+    }
+    debug!("-> {:?}", protocol::VeNCryptVersion::V0_2);
+    protocol::VeNCryptVersion::V0_2.write_to(stream)?;
+    if stream.read_u8()? != 0 {
+        return Err(Error::Unexpected("VeNCrypt version refused"));
+    }
+    let sub_types = protocol::VeNCryptSubTypes::read_from(stream)?;
+    debug!("<- {:?}", sub_types);
+    Ok(sub_types.0)
+}
+
+/// Declines any tunnel offered within the Tight security type and returns the
+/// authentication schemes the server offers next.
+fn tight_handshake<S: Read + Write>(stream: &mut S) -> Result<Vec<protocol::TightCapability>> {
+    let tunnels = protocol::TightCapabilities::read_from(stream)?;
+    debug!("<- tunnels {:?}", tunnels);
+    if !tunnels.0.is_empty() {
+        if !tunnels
+            .0
+            .iter()
+            .any(|tunnel| tunnel.code == protocol::TightCapability::NO_TUNNEL)
+        {
+            return Err(Error::Unexpected("Tight tunnel"));
+        }
+        debug!("-> tunnel {}", protocol::TightCapability::NO_TUNNEL);
+        stream.write_i32::<BigEndian>(protocol::TightCapability::NO_TUNNEL)?;
+    }
+    let auth_types = protocol::TightCapabilities::read_from(stream)?;
+    debug!("<- auth types {:?}", auth_types);
+    Ok(auth_types.0)
+}
+
+/// A security type that only tells the authentication schemes within it once
+/// it has been picked.
+enum Nested {
+    VeNCrypt(Vec<protocol::VeNCryptSubType>),
+    Tight(Vec<protocol::TightCapability>),
+}
+
+/// A connection the client can run over, e.g. a TLS stream or an SSH channel
+/// wrapped by the caller. The server's messages are read on a thread of its
+/// own, from a clone of the connection.
+pub trait ClientStream: Read + Write + Send + 'static {
+    fn try_clone(&self) -> io::Result<Self>
+    where
+        Self: Sized;
+    /// Closes both directions, waking up the thread reading from the connection.
+    fn shutdown(&self) -> io::Result<()>;
+    /// Makes reads give up after `timeout`, like `TcpStream::set_read_timeout`.
+    /// Clones must share the timeout, as duplicated sockets do.
+    fn set_read_timeout(&self, _timeout: Option<Duration>) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "timeouts are not supported",
+        ))
+    }
+    /// Makes writes give up after `timeout`, like `TcpStream::set_write_timeout`.
+    fn set_write_timeout(&self, _timeout: Option<Duration>) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "timeouts are not supported",
+        ))
+    }
+}
+
+impl ClientStream for TcpStream {
+    fn try_clone(&self) -> io::Result<TcpStream> {
+        TcpStream::try_clone(self)
+    }
+
+    fn shutdown(&self) -> io::Result<()> {
+        TcpStream::shutdown(self, Shutdown::Both)
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_read_timeout(self, timeout)
+    }
+
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_write_timeout(self, timeout)
+    }
+}
+
+#[cfg(unix)]
+impl ClientStream for UnixStream {
+    fn try_clone(&self) -> io::Result<UnixStream> {
+        UnixStream::try_clone(self)
+    }
+
+    fn shutdown(&self) -> io::Result<()> {
+        UnixStream::shutdown(self, Shutdown::Both)
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        UnixStream::set_read_timeout(self, timeout)
+    }
+
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        UnixStream::set_write_timeout(self, timeout)
+    }
+}
+
+#[cfg(feature = "websocket")]
+impl ClientStream for WebSocket {
+    fn try_clone(&self) -> io::Result<WebSocket> {
+        WebSocket::try_clone(self)
+    }
+
+    fn shutdown(&self) -> io::Result<()> {
+        WebSocket::shutdown(self)
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        WebSocket::set_read_timeout(self, timeout)
+    }
+
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        WebSocket::set_write_timeout(self, timeout)
+    }
+}
+
+fn check_size(width: u16, height: u16, max_size: (u16, u16)) -> Result<()> {
+    if width > max_size.0 || height > max_size.1 {
+        return Err(Error::FramebufferTooLarge(width, height));
+    }
+    Ok(())
+}
+
+/// Cursors are allocated at the size the server claims, which is bounded by
+/// that of the framebuffer, so that `max_size` caps them too.
+fn check_cursor_size(width: u16, height: u16, size: (u16, u16)) -> Result<()> {
+    if width > size.0 || height > size.1 {
+        return Err(Error::Unexpected("cursor larger than the framebuffer"));
+    }
+    Ok(())
+}
+
+impl Event {
+    fn pump<R: Read>(
+        stream: R,
+        format: Arc<Mutex<protocol::PixelFormat>>,
+        mut size: (u16, u16),
+        max_size: (u16, u16),
+        settings: Arc<Settings>,
+        tx_events: &mut Sender<(Event, Timestamp)>,
+        message_type: &mut Option<u8>,
+    ) -> Result<()> {
+        let mut frame = None;
+        let mut frame_count = 0;
+
+        macro_rules! send {
+            ($chan:expr, $data:expr) => {{
+                match $chan.send(($data, Timestamp::now(frame))) {
+                    Ok(()) => (),
+                    Err(_) => break,
+                }
+            }};
+        }
+
+        let mut hextile_decoder = hextile::Decoder::new();
+        let mut zlib_decoder = zlib::Decoder::new();
+        let mut zlibhex_decoder = zlibhex::Decoder::new();
+        let mut trle_decoder = trle::Decoder::new();
+        #[cfg(feature = "lzo")]
+        let mut ultra_decoder = ultra::Decoder::new();
+        #[cfg(feature = "png")]
+        let mut tightpng_decoder = tightpng::Decoder::new();
+        let mut zrle_decoder = zrle::Decoder::new();
+        let mut h264_decoder = None;
+        let mut stream = Tee {
+            inner: stream,
+            settings: settings.clone(),
+        };
+        loop {
+            *message_type = None;
+            settings.recorder.lock().unwrap().end_message();
+            // Waiting for the next message is the only place where a read timeout
+            // leaves nothing half-read, so it is reported and waited out.
+            let mut first = [0];
+            match stream.inner.read(&mut first) {
+                Ok(0) => return Err(Error::Disconnected),
+                Ok(_) => {
+                    settings.keepalive.lock().unwrap().received();
+                    let format = *format.lock().unwrap();
+                    settings
+                        .recorder
+                        .lock()
+                        .unwrap()
+                        .start_message(first[0], size, format);
+                }
+                Err(error) => match Error::from(error) {
+                    Error::Timeout => {
+                        send!(tx_events, Event::Timeout);
+                        continue;
+                    }
+                    Error::Io(ref error) if error.kind() == io::ErrorKind::Interrupted => continue,
+                    error => return Err(error),
+                },
+            }
+            let max_clipboard_size = settings.max_clipboard_size.load(Ordering::Relaxed);
+            let mut message = (&first[..]).chain(&mut stream);
+            let packet = match protocol::S2C::read_from_limited(&mut message, max_clipboard_size)? {
+                Some(packet) => packet,
+                None => {
+                    warn!(
+                        "ignoring clipboard text longer than {} characters",
+                        max_clipboard_size
+                    );
+                    continue;
+                }
+            };
+            debug!("<- {:?}", packet);
+            *message_type = Some(packet.message_type());
+
+            let format = *format.lock().unwrap();
+            packet.validate(&format)?;
+            match packet {
+                protocol::S2C::SetColourMapEntries {
+                    first_colour,
+                    colours,
+                } => {
+                    send!(
+                        tx_events,
+                        Event::SetColourMap {
+                            first_colour,
+                            colours,
+                        }
+                    )
+                }
+                protocol::S2C::FramebufferUpdate { count } => {
+                    frame = Some(frame_count);
+                    frame_count += 1;
+
+                    for _ in 0..count {
+                        let rectangle = protocol::Rectangle::read_from(&mut stream)?;
+                        debug!("<- {:?}", rectangle);
+
+                        let dst = Rect {
+                            left: rectangle.x_position,
+                            top: rectangle.y_position,
+                            width: rectangle.width,
+                            height: rectangle.height,
+                        };
+                        // Pixels are drawn straight into framebuffers of the size the
+                        // server announced, so a rectangle past its edge is an error.
+                        let carries_pixels = matches!(
+                            rectangle.encoding,
+                            protocol::Encoding::Raw
+                                | protocol::Encoding::Hextile
+                                | protocol::Encoding::ZlibHex
+                                | protocol::Encoding::Trle
+                                | protocol::Encoding::TightPng
+                                | protocol::Encoding::OpenH264
+                                | protocol::Encoding::Zlib
+                                | protocol::Encoding::Ultra
+                                | protocol::Encoding::Zrle
+                        );
+                        if carries_pixels && !Rect::with_size(size.0, size.1).contains_rect(&dst) {
+                            return Err(Error::Unexpected("rectangle out of bounds"));
+                        }
+                        match rectangle.encoding {
+                            protocol::Encoding::Raw => {
+                                let length = (rectangle.width as usize)
+                                    * (rectangle.height as usize)
+                                    * (format.bits_per_pixel as usize / 8);
+                                let mut pixels = vec![0; length];
+                                stream.read_exact(&mut pixels)?;
+                                debug!("<- ...pixels");
+                                send!(tx_events, Event::PutPixels(dst, pixels))
+                            }
+                            protocol::Encoding::CopyRect => {
+                                let copy_rect = protocol::CopyRect::read_from(&mut stream)?;
+                                let src = Rect {
+                                    left: copy_rect.src_x_position,
+                                    top: copy_rect.src_y_position,
+                                    width: rectangle.width,
+                                    height: rectangle.height,
+                                };
+                                // Consumers copy within their own framebuffer, so a server
+                                // pointing either rectangle past its edge is an error
+                                // rather than something to clip.
+                                let framebuffer = Rect::with_size(size.0, size.1);
+                                if !framebuffer.contains_rect(&src) {
+                                    return Err(Error::Unexpected("CopyRect source out of bounds"));
+                                }
+                                if !framebuffer.contains_rect(&dst) {
+                                    return Err(Error::Unexpected(
+                                        "CopyRect destination out of bounds",
+                                    ));
+                                }
+                                send!(tx_events, Event::CopyPixels { src, dst })
+                            }
+                            protocol::Encoding::Hextile => {
+                                let result = hextile_decoder.decode(
+                                    &mut stream,
+                                    format,
+                                    dst,
+                                    |tile, pixels| {
+                                        let event = Event::PutPixels(tile, pixels.to_vec());
+                                        Ok(tx_events.send((event, Timestamp::now(frame))).is_ok())
+                                    },
+                                )?;
+                                if !result {
+                                    break;
+                                }
+                            }
+                            protocol::Encoding::ZlibHex => {
+                                let result = zlibhex_decoder.decode(
+                                    &mut stream,
+                                    format,
+                                    dst,
+                                    |tile, pixels| {
+                                        let event = Event::PutPixels(tile, pixels.to_vec());
+                                        Ok(tx_events.send((event, Timestamp::now(frame))).is_ok())
+                                    },
+                                )?;
+                                if !result {
+                                    break;
+                                }
+                            }
+                            protocol::Encoding::Trle => {
+                                let result = trle_decoder.decode(
+                                    &mut stream,
+                                    format,
+                                    dst,
+                                    |tile, pixels| {
+                                        let event = Event::PutPixels(tile, pixels.to_vec());
+                                        Ok(tx_events.send((event, Timestamp::now(frame))).is_ok())
+                                    },
+                                )?;
+                                if !result {
+                                    break;
+                                }
+                            }
+                            #[cfg(feature = "png")]
+                            protocol::Encoding::TightPng => {
+                                let pixels = tightpng_decoder.decode(&mut stream, format, dst)?;
+                                send!(tx_events, Event::PutPixels(dst, pixels.to_vec()))
+                            }
+                            protocol::Encoding::OpenH264 => {
+                                let decoder = match h264_decoder {
+                                    Some(ref mut decoder) => decoder,
+                                    None => {
+                                        let backend =
+                                            settings.h264_backend.lock().unwrap().take().ok_or(
+                                                Error::Unexpected(
+                                                    "Open H.264 rectangle without a decoder",
+                                                ),
+                                            )?;
+                                        h264_decoder.insert(h264::Decoder::new(backend))
+                                    }
+                                };
+                                if let Some(pixels) = decoder.decode(&mut stream, format, dst)? {
+                                    send!(tx_events, Event::PutPixels(dst, pixels.to_vec()))
+                                }
+                            }
+                            protocol::Encoding::Zlib => {
+                                let data = Vec::<u8>::read_from(&mut stream)?;
+                                debug!("<- ...compressed pixels");
+                                let pixels = zlib_decoder.decode(format, dst, &data)?;
+                                send!(tx_events, Event::PutPixels(dst, pixels.to_vec()))
+                            }
+                            #[cfg(feature = "lzo")]
+                            protocol::Encoding::Ultra => {
+                                let data = Vec::<u8>::read_from(&mut stream)?;
+                                debug!("<- ...compressed pixels");
+                                let pixels = ultra_decoder.decode(format, dst, &data)?;
+                                send!(tx_events, Event::PutPixels(dst, pixels.to_vec()))
+                            }
+                            protocol::Encoding::Zrle => {
+                                let data = Vec::<u8>::read_from(&mut stream)?;
+                                debug!("<- ...compressed pixels");
+                                let result =
+                                    zrle_decoder.decode(format, dst, &data, |tile, pixels| {
+                                        let event = Event::PutPixels(tile, pixels.to_vec());
+                                        Ok(tx_events.send((event, Timestamp::now(frame))).is_ok())
+                                    })?;
+                                if !result {
+                                    break;
+                                }
+                            }
+                            protocol::Encoding::Cursor => {
+                                check_cursor_size(rectangle.width, rectangle.height, size)?;
+                                let mut pixels = vec![
+                                    0;
+                                    (rectangle.width as usize)
+                                        * (rectangle.height as usize)
+                                        * (format.bits_per_pixel as usize / 8)
+                                ];
+                                stream.read_exact(&mut pixels)?;
+                                let mut mask_bits = vec![
+                                    0;
+                                    (rectangle.width as usize).div_ceil(8)
+                                        * (rectangle.height as usize)
+                                ];
+                                stream.read_exact(&mut mask_bits)?;
+                                send!(
+                                    tx_events,
+                                    Event::SetCursor {
+                                        size: (rectangle.width, rectangle.height),
+                                        hotspot: (rectangle.x_position, rectangle.y_position),
+                                        pixels,
+                                        mask_bits,
+                                    }
+                                )
+                            }
+                            protocol::Encoding::CursorWithAlpha => {
+                                check_cursor_size(rectangle.width, rectangle.height, size)?;
+                                // The image is in an encoding of its own; servers use Raw.
+                                if protocol::Encoding::read_from(&mut stream)?
+                                    != protocol::Encoding::Raw
+                                {
+                                    return Err(Error::Unexpected("CursorWithAlpha encoding"));
+                                }
+                                let mut rgba = vec![0; dst.area() * 4];
+                                stream.read_exact(&mut rgba)?;
+                                pixels::unpremultiply_alpha(&mut rgba);
+                                send!(
+                                    tx_events,
+                                    Event::SetCursorAlpha {
+                                        size: (rectangle.width, rectangle.height),
+                                        hotspot: (rectangle.x_position, rectangle.y_position),
+                                        rgba,
+                                    }
+                                )
+                            }
+                            protocol::Encoding::DesktopSize => {
+                                check_size(rectangle.width, rectangle.height, max_size)?;
+                                size = (rectangle.width, rectangle.height);
+                                send!(tx_events, Event::Resize(rectangle.width, rectangle.height))
+                            }
+                            protocol::Encoding::ExtendedDesktopSize => {
+                                let layout = protocol::ScreenLayout::read_from(&mut stream)?;
+                                // The x position tells who asked for the change and the
+                                // y position whether it was made; a refused request
+                                // leaves everything as it was.
+                                if rectangle.y_position != 0 {
+                                    warn!(
+                                        "server did not change the screen layout: status {}",
+                                        rectangle.y_position
+                                    );
+                                    send!(
+                                        tx_events,
+                                        Event::DesktopSizeRefused(rectangle.y_position)
+                                    );
+                                    continue;
+                                }
+                                check_size(rectangle.width, rectangle.height, max_size)?;
+                                // Unlike DesktopSize, this also reports layout changes
+                                // that keep the framebuffer size.
+                                if (rectangle.width, rectangle.height) != size {
+                                    size = (rectangle.width, rectangle.height);
+                                    send!(
+                                        tx_events,
+                                        Event::Resize(rectangle.width, rectangle.height)
+                                    )
+                                }
+                                send!(tx_events, Event::ScreenLayout(layout.0))
+                            }
+                            // Ends an update that was announced with more
+                            // rectangles than it has, as Tight servers do when
+                            // they cannot tell the count up front.
+                            protocol::Encoding::LastRect => break,
+                            protocol::Encoding::DesktopName => {
+                                let name = protocol::DesktopName::read_from(&mut stream)?;
+                                send!(tx_events, Event::NameChange(name.0))
+                            }
+                            protocol::Encoding::LedState => {
+                                let state = stream.read_u8()?;
+                                send!(
+                                    tx_events,
+                                    Event::LedState {
+                                        scroll_lock: state & 1 != 0,
+                                        num_lock: state & 2 != 0,
+                                        caps_lock: state & 4 != 0,
+                                    }
+                                )
+                            }
+                            protocol::Encoding::QemuExtendedKeyEvent => {
+                                send!(tx_events, Event::QemuExtendedKeyEvent)
+                            }
+                            protocol::Encoding::QemuPointerMotionChange => {
+                                let mode = if rectangle.x_position == 0 {
+                                    PointerMotionMode::Relative
+                                } else {
+                                    PointerMotionMode::Absolute
+                                };
+                                send!(tx_events, Event::PointerMotionMode(mode))
+                            }
+                            _ => return Err(Error::Unexpected("encoding")),
+                        };
+                    }
+
+                    send!(tx_events, Event::EndOfFrame);
+                    frame = None;
+                }
+                protocol::S2C::Bell => send!(tx_events, Event::Bell),
+                protocol::S2C::CutText(text) => send!(tx_events, Event::Clipboard(text)),
+                protocol::S2C::Fence(fence) => send!(tx_events, Event::Fence(fence)),
+                protocol::S2C::Gii(message) => send!(tx_events, Event::Gii(message)),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// A connection to a VNC server.
+///
+/// Reading from the server and decoding framebuffer updates happens on a thread
+/// owned by the client; the caller only receives finished events through
+/// `poll_event` and friends, so none of that work happens on the caller's thread.
+pub struct Client {
+    stream: BufWriter<Box<dyn ClientStream>>,
+    auto_flush: bool,
+    // Taken by `split`.
+    events: Option<Events>,
+    name: Arc<Mutex<String>>,
+    size: Arc<Mutex<(u16, u16)>>,
+    format: Arc<Mutex<protocol::PixelFormat>>,
+    version: protocol::Version,
+    security_type: protocol::SecurityType,
+    server_kind: ServerKind,
+    shared: bool,
+    native_format: protocol::PixelFormat,
+    encodings: Vec<protocol::Encoding>,
+    min_update_interval: Option<Duration>,
+    last_update_request: Option<Instant>,
+    pending_update: Option<Rect>,
+    refresh_interval: Option<Duration>,
+    last_refresh: Instant,
+    settings: Arc<Settings>,
+    pointer_motion_mode: Arc<Mutex<PointerMotionMode>>,
+    screens: Arc<Mutex<Vec<Screen>>>,
+    fence_supported: Arc<Mutex<bool>>,
+    extended_key_events: Arc<Mutex<bool>>,
+    gii_versions: Arc<Mutex<Option<(u16, u16)>>>,
+    gii_version_sent: bool,
+    tight_interaction: Option<protocol::TightInteraction>,
+}
+
+impl Client {
+    pub fn from_tcp_stream<Auth>(stream: TcpStream, shared: bool, auth: Auth) -> Result<Client>
+    where
+        Auth: FnOnce(&[AuthMethod]) -> Option<AuthChoice>,
+    {
+        Client::from_tcp_stream_with_max_size(stream, shared, (u16::MAX, u16::MAX), auth)
+    }
+
+    /// Like `from_tcp_stream`, but fails with `Error::FramebufferTooLarge` if the
+    /// server's framebuffer is wider or taller than `max_size`, both when connecting
+    /// and when it is resized later on. This keeps a misbehaving server from making
+    /// the client allocate enormous framebuffers.
+    pub fn from_tcp_stream_with_max_size<Auth>(
+        stream: TcpStream,
+        shared: bool,
+        max_size: (u16, u16),
+        auth: Auth,
+    ) -> Result<Client>
+    where
+        Auth: FnOnce(&[AuthMethod]) -> Option<AuthChoice>,
+    {
+        Client::from_stream_with_max_size(stream, shared, max_size, auth)
+    }
+
+    /// Waits on `addr` for a server to connect to the client, as servers do when
+    /// asked to make a reverse connection (conventionally to port 5500), and
+    /// performs the handshake on the first connection. The server still speaks
+    /// first, so the handshake is the same as with `from_tcp_stream`.
+    pub fn listen<A, Auth>(addr: A, shared: bool, auth: Auth) -> Result<Client>
+    where
+        A: ToSocketAddrs,
+        Auth: FnOnce(&[AuthMethod]) -> Option<AuthChoice>,
+    {
+        let listener = TcpListener::bind(addr)?;
+        debug!(
+            "waiting for a reverse connection on {}",
+            listener.local_addr()?
+        );
+        let (stream, address) = listener.accept()?;
+        debug!("reverse connection from {}", address);
+        Client::from_tcp_stream(stream, shared, auth)
+    }
+
+    /// Like `from_tcp_stream`, over a Unix socket, such as the one QEMU listens
+    /// on with `-vnc unix:PATH`.
+    #[cfg(unix)]
+    pub fn from_unix_stream<Auth>(stream: UnixStream, shared: bool, auth: Auth) -> Result<Client>
+    where
+        Auth: FnOnce(&[AuthMethod]) -> Option<AuthChoice>,
+    {
+        Client::from_unix_stream_with_max_size(stream, shared, (u16::MAX, u16::MAX), auth)
+    }
+
+    /// Like `from_tcp_stream_with_max_size`, over a Unix socket.
+    #[cfg(unix)]
+    pub fn from_unix_stream_with_max_size<Auth>(
+        stream: UnixStream,
+        shared: bool,
+        max_size: (u16, u16),
+        auth: Auth,
+    ) -> Result<Client>
+    where
+        Auth: FnOnce(&[AuthMethod]) -> Option<AuthChoice>,
+    {
+        Client::from_stream_with_max_size(stream, shared, max_size, auth)
+    }
+
+    /// Like `from_tcp_stream`, with RFB framed over a WebSocket, as behind a
+    /// websockify or noVNC gateway. `url` is of the form `ws://HOST[:PORT][/PATH]`;
+    /// `wss://` URLs need TLS, which is not supported.
+    #[cfg(feature = "websocket")]
+    pub fn from_websocket<Auth>(url: &str, shared: bool, auth: Auth) -> Result<Client>
+    where
+        Auth: FnOnce(&[AuthMethod]) -> Option<AuthChoice>,
+    {
+        let stream = WebSocket::connect(url)?;
+        Client::from_stream(stream, shared, auth)
+    }
+
+    /// Like `from_tcp_stream`, for any kind of connection.
+    pub fn from_stream<S, Auth>(stream: S, shared: bool, auth: Auth) -> Result<Client>
+    where
+        S: ClientStream,
+        Auth: FnOnce(&[AuthMethod]) -> Option<AuthChoice>,
+    {
+        Client::from_stream_with_max_size(stream, shared, (u16::MAX, u16::MAX), auth)
+    }
+
+    /// Like `from_stream`, through the UltraVNC repeater `stream` is connected
+    /// to, which pairs the client with the server `id` names.
+    pub fn from_repeater_stream<S, Auth>(
+        mut stream: S,
+        id: &protocol::RepeaterId,
+        shared: bool,
+        auth: Auth,
+    ) -> Result<Client>
+    where
+        S: ClientStream,
+        Auth: FnOnce(&[AuthMethod]) -> Option<AuthChoice>,
+    {
+        let mut greeting = [0; 12];
+        stream.read_exact(&mut greeting)?;
+        if greeting != protocol::RepeaterId::GREETING {
+            return Err(Error::Unexpected("repeater greeting"));
+        }
+        debug!("-> {:?}", id);
+        id.write_to(&mut stream)?;
+        Client::from_stream(stream, shared, auth)
+    }
+
+    /// Like `from_tcp_stream_with_max_size`, for any kind of connection.
+    pub fn from_stream_with_max_size<S, Auth>(
+        mut stream: S,
+        shared: bool,
+        max_size: (u16, u16),
+        auth: Auth,
+    ) -> Result<Client>
+    where
+        S: ClientStream,
+        Auth: FnOnce(&[AuthMethod]) -> Option<AuthChoice>,
+    {
+        let version = protocol::Version::read_from(&mut stream)?;
+        debug!("<- Version::{:?}", version);
+        debug!("-> Version::{:?}", version);
+        protocol::Version::write_to(&version, &mut stream)?;
+
+        let security_types = match version {
+            protocol::Version::Rfb33 => {
+                let security_type = protocol::SecurityType::read_from(&mut stream)?;
+                debug!("<- SecurityType::{:?}", security_type);
+                if security_type == protocol::SecurityType::Invalid {
+                    vec![]
+                } else {
+                    vec![security_type]
+                }
+            }
+            _ => {
+                let security_types = protocol::SecurityTypes::read_from(&mut stream)?;
+                debug!("<- {:?}", security_types);
+                security_types.0
+            }
+        };
+
+        if security_types.is_empty() {
+            let reason = String::read_from(&mut stream)?;
+            debug!("<- {:?}", reason);
+            return Err(Error::Server(reason));
+        }
+
+        let mut auth_methods = Vec::new();
+        for &security_type in &security_types {
+            match security_type {
+                protocol::SecurityType::None => auth_methods.push(AuthMethod::None),
+                protocol::SecurityType::VncAuthentication => {
+                    auth_methods.push(AuthMethod::Password)
+                }
+                protocol::SecurityType::AppleRemoteDesktop => {
+                    auth_methods.push(AuthMethod::AppleRemoteDesktop)
+                }
+                #[cfg(feature = "sasl")]
+                protocol::SecurityType::Sasl => auth_methods.push(AuthMethod::Sasl),
+                _ => (),
+            }
+        }
+
+        // Tight wraps the plain security types and lets the client learn the
+        // server's capabilities, so it is preferred when offered. VeNCrypt only
+        // tells its sub-types once it has been picked, so it is only used if
+        // none of the plain security types will do.
+        let nested = if security_types.contains(&protocol::SecurityType::Tight) {
+            if version != protocol::Version::Rfb33 {
+                debug!("-> SecurityType::{:?}", protocol::SecurityType::Tight);
+                protocol::SecurityType::Tight.write_to(&mut stream)?;
+            }
+            let auth_types = tight_handshake(&mut stream)?;
+            auth_methods.clear();
+            if auth_types.is_empty() {
+                auth_methods.push(AuthMethod::None);
+            }
+            for auth_type in &auth_types {
+                match auth_type.code {
+                    protocol::TightCapability::AUTH_NONE => auth_methods.push(AuthMethod::None),
+                    protocol::TightCapability::AUTH_VNC => auth_methods.push(AuthMethod::Password),
+                    _ => (),
+                }
+            }
+            Some(Nested::Tight(auth_types))
+        } else if auth_methods.is_empty()
+            && security_types.contains(&protocol::SecurityType::VeNCrypt)
+        {
+            if version != protocol::Version::Rfb33 {
+                debug!("-> SecurityType::{:?}", protocol::SecurityType::VeNCrypt);
+                protocol::SecurityType::VeNCrypt.write_to(&mut stream)?;
+            }
+            let sub_types = vencrypt_handshake(&mut stream)?;
+            for &sub_type in &sub_types {
+                match sub_type {
+                    protocol::VeNCryptSubType::None => auth_methods.push(AuthMethod::None),
+                    protocol::VeNCryptSubType::VncAuthentication => {
+                        auth_methods.push(AuthMethod::Password)
+                    }
+                    protocol::VeNCryptSubType::Plain => auth_methods.push(AuthMethod::Plain),
+                    sub_type if sub_type.uses_tls() => {
+                        debug!("VeNCrypt sub-type {:?} needs TLS, skipping it", sub_type)
+                    }
+                    _ => (),
+                }
+            }
+            Some(Nested::VeNCrypt(sub_types))
+        } else {
+            None
+        };
+
+        let auth_choice = auth(&auth_methods).ok_or(Error::AuthenticationUnavailable)?;
+
+        let used_security_type = match nested {
+            Some(Nested::Tight(ref auth_types)) => {
+                let code = match auth_choice {
+                    AuthChoice::None => protocol::TightCapability::AUTH_NONE,
+                    AuthChoice::Password(_) => protocol::TightCapability::AUTH_VNC,
+                    _ => return Err(Error::AuthenticationUnavailable),
+                };
+                // An empty list means there is no authentication, and no answer.
+                if !auth_types.is_empty() {
+                    if !auth_types.iter().any(|auth_type| auth_type.code == code) {
+                        return Err(Error::AuthenticationUnavailable);
+                    }
+                    debug!("-> auth type {}", code);
+                    stream.write_i32::<BigEndian>(code)?;
+                } else if code != protocol::TightCapability::AUTH_NONE {
+                    return Err(Error::AuthenticationUnavailable);
+                }
+                protocol::SecurityType::Tight
+            }
+            Some(Nested::VeNCrypt(ref sub_types)) => {
+                let used_sub_type = match auth_choice {
+                    AuthChoice::None => protocol::VeNCryptSubType::None,
+                    AuthChoice::Password(_) => protocol::VeNCryptSubType::VncAuthentication,
+                    AuthChoice::Plain(_, _) => protocol::VeNCryptSubType::Plain,
+                    _ => return Err(Error::AuthenticationUnavailable),
+                };
+                if !sub_types.contains(&used_sub_type) {
+                    return Err(Error::AuthenticationUnavailable);
+                }
+                debug!("-> VeNCryptSubType::{:?}", used_sub_type);
+                used_sub_type.write_to(&mut stream)?;
+                protocol::SecurityType::VeNCrypt
+            }
+            None => {
+                let used_security_type = match auth_choice {
+                    AuthChoice::None => protocol::SecurityType::None,
+                    AuthChoice::Password(_) => protocol::SecurityType::VncAuthentication,
+                    AuthChoice::AppleRemoteDesktop(_, _) => {
+                        protocol::SecurityType::AppleRemoteDesktop
+                    }
+                    #[cfg(feature = "sasl")]
+                    AuthChoice::Sasl(_, _) => protocol::SecurityType::Sasl,
+                    #[cfg(not(feature = "sasl"))]
+                    AuthChoice::Sasl(_, _) => return Err(Error::AuthenticationUnavailable),
+                    AuthChoice::Plain(_, _) => return Err(Error::AuthenticationUnavailable),
+                };
+
+                // The server only proceeds with a security type it offered; picking
+                // anything else would desynchronize the handshake.
+                if !security_types.contains(&used_security_type) {
+                    return Err(Error::AuthenticationUnavailable);
+                }
+
+                match version {
+                    protocol::Version::Rfb33 => (),
+                    _ => {
+                        debug!("-> SecurityType::{:?}", used_security_type);
+                        protocol::SecurityType::write_to(&used_security_type, &mut stream)?;
+                    }
+                }
+                used_security_type
+            }
+        };
+
+        let no_auth = matches!(auth_choice, AuthChoice::None);
+        match auth_choice {
+            AuthChoice::Password(password) => {
+                let mut challenge = [0; 16];
+                stream.read_exact(&mut challenge)?;
+                let response = des::vnc_auth_response(&challenge, &password);
+                stream.write_all(&response)?;
+            }
+            #[cfg(feature = "apple-auth")]
+            AuthChoice::AppleRemoteDesktop(ref username, ref password) => {
+                let handshake = protocol::AppleAuthHandshake::read_from(&mut stream)?;
+                let response = apple_auth(username, password, &handshake);
+                response.write_to(&mut stream)?;
+            }
+            #[cfg(feature = "sasl")]
+            AuthChoice::Sasl(ref username, ref password) => {
+                let mechanisms = sasl::read_mechanisms(&mut stream)?;
+                debug!("<- SASL mechanisms {:?}", mechanisms);
+                if !mechanisms.iter().any(|mechanism| mechanism == sasl::PLAIN) {
+                    return Err(Error::AuthenticationUnavailable);
+                }
+                debug!("-> SASL {}", sasl::PLAIN);
+                sasl::authenticate_plain(&mut stream, username, password)?;
+            }
+            AuthChoice::Plain(username, password) => {
+                let credentials = protocol::VeNCryptPlain { username, password };
+                debug!("-> VeNCryptPlain for {:?}", credentials.username);
+                credentials.write_to(&mut stream)?;
+            }
+            _ => (),
+        }
+
+        let mut skip_security_result = false;
+        match (used_security_type, version) {
+            (protocol::SecurityType::None, protocol::Version::Rfb33)
+            | (protocol::SecurityType::None, protocol::Version::Rfb37) => {
+                skip_security_result = true
+            }
+            // Before 3.8, Tight servers only report the result of VNC authentication.
+            (protocol::SecurityType::Tight, protocol::Version::Rfb33)
+            | (protocol::SecurityType::Tight, protocol::Version::Rfb37) => {
+                skip_security_result = no_auth
+            }
+            _ => (),
+        }
+
+        if !skip_security_result {
+            match protocol::SecurityResult::read_from(&mut stream)? {
+                protocol::SecurityResult::Succeeded => (),
+                protocol::SecurityResult::Failed => match version {
+                    protocol::Version::Rfb33 | protocol::Version::Rfb37 => {
+                        return Err(Error::AuthenticationFailure(String::from("")))
+                    }
+                    protocol::Version::Rfb38 => {
+                        let reason = String::read_from(&mut stream)?;
+                        debug!("<- {:?}", reason);
+                        return Err(Error::AuthenticationFailure(reason));
+                    }
+                },
+            }
+        }
+
+        let client_init = protocol::ClientInit { shared };
+        debug!("-> {:?}", client_init);
+        protocol::ClientInit::write_to(&client_init, &mut stream)?;
+
+        let server_init = protocol::ServerInit::read_from(&mut stream)?;
+        debug!("<- {:?}", server_init);
+        check_size(
+            server_init.framebuffer_width,
+            server_init.framebuffer_height,
+            max_size,
+        )?;
+
+        let tight_interaction = match used_security_type {
+            protocol::SecurityType::Tight => {
+                let interaction = protocol::TightInteraction::read_from(&mut stream)?;
+                debug!("<- {:?}", interaction);
+                Some(interaction)
+            }
+            _ => None,
+        };
+
+        let format = Arc::new(Mutex::new(server_init.pixel_format));
+        let settings = Arc::new(Settings {
+            max_clipboard_size: AtomicUsize::new(DEFAULT_MAX_CLIPBOARD_SIZE),
+            h264_backend: Mutex::new(None),
+            keepalive: Mutex::new(Keepalive {
+                interval: None,
+                last_received: Instant::now(),
+                probe_sent: None,
+                stale_reported: false,
+            }),
+            recorder: Mutex::new(Recorder::Off),
+        });
+
+        let size = (
+            server_init.framebuffer_width,
+            server_init.framebuffer_height,
+        );
+        let (tx_events, rx_events) = channel();
+        {
+            let stream = stream.try_clone()?;
+            let format = format.clone();
+            let settings = settings.clone();
+            thread::spawn(move || {
+                let mut tx_events = tx_events;
+                let mut message_type = None;
+                if let Err(error) = Event::pump(
+                    stream,
+                    format,
+                    size,
+                    max_size,
+                    settings.clone(),
+                    &mut tx_events,
+                    &mut message_type,
+                ) {
+                    let reason = DisconnectReason::from_error(error, message_type);
+                    let _ = tx_events.send((Event::Disconnected(reason), Timestamp::now(None)));
+                }
+                settings.recorder.lock().unwrap().end_message();
+            });
+        }
+
+        let server_kind = ServerKind::identify(&server_init.name, &security_types);
+        debug!("server looks like {:?}", server_kind);
+
+        let size = Arc::new(Mutex::new(size));
+        let pointer_motion_mode = Arc::new(Mutex::new(PointerMotionMode::Absolute));
+        let screens = Arc::new(Mutex::new(Vec::new()));
+        let fence_supported = Arc::new(Mutex::new(false));
+        let extended_key_events = Arc::new(Mutex::new(false));
+        let gii_versions = Arc::new(Mutex::new(None));
+        let name = Arc::new(Mutex::new(server_init.name));
+        Ok(Client {
+            stream: BufWriter::new(Box::new(stream)),
+            auto_flush: true,
+            events: Some(Events {
+                events: rx_events,
+                size: size.clone(),
+                pointer_motion_mode: pointer_motion_mode.clone(),
+                screens: screens.clone(),
+                fence_supported: fence_supported.clone(),
+                extended_key_events: extended_key_events.clone(),
+                gii_versions: gii_versions.clone(),
+                name: name.clone(),
+                settings: settings.clone(),
+                format: format.clone(),
+                framebuffer: None,
+            }),
+            name,
+            size,
+            format,
+            version,
+            security_type: used_security_type,
+            server_kind,
+            shared,
+            native_format: server_init.pixel_format,
+            encodings: Vec::new(),
+            min_update_interval: None,
+            last_update_request: None,
+            pending_update: None,
+            refresh_interval: None,
+            last_refresh: Instant::now(),
+            settings,
+            pointer_motion_mode,
+            screens,
+            fence_supported,
+            extended_key_events,
+            gii_versions,
+            gii_version_sent: false,
+            tight_interaction,
+        })
+    }
+
+    /// The desktop name from ServerInit, or as of the last `Event::NameChange`
+    /// if `Encoding::DesktopName` is among the client's encodings.
+    pub fn name(&self) -> String {
+        self.name.lock().unwrap().clone()
+    }
+    pub fn size(&self) -> (u16, u16) {
+        *self.size.lock().unwrap()
+    }
+    pub fn format(&self) -> protocol::PixelFormat {
+        *self.format.lock().unwrap()
+    }
+    /// The monitors making up the framebuffer, as of the last `Event::ScreenLayout`.
+    /// This is empty until the server describes them, which it only does if
+    /// `Encoding::ExtendedDesktopSize` is among the client's encodings.
+    pub fn screens(&self) -> Vec<Screen> {
+        self.screens.lock().unwrap().clone()
+    }
+
+    pub fn version(&self) -> protocol::Version {
+        self.version
+    }
+    pub fn security_type(&self) -> protocol::SecurityType {
+        self.security_type
+    }
+    /// The messages and encodings the server announced after ServerInit, if
+    /// the Tight security type was used.
+    pub fn tight_interaction(&self) -> Option<&protocol::TightInteraction> {
+        self.tight_interaction.as_ref()
+    }
+    /// The server implementation, as guessed from the handshake.
+    pub fn server_kind(&self) -> ServerKind {
+        self.server_kind
+    }
+    /// Whether a shared session was requested in ClientInit.
+    pub fn shared(&self) -> bool {
+        self.shared
+    }
+    /// The pixel format the server announced in ServerInit, regardless of
+    /// any later `set_format` calls.
+    pub fn native_format(&self) -> protocol::PixelFormat {
+        self.native_format
+    }
+    /// The encodings most recently sent with `set_encodings`.
+    pub fn encodings(&self) -> &[protocol::Encoding] {
+        &self.encodings
+    }
+
+    fn send(&mut self, message: &protocol::C2S) -> Result<()> {
+        protocol::C2S::write_to(message, &mut self.stream)?;
+        if self.auto_flush {
+            self.stream.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Sends any messages that are still buffered.
+    pub fn flush(&mut self) -> Result<()> {
+        self.stream.flush()?;
+        Ok(())
+    }
+
+    /// Makes the client stop waiting for the server after `timeout` without
+    /// data, rather than forever (`None`, the default). A timeout between two
+    /// messages is reported with `Event::Timeout`, and the client keeps waiting;
+    /// one in the middle of a message ends the connection with
+    /// `DisconnectReason::Timeout`.
+    pub fn set_read_timeout(&mut self, timeout: Option<Duration>) -> Result<()> {
+        self.stream.get_ref().set_read_timeout(timeout)?;
+        Ok(())
+    }
+
+    /// Makes sending give up with `Error::Timeout` after `timeout`, rather than
+    /// blocking while the server does not read (`None`, the default). What has
+    /// not been sent yet stays buffered and goes out with the next `flush`.
+    pub fn set_write_timeout(&mut self, timeout: Option<Duration>) -> Result<()> {
+        self.stream.get_ref().set_write_timeout(timeout)?;
+        Ok(())
+    }
+
+    pub fn auto_flush(&self) -> bool {
+        self.auto_flush
+    }
+
+    /// Controls whether every message is sent as soon as it is queued (the default).
+    /// With automatic flushing disabled, messages are buffered until `flush()`
+    /// is called or the buffer fills up.
+    pub fn set_auto_flush(&mut self, auto_flush: bool) {
+        self.auto_flush = auto_flush
+    }
+
+    /// Queues messages sent through the returned guard and sends them all at once
+    /// when it is dropped or finished.
+    pub fn batch(&mut self) -> Batch<'_> {
+        let auto_flush = self.auto_flush;
+        self.auto_flush = false;
+        Batch {
+            client: self,
+            auto_flush,
+        }
+    }
+
+    /// Tells the server which encodings to use, in order of preference. Those
+    /// left out of this build are left out of the list: `Encoding::Ultra`
+    /// without the `lzo` feature, and `Encoding::TightPng` without `png`.
+    pub fn set_encodings(&mut self, encodings: &[protocol::Encoding]) -> Result<()> {
+        let encodings = encodings
+            .iter()
+            .copied()
+            .filter(|encoding| match encoding {
+                #[cfg(not(feature = "lzo"))]
+                protocol::Encoding::Ultra => false,
+                #[cfg(not(feature = "png"))]
+                protocol::Encoding::TightPng => false,
+                _ => true,
+            })
+            .collect::<Vec<_>>();
+        let set_encodings = protocol::C2S::SetEncodings(encodings.clone());
+        debug!("-> {:?}", set_encodings);
+        self.send(&set_encodings)?;
+        self.encodings = encodings;
+        Ok(())
+    }
+
+    /// Limits how often incremental update requests are actually sent. Since the
+    /// server only sends updates when asked to, this caps the delivered frame rate
+    /// as well. Requests made sooner than `interval` after the previous one are
+    /// merged and sent later, from `request_update` or `poll_event`, once the
+    /// interval has passed. Non-incremental requests are never delayed.
+    pub fn set_min_update_interval(&mut self, interval: Option<Duration>) {
+        self.min_update_interval = interval
+    }
+
+    pub fn min_update_interval(&self) -> Option<Duration> {
+        self.min_update_interval
+    }
+
+    fn update_throttled(&self) -> bool {
+        match (self.min_update_interval, self.last_update_request) {
+            (Some(interval), Some(last_update_request)) => last_update_request.elapsed() < interval,
+            _ => false,
+        }
+    }
+
+    /// When polling next has something to send: a delayed update request, a
+    /// refresh or a keepalive probe.
+    fn next_due(&self) -> Option<Instant> {
+        let update = match (
+            self.pending_update,
+            self.min_update_interval,
+            self.last_update_request,
+        ) {
+            (Some(_), Some(interval), Some(last_update_request)) => {
+                Some(last_update_request + interval)
+            }
+            _ => None,
+        };
+        let refresh = self
+            .refresh_interval
+            .map(|interval| self.last_refresh + interval);
+        let probe = self.settings.keepalive.lock().unwrap().next_probe();
+        [update, refresh, probe].into_iter().flatten().min()
+    }
+
+    fn send_pending_update(&mut self) -> Result<()> {
+        match self.pending_update {
+            Some(rect) if !self.update_throttled() => self.request_update(rect, true),
+            _ => Ok(()),
+        }
+    }
+
+    /// Requests a non-incremental update of the whole framebuffer, e.g. to recover
+    /// from a server that lost track of damage and left stale regions on screen.
+    pub fn refresh(&mut self) -> Result<()> {
+        let (width, height) = self.size();
+        self.request_update(Rect::with_size(width, height), false)
+    }
+
+    /// Makes `poll_event` call `refresh` whenever `interval` has passed since the
+    /// last full update request.
+    pub fn set_refresh_interval(&mut self, interval: Option<Duration>) {
+        self.refresh_interval = interval
+    }
+
+    pub fn refresh_interval(&self) -> Option<Duration> {
+        self.refresh_interval
+    }
+
+    fn refresh_if_due(&mut self) -> Result<()> {
+        match self.refresh_interval {
+            Some(interval) if self.last_refresh.elapsed() >= interval => self.refresh(),
+            _ => Ok(()),
+        }
+    }
+
+    /// Checks that the server is still there once it has been quiet for
+    /// `interval`: `poll_event` then sends a probe, a fence if the server
+    /// supports them and otherwise a request for one pixel, whose answer shows
+    /// up as the usual events. If nothing at all arrives for another `interval`,
+    /// `Event::ConnectionStale` is handed out. `None`, the default, turns this off.
+    pub fn set_keepalive(&mut self, interval: Option<Duration>) {
+        let mut keepalive = self.settings.keepalive.lock().unwrap();
+        keepalive.interval = interval;
+        keepalive.probe_sent = None;
+        keepalive.stale_reported = false;
+    }
+
+    pub fn keepalive(&self) -> Option<Duration> {
+        self.settings.keepalive.lock().unwrap().interval
+    }
+
+    fn keepalive_if_due(&mut self) -> Result<()> {
+        {
+            let mut keepalive = self.settings.keepalive.lock().unwrap();
+            if !keepalive.probe_due() {
+                return Ok(());
+            }
+            // Before sending, so that an answer cannot arrive first.
+            keepalive.probe_sent = Some(Instant::now());
+        }
+        if *self.fence_supported.lock().unwrap() {
+            self.send_fence(Fence::REQUEST, &[])
+        } else {
+            self.request_update(Rect::with_size(1, 1), false)
+        }
+    }
+
+    pub fn request_update(&mut self, mut rect: Rect, incremental: bool) -> Result<()> {
+        if incremental {
+            if let Some(pending_rect) = self.pending_update.take() {
+                rect = rect.union(&pending_rect);
+            }
+            if self.update_throttled() {
+                self.pending_update = Some(rect);
+                return Ok(());
+            }
+        }
+        self.last_update_request = Some(Instant::now());
+        let (width, height) = self.size();
+        if !incremental && rect.contains_rect(&Rect::with_size(width, height)) {
+            self.last_refresh = Instant::now();
+        }
+
+        let update_req = protocol::C2S::FramebufferUpdateRequest {
+            incremental,
+            x_position: rect.left,
+            y_position: rect.top,
+            width: rect.width,
+            height: rect.height,
+        };
+        trace!("-> {:?}", update_req);
+        self.send(&update_req)
+    }
+
+    pub fn send_key_event(&mut self, down: bool, key: u32) -> Result<()> {
+        let key_event = protocol::C2S::KeyEvent { down, key };
+        debug!("-> {:?}", key_event);
+        self.send(&key_event)
+    }
+
+    /// Sends a key event with both the keysym `key` and the XT scancode
+    /// `keycode` of the key, so that QEMU guests see the physical key whatever
+    /// their keyboard layout. Until the server has acknowledged
+    /// `Encoding::QemuExtendedKeyEvent` with `Event::QemuExtendedKeyEvent`,
+    /// this sends a plain key event instead.
+    pub fn send_key_event_ext(&mut self, down: bool, key: u32, keycode: u32) -> Result<()> {
+        if !*self.extended_key_events.lock().unwrap() {
+            return self.send_key_event(down, key);
+        }
+        let key_event = protocol::C2S::QemuExtendedKeyEvent {
+            down,
+            keysym: key,
+            keycode,
+        };
+        debug!("-> {:?}", key_event);
+        self.send(&key_event)
+    }
+
+    pub fn send_pointer_event(&mut self, buttons: u8, x: u16, y: u16) -> Result<()> {
+        let pointer_event = protocol::C2S::PointerEvent {
+            button_mask: buttons,
+            x_position: x,
+            y_position: y,
+        };
+        debug!("-> {:?}", pointer_event);
+        self.send(&pointer_event)
+    }
+
+    /// Whether pointer events are positions or movements, as the server last
+    /// asked for with `Event::PointerMotionMode`. Servers only switch modes if
+    /// `Encoding::QemuPointerMotionChange` is among the client's encodings.
+    pub fn pointer_motion_mode(&self) -> PointerMotionMode {
+        *self.pointer_motion_mode.lock().unwrap()
+    }
+
+    /// Moves the pointer by `dx` and `dy` with `buttons` held down. This is
+    /// only possible while the server is in relative pointer motion mode;
+    /// otherwise `Error::Unexpected` is returned.
+    pub fn send_relative_pointer(&mut self, dx: i16, dy: i16, buttons: u8) -> Result<()> {
+        if self.pointer_motion_mode() != PointerMotionMode::Relative {
+            return Err(Error::Unexpected(
+                "relative pointer motion in absolute mode",
+            ));
+        }
+        // QEMU takes the coordinates as offsets from the middle of the range.
+        let offset = |delta: i16| (0x7fff + delta as i32).clamp(0, 0xffff) as u16;
+        self.send_pointer_event(buttons, offset(dx), offset(dy))
+    }
+
+    /// Moves the pointer along `path`, one point every `interval`, with `buttons`
+    /// held down, e.g. for smooth drag gestures. This blocks until the whole path
+    /// has been sent.
+    ///
+    /// Points that repeat the previous position are skipped. If sending falls
+    /// behind schedule, e.g. because the link is slow and writes block, points
+    /// that are already overdue are dropped in favor of the newest one; the last
+    /// point of the path is always sent.
+    pub fn send_pointer_path(
+        &mut self,
+        buttons: u8,
+        path: &[(u16, u16)],
+        interval: Duration,
+    ) -> Result<()> {
+        let start = Instant::now();
+        let mut last_position = None;
+        for (index, &position) in path.iter().enumerate() {
+            let due = start + interval * index as u32;
+            let is_last = index + 1 == path.len();
+            if !is_last && Instant::now() >= due + interval {
+                continue;
+            }
+            if last_position == Some(position) {
+                continue;
+            }
+
+            let now = Instant::now();
+            if due > now {
+                self.flush()?;
+                thread::sleep(due - now);
+            }
+            self.send_pointer_event(buttons, position.0, position.1)?;
+            last_position = Some(position);
+        }
+        Ok(())
+    }
+
+    /// Limits the length of clipboard text, in characters, in both directions.
+    /// Longer text from the server is skipped without being buffered, and
+    /// `update_clipboard` refuses longer text with `Error::ClipboardTooLarge`.
+    /// The default is `DEFAULT_MAX_CLIPBOARD_SIZE`.
+    pub fn set_max_clipboard_size(&mut self, size: usize) {
+        self.settings
+            .max_clipboard_size
+            .store(size, Ordering::Relaxed)
+    }
+
+    pub fn max_clipboard_size(&self) -> usize {
+        self.settings.max_clipboard_size.load(Ordering::Relaxed)
+    }
+
+    /// Supplies the H.264 decoders for `Encoding::OpenH264`, which the client
+    /// cannot decode by itself; set this before asking for that encoding. It is
+    /// used from the first Open H.264 rectangle on and cannot be replaced after.
+    pub fn set_h264_backend(&mut self, backend: h264::Backend) {
+        *self.settings.h264_backend.lock().unwrap() = Some(backend)
+    }
+
+    /// Asks the server to resize the framebuffer to `width` by `height`, laid
+    /// out as `screens`; an empty layout stands for a single screen covering
+    /// all of it. The outcome arrives as `Event::Resize` and `Event::ScreenLayout`,
+    /// or as `Event::DesktopSizeRefused`.
+    ///
+    /// Only servers that have described their screens support this; for any
+    /// other the message would be fatal, so it is refused here instead.
+    pub fn set_desktop_size(&mut self, width: u16, height: u16, screens: &[Screen]) -> Result<()> {
+        let current = self.screens();
+        if current.is_empty() {
+            return Err(Error::Unexpected(
+                "SetDesktopSize without ExtendedDesktopSize",
+            ));
+        }
+        let screens = if screens.is_empty() {
+            vec![Screen {
+                id: current[0].id,
+                x_position: 0,
+                y_position: 0,
+                width,
+                height,
+                flags: 0,
+            }]
+        } else {
+            screens.to_vec()
+        };
+        let set_desktop_size = protocol::C2S::SetDesktopSize {
+            width,
+            height,
+            screens,
+        };
+        debug!("-> {:?}", set_desktop_size);
+        self.send(&set_desktop_size)
+    }
+
+    /// Sends a fence with `flags`, some of the `Fence` constants, and up to 64
+    /// bytes of `payload`. Besides answering the server's requests, a request
+    /// of the client's own is answered by an `Event::Fence` with the same
+    /// payload once the server has handled everything sent before it.
+    ///
+    /// Servers that support fences send one first, which they only do if
+    /// `Encoding::Fence` is among the client's encodings; until then the
+    /// message would be fatal, so it is refused here instead.
+    pub fn send_fence(&mut self, flags: u32, payload: &[u8]) -> Result<()> {
+        if !*self.fence_supported.lock().unwrap() {
+            return Err(Error::Unexpected("Fence before the server sent one"));
+        }
+        let fence = protocol::C2S::Fence(Fence {
+            flags,
+            payload: payload.to_vec(),
+        });
+        debug!("-> {:?}", fence);
+        self.send(&fence)
+    }
+
+    pub fn update_clipboard(&mut self, text: &str) -> Result<()> {
+        let length = text.chars().count();
+        if length > self.max_clipboard_size() {
+            return Err(Error::ClipboardTooLarge(length));
+        }
+        let cut_text = protocol::C2S::CutText(String::from(text));
+        debug!("-> {:?}", cut_text);
+        self.send(&cut_text)
+    }
+
+    // Note that due to inherent weaknesses of the VNC protocol, this
+    // function is prone to race conditions that break the connection framing.
+    // The ZRLE encoding is self-delimiting and if both the client and server
+    // support and use it, there can be no race condition, but we currently don't.
+    //
+    // Waiting for that update needs the events, so this fails on a split client.
+    pub fn set_format(&mut self, format: protocol::PixelFormat) -> Result<()> {
+        if self.events.is_none() {
+            return Err(Error::Unexpected("set_format on a split client"));
+        }
+
+        // Request (and discard) one full update to try and ensure that there
+        // are no FramebufferUpdate's in the buffers somewhere.
+        // This is not fully robust though (and cannot possibly be).
+        let _ = self.poll_iter().count(); // drain it
+        let (width, height) = self.size();
+        let framebuffer_rect = Rect::with_size(width, height);
+        self.request_update(framebuffer_rect, false)?;
+        self.flush()?;
+        'outer: loop {
+            for event in self.poll_iter() {
+                match event {
+                    Event::PutPixels(rect, _) if rect == framebuffer_rect => break 'outer,
+                    _ => (),
+                }
+            }
+        }
+
+        // Since VNC is fully client-driven, by this point the event thread is stuck
+        // waiting for the next message and the server is not sending us anything,
+        // so it's safe to switch to the new pixel format.
+        self.send_pixel_format(format)
+    }
+
+    /// Switches to `format` without waiting for the server to go quiet, which
+    /// is only safe before the first update request.
+    pub(crate) fn send_pixel_format(&mut self, format: protocol::PixelFormat) -> Result<()> {
+        let set_pixel_format = protocol::C2S::SetPixelFormat(format);
+        debug!("-> {:?}", set_pixel_format);
+        self.send(&set_pixel_format)?;
+        *self.format.lock().unwrap() = format;
+        if let Some(framebuffer) = self.framebuffer_mut() {
+            framebuffer.set_format(format);
+        }
+        Ok(())
+    }
+
+    #[doc(hidden)]
+    pub fn poke_qemu(&mut self) -> Result<()> {
+        let set_pixel_format = protocol::C2S::SetPixelFormat(*self.format.lock().unwrap());
+        debug!("-> {:?}", set_pixel_format);
+        self.send(&set_pixel_format)
+    }
+
+    pub fn poll_event(&mut self) -> Option<Event> {
+        self.poll_timed_event().map(|(event, _)| event)
+    }
+
+    /// Like `poll_event`, but also returns when the event was received.
+    ///
+    /// Polling also sends update requests delayed by `set_min_update_interval`,
+    /// refreshes due according to `set_refresh_interval` and keepalive probes
+    /// due according to `set_keepalive`. After `split`,
+    /// that is all it does, and it never returns an event.
+    pub fn poll_timed_event(&mut self) -> Option<(Event, Timestamp)> {
+        // A send error here means the connection is gone, which the event
+        // thread reports with Event::Disconnected.
+        let _ = self.send_pending_update();
+        let _ = self.refresh_if_due();
+        let _ = self.keepalive_if_due();
+
+        self.events.as_mut()?.poll_timed_event()
+    }
+
+    /// Like `poll_event`, but waits up to `timeout` for an event, still sending
+    /// whatever polling sends on time. After `split`, this only does the latter,
+    /// and returns `None` once `timeout` has passed.
+    pub fn poll_event_timeout(&mut self, timeout: Duration) -> Option<Event> {
+        self.poll_timed_event_timeout(timeout)
+            .map(|(event, _)| event)
+    }
+
+    /// Like `poll_event_timeout`, but also returns when the event was received.
+    pub fn poll_timed_event_timeout(&mut self, timeout: Duration) -> Option<(Event, Timestamp)> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if let Some(timed_event) = self.poll_timed_event() {
+                return Some(timed_event);
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return None;
+            }
+            let wake = match self.next_due() {
+                Some(due) => due.clamp(now, deadline),
+                None => deadline,
+            };
+            match self.events {
+                Some(ref mut events) => match events.wait_timed_event_until(Some(wake)) {
+                    Ok(timed_event) => return Some(timed_event),
+                    Err(RecvTimeoutError::Timeout) => (),
+                    Err(RecvTimeoutError::Disconnected) => return None,
+                },
+                None => thread::sleep(wake - now),
+            }
+        }
+    }
+
+    pub fn poll_iter(&mut self) -> EventPollIterator<'_> {
+        EventPollIterator { client: self }
+    }
+
+    /// Keeps a copy of the framebuffer, updated as events are handed out, so
+    /// that the application does not have to apply them itself; see
+    /// `framebuffer`. It starts out black, at the current size and format.
+    /// After `split`, the copy belongs to the events half.
+    pub fn enable_framebuffer(&mut self) {
+        if let Some(ref mut events) = self.events {
+            events.enable_framebuffer();
+        }
+    }
+
+    pub fn framebuffer(&self) -> Option<&Framebuffer> {
+        self.events.as_ref()?.framebuffer()
+    }
+
+    pub fn framebuffer_mut(&mut self) -> Option<&mut Framebuffer> {
+        self.events.as_mut()?.framebuffer_mut()
+    }
+
+    /// Takes an image of the framebuffer kept since `enable_framebuffer`.
+    pub fn screenshot(&self) -> Option<Screenshot> {
+        Some(self.framebuffer()?.screenshot())
+    }
+
+    /// Splits off the events, so that one thread can wait for and handle them
+    /// while another sends input through the client, without either blocking
+    /// the other. Both halves are `Send`; `name`, `size`, `pointer_motion_mode`
+    /// and `screens` are kept up to date as the events half hands out the
+    /// events changing them.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the client has already been split.
+    pub fn split(mut self) -> (Client, Events) {
+        let events = self.events.take().expect("client has already been split");
+        (self, events)
+    }
+
+    /// Splits off the events like `split`, and delivers them over a channel
+    /// from a thread of their own, for applications whose event loops can
+    /// watch channels. The channel closes after `Event::Disconnected`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the client has already been split.
+    pub fn into_event_channel(self) -> (Client, Receiver<Event>) {
+        let (client, mut events) = self.split();
+        let (tx_events, rx_events) = channel();
+        thread::spawn(move || {
+            while let Some(event) = events.wait_event() {
+                if tx_events.send(event).is_err() {
+                    break;
+                }
+            }
+        });
+        (client, rx_events)
+    }
+
+    /// Records what the server sends from the next message on to `writer`, as
+    /// an FBS 001.000 file like rfbproxy and vncrec write, until
+    /// `stop_recording`. The file starts with an RFB 3.3 handshake for the
+    /// current size, format and name.
+    ///
+    /// To make a recording that plays back, start it before the first update
+    /// request and keep the pixel format: compressed encodings depend on
+    /// earlier updates, and the file has no record of the client's requests.
+    pub fn record<W: Write + Send + 'static>(&mut self, writer: W) -> Result<()> {
+        self.stop_recording()?;
+        *self.settings.recorder.lock().unwrap() = Recorder::Pending {
+            writer: Box::new(writer),
+            name: self.name(),
+        };
+        Ok(())
+    }
+
+    /// Writes out what has been recorded and flushes the file.
+    pub fn stop_recording(&mut self) -> Result<()> {
+        self.settings.recorder.lock().unwrap().stop()
+    }
+
+    pub fn disconnect(mut self) -> Result<()> {
+        let _ = self.stream.flush();
+        self.stream.get_ref().shutdown()?;
+        Ok(())
+    }
+}
+
+pub struct Batch<'a> {
+    client: &'a mut Client,
+    auto_flush: bool,
+}
+
+impl<'a> Batch<'a> {
+    /// Sends the queued messages, reporting any error that occurs.
+    pub fn finish(self) -> Result<()> {
+        self.client.auto_flush = self.auto_flush;
+        self.client.flush()
+    }
+}
+
+impl<'a> Deref for Batch<'a> {
+    type Target = Client;
+
+    fn deref(&self) -> &Client {
+        self.client
+    }
+}
+
+impl<'a> DerefMut for Batch<'a> {
+    fn deref_mut(&mut self) -> &mut Client {
+        self.client
+    }
+}
+
+impl<'a> Drop for Batch<'a> {
+    fn drop(&mut self) {
+        self.client.auto_flush = self.auto_flush;
+        let _ = self.client.flush();
+    }
+}
+
+/// The events of a client that has been split with `Client::split`.
+pub struct Events {
+    events: Receiver<(Event, Timestamp)>,
+    size: Arc<Mutex<(u16, u16)>>,
+    pointer_motion_mode: Arc<Mutex<PointerMotionMode>>,
+    screens: Arc<Mutex<Vec<Screen>>>,
+    fence_supported: Arc<Mutex<bool>>,
+    extended_key_events: Arc<Mutex<bool>>,
+    gii_versions: Arc<Mutex<Option<(u16, u16)>>>,
+    name: Arc<Mutex<String>>,
+    settings: Arc<Settings>,
+    format: Arc<Mutex<protocol::PixelFormat>>,
+    framebuffer: Option<Framebuffer>,
+}
+
+impl Events {
+    fn received(&mut self, timed_event: (Event, Timestamp)) -> (Event, Timestamp) {
+        if let Some(ref mut framebuffer) = self.framebuffer {
+            framebuffer.apply(&timed_event.0);
+        }
+        match timed_event.0 {
+            Event::Resize(width, height) => *self.size.lock().unwrap() = (width, height),
+            Event::PointerMotionMode(mode) => *self.pointer_motion_mode.lock().unwrap() = mode,
+            Event::ScreenLayout(ref screens) => *self.screens.lock().unwrap() = screens.clone(),
+            Event::Fence(_) => *self.fence_supported.lock().unwrap() = true,
+            Event::QemuExtendedKeyEvent => *self.extended_key_events.lock().unwrap() = true,
+            Event::Gii(gii::ServerMessage::Version { maximum, minimum }) => {
+                *self.gii_versions.lock().unwrap() = Some((maximum, minimum))
+            }
+            Event::NameChange(ref name) => *self.name.lock().unwrap() = name.clone(),
+            _ => (),
+        }
+        timed_event
+    }
+
+    /// Like `Client::enable_framebuffer`.
+    pub fn enable_framebuffer(&mut self) {
+        if self.framebuffer.is_none() {
+            let (width, height) = *self.size.lock().unwrap();
+            let format = *self.format.lock().unwrap();
+            self.framebuffer = Some(Framebuffer::new(width, height, format));
+        }
+    }
+
+    pub fn framebuffer(&self) -> Option<&Framebuffer> {
+        self.framebuffer.as_ref()
+    }
+
+    pub fn framebuffer_mut(&mut self) -> Option<&mut Framebuffer> {
+        self.framebuffer.as_mut()
+    }
+
+    pub fn poll_event(&mut self) -> Option<Event> {
+        self.poll_timed_event().map(|(event, _)| event)
+    }
+
+    /// Like `poll_event`, but also returns when the event was received.
+    pub fn poll_timed_event(&mut self) -> Option<(Event, Timestamp)> {
+        match self.events.try_recv() {
+            Err(TryRecvError::Empty) if self.settings.keepalive.lock().unwrap().take_stale() => {
+                Some((Event::ConnectionStale, Timestamp::now(None)))
+            }
+            Err(TryRecvError::Empty) | Err(TryRecvError::Disconnected) => None,
+            Ok(timed_event) => Some(self.received(timed_event)),
+        }
+    }
+
+    /// Hands out the events that have arrived, without blocking, like
+    /// `Client::poll_iter`.
+    pub fn poll_iter(&mut self) -> impl Iterator<Item = Event> + '_ {
+        std::iter::from_fn(move || self.poll_event())
+    }
+
+    /// Blocks until an event arrives. Returns `None` once the connection is gone
+    /// and `Event::Disconnected` has been handed out.
+    pub fn wait_event(&mut self) -> Option<Event> {
+        self.wait_timed_event().map(|(event, _)| event)
+    }
+
+    /// Like `wait_event`, but also returns when the event was received.
+    pub fn wait_timed_event(&mut self) -> Option<(Event, Timestamp)> {
+        self.wait_timed_event_until(None).ok()
+    }
+
+    /// Like `poll_event`, but waits up to `timeout` for an event to arrive.
+    pub fn poll_event_timeout(&mut self, timeout: Duration) -> Option<Event> {
+        self.poll_timed_event_timeout(timeout)
+            .map(|(event, _)| event)
+    }
+
+    /// Like `poll_event_timeout`, but also returns when the event was received.
+    pub fn poll_timed_event_timeout(&mut self, timeout: Duration) -> Option<(Event, Timestamp)> {
+        self.wait_timed_event_until(Some(Instant::now() + timeout))
+            .ok()
+    }
+
+    fn wait_timed_event_until(
+        &mut self,
+        deadline: Option<Instant>,
+    ) -> std::result::Result<(Event, Timestamp), RecvTimeoutError> {
+        loop {
+            // With keepalive on, wake up in time to notice a stale connection.
+            let time_to_stale = self.settings.keepalive.lock().unwrap().time_to_stale();
+            let remaining =
+                deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()));
+            let timeout = match (time_to_stale, remaining) {
+                (Some(time_to_stale), Some(remaining)) => Some(time_to_stale.min(remaining)),
+                (time_to_stale, remaining) => time_to_stale.or(remaining),
+            };
+            let result = match timeout {
+                Some(timeout) => self.events.recv_timeout(timeout),
+                None => self
+                    .events
+                    .recv()
+                    .map_err(|RecvError| RecvTimeoutError::Disconnected),
+            };
+            match result {
+                Ok(timed_event) => return Ok(self.received(timed_event)),
+                Err(RecvTimeoutError::Timeout) => {
+                    if self.settings.keepalive.lock().unwrap().take_stale() {
+                        return Ok((Event::ConnectionStale, Timestamp::now(None)));
+                    }
+                    if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                        return Err(RecvTimeoutError::Timeout);
+                    }
+                }
+                Err(RecvTimeoutError::Disconnected) => return Err(RecvTimeoutError::Disconnected),
+            }
+        }
+    }
+}
+
+pub struct EventPollIterator<'a> {
+    client: &'a mut Client,
+}
+
+impl<'a> Iterator for EventPollIterator<'a> {
+    type Item = Event;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.client.poll_event()
+    }
+}
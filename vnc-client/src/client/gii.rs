@@ -0,0 +1,48 @@
+//! Input devices with more axes and buttons than pointer events have room
+//! for, through the gii extension. Add `Encoding::Gii` to the client's
+//! encodings; once the server has answered with
+//! `Event::Gii(ServerMessage::Version { .. })`, create a device with
+//! `Client::gii_create_device` and inject its events with `Client::gii_inject`.
+
+use super::Client;
+use log::debug;
+use vnc_proto::{protocol, Error, Result};
+
+pub use vnc_proto::gii::*;
+
+impl Client {
+    fn send_gii(&mut self, message: ClientMessage) -> Result<()> {
+        match *self.gii_versions.lock().unwrap() {
+            None => return Err(Error::Unexpected("gii before the server offered it")),
+            Some((maximum, minimum)) if !(minimum..=maximum).contains(&VERSION_1) => {
+                return Err(Error::Unexpected("gii version"))
+            }
+            Some(_) => (),
+        }
+        if !self.gii_version_sent {
+            let version = protocol::C2S::Gii(ClientMessage::Version(VERSION_1));
+            debug!("-> {:?}", version);
+            self.send(&version)?;
+            self.gii_version_sent = true;
+        }
+        let message = protocol::C2S::Gii(message);
+        debug!("-> {:?}", message);
+        self.send(&message)
+    }
+
+    /// Creates `device` on the server, which answers with
+    /// `Event::Gii(ServerMessage::DeviceCreated(origin))`; events of the
+    /// device carry that origin.
+    pub fn gii_create_device(&mut self, device: &Device) -> Result<()> {
+        self.send_gii(ClientMessage::CreateDevice(device.clone()))
+    }
+
+    pub fn gii_destroy_device(&mut self, origin: u32) -> Result<()> {
+        self.send_gii(ClientMessage::DestroyDevice(origin))
+    }
+
+    /// Sends `events` in a single message.
+    pub fn gii_inject(&mut self, events: &[InjectEvent]) -> Result<()> {
+        self.send_gii(ClientMessage::InjectEvents(events.to_vec()))
+    }
+}
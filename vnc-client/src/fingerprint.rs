@@ -0,0 +1,77 @@
+use vnc_proto::SecurityType;
+
+/// The server implementation, as far as it can be told from the handshake.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ServerKind {
+    /// The VNC server built into QEMU (and Xen HVM). It does not send anything
+    /// in reply to incremental update requests; see `Client::poke_qemu`.
+    Qemu,
+    /// macOS Screen Sharing.
+    Apple,
+    RealVnc,
+    TightVnc,
+    Unknown,
+}
+
+impl ServerKind {
+    /// Guesses the server implementation from the desktop name and the security
+    /// types the server offered. This is a heuristic; `Unknown` is returned when
+    /// nothing gives the server away.
+    pub fn identify(name: &str, security_types: &[SecurityType]) -> ServerKind {
+        let offers = |number: u8| security_types.contains(&SecurityType::Unknown(number));
+
+        // QEMU names the desktop after the VM, as "QEMU" or "QEMU (<name>)".
+        if name == "QEMU" || name.starts_with("QEMU (") {
+            ServerKind::Qemu
+        } else if security_types.contains(&SecurityType::AppleRemoteDesktop) {
+            ServerKind::Apple
+        } else if offers(5) || offers(6) || offers(13) {
+            // The RA2 family of security types is specific to RealVNC.
+            ServerKind::RealVnc
+        } else if security_types.contains(&SecurityType::Tight) {
+            ServerKind::TightVnc
+        } else {
+            ServerKind::Unknown
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ServerKind;
+    use vnc_proto::SecurityType;
+
+    #[test]
+    fn test_identify() {
+        let none = [SecurityType::None];
+        assert_eq!(ServerKind::identify("QEMU", &none), ServerKind::Qemu);
+        assert_eq!(
+            ServerKind::identify("QEMU (win10)", &[SecurityType::VncAuthentication]),
+            ServerKind::Qemu
+        );
+        assert_eq!(
+            ServerKind::identify("QEMUlator", &none),
+            ServerKind::Unknown
+        );
+        assert_eq!(
+            ServerKind::identify(
+                "MacBook",
+                &[SecurityType::AppleRemoteDesktop, SecurityType::Unknown(35)]
+            ),
+            ServerKind::Apple
+        );
+        assert_eq!(
+            ServerKind::identify(
+                "host",
+                &[SecurityType::Unknown(13), SecurityType::Unknown(6)]
+            ),
+            ServerKind::RealVnc
+        );
+        assert_eq!(
+            ServerKind::identify("host:1", &[SecurityType::Tight]),
+            ServerKind::TightVnc
+        );
+        assert_eq!(ServerKind::identify("host:1", &none), ServerKind::Unknown);
+    }
+}
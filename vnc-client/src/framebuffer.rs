@@ -0,0 +1,172 @@
+//! A copy of the server's framebuffer, kept up to date from the events.
+
+use crate::client::Event;
+use crate::screenshot::Screenshot;
+use vnc_proto::{pixels, Colour, Damage, PixelFormat, Rect};
+
+/// The pixels of the whole framebuffer, in the format of the session, and the
+/// regions that changed since the damage was last cleared. See
+/// `Client::enable_framebuffer`; it can also be fed events directly.
+#[derive(Debug, Clone)]
+pub struct Framebuffer {
+    width: u16,
+    height: u16,
+    format: PixelFormat,
+    pixels: Vec<u8>,
+    colour_map: Vec<Colour>,
+    damage: Damage,
+}
+
+impl Framebuffer {
+    /// A black framebuffer, without damage.
+    pub fn new(width: u16, height: u16, format: PixelFormat) -> Framebuffer {
+        let length = width as usize * height as usize * (format.bits_per_pixel as usize / 8);
+        Framebuffer {
+            width,
+            height,
+            format,
+            pixels: vec![0; length],
+            colour_map: Vec::new(),
+            damage: Damage::new(),
+        }
+    }
+
+    pub fn size(&self) -> (u16, u16) {
+        (self.width, self.height)
+    }
+
+    pub fn format(&self) -> PixelFormat {
+        self.format
+    }
+
+    fn bytes_per_pixel(&self) -> usize {
+        self.format.bits_per_pixel as usize / 8
+    }
+
+    /// Rows of `size().0` pixels, packed.
+    pub fn pixels(&self) -> &[u8] {
+        &self.pixels
+    }
+
+    /// The colours of colour-mapped formats, as far as the server set them;
+    /// `pixels::expand_indexed` turns pixels into RGB with it.
+    pub fn colour_map(&self) -> &[Colour] {
+        &self.colour_map
+    }
+
+    /// The regions changed since `clear_damage`.
+    pub fn dirty_regions(&self) -> &[Rect] {
+        self.damage.rects()
+    }
+
+    pub fn clear_damage(&mut self) {
+        self.damage.take();
+    }
+
+    /// Converts the framebuffer to 8-bit RGBA, looking colour-mapped pixels up
+    /// in the colour map.
+    pub fn screenshot(&self) -> Screenshot {
+        let mut rgb = Vec::with_capacity(self.width as usize * self.height as usize * 3);
+        match self.format.true_colour {
+            true => pixels::unpack_rgb(self.format, &self.pixels, &mut rgb),
+            false => pixels::expand_indexed(self.format, &self.colour_map, &self.pixels, &mut rgb),
+        }
+        Screenshot {
+            width: self.width,
+            height: self.height,
+            rgba: rgb
+                .chunks_exact(3)
+                .flat_map(|pixel| [pixel[0], pixel[1], pixel[2], 0xff])
+                .collect(),
+        }
+    }
+
+    /// Applies `PutPixels`, `CopyPixels`, `Resize` and `SetColourMap`, ignoring
+    /// other events. A resize keeps what the old and new sizes have in common;
+    /// it and a change of the colour map damage everything. Whatever falls
+    /// outside the framebuffer, or beyond the pixels given, is left out.
+    pub fn apply(&mut self, event: &Event) {
+        let bytes_per_pixel = self.bytes_per_pixel();
+        match *event {
+            Event::Resize(width, height) => {
+                (self.pixels, _) = pixels::resize(
+                    &self.pixels,
+                    bytes_per_pixel,
+                    (self.width, self.height),
+                    (width, height),
+                );
+                self.width = width;
+                self.height = height;
+                self.damage.take();
+                self.damage.add(Rect::with_size(width, height));
+            }
+            Event::PutPixels(rect, ref pixels) => {
+                let clipped = match (rect.clip_to(self.width, self.height), bytes_per_pixel) {
+                    (Some(clipped), 1..) => clipped,
+                    _ => return,
+                };
+                let row_length = rect.width as usize * bytes_per_pixel;
+                let length = clipped.width as usize * bytes_per_pixel;
+                let rows = pixels
+                    .chunks_exact(row_length)
+                    .take(clipped.height as usize);
+                for (y, row) in rows.enumerate() {
+                    let offset = ((rect.top as usize + y) * self.width as usize
+                        + rect.left as usize)
+                        * bytes_per_pixel;
+                    self.pixels[offset..offset + length].copy_from_slice(&row[..length]);
+                }
+                self.damage.add(clipped);
+            }
+            Event::SetColourMap {
+                first_colour,
+                ref colours,
+            } => {
+                let first_colour = first_colour as usize;
+                let end = first_colour + colours.len();
+                if self.colour_map.len() < end {
+                    let black = Colour {
+                        red: 0,
+                        green: 0,
+                        blue: 0,
+                    };
+                    self.colour_map.resize(end, black);
+                }
+                self.colour_map[first_colour..end].clone_from_slice(colours);
+                self.damage.add(Rect::with_size(self.width, self.height));
+            }
+            Event::CopyPixels { src, dst } => {
+                let width = src
+                    .width
+                    .min(self.width.saturating_sub(src.left))
+                    .min(self.width.saturating_sub(dst.left));
+                let height = src
+                    .height
+                    .min(self.height.saturating_sub(src.top))
+                    .min(self.height.saturating_sub(dst.top));
+                if width == 0 || height == 0 {
+                    return;
+                }
+                let src = Rect::new(src.left, src.top, width, height);
+                let dst = Rect::new(dst.left, dst.top, width, height);
+                pixels::copy_rect(
+                    &mut self.pixels,
+                    self.width as usize,
+                    bytes_per_pixel,
+                    src,
+                    dst,
+                );
+                self.damage.add(dst);
+            }
+            _ => (),
+        }
+    }
+
+    /// Starts over in `format`, as the pixels cannot be converted.
+    pub(crate) fn set_format(&mut self, format: PixelFormat) {
+        let colour_map = std::mem::take(&mut self.colour_map);
+        *self = Framebuffer::new(self.width, self.height, format);
+        self.colour_map = colour_map;
+        self.damage.add(Rect::with_size(self.width, self.height));
+    }
+}
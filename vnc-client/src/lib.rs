@@ -0,0 +1,26 @@
+mod client;
+mod fingerprint;
+mod framebuffer;
+mod reconnect;
+mod record;
+mod screenshot;
+mod security;
+pub mod socks5;
+#[cfg(feature = "websocket")]
+mod websocket;
+
+pub use client::gii;
+pub use client::{
+    AuthChoice, AuthMethod, Batch, Client, ClientStream, DisconnectReason, Event,
+    EventPollIterator, Events, PointerMotionMode, Timestamp, DEFAULT_MAX_CLIPBOARD_SIZE,
+};
+pub use fingerprint::ServerKind;
+pub use framebuffer::Framebuffer;
+pub use reconnect::ReconnectingClient;
+pub use screenshot::Screenshot;
+pub use vnc_proto::protocol::RepeaterId;
+pub use vnc_proto::{h264, pixels};
+pub use vnc_proto::{
+    Colour, Damage, Encoding, Error, Fence, PixelFormat, Rect, Result, Screen, SecurityType,
+    Version,
+};
@@ -0,0 +1,141 @@
+//! A client that survives dropped connections, for kiosks and monitoring.
+
+use crate::client::{Client, Event};
+use log::{info, warn};
+use std::ops::{Deref, DerefMut};
+use std::time::{Duration, Instant};
+use vnc_proto::Result;
+
+/// How long to wait between attempts to connect again, unless told otherwise.
+const DEFAULT_RETRY_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A `Client` that connects again when its connection is lost. Once it is back,
+/// the pixel format, encodings and update, refresh and keepalive settings of
+/// the lost connection are applied to the new one, a full update is requested
+/// and `Event::Reconnected` is handed out; `Event::Disconnected` never is.
+///
+/// Input goes to the current connection through `Deref`; while there is none,
+/// sending fails. Timeouts set on the stream are up to `connect`.
+pub struct ReconnectingClient {
+    client: Client,
+    connect: Box<dyn FnMut() -> Result<Client> + Send>,
+    retry_interval: Duration,
+    // While disconnected, when to try connecting again.
+    next_attempt: Option<Instant>,
+    pending: Option<Event>,
+}
+
+impl ReconnectingClient {
+    /// Connects with `connect`, which dials and authenticates, e.g. with
+    /// `Client::from_tcp_stream`, and is called again whenever the connection
+    /// is lost.
+    pub fn new<F>(mut connect: F) -> Result<ReconnectingClient>
+    where
+        F: FnMut() -> Result<Client> + Send + 'static,
+    {
+        Ok(ReconnectingClient {
+            client: connect()?,
+            connect: Box::new(connect),
+            retry_interval: DEFAULT_RETRY_INTERVAL,
+            next_attempt: None,
+            pending: None,
+        })
+    }
+
+    /// Sets how long to wait after a failed attempt to connect again.
+    pub fn set_retry_interval(&mut self, interval: Duration) {
+        self.retry_interval = interval
+    }
+
+    pub fn retry_interval(&self) -> Duration {
+        self.retry_interval
+    }
+
+    pub fn connected(&self) -> bool {
+        self.next_attempt.is_none()
+    }
+
+    /// Like `Client::poll_event`. While disconnected, this is where the
+    /// attempts to connect again are made, blocking while they last.
+    pub fn poll_event(&mut self) -> Option<Event> {
+        if let Some(event) = self.pending.take() {
+            return Some(event);
+        }
+        match self.next_attempt {
+            Some(next_attempt) if Instant::now() >= next_attempt => match self.reconnect() {
+                Ok(()) => {
+                    info!("reconnected");
+                    self.next_attempt = None;
+                    return Some(Event::Reconnected);
+                }
+                Err(error) => {
+                    warn!("cannot reconnect: {}", error);
+                    self.next_attempt = Some(Instant::now() + self.retry_interval);
+                    return None;
+                }
+            },
+            Some(_) => return None,
+            None => (),
+        }
+        match self.client.poll_event() {
+            Some(Event::Disconnected(reason)) => {
+                warn!("connection lost: {}", reason);
+                self.next_attempt = Some(Instant::now());
+                None
+            }
+            event => event,
+        }
+    }
+
+    pub fn poll_iter(&mut self) -> impl Iterator<Item = Event> + '_ {
+        std::iter::from_fn(move || self.poll_event())
+    }
+
+    pub fn disconnect(self) -> Result<()> {
+        self.client.disconnect()
+    }
+
+    fn reconnect(&mut self) -> Result<()> {
+        let mut client = (self.connect)()?;
+        let old = &self.client;
+        client.set_auto_flush(false);
+        // Nothing has been requested yet, so the format can change right away.
+        if client.format() != old.format() {
+            client.send_pixel_format(old.format())?;
+        }
+        if !old.encodings().is_empty() {
+            client.set_encodings(old.encodings())?;
+        }
+        client.set_min_update_interval(old.min_update_interval());
+        client.set_refresh_interval(old.refresh_interval());
+        client.set_keepalive(old.keepalive());
+        client.set_max_clipboard_size(old.max_clipboard_size());
+        if old.framebuffer().is_some() {
+            client.enable_framebuffer();
+        }
+        client.refresh()?;
+        client.flush()?;
+        client.set_auto_flush(old.auto_flush());
+
+        if client.size() != old.size() {
+            let (width, height) = client.size();
+            self.pending = Some(Event::Resize(width, height));
+        }
+        self.client = client;
+        Ok(())
+    }
+}
+
+impl Deref for ReconnectingClient {
+    type Target = Client;
+
+    fn deref(&self) -> &Client {
+        &self.client
+    }
+}
+
+impl DerefMut for ReconnectingClient {
+    fn deref_mut(&mut self) -> &mut Client {
+        &mut self.client
+    }
+}
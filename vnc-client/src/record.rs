@@ -0,0 +1,105 @@
+//! Recording what the server sends as an FBS file; see `Client::record`.
+
+use log::warn;
+use std::io::Write;
+use std::time::Instant;
+use vnc_proto::fbs;
+use vnc_proto::protocol::{Message, PixelFormat, ServerInit};
+use vnc_proto::Result;
+
+pub(crate) enum Recorder {
+    Off,
+    /// Waiting for the next message, where the recording can begin.
+    Pending {
+        writer: Box<dyn Write + Send>,
+        name: String,
+    },
+    Recording {
+        writer: Box<dyn Write + Send>,
+        start: Instant,
+        // The message being received, and when it started arriving.
+        block: fbs::Block,
+    },
+}
+
+impl Recorder {
+    /// Writes the header and the handshake for a session of `size`, `format`
+    /// and `name`.
+    fn begin(
+        mut writer: Box<dyn Write + Send>,
+        name: String,
+        size: (u16, u16),
+        format: PixelFormat,
+    ) -> Recorder {
+        let server_init = ServerInit {
+            framebuffer_width: size.0,
+            framebuffer_height: size.1,
+            pixel_format: format,
+            name,
+        };
+        let header = fbs::handshake(&server_init).and_then(|data| {
+            writer.write_all(fbs::MAGIC)?;
+            fbs::Block { data, timestamp: 0 }.write_to(&mut writer)
+        });
+        match header {
+            Ok(()) => Recorder::Recording {
+                writer,
+                start: Instant::now(),
+                block: fbs::Block {
+                    data: Vec::new(),
+                    timestamp: 0,
+                },
+            },
+            Err(error) => {
+                warn!("cannot record: {}", error);
+                Recorder::Off
+            }
+        }
+    }
+
+    /// Called by the pump with the first byte of every message.
+    pub(crate) fn start_message(&mut self, first: u8, size: (u16, u16), format: PixelFormat) {
+        *self = match std::mem::replace(self, Recorder::Off) {
+            Recorder::Pending { writer, name } => Recorder::begin(writer, name, size, format),
+            recorder => recorder,
+        };
+        if let Recorder::Recording { start, block, .. } = self {
+            block.timestamp = start.elapsed().as_millis().min(u32::MAX as u128) as u32;
+            block.data.push(first);
+        }
+    }
+
+    pub(crate) fn capture(&mut self, data: &[u8]) {
+        if let Recorder::Recording { block, .. } = self {
+            block.data.extend_from_slice(data);
+        }
+    }
+
+    /// Writes out the message received since `start_message`.
+    pub(crate) fn end_message(&mut self) {
+        if let Err(error) = self.write_block() {
+            warn!("cannot record: {}", error);
+            *self = Recorder::Off;
+        }
+    }
+
+    fn write_block(&mut self) -> Result<()> {
+        if let Recorder::Recording { writer, block, .. } = self {
+            if !block.data.is_empty() {
+                block.write_to(writer)?;
+                block.data.clear();
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes out what has been received and ends the recording.
+    pub(crate) fn stop(&mut self) -> Result<()> {
+        self.write_block()?;
+        match std::mem::replace(self, Recorder::Off) {
+            Recorder::Recording { mut writer, .. } => writer.flush()?,
+            Recorder::Pending { .. } | Recorder::Off => (),
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,41 @@
+//! Images of the framebuffer, for saving as PPM or, with the `image` feature,
+//! PNG files.
+
+use std::io::{self, Write};
+#[cfg(feature = "image")]
+use std::{fs::File, io::BufWriter, path::Path};
+
+/// An image with four bytes, red, green, blue and alpha, per pixel. Taken from
+/// the framebuffer, it is opaque throughout.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Screenshot {
+    pub width: u16,
+    pub height: u16,
+    pub rgba: Vec<u8>,
+}
+
+impl Screenshot {
+    /// Writes a binary PPM image, which has no alpha.
+    pub fn write_ppm<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        write!(writer, "P6\n{} {}\n255\n", self.width, self.height)?;
+        for pixel in self.rgba.chunks_exact(4) {
+            writer.write_all(&pixel[..3])?;
+        }
+        Ok(())
+    }
+
+    /// Writes a PNG image, unfiltered. This needs the `image` feature.
+    #[cfg(feature = "image")]
+    pub fn write_png<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        let (width, height) = (self.width as u32, self.height as u32);
+        vnc_proto::png::encode(writer, width, height, &self.rgba)
+    }
+
+    /// Saves the image as a PNG file. This needs the `image` feature.
+    #[cfg(feature = "image")]
+    pub fn save_png<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        self.write_png(&mut writer)?;
+        writer.flush()
+    }
+}
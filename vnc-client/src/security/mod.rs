@@ -0,0 +1,2 @@
+#[cfg(feature = "sasl")]
+pub mod sasl;
@@ -0,0 +1,188 @@
+//! Reaching a server through a SOCKS5 proxy (RFC 1928), such as `ssh -D`
+//! provides, optionally with a username and password (RFC 1929).
+
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use std::io::{Read, Write};
+use std::net::IpAddr;
+use vnc_proto::{Error, Result};
+
+const VERSION: u8 = 5;
+const NO_AUTHENTICATION: u8 = 0x00;
+const USERNAME_PASSWORD: u8 = 0x02;
+const NO_ACCEPTABLE_METHODS: u8 = 0xff;
+const CONNECT: u8 = 1;
+const ADDRESS_IPV4: u8 = 1;
+const ADDRESS_DOMAIN: u8 = 3;
+const ADDRESS_IPV6: u8 = 4;
+
+fn reply_message(reply: u8) -> &'static str {
+    match reply {
+        1 => "general SOCKS server failure",
+        2 => "connection not allowed by ruleset",
+        3 => "network unreachable",
+        4 => "host unreachable",
+        5 => "connection refused",
+        6 => "TTL expired",
+        7 => "command not supported",
+        8 => "address type not supported",
+        _ => "unknown SOCKS error",
+    }
+}
+
+/// Asks the SOCKS5 proxy `stream` is connected to for a connection to
+/// `host:port`. Once this returns, `stream` carries that connection, and can be
+/// passed to e.g. `Client::from_tcp_stream`. Host names are resolved by the
+/// proxy.
+pub fn connect<S: Read + Write>(
+    stream: &mut S,
+    host: &str,
+    port: u16,
+    credentials: Option<(&str, &str)>,
+) -> Result<()> {
+    let method = match credentials {
+        Some(_) => USERNAME_PASSWORD,
+        None => NO_AUTHENTICATION,
+    };
+    stream.write_all(&[VERSION, 1, method])?;
+    if stream.read_u8()? != VERSION {
+        return Err(Error::Unexpected("SOCKS version"));
+    }
+    match stream.read_u8()? {
+        NO_AUTHENTICATION if method == NO_AUTHENTICATION => (),
+        USERNAME_PASSWORD => {
+            let (username, password) = credentials.ok_or(Error::AuthenticationUnavailable)?;
+            if username.len() > 255 || password.len() > 255 {
+                return Err(Error::Unexpected("SOCKS credentials length"));
+            }
+            let mut request = vec![1, username.len() as u8];
+            request.extend_from_slice(username.as_bytes());
+            request.push(password.len() as u8);
+            request.extend_from_slice(password.as_bytes());
+            stream.write_all(&request)?;
+            stream.read_u8()?;
+            if stream.read_u8()? != 0 {
+                return Err(Error::AuthenticationFailure(String::from(
+                    "SOCKS proxy refused the credentials",
+                )));
+            }
+        }
+        NO_ACCEPTABLE_METHODS => return Err(Error::AuthenticationUnavailable),
+        _ => return Err(Error::Unexpected("SOCKS authentication method")),
+    }
+
+    let mut request = vec![VERSION, CONNECT, 0];
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    match host.parse() {
+        Ok(IpAddr::V4(address)) => {
+            request.push(ADDRESS_IPV4);
+            request.extend_from_slice(&address.octets());
+        }
+        Ok(IpAddr::V6(address)) => {
+            request.push(ADDRESS_IPV6);
+            request.extend_from_slice(&address.octets());
+        }
+        Err(_) => {
+            if host.len() > 255 {
+                return Err(Error::Unexpected("SOCKS host name length"));
+            }
+            request.push(ADDRESS_DOMAIN);
+            request.push(host.len() as u8);
+            request.extend_from_slice(host.as_bytes());
+        }
+    }
+    request.write_u16::<BigEndian>(port)?;
+    stream.write_all(&request)?;
+
+    let mut reply = [0; 4];
+    stream.read_exact(&mut reply)?;
+    if reply[0] != VERSION {
+        return Err(Error::Unexpected("SOCKS version"));
+    }
+    if reply[1] != 0 {
+        return Err(Error::Server(String::from(reply_message(reply[1]))));
+    }
+    // The address the proxy connected from is of no interest.
+    let length = match reply[3] {
+        ADDRESS_IPV4 => 4,
+        ADDRESS_IPV6 => 16,
+        ADDRESS_DOMAIN => stream.read_u8()? as usize,
+        _ => return Err(Error::Unexpected("SOCKS address type")),
+    };
+    stream.read_exact(&mut vec![0; length + 2])?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::connect;
+    use std::io::{self, Cursor, Read, Write};
+    use vnc_proto::Error;
+
+    // Replies with `input`, and records what is sent.
+    struct Script {
+        input: Cursor<Vec<u8>>,
+        output: Vec<u8>,
+    }
+
+    impl Script {
+        fn new(input: &[u8]) -> Script {
+            Script {
+                input: Cursor::new(input.to_vec()),
+                output: Vec::new(),
+            }
+        }
+    }
+
+    impl Read for Script {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.input.read(buf)
+        }
+    }
+
+    impl Write for Script {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.output.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_connect() {
+        let mut stream = Script::new(&[5, 0, 5, 0, 0, 1, 10, 0, 0, 1, 0x17, 0x0c]);
+        connect(&mut stream, "vnc.internal", 5900, None).unwrap();
+        let mut expected = vec![5, 1, 0, 5, 1, 0, 3, 12];
+        expected.extend_from_slice(b"vnc.internal");
+        expected.extend_from_slice(&[0x17, 0x0c]);
+        assert_eq!(stream.output, expected);
+    }
+
+    #[test]
+    fn test_connect_with_credentials() {
+        let mut stream = Script::new(&[
+            5, 2, 1, 0, 5, 0, 0, 4, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+        ]);
+        connect(&mut stream, "[::1]", 5900, Some(("user", "pw"))).unwrap();
+        let mut expected = vec![5, 1, 2, 1, 4, b'u', b's', b'e', b'r', 2, b'p', b'w'];
+        expected.extend_from_slice(&[5, 1, 0, 4, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1]);
+        expected.extend_from_slice(&[0x17, 0x0c]);
+        assert_eq!(stream.output, expected);
+
+        let mut stream = Script::new(&[5, 2, 1, 1]);
+        assert!(matches!(
+            connect(&mut stream, "::1", 5900, Some(("user", "pw"))),
+            Err(Error::AuthenticationFailure(_))
+        ));
+    }
+
+    #[test]
+    fn test_connect_refused() {
+        let mut stream = Script::new(&[5, 0, 5, 5, 0, 1, 0, 0, 0, 0, 0, 0]);
+        match connect(&mut stream, "10.0.0.2", 5900, None) {
+            Err(Error::Server(reason)) => assert_eq!(reason, "connection refused"),
+            result => panic!("unexpected {:?}", result.map(|_| ())),
+        }
+    }
+}
@@ -0,0 +1,442 @@
+//! RFB framed over a WebSocket (RFC 6455), as websockify and the noVNC gateways
+//! in front of many cloud consoles speak it. Only `ws://` URLs are supported,
+//! since there is no TLS.
+
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use vnc_proto::{Error, Result};
+
+const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+// Handshake responses longer than this are not plausible.
+const MAX_RESPONSE_LENGTH: usize = 8192;
+
+const OPCODE_CONTINUATION: u8 = 0x0;
+const OPCODE_TEXT: u8 = 0x1;
+const OPCODE_BINARY: u8 = 0x2;
+const OPCODE_CLOSE: u8 = 0x8;
+const OPCODE_PING: u8 = 0x9;
+const OPCODE_PONG: u8 = 0xa;
+
+/// A WebSocket connection carrying RFB in binary messages. Every write is sent
+/// as one message; reads return the payload of the messages the server sends,
+/// however it splits them, and answer pings along the way.
+pub struct WebSocket {
+    stream: TcpStream,
+    // Shared between clones, so that a pong sent by the reader does not end up
+    // in the middle of a message sent by the writer.
+    write_lock: Arc<Mutex<()>>,
+    // Left to read of the current frame's payload, and its masking key, if any.
+    remaining: u64,
+    mask: Option<[u8; 4]>,
+    offset: usize,
+}
+
+impl WebSocket {
+    /// Connects to `url`, of the form `ws://HOST[:PORT][/PATH]`, and performs
+    /// the opening handshake.
+    pub fn connect(url: &str) -> Result<WebSocket> {
+        let (host, port, path) = parse_url(url)?;
+        let mut stream = TcpStream::connect((host, port))?;
+        handshake(&mut stream, &host_header(host, port), path)?;
+        Ok(WebSocket {
+            stream,
+            write_lock: Arc::new(Mutex::new(())),
+            remaining: 0,
+            mask: None,
+            offset: 0,
+        })
+    }
+
+    pub fn try_clone(&self) -> io::Result<WebSocket> {
+        Ok(WebSocket {
+            stream: self.stream.try_clone()?,
+            write_lock: self.write_lock.clone(),
+            remaining: 0,
+            mask: None,
+            offset: 0,
+        })
+    }
+
+    pub fn shutdown(&self) -> io::Result<()> {
+        self.stream.shutdown(Shutdown::Both)
+    }
+
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.stream.set_read_timeout(timeout)
+    }
+
+    pub fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.stream.set_write_timeout(timeout)
+    }
+
+    fn send_frame(&self, opcode: u8, payload: &[u8]) -> io::Result<()> {
+        let mut frame = Vec::with_capacity(payload.len() + 14);
+        frame.push(0x80 | opcode);
+        // Frames from the client are always masked.
+        if payload.len() < 126 {
+            frame.push(0x80 | payload.len() as u8);
+        } else if payload.len() <= u16::MAX as usize {
+            frame.push(0x80 | 126);
+            frame.write_u16::<BigEndian>(payload.len() as u16)?;
+        } else {
+            frame.push(0x80 | 127);
+            frame.write_u64::<BigEndian>(payload.len() as u64)?;
+        }
+        let mask: [u8; 4] = random_bytes();
+        frame.extend_from_slice(&mask);
+        frame.extend(payload.iter().zip(mask.iter().cycle()).map(|(b, m)| b ^ m));
+
+        let _guard = self.write_lock.lock().unwrap();
+        (&self.stream).write_all(&frame)
+    }
+
+    /// Reads frame headers until a data frame with a non-empty payload starts.
+    /// Returns false if the server closed the connection.
+    fn next_frame(&mut self) -> io::Result<bool> {
+        loop {
+            let opcode = match self.stream.read_u8() {
+                Ok(byte) => byte & 0x0f,
+                Err(error) if error.kind() == io::ErrorKind::UnexpectedEof => return Ok(false),
+                Err(error) => return Err(error),
+            };
+            let length = self.stream.read_u8()?;
+            let masked = length & 0x80 != 0;
+            let length = match length & 0x7f {
+                126 => self.stream.read_u16::<BigEndian>()? as u64,
+                127 => self.stream.read_u64::<BigEndian>()?,
+                length => length as u64,
+            };
+            let mask = match masked {
+                true => {
+                    let mut mask = [0; 4];
+                    self.stream.read_exact(&mut mask)?;
+                    Some(mask)
+                }
+                false => None,
+            };
+
+            match opcode {
+                OPCODE_CONTINUATION | OPCODE_TEXT | OPCODE_BINARY => {
+                    if length > 0 {
+                        self.remaining = length;
+                        self.mask = mask;
+                        self.offset = 0;
+                        return Ok(true);
+                    }
+                }
+                OPCODE_CLOSE => return Ok(false),
+                OPCODE_PING | OPCODE_PONG if length <= 125 => {
+                    let mut payload = vec![0; length as usize];
+                    self.stream.read_exact(&mut payload)?;
+                    if opcode == OPCODE_PING {
+                        if let Some(mask) = mask {
+                            unmask(&mut payload, mask, 0);
+                        }
+                        self.send_frame(OPCODE_PONG, &payload)?;
+                    }
+                }
+                _ => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "unexpected WebSocket frame",
+                    ))
+                }
+            }
+        }
+    }
+}
+
+impl Read for WebSocket {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        if self.remaining == 0 && !self.next_frame()? {
+            return Ok(0);
+        }
+        let length = (buf.len() as u64).min(self.remaining) as usize;
+        let length = self.stream.read(&mut buf[..length])?;
+        if length == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        if let Some(mask) = self.mask {
+            unmask(&mut buf[..length], mask, self.offset);
+        }
+        self.remaining -= length as u64;
+        self.offset += length;
+        Ok(length)
+    }
+}
+
+impl Write for WebSocket {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.send_frame(OPCODE_BINARY, buf)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn unmask(data: &mut [u8], mask: [u8; 4], offset: usize) {
+    for (index, byte) in data.iter_mut().enumerate() {
+        *byte ^= mask[(offset + index) % 4];
+    }
+}
+
+fn parse_url(url: &str) -> Result<(&str, u16, &str)> {
+    let rest = match url.strip_prefix("ws://") {
+        Some(rest) => rest,
+        None if url.starts_with("wss://") => {
+            return Err(Error::Unexpected("wss:// URL without TLS"))
+        }
+        None => return Err(Error::Unexpected("WebSocket URL")),
+    };
+    let (authority, path) = match rest.find('/') {
+        Some(index) => rest.split_at(index),
+        None => (rest, "/"),
+    };
+    // IPv6 addresses are bracketed, and contain colons of their own.
+    let port_separator = match authority.rfind(']') {
+        Some(end) => authority[end..].find(':').map(|index| end + index),
+        None => authority.rfind(':'),
+    };
+    let (host, port) = match port_separator {
+        Some(index) => match authority[index + 1..].parse() {
+            Ok(port) => (&authority[..index], port),
+            Err(_) => return Err(Error::Unexpected("port in WebSocket URL")),
+        },
+        None => (authority, 80),
+    };
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    if host.is_empty() {
+        return Err(Error::Unexpected("WebSocket URL"));
+    }
+    Ok((host, port, path))
+}
+
+fn host_header(host: &str, port: u16) -> String {
+    let host = match host.contains(':') {
+        true => format!("[{}]", host),
+        false => String::from(host),
+    };
+    match port {
+        80 => host,
+        port => format!("{}:{}", host, port),
+    }
+}
+
+fn handshake<S: Read + Write>(stream: &mut S, host: &str, path: &str) -> Result<()> {
+    let key = base64(&random_bytes::<16>());
+    let request = format!(
+        "GET {} HTTP/1.1\r\n\
+         Host: {}\r\n\
+         Upgrade: websocket\r\n\
+         Connection: Upgrade\r\n\
+         Sec-WebSocket-Key: {}\r\n\
+         Sec-WebSocket-Version: 13\r\n\
+         Sec-WebSocket-Protocol: binary\r\n\
+         \r\n",
+        path, host, key
+    );
+    stream.write_all(request.as_bytes())?;
+
+    // Read byte by byte, so that nothing past the headers is consumed.
+    let mut response = Vec::new();
+    while !response.ends_with(b"\r\n\r\n") {
+        if response.len() == MAX_RESPONSE_LENGTH {
+            return Err(Error::Unexpected("WebSocket handshake response length"));
+        }
+        response.push(stream.read_u8()?);
+    }
+    let response = String::from_utf8_lossy(&response);
+    let mut lines = response.split("\r\n");
+    let status = lines.next().unwrap_or_default();
+    if status.split(' ').nth(1) != Some("101") {
+        return Err(Error::Server(String::from(status)));
+    }
+    let accept = lines.find_map(|line| {
+        let (name, value) = line.split_once(':')?;
+        match name.trim().eq_ignore_ascii_case("Sec-WebSocket-Accept") {
+            true => Some(value.trim()),
+            false => None,
+        }
+    });
+    if accept != Some(&accept_key(&key)) {
+        return Err(Error::Unexpected("WebSocket accept key"));
+    }
+    Ok(())
+}
+
+fn accept_key(key: &str) -> String {
+    base64(&sha1(format!("{}{}", key, GUID).as_bytes()))
+}
+
+/// Bytes that are unpredictable enough for handshake keys and masks, which
+/// only need to keep intermediaries from recognizing the traffic.
+fn random_bytes<const N: usize>() -> [u8; N] {
+    let mut bytes = [0; N];
+    for chunk in bytes.chunks_mut(8) {
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_usize(chunk.as_ptr() as usize);
+        chunk.copy_from_slice(&hasher.finish().to_le_bytes()[..chunk.len()]);
+    }
+    bytes
+}
+
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::new();
+    for chunk in data.chunks(3) {
+        let bits = chunk.iter().enumerate().fold(0u32, |bits, (index, &byte)| {
+            bits | (byte as u32) << (16 - 8 * index)
+        });
+        for index in 0..4 {
+            encoded.push(match index <= chunk.len() {
+                true => ALPHABET[(bits >> (18 - 6 * index) & 0x3f) as usize] as char,
+                false => '=',
+            });
+        }
+    }
+    encoded
+}
+
+// The handshake needs SHA-1 only to check that the server understood it.
+fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut state: [u32; 5] = [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476, 0xc3d2e1f0];
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&(data.len() as u64 * 8).to_be_bytes());
+
+    for block in message.chunks(64) {
+        let mut w = [0u32; 80];
+        for (index, word) in block.chunks(4).enumerate() {
+            w[index] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for index in 16..80 {
+            w[index] = (w[index - 3] ^ w[index - 8] ^ w[index - 14] ^ w[index - 16]).rotate_left(1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e] = state;
+        for (index, word) in w.iter().enumerate() {
+            let (f, k) = match index {
+                0..=19 => ((b & c) | (!b & d), 0x5a827999),
+                20..=39 => (b ^ c ^ d, 0x6ed9eba1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8f1bbcdc),
+                _ => (b ^ c ^ d, 0xca62c1d6),
+            };
+            let temp = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(*word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+        for (word, value) in state.iter_mut().zip([a, b, c, d, e]) {
+            *word = word.wrapping_add(value);
+        }
+    }
+
+    let mut digest = [0; 20];
+    for (bytes, word) in digest.chunks_mut(4).zip(state) {
+        bytes.copy_from_slice(&word.to_be_bytes());
+    }
+    digest
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{accept_key, base64, parse_url, WebSocket};
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    #[test]
+    fn test_accept_key() {
+        // From RFC 6455, section 1.3.
+        assert_eq!(
+            accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foobar"), "Zm9vYmFy");
+    }
+
+    #[test]
+    fn test_parse_url() {
+        assert_eq!(
+            parse_url("ws://console.example:6080/websockify?token=x").unwrap(),
+            ("console.example", 6080, "/websockify?token=x")
+        );
+        assert_eq!(parse_url("ws://[::1]").unwrap(), ("::1", 80, "/"));
+        assert!(parse_url("wss://console.example/").is_err());
+        assert!(parse_url("console.example:5900").is_err());
+    }
+
+    #[test]
+    fn test_websocket() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("ws://{}/websockify", listener.local_addr().unwrap());
+        let server = thread::spawn(move || {
+            let mut stream = listener.accept().unwrap().0;
+            let mut key = None;
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            assert_eq!(line, "GET /websockify HTTP/1.1\r\n");
+            while line != "\r\n" {
+                line.clear();
+                reader.read_line(&mut line).unwrap();
+                if let Some(value) = line.strip_prefix("Sec-WebSocket-Key: ") {
+                    key = Some(value.trim().to_owned());
+                }
+            }
+            write!(
+                stream,
+                "HTTP/1.1 101 Switching Protocols\r\n\
+                 Upgrade: websocket\r\n\
+                 Connection: Upgrade\r\n\
+                 sec-websocket-accept: {}\r\n\r\n",
+                accept_key(&key.unwrap())
+            )
+            .unwrap();
+
+            // A ping, then "RFB" split across a binary frame and a continuation.
+            stream.write_all(&[0x89, 1, b'!']).unwrap();
+            stream.write_all(&[0x02, 2, b'R', b'F']).unwrap();
+            stream.write_all(&[0x80, 1, b'B']).unwrap();
+
+            let mut frame = [0; 7];
+            stream.read_exact(&mut frame).unwrap();
+            assert_eq!(&frame[..2], &[0x8a, 0x81]);
+            assert_eq!(frame[6] ^ frame[2], b'!');
+            let mut frame = [0; 8];
+            stream.read_exact(&mut frame).unwrap();
+            assert_eq!(&frame[..2], &[0x82, 0x82]);
+            assert_eq!([frame[6] ^ frame[2], frame[7] ^ frame[3]], *b"ok");
+            stream.write_all(&[0x88, 0]).unwrap();
+        });
+
+        let mut websocket = WebSocket::connect(&url).unwrap();
+        let mut rfb = [0; 3];
+        websocket.read_exact(&mut rfb).unwrap();
+        assert_eq!(&rfb, b"RFB");
+        websocket.write_all(b"ok").unwrap();
+        assert_eq!(websocket.read(&mut rfb).unwrap(), 0);
+        server.join().unwrap();
+    }
+}
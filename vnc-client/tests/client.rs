@@ -0,0 +1,723 @@
+//! The ways of connecting a client and of taking its events, and the protocol
+//! extensions it speaks. The scripted update is only a source of traffic here;
+//! `replay.rs` checks how it is decoded.
+
+mod common;
+
+use common::scripted::{connect, expected_pixels, handshake, scripted_server, FORMAT};
+use common::{next_event, run_until_frame, serve, TIMEOUT};
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::channel;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+use vnc_client::gii::{self, ClientMessage, ServerMessage};
+use vnc_client::h264::H264;
+use vnc_client::{
+    AuthChoice, Client, ClientStream, Encoding, Event, Fence, Framebuffer, Rect, Result, Screen,
+};
+use vnc_proto::protocol::{self, Message};
+
+/// Sets the encodings of the scripted server and asks for its update.
+fn request(client: &mut Client) {
+    client
+        .set_encodings(&[Encoding::Zrle, Encoding::CopyRect, Encoding::Raw])
+        .unwrap();
+    client.request_update(Rect::with_size(4, 4), false).unwrap();
+}
+
+#[test]
+fn test_poll_event_timeout() {
+    let mut client = connect(serve(scripted_server));
+    run_until_frame(&mut client);
+
+    // The server has nothing more to say.
+    let start = Instant::now();
+    assert!(client
+        .poll_event_timeout(Duration::from_millis(50))
+        .is_none());
+    assert!(start.elapsed() >= Duration::from_millis(50));
+
+    let (client, mut events) = client.split();
+    assert!(events
+        .poll_event_timeout(Duration::from_millis(10))
+        .is_none());
+    client.disconnect().unwrap();
+    assert!(matches!(
+        events.poll_event_timeout(TIMEOUT),
+        Some(Event::Disconnected(_))
+    ));
+}
+
+#[test]
+fn test_split() {
+    let (mut client, mut events) = connect(serve(scripted_server)).split();
+    let (tx_frame, rx_frame) = channel();
+    let reader = thread::spawn(move || {
+        while let Some(event) = events.wait_event() {
+            match event {
+                Event::EndOfFrame => tx_frame.send(()).unwrap(),
+                Event::Disconnected(_) => break,
+                _ => (),
+            }
+        }
+        assert!(events.wait_event().is_none());
+    });
+
+    // Input goes out while the other thread is blocked waiting for events,
+    // and the events all go to that thread.
+    client.send_key_event(true, 0x61).unwrap();
+    client.send_key_event(false, 0x61).unwrap();
+    assert!(client.poll_event().is_none());
+    rx_frame.recv_timeout(TIMEOUT).unwrap();
+    client.disconnect().unwrap();
+    reader.join().unwrap();
+}
+
+#[test]
+fn test_split_poll_iter() {
+    let (client, mut events) = connect(serve(scripted_server)).split();
+    let deadline = Instant::now() + TIMEOUT;
+    while !events
+        .poll_iter()
+        .any(|event| matches!(event, Event::EndOfFrame))
+    {
+        assert!(Instant::now() < deadline, "no update within the timeout");
+        thread::sleep(Duration::from_millis(5));
+    }
+    client.disconnect().unwrap();
+}
+
+#[test]
+fn test_event_channel() {
+    let (client, events) = connect(serve(scripted_server)).into_event_channel();
+    while !matches!(events.recv_timeout(TIMEOUT).unwrap(), Event::EndOfFrame) {}
+    client.disconnect().unwrap();
+    assert!(matches!(
+        events.recv_timeout(TIMEOUT),
+        Ok(Event::Disconnected(_))
+    ));
+    assert!(events.recv().is_err());
+}
+
+#[test]
+fn test_framebuffer() {
+    let mut client = connect(serve(scripted_server));
+    client.enable_framebuffer();
+    assert!(client.framebuffer().unwrap().dirty_regions().is_empty());
+    run_until_frame(&mut client);
+
+    let framebuffer = client.framebuffer_mut().unwrap();
+    assert_eq!(framebuffer.size(), (4, 4));
+    assert_eq!(framebuffer.pixels(), &expected_pixels()[..]);
+    assert_eq!(framebuffer.dirty_regions(), &[Rect::with_size(4, 4)]);
+    framebuffer.clear_damage();
+    assert!(framebuffer.dirty_regions().is_empty());
+
+    // A resize keeps the common part and damages everything.
+    framebuffer.apply(&Event::Resize(2, 5));
+    assert_eq!(framebuffer.dirty_regions(), &[Rect::with_size(2, 5)]);
+    assert_eq!(&framebuffer.pixels()[..8], &expected_pixels()[..8]);
+    assert_eq!(&framebuffer.pixels()[8..16], &expected_pixels()[16..24]);
+    assert!(framebuffer.pixels()[32..].iter().all(|&byte| byte == 0));
+    client.disconnect().unwrap();
+}
+
+#[test]
+fn test_framebuffer_out_of_bounds() {
+    let mut framebuffer = Framebuffer::new(2, 2, FORMAT);
+    // Only the top left pixel of each is inside.
+    framebuffer.apply(&Event::PutPixels(Rect::new(1, 1, 2, 2), vec![0xff; 16]));
+    framebuffer.apply(&Event::CopyPixels {
+        src: Rect::new(1, 1, 3, 3),
+        dst: Rect::new(0, 0, 3, 3),
+    });
+    // Pixels that are short of the rectangle, and rectangles outside.
+    framebuffer.apply(&Event::PutPixels(Rect::new(0, 1, 2, 2), vec![0x11; 8]));
+    framebuffer.apply(&Event::PutPixels(Rect::new(5, 5, 1, 1), vec![0; 4]));
+    framebuffer.apply(&Event::CopyPixels {
+        src: Rect::new(5, 0, 1, 1),
+        dst: Rect::new(0, 0, 1, 1),
+    });
+    let mut expected = vec![0xff; 4];
+    expected.extend([0; 4]);
+    expected.extend([0x11; 8]);
+    assert_eq!(framebuffer.pixels(), &expected[..]);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_unix_stream() {
+    use std::os::unix::net::{UnixListener, UnixStream};
+
+    let path = std::env::temp_dir().join(format!("vnc-client-test-{}.sock", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let listener = UnixListener::bind(&path).unwrap();
+    let server = thread::spawn(move || scripted_server(listener.accept().unwrap().0));
+
+    let stream = UnixStream::connect(&path).unwrap();
+    let mut client = Client::from_unix_stream(stream, true, |_| Some(AuthChoice::None)).unwrap();
+    request(&mut client);
+    run_until_frame(&mut client);
+    client.disconnect().unwrap();
+    server.join().unwrap();
+    std::fs::remove_file(&path).unwrap();
+}
+
+/// A connection of the caller's own, which counts the bytes it carries.
+struct Counted {
+    stream: TcpStream,
+    count: Arc<AtomicUsize>,
+}
+
+impl Read for Counted {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let length = self.stream.read(buf)?;
+        self.count.fetch_add(length, Ordering::Relaxed);
+        Ok(length)
+    }
+}
+
+impl Write for Counted {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let length = self.stream.write(buf)?;
+        self.count.fetch_add(length, Ordering::Relaxed);
+        Ok(length)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+impl ClientStream for Counted {
+    fn try_clone(&self) -> io::Result<Counted> {
+        Ok(Counted {
+            stream: self.stream.try_clone()?,
+            count: self.count.clone(),
+        })
+    }
+
+    fn shutdown(&self) -> io::Result<()> {
+        self.stream.shutdown(Shutdown::Both)
+    }
+}
+
+#[test]
+fn test_from_stream() {
+    let count = Arc::new(AtomicUsize::new(0));
+    let stream = Counted {
+        stream: serve(scripted_server),
+        count: count.clone(),
+    };
+    let mut client = Client::from_stream(stream, true, |_| Some(AuthChoice::None)).unwrap();
+    request(&mut client);
+    run_until_frame(&mut client);
+    client.disconnect().unwrap();
+    assert!(count.load(Ordering::Relaxed) > 0);
+}
+
+/// A connection that cannot be shared between threads.
+struct Unclonable(TcpStream);
+
+impl Read for Unclonable {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl Write for Unclonable {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+impl ClientStream for Unclonable {
+    fn try_clone(&self) -> io::Result<Unclonable> {
+        Err(io::Error::new(io::ErrorKind::Unsupported, "cannot clone"))
+    }
+
+    fn shutdown(&self) -> io::Result<()> {
+        self.0.shutdown(Shutdown::Both)
+    }
+}
+
+#[test]
+fn test_from_stream_unclonable() {
+    let stream = Unclonable(serve(scripted_server));
+    assert!(Client::from_stream(stream, true, |_| Some(AuthChoice::None)).is_err());
+}
+
+#[test]
+fn test_listen() {
+    let address = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    // The server makes the connection, once the client is listening.
+    let server = thread::spawn(move || loop {
+        match TcpStream::connect(address) {
+            Ok(stream) => return scripted_server(stream),
+            Err(_) => thread::sleep(Duration::from_millis(10)),
+        }
+    });
+
+    let mut client = Client::listen(address, true, |_| Some(AuthChoice::None)).unwrap();
+    request(&mut client);
+    run_until_frame(&mut client);
+    client.disconnect().unwrap();
+    server.join().unwrap();
+}
+
+#[test]
+fn test_fence() {
+    let stream = serve(|mut stream| {
+        handshake(&mut stream);
+        protocol::C2S::read_from(&mut stream).unwrap(); // SetEncodings
+        protocol::C2S::read_from(&mut stream).unwrap(); // FramebufferUpdateRequest
+
+        protocol::S2C::Fence(Fence {
+            flags: Fence::REQUEST | Fence::BLOCK_BEFORE,
+            payload: b"server".to_vec(),
+        })
+        .write_to(&mut stream)
+        .unwrap();
+
+        // The answer, then a request of the client's own.
+        assert_eq!(
+            protocol::C2S::read_from(&mut stream).unwrap(),
+            protocol::C2S::Fence(Fence {
+                flags: Fence::BLOCK_BEFORE,
+                payload: b"server".to_vec(),
+            })
+        );
+        let request = match protocol::C2S::read_from(&mut stream).unwrap() {
+            protocol::C2S::Fence(fence) => fence,
+            message => panic!("expected a fence, got {:?}", message),
+        };
+        assert_eq!(request.flags, Fence::REQUEST);
+        protocol::S2C::Fence(Fence {
+            flags: 0,
+            payload: request.payload,
+        })
+        .write_to(&mut stream)
+        .unwrap();
+        let _ = protocol::C2S::read_from(&mut stream);
+    });
+
+    let mut client = connect(stream);
+    // The server has not said it supports fences yet.
+    assert!(client.send_fence(Fence::REQUEST, b"early").is_err());
+
+    let deadline = Instant::now() + TIMEOUT;
+    loop {
+        match next_event(&mut client, deadline) {
+            Event::Fence(fence) if fence.flags & Fence::REQUEST != 0 => {
+                let flags = fence.flags & !Fence::REQUEST;
+                client.send_fence(flags, &fence.payload).unwrap();
+                client.send_fence(Fence::REQUEST, b"client").unwrap();
+            }
+            Event::Fence(fence) => {
+                assert_eq!(fence.payload, b"client");
+                break;
+            }
+            Event::Disconnected(reason) => panic!("disconnected: {}", reason),
+            _ => (),
+        }
+    }
+    assert!(client.send_fence(0, &[0; 65]).is_err());
+}
+
+#[test]
+fn test_name_change() {
+    let stream = serve(|mut stream| {
+        handshake(&mut stream);
+        protocol::C2S::read_from(&mut stream).unwrap(); // SetEncodings
+        protocol::C2S::read_from(&mut stream).unwrap(); // FramebufferUpdateRequest
+
+        protocol::S2C::FramebufferUpdate { count: 1 }
+            .write_to(&mut stream)
+            .unwrap();
+        protocol::Rectangle {
+            x_position: 0,
+            y_position: 0,
+            width: 0,
+            height: 0,
+            encoding: Encoding::DesktopName,
+        }
+        .write_to(&mut stream)
+        .unwrap();
+        protocol::DesktopName(String::from("display :1 \u{2013} x11vnc"))
+            .write_to(&mut stream)
+            .unwrap();
+        let _ = protocol::C2S::read_from(&mut stream);
+    });
+
+    let mut client = connect(stream);
+    let initial = client.name();
+
+    let mut names = Vec::new();
+    let deadline = Instant::now() + TIMEOUT;
+    loop {
+        match next_event(&mut client, deadline) {
+            Event::NameChange(name) => names.push(name),
+            Event::EndOfFrame => break,
+            Event::Disconnected(reason) => panic!("disconnected: {}", reason),
+            _ => (),
+        }
+    }
+    assert_eq!(names, ["display :1 \u{2013} x11vnc"]);
+    assert_ne!(client.name(), initial);
+    assert_eq!(client.name(), "display :1 \u{2013} x11vnc");
+}
+
+fn tablet() -> gii::Device {
+    gii::Device {
+        name: String::from("tablet"),
+        vendor_id: 0,
+        product_id: 0,
+        can_generate: gii::VALUATOR_ABSOLUTE_MASK,
+        num_registers: 0,
+        num_buttons: 0,
+        valuators: vec![gii::Valuator {
+            index: 0,
+            long_name: String::from("Pressure"),
+            short_name: String::from("p"),
+            range_min: 0,
+            range_center: 0,
+            range_max: 1023,
+            si_unit: 0,
+            si_add: 0,
+            si_mul: 1,
+            si_div: 1,
+            si_shift: 0,
+        }],
+    }
+}
+
+fn pressure(origin: u32) -> gii::InjectEvent {
+    gii::InjectEvent::Valuators {
+        origin,
+        relative: false,
+        first: 0,
+        values: vec![512],
+    }
+}
+
+fn expect_gii(stream: &mut TcpStream, message: ClientMessage) {
+    assert_eq!(
+        protocol::C2S::read_from(stream).unwrap(),
+        protocol::C2S::Gii(message)
+    )
+}
+
+#[test]
+fn test_gii() {
+    let stream = serve(|mut stream| {
+        handshake(&mut stream);
+        protocol::C2S::read_from(&mut stream).unwrap(); // SetEncodings
+        protocol::C2S::read_from(&mut stream).unwrap(); // FramebufferUpdateRequest
+
+        protocol::S2C::Gii(ServerMessage::Version {
+            maximum: 1,
+            minimum: 1,
+        })
+        .write_to(&mut stream)
+        .unwrap();
+        expect_gii(&mut stream, ClientMessage::Version(1));
+        expect_gii(&mut stream, ClientMessage::CreateDevice(tablet()));
+        protocol::S2C::Gii(ServerMessage::DeviceCreated(7))
+            .write_to(&mut stream)
+            .unwrap();
+        expect_gii(&mut stream, ClientMessage::InjectEvents(vec![pressure(7)]));
+        expect_gii(&mut stream, ClientMessage::DestroyDevice(7));
+    });
+
+    let mut client = connect(stream);
+    // The server has not offered gii yet.
+    assert!(client.gii_create_device(&tablet()).is_err());
+
+    let deadline = Instant::now() + TIMEOUT;
+    loop {
+        match next_event(&mut client, deadline) {
+            Event::Gii(ServerMessage::Version { .. }) => {
+                client.gii_create_device(&tablet()).unwrap()
+            }
+            Event::Gii(ServerMessage::DeviceCreated(origin)) => {
+                client.gii_inject(&[pressure(origin)]).unwrap();
+                client.gii_destroy_device(origin).unwrap();
+                break;
+            }
+            Event::Disconnected(reason) => panic!("disconnected: {}", reason),
+            _ => (),
+        }
+    }
+}
+
+#[test]
+fn test_last_rect() {
+    let stream = serve(|mut stream| {
+        handshake(&mut stream);
+        protocol::C2S::read_from(&mut stream).unwrap(); // SetEncodings
+        protocol::C2S::read_from(&mut stream).unwrap(); // FramebufferUpdateRequest
+
+        // Far more rectangles than follow, ended by LastRect.
+        protocol::S2C::FramebufferUpdate { count: 0xffff }
+            .write_to(&mut stream)
+            .unwrap();
+        let rectangle = |width, height, encoding| protocol::Rectangle {
+            x_position: 0,
+            y_position: 0,
+            width,
+            height,
+            encoding,
+        };
+        rectangle(1, 1, Encoding::Raw)
+            .write_to(&mut stream)
+            .unwrap();
+        stream.write_all(&[1, 2, 3, 0]).unwrap();
+        rectangle(0, 0, Encoding::LastRect)
+            .write_to(&mut stream)
+            .unwrap();
+        protocol::S2C::Bell.write_to(&mut stream).unwrap();
+        thread::sleep(Duration::from_millis(100));
+    });
+
+    let mut client = connect(stream);
+    let mut events = Vec::new();
+    let deadline = Instant::now() + TIMEOUT;
+    while !matches!(events.last(), Some(Event::Bell)) {
+        match next_event(&mut client, deadline) {
+            Event::Disconnected(reason) => panic!("disconnected: {}", reason),
+            event => events.push(event),
+        }
+    }
+    // The Bell is read as a message of its own, not as a rectangle.
+    match &events[..] {
+        [Event::PutPixels(rect, pixels), Event::EndOfFrame, Event::Bell] => {
+            assert_eq!(*rect, Rect::new(0, 0, 1, 1));
+            assert_eq!(pixels, &[1, 2, 3, 0]);
+        }
+        events => panic!("unexpected {:?}", events),
+    }
+}
+
+// Completes a red picture of the given size with everything it is fed.
+struct Red(usize);
+
+impl H264 for Red {
+    fn decode(&mut self, _data: &[u8], rgb: &mut Vec<u8>) -> Result<bool> {
+        rgb.extend([255, 0, 0].repeat(self.0));
+        Ok(true)
+    }
+}
+
+fn h264_server(mut stream: TcpStream) {
+    handshake(&mut stream);
+    protocol::C2S::read_from(&mut stream).unwrap(); // SetEncodings
+    protocol::C2S::read_from(&mut stream).unwrap(); // FramebufferUpdateRequest
+
+    protocol::S2C::FramebufferUpdate { count: 1 }
+        .write_to(&mut stream)
+        .unwrap();
+    protocol::Rectangle {
+        x_position: 1,
+        y_position: 1,
+        width: 2,
+        height: 2,
+        encoding: Encoding::OpenH264,
+    }
+    .write_to(&mut stream)
+    .unwrap();
+    // The length and flags, then a start code.
+    stream
+        .write_all(&[0, 0, 0, 4, 0, 0, 0, 0, 0, 0, 0, 1])
+        .unwrap();
+    thread::sleep(Duration::from_millis(100));
+}
+
+fn h264_events(mut client: Client) -> Vec<Event> {
+    let mut events = Vec::new();
+    let deadline = Instant::now() + TIMEOUT;
+    loop {
+        match next_event(&mut client, deadline) {
+            Event::EndOfFrame => return events,
+            event @ Event::Disconnected(_) => {
+                events.push(event);
+                return events;
+            }
+            event => events.push(event),
+        }
+    }
+}
+
+#[test]
+fn test_open_h264() {
+    let stream = serve(h264_server);
+    let mut client = Client::from_tcp_stream(stream, true, |_| Some(AuthChoice::None)).unwrap();
+    // Before the update request, so that the backend is there in time.
+    client.set_h264_backend(Box::new(|| Box::new(Red(4))));
+    client.set_encodings(&[Encoding::OpenH264]).unwrap();
+    client.request_update(Rect::with_size(4, 4), false).unwrap();
+
+    let pictures: Vec<_> = h264_events(client)
+        .into_iter()
+        .filter_map(|event| match event {
+            Event::PutPixels(rect, pixels) => Some((rect, pixels)),
+            Event::Disconnected(reason) => panic!("disconnected: {}", reason),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(
+        pictures,
+        [(Rect::new(1, 1, 2, 2), [0, 0, 255, 0].repeat(4))]
+    );
+}
+
+#[test]
+fn test_open_h264_without_backend() {
+    let stream = serve(h264_server);
+    let client = connect(stream);
+    assert!(matches!(
+        h264_events(client).last(),
+        Some(Event::Disconnected(_))
+    ));
+}
+
+fn screens() -> Vec<Screen> {
+    vec![
+        Screen {
+            id: 1,
+            x_position: 0,
+            y_position: 0,
+            width: 4,
+            height: 4,
+            flags: 0,
+        },
+        Screen {
+            id: 2,
+            x_position: 4,
+            y_position: 0,
+            width: 4,
+            height: 4,
+            flags: 0,
+        },
+    ]
+}
+
+fn write_layout(stream: &mut TcpStream, status: u16, width: u16, screens: Vec<Screen>) {
+    protocol::Rectangle {
+        x_position: 0,
+        y_position: status,
+        width,
+        height: 4,
+        encoding: Encoding::ExtendedDesktopSize,
+    }
+    .write_to(stream)
+    .unwrap();
+    protocol::ScreenLayout(screens).write_to(stream).unwrap();
+}
+
+#[test]
+fn test_screens() {
+    let stream = serve(|mut stream| {
+        handshake(&mut stream);
+        protocol::C2S::read_from(&mut stream).unwrap(); // SetEncodings
+        protocol::C2S::read_from(&mut stream).unwrap(); // FramebufferUpdateRequest
+
+        protocol::S2C::FramebufferUpdate { count: 3 }
+            .write_to(&mut stream)
+            .unwrap();
+        // The initial layout, a second screen making the framebuffer wider,
+        // and a refused request, which must not change anything else.
+        write_layout(&mut stream, 0, 4, screens()[..1].to_vec());
+        write_layout(&mut stream, 0, 8, screens());
+        write_layout(&mut stream, 3, 12, vec![]);
+        let _ = protocol::C2S::read_from(&mut stream);
+    });
+
+    let mut client = connect(stream);
+    assert!(client.screens().is_empty());
+
+    let mut events = Vec::new();
+    let deadline = Instant::now() + TIMEOUT;
+    loop {
+        match next_event(&mut client, deadline) {
+            Event::EndOfFrame => break,
+            Event::Disconnected(reason) => panic!("disconnected: {}", reason),
+            event => events.push(event),
+        }
+    }
+    assert!(matches!(
+        &events[..],
+        [
+            Event::ScreenLayout(first),
+            Event::Resize(8, 4),
+            Event::ScreenLayout(second),
+            Event::DesktopSizeRefused(3),
+        ] if *first == screens()[..1] && *second == screens()
+    ));
+    assert_eq!(client.size(), (8, 4));
+    assert_eq!(client.screens(), screens());
+}
+
+#[test]
+fn test_set_desktop_size() {
+    let stream = serve(|mut stream| {
+        handshake(&mut stream);
+        protocol::C2S::read_from(&mut stream).unwrap(); // SetEncodings
+        protocol::C2S::read_from(&mut stream).unwrap(); // FramebufferUpdateRequest
+
+        protocol::S2C::FramebufferUpdate { count: 1 }
+            .write_to(&mut stream)
+            .unwrap();
+        write_layout(&mut stream, 0, 4, screens()[..1].to_vec());
+
+        let request = protocol::C2S::read_from(&mut stream).unwrap();
+        let resized = vec![Screen {
+            width: 6,
+            ..screens()[0]
+        }];
+        assert_eq!(
+            request,
+            protocol::C2S::SetDesktopSize {
+                width: 6,
+                height: 4,
+                screens: resized.clone(),
+            }
+        );
+        protocol::S2C::FramebufferUpdate { count: 1 }
+            .write_to(&mut stream)
+            .unwrap();
+        write_layout(&mut stream, 0, 6, resized);
+        let _ = protocol::C2S::read_from(&mut stream);
+    });
+
+    let mut client = connect(stream);
+    // The server has not said it supports resizing yet.
+    assert!(client.set_desktop_size(6, 4, &[]).is_err());
+
+    let mut requested = false;
+    let deadline = Instant::now() + TIMEOUT;
+    loop {
+        match next_event(&mut client, deadline) {
+            Event::ScreenLayout(_) if !requested => {
+                client.set_desktop_size(6, 4, &[]).unwrap();
+                requested = true;
+            }
+            Event::Resize(width, height) => {
+                assert_eq!((width, height), (6, 4));
+                break;
+            }
+            Event::Disconnected(reason) => panic!("disconnected: {}", reason),
+            _ => (),
+        }
+    }
+    assert_eq!(client.size(), (6, 4));
+}
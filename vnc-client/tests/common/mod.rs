@@ -0,0 +1,245 @@
+//! A record/replay harness for regression tests.
+//!
+//! `Recording::record` sits between a client and a server and captures the bytes
+//! going in both directions, with the time they were seen. `Recording::replay`
+//! later plays the server side of such a recording to a client, checking that the
+//! client sends the same bytes it did when the recording was made. Together with
+//! `vnc_client::Framebuffer`, which applies client events to a framebuffer, this
+//! lets decoder changes be checked against sessions captured earlier.
+//!
+//! `assert_golden` compares a framebuffer against a PNG fixture in `tests/golden`.
+//! Running the tests with `VNC_UPDATE_GOLDEN=1` writes the fixtures instead.
+
+#![allow(dead_code)]
+
+pub mod scripted;
+pub mod shaped;
+
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use vnc_client::{Client, Event, Framebuffer};
+use vnc_proto::png;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    ToServer,
+    ToClient,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Chunk {
+    pub direction: Direction,
+    /// Time since the start of the session.
+    pub time: Duration,
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Recording {
+    pub chunks: Vec<Chunk>,
+}
+
+impl Recording {
+    /// Forwards bytes between `client` and `server` until both directions are
+    /// closed, and returns everything that went through.
+    pub fn record(client: TcpStream, server: TcpStream) -> Recording {
+        let start = Instant::now();
+        let chunks = Arc::new(Mutex::new(Vec::new()));
+
+        let forward = |direction, mut from: TcpStream, mut to: TcpStream| {
+            let chunks = chunks.clone();
+            thread::spawn(move || {
+                let mut buffer = [0; 4096];
+                loop {
+                    let length = match from.read(&mut buffer) {
+                        Ok(0) | Err(_) => break,
+                        Ok(length) => length,
+                    };
+                    // Record before forwarding, so that a reply never ends up
+                    // ahead of the message that caused it.
+                    chunks.lock().unwrap().push(Chunk {
+                        direction,
+                        time: start.elapsed(),
+                        data: buffer[..length].to_vec(),
+                    });
+                    if to.write_all(&buffer[..length]).is_err() {
+                        break;
+                    }
+                }
+                let _ = from.shutdown(Shutdown::Both);
+                let _ = to.shutdown(Shutdown::Both);
+            })
+        };
+
+        let to_server = forward(
+            Direction::ToServer,
+            client.try_clone().unwrap(),
+            server.try_clone().unwrap(),
+        );
+        let to_client = forward(Direction::ToClient, server, client);
+        to_server.join().unwrap();
+        to_client.join().unwrap();
+
+        let chunks = chunks.lock().unwrap().clone();
+        Recording { chunks }
+    }
+
+    /// Plays the server side of the recording to `client`. Bytes the client sends
+    /// have to match the recording; the server side waits for them, so the replay
+    /// does not depend on timing.
+    pub fn replay(&self, mut client: TcpStream) -> io::Result<()> {
+        for chunk in &self.chunks {
+            match chunk.direction {
+                Direction::ToClient => client.write_all(&chunk.data)?,
+                Direction::ToServer => {
+                    let mut data = vec![0; chunk.data.len()];
+                    client.read_exact(&mut data)?;
+                    if data != chunk.data {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!("client sent {:?}, expected {:?}", data, chunk.data),
+                        ));
+                    }
+                }
+            }
+        }
+        client.shutdown(Shutdown::Both)
+    }
+
+    pub fn read_from<R: Read>(reader: &mut R) -> io::Result<Recording> {
+        let mut chunks = Vec::new();
+        loop {
+            let direction = match reader.read_u8() {
+                Ok(0) => Direction::ToServer,
+                Ok(1) => Direction::ToClient,
+                Ok(_) => return Err(io::ErrorKind::InvalidData.into()),
+                Err(ref error) if error.kind() == io::ErrorKind::UnexpectedEof => break,
+                Err(error) => return Err(error),
+            };
+            let time = Duration::from_micros(reader.read_u64::<BigEndian>()?);
+            let mut data = vec![0; reader.read_u32::<BigEndian>()? as usize];
+            reader.read_exact(&mut data)?;
+            chunks.push(Chunk {
+                direction,
+                time,
+                data,
+            });
+        }
+        Ok(Recording { chunks })
+    }
+
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        for chunk in &self.chunks {
+            writer.write_u8(match chunk.direction {
+                Direction::ToServer => 0,
+                Direction::ToClient => 1,
+            })?;
+            writer.write_u64::<BigEndian>(chunk.time.as_micros() as u64)?;
+            writer.write_u32::<BigEndian>(chunk.data.len() as u32)?;
+            writer.write_all(&chunk.data)?;
+        }
+        Ok(())
+    }
+}
+
+/// Runs `server` on a local port and returns a stream connected to it.
+pub fn serve<F>(server: F) -> TcpStream
+where
+    F: FnOnce(TcpStream) + Send + 'static,
+{
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    thread::spawn(move || server(listener.accept().unwrap().0));
+    TcpStream::connect(address).unwrap()
+}
+
+/// Checks that `framebuffer` matches the golden image `tests/golden/<name>.png`,
+/// with every colour component within `tolerance` of the fixture.
+pub fn assert_golden(framebuffer: &Framebuffer, name: &str, tolerance: u8) {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/golden")
+        .join(format!("{}.png", name));
+    let screenshot = framebuffer.screenshot();
+    let (width, height) = (screenshot.width as u32, screenshot.height as u32);
+
+    if std::env::var_os("VNC_UPDATE_GOLDEN").is_some() {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        let mut file = File::create(&path).unwrap();
+        png::encode(&mut file, width, height, &screenshot.rgba).unwrap();
+        return;
+    }
+
+    let expected = match std::fs::read(&path) {
+        Ok(data) => png::decode(&data, width, height)
+            .unwrap_or_else(|error| panic!("cannot decode {}: {}", path.display(), error)),
+        Err(error) => panic!("cannot open {}: {}", path.display(), error),
+    };
+    let actual = screenshot
+        .rgba
+        .chunks_exact(4)
+        .flat_map(|pixel| [pixel[0], pixel[1], pixel[2]])
+        .collect::<Vec<_>>();
+    let mismatches = actual
+        .iter()
+        .zip(&expected)
+        .enumerate()
+        .filter(|(_, (&actual, &expected))| actual.abs_diff(expected) > tolerance)
+        .map(|(i, _)| i / 3)
+        .collect::<Vec<_>>();
+    if let Some(&first) = mismatches.first() {
+        panic!(
+            "{} pixel components differ from {}, first at ({}, {})",
+            mismatches.len(),
+            path.display(),
+            first % width as usize,
+            first / width as usize
+        );
+    }
+}
+
+/// How long the helpers below wait for the client before failing the test.
+pub const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The next event from `client`, failing the test if there is none by
+/// `deadline`, which is usually `TIMEOUT` from the start of the test.
+pub fn next_event(client: &mut Client, deadline: Instant) -> Event {
+    let timeout = deadline.saturating_duration_since(Instant::now());
+    match client.poll_event_timeout(timeout) {
+        Some(event) => event,
+        None => panic!("no event within {:?}", TIMEOUT),
+    }
+}
+
+/// Applies events from `client` to a framebuffer until the server disconnects.
+pub fn run_client(mut client: Client) -> Framebuffer {
+    let (width, height) = client.size();
+    let mut framebuffer = Framebuffer::new(width, height, client.format());
+    let deadline = Instant::now() + TIMEOUT;
+    loop {
+        match next_event(&mut client, deadline) {
+            Event::Disconnected(_) => return framebuffer,
+            event => framebuffer.apply(&event),
+        }
+    }
+}
+
+/// Applies events from `client` to a framebuffer until the end of the first
+/// framebuffer update.
+pub fn run_until_frame(client: &mut Client) -> Framebuffer {
+    let (width, height) = client.size();
+    let mut framebuffer = Framebuffer::new(width, height, client.format());
+    let deadline = Instant::now() + TIMEOUT;
+    loop {
+        match next_event(client, deadline) {
+            Event::EndOfFrame => return framebuffer,
+            Event::Disconnected(reason) => panic!("disconnected: {}", reason),
+            event => framebuffer.apply(&event),
+        }
+    }
+}
@@ -0,0 +1,118 @@
+//! A scripted server for tests that need some traffic between a client and a server.
+
+use flate2::{Compress, Compression, FlushCompress};
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use vnc_client::{AuthChoice, Client, Encoding, PixelFormat, Rect};
+use vnc_proto::protocol::{self, Message};
+
+/// The pixel format of the scripted server.
+pub const FORMAT: PixelFormat = PixelFormat {
+    bits_per_pixel: 32,
+    depth: 24,
+    big_endian: false,
+    true_colour: true,
+    red_max: 255,
+    green_max: 255,
+    blue_max: 255,
+    red_shift: 16,
+    green_shift: 8,
+    blue_shift: 0,
+};
+
+/// Plays the server side of the handshake, for a 4x4 framebuffer.
+pub fn handshake<S: Read + Write>(stream: &mut S) {
+    protocol::Version::Rfb38.write_to(stream).unwrap();
+    protocol::Version::read_from(stream).unwrap();
+    protocol::SecurityTypes(vec![protocol::SecurityType::None])
+        .write_to(stream)
+        .unwrap();
+    protocol::SecurityType::read_from(stream).unwrap();
+    protocol::SecurityResult::Succeeded
+        .write_to(stream)
+        .unwrap();
+    protocol::ClientInit::read_from(stream).unwrap();
+    protocol::ServerInit {
+        framebuffer_width: 4,
+        framebuffer_height: 4,
+        pixel_format: FORMAT,
+        name: String::from("scripted"),
+    }
+    .write_to(stream)
+    .unwrap();
+}
+
+/// A 4x4 server that answers the first update request with a Raw, an overlapping
+/// CopyRect and a ZRLE rectangle, and waits for the client to hang up.
+pub fn scripted_server<S: Read + Write>(mut stream: S) {
+    handshake(&mut stream);
+
+    protocol::C2S::read_from(&mut stream).unwrap(); // SetEncodings
+    protocol::C2S::read_from(&mut stream).unwrap(); // FramebufferUpdateRequest
+
+    protocol::S2C::FramebufferUpdate { count: 3 }
+        .write_to(&mut stream)
+        .unwrap();
+    let rectangle = |x_position, y_position, width, height, encoding| protocol::Rectangle {
+        x_position,
+        y_position,
+        width,
+        height,
+        encoding,
+    };
+
+    rectangle(0, 0, 4, 4, Encoding::Raw)
+        .write_to(&mut stream)
+        .unwrap();
+    for i in 0..16u32 {
+        stream.write_all(&(i * 0x010101).to_le_bytes()).unwrap();
+    }
+
+    rectangle(1, 1, 3, 3, Encoding::CopyRect)
+        .write_to(&mut stream)
+        .unwrap();
+    protocol::CopyRect {
+        src_x_position: 0,
+        src_y_position: 0,
+    }
+    .write_to(&mut stream)
+    .unwrap();
+
+    // A single solid tile, with a 3-byte CPIXEL. The zlib stream spans the whole
+    // session, so it is flushed rather than finished.
+    rectangle(0, 0, 2, 2, Encoding::Zrle)
+        .write_to(&mut stream)
+        .unwrap();
+    let mut compressed = Vec::with_capacity(64);
+    Compress::new(Compression::default(), true)
+        .compress_vec(&[1, 0x33, 0x22, 0x11], &mut compressed, FlushCompress::Sync)
+        .unwrap();
+    compressed.write_to(&mut stream).unwrap();
+
+    // Wait for the client to hang up, so that the recording ends cleanly.
+    let _ = stream.read_to_end(&mut Vec::new());
+}
+
+/// Connects and requests the whole framebuffer.
+pub fn connect(stream: TcpStream) -> Client {
+    let mut client = Client::from_tcp_stream(stream, true, |_| Some(AuthChoice::None)).unwrap();
+    client
+        .set_encodings(&[Encoding::Zrle, Encoding::CopyRect, Encoding::Raw])
+        .unwrap();
+    client.request_update(Rect::with_size(4, 4), false).unwrap();
+    client
+}
+
+/// The framebuffer a client ends up with after the scripted update.
+pub fn expected_pixels() -> Vec<u8> {
+    let pixels = [
+        0x112233, 0x112233, 0x020202, 0x030303, //
+        0x112233, 0x112233, 0x010101, 0x020202, //
+        0x080808, 0x040404, 0x050505, 0x060606, //
+        0x0c0c0c, 0x080808, 0x090909, 0x0a0a0a, //
+    ];
+    pixels
+        .iter()
+        .flat_map(|pixel: &u32| pixel.to_le_bytes())
+        .collect()
+}
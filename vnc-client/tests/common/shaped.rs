@@ -0,0 +1,129 @@
+//! Simulated network conditions.
+//!
+//! `ShapedStream` wraps a stream and delays and fragments whatever is written to
+//! it: data goes out in segments no larger than `segment_size`, each one after the
+//! configured latency, plus jitter and the occasional stall, and paced to the
+//! bandwidth cap. Delays are deterministic for a given seed.
+//!
+//! The client only talks to a `TcpStream`, so `relay` puts a pair of shaped
+//! streams between a client and a server socket.
+
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, TcpStream};
+use std::thread;
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Conditions {
+    /// Bytes per second, or `None` for no limit.
+    pub bandwidth: Option<u64>,
+    pub latency: Duration,
+    /// Each segment is delayed by a further random amount up to this much.
+    pub jitter: Duration,
+    /// The chance that a segment is held back by `stall` on top of everything else.
+    pub stall_probability: f64,
+    pub stall: Duration,
+    pub segment_size: usize,
+    pub seed: u64,
+}
+
+impl Default for Conditions {
+    fn default() -> Conditions {
+        Conditions {
+            bandwidth: None,
+            latency: Duration::ZERO,
+            jitter: Duration::ZERO,
+            stall_probability: 0.0,
+            stall: Duration::ZERO,
+            segment_size: usize::MAX,
+            seed: 1,
+        }
+    }
+}
+
+pub struct ShapedStream<S> {
+    inner: S,
+    conditions: Conditions,
+    random: u64,
+}
+
+impl<S> ShapedStream<S> {
+    pub fn new(inner: S, conditions: Conditions) -> ShapedStream<S> {
+        ShapedStream {
+            inner,
+            conditions,
+            random: conditions.seed.max(1),
+        }
+    }
+
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+
+    // xorshift64; good enough to make delays irregular, and reproducible.
+    fn next_random(&mut self) -> f64 {
+        self.random ^= self.random << 13;
+        self.random ^= self.random >> 7;
+        self.random ^= self.random << 17;
+        (self.random >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    fn delay(&mut self, length: usize) -> Duration {
+        let mut delay =
+            self.conditions.latency + self.conditions.jitter.mul_f64(self.next_random());
+        if self.next_random() < self.conditions.stall_probability {
+            delay += self.conditions.stall;
+        }
+        if let Some(bandwidth) = self.conditions.bandwidth {
+            delay += Duration::from_secs_f64(length as f64 / bandwidth as f64);
+        }
+        delay
+    }
+}
+
+impl<S: Read> Read for ShapedStream<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf)
+    }
+}
+
+impl<S: Write> Write for ShapedStream<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let length = buf.len().min(self.conditions.segment_size);
+        thread::sleep(self.delay(length));
+        self.inner.write_all(&buf[..length])?;
+        self.inner.flush()?;
+        Ok(length)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Forwards between `client` and `server`, shaping both directions with
+/// `conditions`, until either side hangs up.
+pub fn relay(client: TcpStream, server: TcpStream, conditions: Conditions) {
+    let forward = |mut from: TcpStream, to: TcpStream, seed| {
+        let mut to = ShapedStream::new(to, Conditions { seed, ..conditions });
+        thread::spawn(move || {
+            let mut buffer = [0; 4096];
+            while let Ok(length @ 1..) = from.read(&mut buffer) {
+                if to.write_all(&buffer[..length]).is_err() {
+                    break;
+                }
+            }
+            let _ = from.shutdown(Shutdown::Both);
+            let _ = to.into_inner().shutdown(Shutdown::Both);
+        })
+    };
+
+    let to_server = forward(
+        client.try_clone().unwrap(),
+        server.try_clone().unwrap(),
+        conditions.seed,
+    );
+    let to_client = forward(server, client, conditions.seed.wrapping_add(1));
+    to_server.join().unwrap();
+    to_client.join().unwrap();
+}
@@ -0,0 +1,110 @@
+//! Servers that send what no framebuffer has room for. The client has to hang
+//! up on them with a protocol error, not panic or run out of memory.
+
+mod common;
+
+use common::scripted::{connect, handshake};
+use common::{serve, TIMEOUT};
+use std::io::{ErrorKind, Read, Write};
+use std::net::{Shutdown, TcpStream};
+use std::time::Instant;
+use vnc_client::{DisconnectReason, Encoding, Error, Event};
+use vnc_proto::protocol::{self, Message};
+
+/// Connects to a 4x4 server that answers the update request with a single
+/// `rectangle` followed by `data`, and returns the reason the client hung up.
+fn disconnect_reason(rectangle: protocol::Rectangle, data: &'static [u8]) -> DisconnectReason {
+    let stream = serve(move |mut stream: TcpStream| {
+        handshake(&mut stream);
+        protocol::C2S::read_from(&mut stream).unwrap(); // SetEncodings
+        protocol::C2S::read_from(&mut stream).unwrap(); // FramebufferUpdateRequest
+        protocol::S2C::FramebufferUpdate { count: 1 }
+            .write_to(&mut stream)
+            .unwrap();
+        rectangle.write_to(&mut stream).unwrap();
+        let _ = stream.write_all(data);
+        // Nothing more comes, whatever the rectangle promised.
+        let _ = stream.shutdown(Shutdown::Write);
+        let _ = stream.read_to_end(&mut Vec::new());
+    });
+
+    let mut client = connect(stream);
+    client.enable_framebuffer();
+    let deadline = Instant::now() + TIMEOUT;
+    loop {
+        let timeout = deadline.saturating_duration_since(Instant::now());
+        match client.poll_event_timeout(timeout) {
+            Some(Event::Disconnected(reason)) => return reason,
+            Some(_) => (),
+            None => panic!("the client did not hang up"),
+        }
+    }
+}
+
+fn assert_protocol_error(reason: DisconnectReason, expected: &str) {
+    match reason {
+        DisconnectReason::Protocol {
+            error: Error::Unexpected(error),
+            ..
+        } => assert_eq!(error, expected),
+        reason => panic!("unexpected {:?}", reason),
+    }
+}
+
+#[test]
+fn test_rectangle_out_of_bounds() {
+    let rectangle = protocol::Rectangle {
+        x_position: 3,
+        y_position: 3,
+        width: 2,
+        height: 2,
+        encoding: Encoding::Raw,
+    };
+    let reason = disconnect_reason(rectangle, &[0; 16]);
+    assert_protocol_error(reason, "rectangle out of bounds");
+}
+
+#[test]
+fn test_cursor_too_large() {
+    let rectangle = protocol::Rectangle {
+        x_position: 0,
+        y_position: 0,
+        width: 0xffff,
+        height: 0xffff,
+        encoding: Encoding::Cursor,
+    };
+    let reason = disconnect_reason(rectangle, &[]);
+    assert_protocol_error(reason, "cursor larger than the framebuffer");
+}
+
+/// A length of 4 GiB is read in pieces as the data arrives, rather than
+/// allocated up front.
+fn assert_length_read_in_pieces(encoding: Encoding) {
+    let rectangle = protocol::Rectangle {
+        x_position: 0,
+        y_position: 0,
+        width: 4,
+        height: 4,
+        encoding,
+    };
+    match disconnect_reason(rectangle, &[0xff, 0xff, 0xff, 0xff, 1, 2, 3]) {
+        DisconnectReason::Io(error) => assert_eq!(error.kind(), ErrorKind::UnexpectedEof),
+        reason => panic!("unexpected {:?}", reason),
+    }
+}
+
+#[test]
+fn test_zrle_length() {
+    assert_length_read_in_pieces(Encoding::Zrle);
+}
+
+#[test]
+fn test_zlib_length() {
+    assert_length_read_in_pieces(Encoding::Zlib);
+}
+
+#[cfg(feature = "lzo")]
+#[test]
+fn test_ultra_length() {
+    assert_length_read_in_pieces(Encoding::Ultra);
+}
@@ -0,0 +1,45 @@
+mod common;
+
+use common::scripted::handshake;
+use common::{serve, TIMEOUT};
+use std::thread;
+use std::time::{Duration, Instant};
+use vnc_client::{AuthChoice, Client, Event};
+use vnc_proto::protocol::{self, Message};
+
+#[test]
+fn test_keepalive() {
+    let stream = serve(|mut stream| {
+        handshake(&mut stream);
+        // Answer the first probe, and let the second one go unanswered.
+        match protocol::C2S::read_from(&mut stream).unwrap() {
+            protocol::C2S::FramebufferUpdateRequest {
+                incremental: false,
+                width: 1,
+                height: 1,
+                ..
+            } => (),
+            message => panic!("unexpected {:?}", message),
+        }
+        protocol::S2C::FramebufferUpdate { count: 0 }
+            .write_to(&mut stream)
+            .unwrap();
+        let _ = protocol::C2S::read_from(&mut stream);
+        let _ = protocol::C2S::read_from(&mut stream);
+    });
+    let mut client = Client::from_tcp_stream(stream, true, |_| Some(AuthChoice::None)).unwrap();
+    client.set_keepalive(Some(Duration::from_millis(50)));
+
+    let mut events = Vec::new();
+    let deadline = Instant::now() + TIMEOUT;
+    while !matches!(events.last(), Some(Event::ConnectionStale)) {
+        assert!(Instant::now() < deadline, "the connection never went stale");
+        events.extend(client.poll_event());
+        thread::sleep(Duration::from_millis(5));
+    }
+    assert!(matches!(
+        events[..],
+        [Event::EndOfFrame, Event::ConnectionStale]
+    ));
+    client.disconnect().unwrap();
+}
@@ -0,0 +1,82 @@
+mod common;
+
+use common::{run_until_frame, serve};
+use std::io::Write;
+use vnc_client::{pixels, AuthChoice, Client, Colour, Encoding, PixelFormat, Rect};
+use vnc_proto::protocol::{self, Message};
+
+const FORMAT: PixelFormat = PixelFormat {
+    bits_per_pixel: 8,
+    depth: 8,
+    big_endian: false,
+    true_colour: false,
+    red_max: 0,
+    green_max: 0,
+    blue_max: 0,
+    red_shift: 0,
+    green_shift: 0,
+    blue_shift: 0,
+};
+
+#[test]
+fn test_colour_map() {
+    let colour = |red, green, blue| Colour { red, green, blue };
+    let stream = serve(move |mut stream| {
+        protocol::Version::Rfb38.write_to(&mut stream).unwrap();
+        protocol::Version::read_from(&mut stream).unwrap();
+        protocol::SecurityTypes(vec![protocol::SecurityType::None])
+            .write_to(&mut stream)
+            .unwrap();
+        protocol::SecurityType::read_from(&mut stream).unwrap();
+        protocol::SecurityResult::Succeeded
+            .write_to(&mut stream)
+            .unwrap();
+        protocol::ClientInit::read_from(&mut stream).unwrap();
+        protocol::ServerInit {
+            framebuffer_width: 2,
+            framebuffer_height: 1,
+            pixel_format: FORMAT,
+            name: String::from("palette"),
+        }
+        .write_to(&mut stream)
+        .unwrap();
+
+        protocol::C2S::read_from(&mut stream).unwrap(); // FramebufferUpdateRequest
+        protocol::S2C::SetColourMapEntries {
+            first_colour: 1,
+            colours: vec![colour(0xffff, 0, 0), colour(0, 0, 0xffff)],
+        }
+        .write_to(&mut stream)
+        .unwrap();
+        protocol::S2C::FramebufferUpdate { count: 1 }
+            .write_to(&mut stream)
+            .unwrap();
+        protocol::Rectangle {
+            x_position: 0,
+            y_position: 0,
+            width: 2,
+            height: 1,
+            encoding: Encoding::Raw,
+        }
+        .write_to(&mut stream)
+        .unwrap();
+        stream.write_all(&[2, 1]).unwrap();
+        let _ = protocol::C2S::read_from(&mut stream);
+    });
+    let mut client = Client::from_tcp_stream(stream, true, |_| Some(AuthChoice::None)).unwrap();
+    client.enable_framebuffer();
+    client.request_update(Rect::with_size(2, 1), false).unwrap();
+
+    run_until_frame(&mut client);
+    let framebuffer = client.framebuffer().unwrap();
+    assert_eq!(framebuffer.colour_map().len(), 3);
+    let mut rgb = Vec::new();
+    pixels::expand_indexed(
+        framebuffer.format(),
+        framebuffer.colour_map(),
+        framebuffer.pixels(),
+        &mut rgb,
+    );
+    assert_eq!(rgb, [0, 0, 0xff, 0xff, 0, 0]);
+    client.disconnect().unwrap();
+}
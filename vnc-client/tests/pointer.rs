@@ -0,0 +1,58 @@
+mod common;
+
+use common::scripted::{connect, handshake};
+use common::{next_event, run_until_frame, serve, TIMEOUT};
+use std::time::Instant;
+use vnc_client::{Encoding, Event, PointerMotionMode};
+use vnc_proto::protocol::{self, Message};
+
+#[test]
+fn test_relative_pointer() {
+    let stream = serve(|mut stream| {
+        handshake(&mut stream);
+        protocol::C2S::read_from(&mut stream).unwrap(); // SetEncodings
+        protocol::C2S::read_from(&mut stream).unwrap(); // FramebufferUpdateRequest
+
+        // QEMU reports the mode in the x position of a pseudo-rectangle.
+        protocol::S2C::FramebufferUpdate { count: 1 }
+            .write_to(&mut stream)
+            .unwrap();
+        protocol::Rectangle {
+            x_position: 0,
+            y_position: 0,
+            width: 4,
+            height: 4,
+            encoding: Encoding::QemuPointerMotionChange,
+        }
+        .write_to(&mut stream)
+        .unwrap();
+
+        let pointer_event = protocol::C2S::read_from(&mut stream).unwrap();
+        assert_eq!(
+            pointer_event,
+            protocol::C2S::PointerEvent {
+                button_mask: 1,
+                x_position: 0x7fff - 5,
+                y_position: 0x7fff + 12,
+            }
+        );
+    });
+
+    let mut client = connect(stream);
+    assert_eq!(client.pointer_motion_mode(), PointerMotionMode::Absolute);
+    assert!(client.send_relative_pointer(-5, 12, 1).is_err());
+
+    let mut mode = None;
+    let deadline = Instant::now() + TIMEOUT;
+    while mode.is_none() {
+        match next_event(&mut client, deadline) {
+            Event::PointerMotionMode(new_mode) => mode = Some(new_mode),
+            Event::Disconnected(reason) => panic!("disconnected: {}", reason),
+            _ => (),
+        }
+    }
+    assert_eq!(mode, Some(PointerMotionMode::Relative));
+    assert_eq!(client.pointer_motion_mode(), PointerMotionMode::Relative);
+    client.send_relative_pointer(-5, 12, 1).unwrap();
+    run_until_frame(&mut client);
+}
@@ -0,0 +1,60 @@
+mod common;
+
+use common::scripted::{connect, handshake};
+use common::{next_event, run_until_frame, serve, TIMEOUT};
+use std::time::Instant;
+use vnc_client::{Encoding, Event};
+use vnc_proto::protocol::{self, Message};
+
+#[test]
+fn test_extended_key_event() {
+    let stream = serve(|mut stream| {
+        handshake(&mut stream);
+        protocol::C2S::read_from(&mut stream).unwrap(); // SetEncodings
+        protocol::C2S::read_from(&mut stream).unwrap(); // FramebufferUpdateRequest
+
+        // Before the acknowledgement, only the keysym is sent.
+        assert_eq!(
+            protocol::C2S::read_from(&mut stream).unwrap(),
+            protocol::C2S::KeyEvent {
+                down: true,
+                key: 0x61,
+            }
+        );
+        protocol::S2C::FramebufferUpdate { count: 1 }
+            .write_to(&mut stream)
+            .unwrap();
+        protocol::Rectangle {
+            x_position: 0,
+            y_position: 0,
+            width: 0,
+            height: 0,
+            encoding: Encoding::QemuExtendedKeyEvent,
+        }
+        .write_to(&mut stream)
+        .unwrap();
+
+        assert_eq!(
+            protocol::C2S::read_from(&mut stream).unwrap(),
+            protocol::C2S::QemuExtendedKeyEvent {
+                down: false,
+                keysym: 0x61,
+                keycode: 0x10,
+            }
+        );
+    });
+
+    let mut client = connect(stream);
+    client.send_key_event_ext(true, 0x61, 0x10).unwrap();
+
+    let deadline = Instant::now() + TIMEOUT;
+    loop {
+        match next_event(&mut client, deadline) {
+            Event::QemuExtendedKeyEvent => break,
+            Event::Disconnected(reason) => panic!("disconnected: {}", reason),
+            _ => (),
+        }
+    }
+    client.send_key_event_ext(false, 0x61, 0x10).unwrap();
+    run_until_frame(&mut client);
+}
@@ -0,0 +1,64 @@
+mod common;
+
+use common::scripted::handshake;
+use common::TIMEOUT;
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+use std::time::{Duration, Instant};
+use vnc_client::{AuthChoice, Client, Encoding, Event, ReconnectingClient};
+use vnc_proto::protocol::{self, Message};
+
+#[test]
+fn test_reconnect() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let server = thread::spawn(move || {
+        // The first connection is dropped once the encodings are set.
+        let mut stream = listener.accept().unwrap().0;
+        handshake(&mut stream);
+        protocol::C2S::read_from(&mut stream).unwrap();
+        drop(stream);
+
+        let mut stream = listener.accept().unwrap().0;
+        handshake(&mut stream);
+        assert_eq!(
+            protocol::C2S::read_from(&mut stream).unwrap(),
+            protocol::C2S::SetEncodings(vec![Encoding::Zrle, Encoding::Raw])
+        );
+        assert_eq!(
+            protocol::C2S::read_from(&mut stream).unwrap(),
+            protocol::C2S::FramebufferUpdateRequest {
+                incremental: false,
+                x_position: 0,
+                y_position: 0,
+                width: 4,
+                height: 4,
+            }
+        );
+        protocol::S2C::Bell.write_to(&mut stream).unwrap();
+        let _ = protocol::C2S::read_from(&mut stream);
+    });
+
+    let mut client = ReconnectingClient::new(move || {
+        Client::from_tcp_stream(TcpStream::connect(address)?, true, |_| {
+            Some(AuthChoice::None)
+        })
+    })
+    .unwrap();
+    client.set_retry_interval(Duration::from_millis(10));
+    client
+        .set_encodings(&[Encoding::Zrle, Encoding::Raw])
+        .unwrap();
+
+    let mut events = Vec::new();
+    let deadline = Instant::now() + TIMEOUT;
+    while !matches!(events.last(), Some(Event::Bell)) {
+        assert!(Instant::now() < deadline, "no bell after reconnecting");
+        events.extend(client.poll_event());
+        thread::sleep(Duration::from_millis(5));
+    }
+    assert!(matches!(events[..], [Event::Reconnected, Event::Bell]));
+    assert!(client.connected());
+    client.disconnect().unwrap();
+    server.join().unwrap();
+}
@@ -0,0 +1,66 @@
+mod common;
+
+use common::scripted::scripted_server;
+use common::{run_until_frame, serve};
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+use vnc_client::{AuthChoice, Client, Encoding, Rect};
+use vnc_proto::fbs::{self, Block};
+use vnc_proto::protocol::{Message, ServerInit, Version, S2C};
+
+#[derive(Clone, Default)]
+struct Shared(Arc<Mutex<Vec<u8>>>);
+
+impl Write for Shared {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn test_record() {
+    let stream = serve(scripted_server);
+    let mut client = Client::from_tcp_stream(stream, true, |_| Some(AuthChoice::None)).unwrap();
+    let file = Shared::default();
+    client.record(file.clone()).unwrap();
+    client
+        .set_encodings(&[Encoding::Zrle, Encoding::CopyRect, Encoding::Raw])
+        .unwrap();
+    client.request_update(Rect::with_size(4, 4), false).unwrap();
+    run_until_frame(&mut client);
+    client.stop_recording().unwrap();
+    let format = client.format();
+    client.disconnect().unwrap();
+
+    let file = file.0.lock().unwrap().clone();
+    let mut reader = &file[..];
+    fbs::read_magic(&mut reader).unwrap();
+    let handshake = Block::read_from(&mut reader).unwrap();
+    assert_eq!(handshake.timestamp, 0);
+    let mut data = &handshake.data[..];
+    assert_eq!(Version::read_from(&mut data).unwrap(), Version::Rfb33);
+    assert_eq!(&data[..4], b"\0\0\0\x01");
+    let server_init = ServerInit::read_from(&mut &data[4..]).unwrap();
+    assert_eq!(
+        (
+            server_init.framebuffer_width,
+            server_init.framebuffer_height
+        ),
+        (4, 4)
+    );
+    assert_eq!(server_init.pixel_format, format);
+    assert_eq!(server_init.name, "scripted");
+
+    // The update, in one block, and nothing after it.
+    let update = Block::read_from(&mut reader).unwrap();
+    assert!(matches!(
+        S2C::read_from(&mut &update.data[..]).unwrap(),
+        S2C::FramebufferUpdate { count: 3 }
+    ));
+    assert!(reader.is_empty());
+}
@@ -0,0 +1,31 @@
+mod common;
+
+use common::run_until_frame;
+use common::scripted::{expected_pixels, scripted_server};
+use common::serve;
+use std::io::Write;
+use vnc_client::{AuthChoice, Client, Encoding, Rect, RepeaterId};
+use vnc_proto::protocol::Message;
+
+#[test]
+fn test_repeater() {
+    let stream = serve(|mut stream| {
+        stream.write_all(&RepeaterId::GREETING).unwrap();
+        assert_eq!(
+            RepeaterId::read_from(&mut stream).unwrap(),
+            RepeaterId::Server(String::from("10.0.0.2:5901"))
+        );
+        scripted_server(stream)
+    });
+
+    let id = RepeaterId::Server(String::from("10.0.0.2:5901"));
+    let mut client =
+        Client::from_repeater_stream(stream, &id, true, |_| Some(AuthChoice::None)).unwrap();
+    client
+        .set_encodings(&[Encoding::Zrle, Encoding::CopyRect, Encoding::Raw])
+        .unwrap();
+    client.request_update(Rect::with_size(4, 4), false).unwrap();
+    let framebuffer = run_until_frame(&mut client);
+    assert_eq!(framebuffer.pixels(), expected_pixels());
+    client.disconnect().unwrap();
+}
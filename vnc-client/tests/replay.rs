@@ -0,0 +1,42 @@
+mod common;
+
+use common::scripted::{connect, expected_pixels, scripted_server};
+use common::{assert_golden, run_client, run_until_frame, serve, Direction, Recording};
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+
+#[test]
+fn test_record_replay() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let server_stream = serve(scripted_server);
+    let recording = thread::spawn(move || {
+        let client_stream = listener.accept().unwrap().0;
+        Recording::record(client_stream, server_stream)
+    });
+
+    // Hang up once the update has been applied; the server waits for that.
+    let mut client = connect(TcpStream::connect(address).unwrap());
+    let framebuffer = run_until_frame(&mut client);
+    client.disconnect().unwrap();
+    assert_eq!(framebuffer.pixels(), expected_pixels());
+
+    let recording = recording.join().unwrap();
+    assert!(recording
+        .chunks
+        .iter()
+        .any(|chunk| chunk.direction == Direction::ToClient));
+
+    let mut file = Vec::new();
+    recording.write_to(&mut file).unwrap();
+    let recording = Recording::read_from(&mut &file[..]).unwrap();
+
+    // Replaying only needs the client; the server side comes from the recording.
+    let replayed = {
+        let recording = recording.clone();
+        let stream = serve(move |stream| recording.replay(stream).unwrap());
+        run_client(connect(stream))
+    };
+    assert_eq!(replayed.pixels(), expected_pixels());
+    assert_golden(&replayed, "scripted", 0);
+}
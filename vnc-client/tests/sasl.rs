@@ -0,0 +1,98 @@
+#![cfg(feature = "sasl")]
+
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+use vnc_client::{AuthChoice, AuthMethod, Client, PixelFormat, SecurityType};
+use vnc_proto::protocol::{self, Message};
+
+const FORMAT: PixelFormat = PixelFormat {
+    bits_per_pixel: 32,
+    depth: 24,
+    big_endian: false,
+    true_colour: true,
+    red_max: 255,
+    green_max: 255,
+    blue_max: 255,
+    red_shift: 16,
+    green_shift: 8,
+    blue_shift: 0,
+};
+
+fn read_data(stream: &mut TcpStream) -> Vec<u8> {
+    let mut data = vec![0; stream.read_u32::<BigEndian>().unwrap() as usize];
+    stream.read_exact(&mut data).unwrap();
+    data
+}
+
+/// Plays the server up to offering `mechanisms`.
+fn offer(stream: &mut TcpStream, mechanisms: &str) {
+    protocol::Version::Rfb38.write_to(stream).unwrap();
+    protocol::Version::read_from(stream).unwrap();
+    protocol::SecurityTypes(vec![SecurityType::Sasl])
+        .write_to(stream)
+        .unwrap();
+    assert_eq!(SecurityType::read_from(stream).unwrap(), SecurityType::Sasl);
+    stream
+        .write_u32::<BigEndian>(mechanisms.len() as u32)
+        .unwrap();
+    stream.write_all(mechanisms.as_bytes()).unwrap();
+}
+
+fn connect<F>(script: F) -> (vnc_client::Result<Client>, Vec<String>)
+where
+    F: FnOnce(TcpStream) + Send + 'static,
+{
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let server = thread::spawn(move || script(listener.accept().unwrap().0));
+    let mut offered = Vec::new();
+    let client = Client::from_tcp_stream(TcpStream::connect(address).unwrap(), true, |methods| {
+        offered = methods
+            .iter()
+            .map(|method| format!("{:?}", method))
+            .collect();
+        Some(AuthChoice::Sasl(
+            String::from("user"),
+            String::from("secret"),
+        ))
+    });
+    server.join().unwrap();
+    (client, offered)
+}
+
+#[test]
+fn test_sasl_plain() {
+    let (client, offered) = connect(|mut stream| {
+        offer(&mut stream, "GSSAPI,PLAIN");
+        assert_eq!(read_data(&mut stream), b"PLAIN");
+        assert_eq!(read_data(&mut stream), b"\0user\0secret\0");
+        stream.write_all(&[0, 0, 0, 0, 1]).unwrap();
+        protocol::SecurityResult::Succeeded
+            .write_to(&mut stream)
+            .unwrap();
+        protocol::ClientInit::read_from(&mut stream).unwrap();
+        protocol::ServerInit {
+            framebuffer_width: 4,
+            framebuffer_height: 4,
+            pixel_format: FORMAT,
+            name: String::from("sasl"),
+        }
+        .write_to(&mut stream)
+        .unwrap();
+    });
+    assert_eq!(offered, [format!("{:?}", AuthMethod::Sasl)]);
+    let client = client.unwrap();
+    assert_eq!(client.security_type(), SecurityType::Sasl);
+    assert_eq!(client.name(), "sasl");
+}
+
+#[test]
+fn test_sasl_without_plain() {
+    let (client, _) = connect(|mut stream| offer(&mut stream, "GSSAPI"));
+    assert!(matches!(
+        client,
+        Err(vnc_client::Error::AuthenticationUnavailable)
+    ));
+}
@@ -0,0 +1,38 @@
+mod common;
+
+use common::scripted::{connect, expected_pixels, scripted_server};
+use common::{run_until_frame, serve};
+
+#[test]
+fn test_screenshot() {
+    let mut client = connect(serve(scripted_server));
+    assert!(client.screenshot().is_none());
+    client.enable_framebuffer();
+    run_until_frame(&mut client);
+
+    let screenshot = client.screenshot().unwrap();
+    assert_eq!((screenshot.width, screenshot.height), (4, 4));
+    // The scripted pixels are 0x00RRGGBB, little-endian.
+    let rgba: Vec<u8> = expected_pixels()
+        .chunks(4)
+        .flat_map(|pixel| [pixel[2], pixel[1], pixel[0], 0xff])
+        .collect();
+    assert_eq!(screenshot.rgba, rgba);
+
+    let mut ppm = Vec::new();
+    screenshot.write_ppm(&mut ppm).unwrap();
+    assert!(ppm.starts_with(b"P6\n4 4\n255\n\x11\x22\x33\x11\x22\x33\x02\x02\x02"));
+    assert_eq!(ppm.len(), 11 + 4 * 4 * 3);
+
+    #[cfg(feature = "image")]
+    {
+        let mut png = Vec::new();
+        screenshot.write_png(&mut png).unwrap();
+        let rgb: Vec<u8> = rgba
+            .chunks(4)
+            .flat_map(|pixel| [pixel[0], pixel[1], pixel[2]])
+            .collect();
+        assert_eq!(vnc_proto::png::decode(&png, 4, 4).unwrap(), rgb);
+    }
+    client.disconnect().unwrap();
+}
@@ -0,0 +1,35 @@
+mod common;
+
+use common::scripted::{connect, expected_pixels, scripted_server};
+use common::shaped::{relay, Conditions};
+use common::{run_until_frame, serve};
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+use std::time::Duration;
+
+#[test]
+fn test_slow_fragmented_link() {
+    let conditions = Conditions {
+        bandwidth: Some(64 * 1024),
+        latency: Duration::from_millis(1),
+        jitter: Duration::from_millis(2),
+        stall_probability: 0.05,
+        stall: Duration::from_millis(20),
+        segment_size: 5,
+        seed: 42,
+    };
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let server_stream = serve(scripted_server);
+    let relay = thread::spawn(move || {
+        let client_stream = listener.accept().unwrap().0;
+        relay(client_stream, server_stream, conditions)
+    });
+
+    let mut client = connect(TcpStream::connect(address).unwrap());
+    let framebuffer = run_until_frame(&mut client);
+    client.disconnect().unwrap();
+    relay.join().unwrap();
+    assert_eq!(framebuffer.pixels(), expected_pixels());
+}
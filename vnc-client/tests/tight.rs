@@ -0,0 +1,131 @@
+use byteorder::{BigEndian, ReadBytesExt};
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+use vnc_client::{AuthChoice, AuthMethod, Client, PixelFormat, SecurityType, ServerKind};
+use vnc_proto::protocol::{self, Message, TightCapabilities, TightCapability, TightInteraction};
+
+const FORMAT: PixelFormat = PixelFormat {
+    bits_per_pixel: 32,
+    depth: 24,
+    big_endian: false,
+    true_colour: true,
+    red_max: 255,
+    green_max: 255,
+    blue_max: 255,
+    red_shift: 16,
+    green_shift: 8,
+    blue_shift: 0,
+};
+
+fn capability(code: i32, vendor: &[u8; 4], name: &[u8; 8]) -> TightCapability {
+    TightCapability {
+        code,
+        vendor: *vendor,
+        name: *name,
+    }
+}
+
+fn interaction() -> TightInteraction {
+    TightInteraction {
+        server_messages: vec![capability(130, b"TGHT", b"FTS_LSDT")],
+        client_messages: vec![],
+        encodings: vec![capability(7, b"TGHT", b"TIGHT___")],
+    }
+}
+
+/// Plays the server up to offering `auth_types` within the Tight security type.
+fn offer(stream: &mut TcpStream, version: protocol::Version, auth_types: Vec<TightCapability>) {
+    version.write_to(stream).unwrap();
+    protocol::Version::read_from(stream).unwrap();
+    protocol::SecurityTypes(vec![SecurityType::VncAuthentication, SecurityType::Tight])
+        .write_to(stream)
+        .unwrap();
+    assert_eq!(
+        SecurityType::read_from(stream).unwrap(),
+        SecurityType::Tight
+    );
+    TightCapabilities(vec![capability(0, b"TGHT", b"NOTUNNEL")])
+        .write_to(stream)
+        .unwrap();
+    assert_eq!(stream.read_i32::<BigEndian>().unwrap(), 0);
+    TightCapabilities(auth_types).write_to(stream).unwrap();
+}
+
+fn init(stream: &mut TcpStream) {
+    protocol::ClientInit::read_from(stream).unwrap();
+    protocol::ServerInit {
+        framebuffer_width: 4,
+        framebuffer_height: 4,
+        pixel_format: FORMAT,
+        name: String::from("tight"),
+    }
+    .write_to(stream)
+    .unwrap();
+    interaction().write_to(stream).unwrap();
+}
+
+fn connect<F>(script: F, auth: AuthChoice) -> (vnc_client::Result<Client>, Vec<String>)
+where
+    F: FnOnce(TcpStream) + Send + 'static,
+{
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let server = thread::spawn(move || script(listener.accept().unwrap().0));
+    let mut offered = Vec::new();
+    let client = Client::from_tcp_stream(TcpStream::connect(address).unwrap(), true, |methods| {
+        offered = methods
+            .iter()
+            .map(|method| format!("{:?}", method))
+            .collect();
+        Some(auth)
+    });
+    server.join().unwrap();
+    (client, offered)
+}
+
+#[test]
+fn test_tight_vnc_auth() {
+    let (client, offered) = connect(
+        |mut stream| {
+            offer(
+                &mut stream,
+                protocol::Version::Rfb38,
+                vec![
+                    capability(2, b"STDV", b"VNCAUTH_"),
+                    capability(129, b"TGHT", b"ULGNAUTH"),
+                ],
+            );
+            assert_eq!(stream.read_i32::<BigEndian>().unwrap(), 2);
+            stream.write_all(&[0; 16]).unwrap();
+            stream.read_exact(&mut [0; 16]).unwrap();
+            protocol::SecurityResult::Succeeded
+                .write_to(&mut stream)
+                .unwrap();
+            init(&mut stream);
+        },
+        AuthChoice::Password(*b"password"),
+    );
+    // The Unix login scheme is not offered to the caller.
+    assert_eq!(offered, [format!("{:?}", AuthMethod::Password)]);
+    let client = client.unwrap();
+    assert_eq!(client.security_type(), SecurityType::Tight);
+    assert_eq!(client.server_kind(), ServerKind::TightVnc);
+    assert_eq!(client.tight_interaction(), Some(&interaction()));
+    assert_eq!(client.name(), "tight");
+}
+
+#[test]
+fn test_tight_no_auth_rfb37() {
+    let (client, offered) = connect(
+        |mut stream| {
+            // No authentication schemes at all, and no SecurityResult before 3.8.
+            offer(&mut stream, protocol::Version::Rfb37, vec![]);
+            init(&mut stream);
+        },
+        AuthChoice::None,
+    );
+    assert_eq!(offered, [format!("{:?}", AuthMethod::None)]);
+    let client = client.unwrap();
+    assert_eq!(client.tight_interaction(), Some(&interaction()));
+}
@@ -0,0 +1,41 @@
+mod common;
+
+use common::scripted::handshake;
+use common::serve;
+use std::io::Write;
+use std::sync::mpsc::channel;
+use std::time::Duration;
+use vnc_client::{AuthChoice, Client, DisconnectReason, Event};
+use vnc_proto::protocol::{self, Message};
+
+#[test]
+fn test_read_timeout() {
+    let (tx_go, rx_go) = channel();
+    let stream = serve(move |mut stream| {
+        handshake(&mut stream);
+        // Stall between messages, then in the middle of one.
+        rx_go.recv().unwrap();
+        protocol::S2C::Bell.write_to(&mut stream).unwrap();
+        stream.write_all(&[0, 0]).unwrap();
+        let _ = rx_go.recv();
+    });
+    let mut client = Client::from_tcp_stream(stream, true, |_| Some(AuthChoice::None)).unwrap();
+    client
+        .set_read_timeout(Some(Duration::from_millis(50)))
+        .unwrap();
+    let (_client, mut events) = client.split();
+
+    assert!(matches!(events.wait_event(), Some(Event::Timeout)));
+    tx_go.send(()).unwrap();
+    loop {
+        match events.wait_event() {
+            Some(Event::Timeout) => (),
+            Some(Event::Bell) => break,
+            event => panic!("unexpected {:?}", event),
+        }
+    }
+    assert!(matches!(
+        events.wait_event(),
+        Some(Event::Disconnected(DisconnectReason::Timeout))
+    ));
+}
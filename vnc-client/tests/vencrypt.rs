@@ -0,0 +1,147 @@
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+use vnc_client::{AuthChoice, AuthMethod, Client, PixelFormat, SecurityType};
+use vnc_proto::protocol::{self, Message, VeNCryptPlain, VeNCryptSubType, VeNCryptVersion};
+
+const FORMAT: PixelFormat = PixelFormat {
+    bits_per_pixel: 32,
+    depth: 24,
+    big_endian: false,
+    true_colour: true,
+    red_max: 255,
+    green_max: 255,
+    blue_max: 255,
+    red_shift: 16,
+    green_shift: 8,
+    blue_shift: 0,
+};
+
+/// Plays the server up to offering `sub_types`.
+fn offer(stream: &mut TcpStream, sub_types: Vec<VeNCryptSubType>) {
+    protocol::Version::Rfb38.write_to(stream).unwrap();
+    protocol::Version::read_from(stream).unwrap();
+    protocol::SecurityTypes(vec![SecurityType::VeNCrypt])
+        .write_to(stream)
+        .unwrap();
+    assert_eq!(
+        SecurityType::read_from(stream).unwrap(),
+        SecurityType::VeNCrypt
+    );
+    VeNCryptVersion::V0_2.write_to(stream).unwrap();
+    assert_eq!(
+        VeNCryptVersion::read_from(stream).unwrap(),
+        VeNCryptVersion::V0_2
+    );
+    stream.write_all(&[0]).unwrap();
+    protocol::VeNCryptSubTypes(sub_types)
+        .write_to(stream)
+        .unwrap();
+}
+
+fn connect<F>(script: F, auth: AuthChoice) -> (vnc_client::Result<Client>, Vec<String>)
+where
+    F: FnOnce(TcpStream) + Send + 'static,
+{
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let server = thread::spawn(move || script(listener.accept().unwrap().0));
+    let mut offered = Vec::new();
+    let client = Client::from_tcp_stream(TcpStream::connect(address).unwrap(), true, |methods| {
+        offered = methods
+            .iter()
+            .map(|method| format!("{:?}", method))
+            .collect();
+        Some(auth)
+    });
+    server.join().unwrap();
+    (client, offered)
+}
+
+#[test]
+fn test_vencrypt_vnc_auth() {
+    let (client, offered) = connect(
+        |mut stream| {
+            offer(
+                &mut stream,
+                vec![VeNCryptSubType::X509Vnc, VeNCryptSubType::VncAuthentication],
+            );
+            assert_eq!(
+                VeNCryptSubType::read_from(&mut stream).unwrap(),
+                VeNCryptSubType::VncAuthentication
+            );
+            stream.write_all(&[0; 16]).unwrap();
+            stream.read_exact(&mut [0; 16]).unwrap();
+            protocol::SecurityResult::Succeeded
+                .write_to(&mut stream)
+                .unwrap();
+            protocol::ClientInit::read_from(&mut stream).unwrap();
+            protocol::ServerInit {
+                framebuffer_width: 4,
+                framebuffer_height: 4,
+                pixel_format: FORMAT,
+                name: String::from("vencrypt"),
+            }
+            .write_to(&mut stream)
+            .unwrap();
+        },
+        AuthChoice::Password(*b"password"),
+    );
+    // The TLS sub-type is not offered to the caller.
+    assert_eq!(offered, [format!("{:?}", AuthMethod::Password)]);
+    let client = client.unwrap();
+    assert_eq!(client.security_type(), SecurityType::VeNCrypt);
+    assert_eq!(client.name(), "vencrypt");
+}
+
+#[test]
+fn test_vencrypt_plain() {
+    let (client, offered) = connect(
+        |mut stream| {
+            offer(
+                &mut stream,
+                vec![VeNCryptSubType::TlsPlain, VeNCryptSubType::Plain],
+            );
+            assert_eq!(
+                VeNCryptSubType::read_from(&mut stream).unwrap(),
+                VeNCryptSubType::Plain
+            );
+            assert_eq!(
+                VeNCryptPlain::read_from(&mut stream).unwrap(),
+                VeNCryptPlain {
+                    username: String::from("libvirt"),
+                    password: String::from("s\u{e9}same"),
+                }
+            );
+            protocol::SecurityResult::Succeeded
+                .write_to(&mut stream)
+                .unwrap();
+            protocol::ClientInit::read_from(&mut stream).unwrap();
+            protocol::ServerInit {
+                framebuffer_width: 4,
+                framebuffer_height: 4,
+                pixel_format: FORMAT,
+                name: String::from("plain"),
+            }
+            .write_to(&mut stream)
+            .unwrap();
+        },
+        AuthChoice::Plain(String::from("libvirt"), String::from("s\u{e9}same")),
+    );
+    assert_eq!(offered, [format!("{:?}", AuthMethod::Plain)]);
+    let client = client.unwrap();
+    assert_eq!(client.security_type(), SecurityType::VeNCrypt);
+    assert_eq!(client.name(), "plain");
+}
+
+#[test]
+fn test_vencrypt_tls_only() {
+    let (client, offered) = connect(
+        |mut stream| {
+            offer(&mut stream, vec![VeNCryptSubType::X509Plain]);
+        },
+        AuthChoice::None,
+    );
+    assert!(offered.is_empty());
+    assert!(client.is_err());
+}
@@ -21,6 +21,9 @@
 
 #![allow(dead_code)]
 
+use alloc::vec;
+use alloc::vec::Vec;
+
 pub type Key = [u8; 8];
 
 const FIRST_BIT: u64 = 1 << 63;
@@ -108,6 +111,28 @@ pub fn encrypt(message: &[u8], key: &Key) -> Vec<u8> {
     des(message, subkeys)
 }
 
+/// The response to the `challenge` of VNC authentication with `password`.
+pub fn vnc_auth_response(challenge: &[u8], password: &Key) -> Vec<u8> {
+    // Reverse the bits in every byte of password.
+    // DES is 56-bit and as commonly implemented, it takes a 8-octet key
+    // and ignores LSB of every octet; this of course would be bad for
+    // ASCII passwords.
+    //
+    // I've spent *hours* figuring this out.
+    // I hate every single fucker involved in the chain of decisions that
+    // led to this authentication scheme, and doubly so because it is completely
+    // undocumented in what passes for the specification of the RFB protocol.
+    let mut key = *password;
+    for c in &mut key {
+        let mut cs = 0u8;
+        for j in 0..8 {
+            cs |= ((*c >> j) & 1) << (7 - j)
+        }
+        *c = cs;
+    }
+    encrypt(challenge, &key)
+}
+
 /// Feistel function.
 fn feistel(half_block: u64, subkey: u64) -> u64 {
     let expanded = e(half_block);
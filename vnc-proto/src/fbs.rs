@@ -0,0 +1,111 @@
+//! The FBS 001.000 format of rfbproxy and vncrec recordings: after `MAGIC`,
+//! what the server sent, in blocks stamped with the milliseconds since the
+//! recording started. The data starts with an RFB 3.3 handshake without
+//! authentication, which is all a player needs to know about the session.
+
+use crate::io::{BigEndian, Read, ReadBytesExt, Write, WriteBytesExt};
+use crate::protocol::{Message, ServerInit, Version};
+use crate::{Error, Result};
+use alloc::vec::Vec;
+
+pub const MAGIC: &[u8; 12] = b"FBS 001.000\n";
+
+/// The security type `None` as an RFB 3.3 server announces it.
+const SECURITY_NONE: u32 = 1;
+
+pub fn read_magic<R: Read>(reader: &mut R) -> Result<()> {
+    let mut magic = [0; 12];
+    reader.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(Error::Unexpected("FBS magic"));
+    }
+    Ok(())
+}
+
+/// The bytes a recording starts with: the server's side of an RFB 3.3
+/// handshake ending in `server_init`.
+pub fn handshake(server_init: &ServerInit) -> Result<Vec<u8>> {
+    let mut data = Vec::new();
+    Version::Rfb33.write_to(&mut data)?;
+    data.write_u32::<BigEndian>(SECURITY_NONE)?;
+    server_init.write_to(&mut data)?;
+    Ok(data)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Block {
+    pub data: Vec<u8>,
+    /// Milliseconds since the start of the recording.
+    pub timestamp: u32,
+}
+
+impl Message for Block {
+    fn read_from<R: Read>(reader: &mut R) -> Result<Block> {
+        let data = Vec::<u8>::read_from(reader)?;
+        // The data is padded to a multiple of 4 bytes.
+        reader.read_exact(&mut [0; 3][..(4 - data.len() % 4) % 4])?;
+        Ok(Block {
+            data,
+            timestamp: reader.read_u32::<BigEndian>()?,
+        })
+    }
+
+    fn write_to<W: Write>(&self, writer: &mut W) -> Result<()> {
+        self.data.write_to(writer)?;
+        writer.write_all(&[0; 3][..(4 - self.data.len() % 4) % 4])?;
+        writer.write_u32::<BigEndian>(self.timestamp)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{handshake, read_magic, Block, MAGIC};
+    use crate::protocol::{Message, PixelFormat, ServerInit};
+    use alloc::string::String;
+    use alloc::vec::Vec;
+
+    #[test]
+    fn test_block() {
+        let block = Block {
+            data: Vec::from(&b"hello"[..]),
+            timestamp: 0x0102,
+        };
+        let mut file = Vec::from(&MAGIC[..]);
+        block.write_to(&mut file).unwrap();
+        assert_eq!(&file[12..], b"\0\0\0\x05hello\0\0\0\0\0\x01\x02");
+
+        let mut reader = &file[..];
+        read_magic(&mut reader).unwrap();
+        assert_eq!(Block::read_from(&mut reader).unwrap(), block);
+        assert!(reader.is_empty());
+        assert!(read_magic(&mut &b"FBS 002.000\n"[..]).is_err());
+    }
+
+    #[test]
+    fn test_handshake() {
+        let server_init = ServerInit {
+            framebuffer_width: 640,
+            framebuffer_height: 480,
+            pixel_format: PixelFormat {
+                bits_per_pixel: 8,
+                depth: 8,
+                big_endian: false,
+                true_colour: false,
+                red_max: 0,
+                green_max: 0,
+                blue_max: 0,
+                red_shift: 0,
+                green_shift: 0,
+                blue_shift: 0,
+            },
+            name: String::from("fbs"),
+        };
+        let data = handshake(&server_init).unwrap();
+        assert_eq!(&data[..16], b"RFB 003.003\n\0\0\0\x01");
+        assert_eq!(
+            ServerInit::read_from(&mut &data[16..]).unwrap(),
+            server_init
+        );
+    }
+}
@@ -0,0 +1,593 @@
+//! The General Input Interface (gii) extension, which lets a client create
+//! input devices on the server, such as tablets with pressure and tilt
+//! axes, and inject their events.
+//!
+//! Both directions carry an endianness bit in every message. Messages are
+//! always written big-endian, and read in whichever the peer chose.
+
+use crate::io::{Read, Write, WriteBytesExt};
+use crate::{Error, Result};
+use alloc::string::String;
+use alloc::vec::Vec;
+
+// Sub-types of the messages in either direction.
+const INJECT_EVENTS: u8 = 0;
+const VERSION: u8 = 1;
+const DEVICE_CREATION: u8 = 2;
+const DEVICE_DESTRUCTION: u8 = 3;
+
+const BIG_ENDIAN: u8 = 0x80;
+
+// Event types; the bits of `Device::can_generate` are `1 << type`.
+const KEY_PRESS: u8 = 5;
+const KEY_RELEASE: u8 = 6;
+const KEY_REPEAT: u8 = 7;
+const POINTER_RELATIVE: u8 = 8;
+const POINTER_ABSOLUTE: u8 = 9;
+const BUTTON_PRESS: u8 = 10;
+const BUTTON_RELEASE: u8 = 11;
+const VALUATOR_RELATIVE: u8 = 12;
+const VALUATOR_ABSOLUTE: u8 = 13;
+
+pub const KEY_PRESS_MASK: u32 = 1 << KEY_PRESS;
+pub const KEY_RELEASE_MASK: u32 = 1 << KEY_RELEASE;
+pub const KEY_REPEAT_MASK: u32 = 1 << KEY_REPEAT;
+pub const POINTER_RELATIVE_MASK: u32 = 1 << POINTER_RELATIVE;
+pub const POINTER_ABSOLUTE_MASK: u32 = 1 << POINTER_ABSOLUTE;
+pub const BUTTON_PRESS_MASK: u32 = 1 << BUTTON_PRESS;
+pub const BUTTON_RELEASE_MASK: u32 = 1 << BUTTON_RELEASE;
+pub const VALUATOR_RELATIVE_MASK: u32 = 1 << VALUATOR_RELATIVE;
+pub const VALUATOR_ABSOLUTE_MASK: u32 = 1 << VALUATOR_ABSOLUTE;
+
+/// The only version of the extension there is.
+pub const VERSION_1: u16 = 1;
+
+/// The most values a single valuator event can carry.
+pub const MAX_VALUATOR_VALUES: usize = (u8::MAX as usize - 16) / 4;
+
+/// One axis of a device, e.g. the pressure of a stylus.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Valuator {
+    pub index: u32,
+    /// Up to 74 bytes.
+    pub long_name: String,
+    /// Up to 4 bytes.
+    pub short_name: String,
+    pub range_min: i32,
+    pub range_center: i32,
+    pub range_max: i32,
+    /// The SI unit of the axis: 0 for none, then time, length, mass,
+    /// current, temperature, amount of substance, luminous intensity,
+    /// angle and solid angle.
+    pub si_unit: u32,
+    /// How to get from a value to the unit: add `si_add`, multiply by
+    /// `si_mul`, divide by `si_div`, and shift left by `si_shift`.
+    pub si_add: i32,
+    pub si_mul: i32,
+    pub si_div: i32,
+    pub si_shift: i32,
+}
+
+/// A device to create on the server.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Device {
+    /// Up to 31 bytes.
+    pub name: String,
+    pub vendor_id: u32,
+    pub product_id: u32,
+    /// The events the device sends, as a combination of the `_MASK`
+    /// constants.
+    pub can_generate: u32,
+    pub num_registers: u32,
+    pub num_buttons: u32,
+    pub valuators: Vec<Valuator>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyAction {
+    Press,
+    Release,
+    Repeat,
+}
+
+/// An input event of the device created as `origin`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InjectEvent {
+    Key {
+        origin: u32,
+        action: KeyAction,
+        modifiers: u32,
+        symbol: u32,
+        label: u32,
+        button: u32,
+    },
+    PointerMove {
+        origin: u32,
+        relative: bool,
+        x: i32,
+        y: i32,
+        z: i32,
+        wheel: i32,
+    },
+    Button {
+        origin: u32,
+        down: bool,
+        button: u32,
+    },
+    /// New values of the valuators from index `first` on.
+    Valuators {
+        origin: u32,
+        relative: bool,
+        first: u32,
+        values: Vec<i32>,
+    },
+}
+
+/// A gii message from the client, following message type 253.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ClientMessage {
+    InjectEvents(Vec<InjectEvent>),
+    /// The version the client picked from the range the server offered.
+    Version(u16),
+    CreateDevice(Device),
+    /// Removes the device created as this origin.
+    DestroyDevice(u32),
+}
+
+/// A gii message from the server, following message type 253.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ServerMessage {
+    /// Sent in answer to `Encoding::Gii`, offering a range of versions.
+    Version { maximum: u16, minimum: u16 },
+    /// The origin of the device created by the last `CreateDevice`, or 0 if
+    /// the server refused it.
+    DeviceCreated(u32),
+}
+
+/// The body of a message, read in the endianness its header announced.
+struct Body<'a> {
+    data: &'a [u8],
+    big_endian: bool,
+}
+
+impl<'a> Body<'a> {
+    fn take(&mut self, length: usize) -> Result<&'a [u8]> {
+        if length > self.data.len() {
+            return Err(Error::Unexpected("end of gii message"));
+        }
+        let (bytes, rest) = self.data.split_at(length);
+        self.data = rest;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        let bytes = self.take(2)?;
+        let bytes = [bytes[0], bytes[1]];
+        Ok(match self.big_endian {
+            true => u16::from_be_bytes(bytes),
+            false => u16::from_le_bytes(bytes),
+        })
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        let bytes = self.take(4)?;
+        let bytes = [bytes[0], bytes[1], bytes[2], bytes[3]];
+        Ok(match self.big_endian {
+            true => u32::from_be_bytes(bytes),
+            false => u32::from_le_bytes(bytes),
+        })
+    }
+
+    fn i32(&mut self) -> Result<i32> {
+        Ok(self.u32()? as i32)
+    }
+
+    /// Reads a NUL-padded string of `length` bytes, the last of which is
+    /// always NUL.
+    fn string(&mut self, length: usize) -> Result<String> {
+        let bytes = self.take(length)?;
+        let end = bytes.iter().position(|&byte| byte == 0).unwrap_or(length);
+        Ok(String::from_utf8_lossy(&bytes[..end]).into_owned())
+    }
+
+    fn finish(self) -> Result<()> {
+        match self.data.is_empty() {
+            true => Ok(()),
+            false => Err(Error::Unexpected("gii message length")),
+        }
+    }
+}
+
+fn read_body<R: Read>(reader: &mut R, data: &mut Vec<u8>) -> Result<(u8, bool)> {
+    let mut header = [0; 3];
+    reader.read_exact(&mut header)?;
+    let big_endian = header[0] & BIG_ENDIAN != 0;
+    let length = match big_endian {
+        true => u16::from_be_bytes([header[1], header[2]]),
+        false => u16::from_le_bytes([header[1], header[2]]),
+    };
+    data.resize(length as usize, 0);
+    reader.read_exact(data)?;
+    Ok((header[0] & !BIG_ENDIAN, big_endian))
+}
+
+fn write_message<W: Write>(writer: &mut W, sub_type: u8, body: &[u8]) -> Result<()> {
+    if body.len() > u16::MAX as usize {
+        return Err(Error::Unexpected("gii message longer than 65535 bytes"));
+    }
+    writer.write_u8(sub_type | BIG_ENDIAN)?;
+    writer.write_all(&(body.len() as u16).to_be_bytes())?;
+    writer.write_all(body)?;
+    Ok(())
+}
+
+fn put_u32(body: &mut Vec<u8>, value: u32) {
+    body.extend_from_slice(&value.to_be_bytes())
+}
+
+fn put_string(body: &mut Vec<u8>, string: &str, length: usize) -> Result<()> {
+    if string.len() >= length {
+        return Err(Error::Unexpected("gii name too long"));
+    }
+    body.extend_from_slice(string.as_bytes());
+    body.resize(body.len() + length - string.len(), 0);
+    Ok(())
+}
+
+impl crate::protocol::Message for ClientMessage {
+    fn read_from<R: Read>(reader: &mut R) -> Result<ClientMessage> {
+        let mut data = Vec::new();
+        let (sub_type, big_endian) = read_body(reader, &mut data)?;
+        let mut body = Body {
+            data: &data,
+            big_endian,
+        };
+        let message = match sub_type {
+            INJECT_EVENTS => {
+                let mut events = Vec::new();
+                while !body.data.is_empty() {
+                    events.push(read_event(&mut body)?);
+                }
+                ClientMessage::InjectEvents(events)
+            }
+            VERSION => ClientMessage::Version(body.u16()?),
+            DEVICE_CREATION => {
+                let name = body.string(32)?;
+                let vendor_id = body.u32()?;
+                let product_id = body.u32()?;
+                let can_generate = body.u32()?;
+                let num_registers = body.u32()?;
+                let num_valuators = body.u32()?;
+                let num_buttons = body.u32()?;
+                let mut valuators = Vec::new();
+                for _ in 0..num_valuators {
+                    valuators.push(Valuator {
+                        index: body.u32()?,
+                        long_name: body.string(75)?,
+                        short_name: body.string(5)?,
+                        range_min: body.i32()?,
+                        range_center: body.i32()?,
+                        range_max: body.i32()?,
+                        si_unit: body.u32()?,
+                        si_add: body.i32()?,
+                        si_mul: body.i32()?,
+                        si_div: body.i32()?,
+                        si_shift: body.i32()?,
+                    });
+                }
+                ClientMessage::CreateDevice(Device {
+                    name,
+                    vendor_id,
+                    product_id,
+                    can_generate,
+                    num_registers,
+                    num_buttons,
+                    valuators,
+                })
+            }
+            DEVICE_DESTRUCTION => ClientMessage::DestroyDevice(body.u32()?),
+            _ => return Err(Error::Unexpected("gii message type")),
+        };
+        body.finish()?;
+        Ok(message)
+    }
+
+    fn write_to<W: Write>(&self, writer: &mut W) -> Result<()> {
+        let mut body = Vec::new();
+        let sub_type = match self {
+            ClientMessage::InjectEvents(ref events) => {
+                for event in events {
+                    write_event(&mut body, event)?;
+                }
+                INJECT_EVENTS
+            }
+            ClientMessage::Version(version) => {
+                body.extend_from_slice(&version.to_be_bytes());
+                VERSION
+            }
+            ClientMessage::CreateDevice(ref device) => {
+                put_string(&mut body, &device.name, 32)?;
+                put_u32(&mut body, device.vendor_id);
+                put_u32(&mut body, device.product_id);
+                put_u32(&mut body, device.can_generate);
+                put_u32(&mut body, device.num_registers);
+                put_u32(&mut body, device.valuators.len() as u32);
+                put_u32(&mut body, device.num_buttons);
+                for valuator in &device.valuators {
+                    put_u32(&mut body, valuator.index);
+                    put_string(&mut body, &valuator.long_name, 75)?;
+                    put_string(&mut body, &valuator.short_name, 5)?;
+                    for value in [
+                        valuator.range_min,
+                        valuator.range_center,
+                        valuator.range_max,
+                        valuator.si_unit as i32,
+                        valuator.si_add,
+                        valuator.si_mul,
+                        valuator.si_div,
+                        valuator.si_shift,
+                    ] {
+                        put_u32(&mut body, value as u32);
+                    }
+                }
+                DEVICE_CREATION
+            }
+            ClientMessage::DestroyDevice(origin) => {
+                put_u32(&mut body, *origin);
+                DEVICE_DESTRUCTION
+            }
+        };
+        write_message(writer, sub_type, &body)
+    }
+}
+
+fn read_event(body: &mut Body) -> Result<InjectEvent> {
+    let size = body.u8()? as usize;
+    let event_type = body.u8()?;
+    body.take(2)?;
+    // Valuator events have as many values as the size leaves room for, which
+    // has to agree with the count they give.
+    let fixed_size = match event_type {
+        KEY_PRESS..=POINTER_ABSOLUTE => 24,
+        BUTTON_PRESS | BUTTON_RELEASE => 12,
+        VALUATOR_RELATIVE | VALUATOR_ABSOLUTE => size,
+        _ => return Err(Error::Unexpected("gii event type")),
+    };
+    if size != fixed_size || size < 12 {
+        return Err(Error::Unexpected("gii event size"));
+    }
+    let origin = body.u32()?;
+    Ok(match event_type {
+        KEY_PRESS..=KEY_REPEAT => InjectEvent::Key {
+            origin,
+            action: match event_type {
+                KEY_PRESS => KeyAction::Press,
+                KEY_RELEASE => KeyAction::Release,
+                _ => KeyAction::Repeat,
+            },
+            modifiers: body.u32()?,
+            symbol: body.u32()?,
+            label: body.u32()?,
+            button: body.u32()?,
+        },
+        POINTER_RELATIVE | POINTER_ABSOLUTE => InjectEvent::PointerMove {
+            origin,
+            relative: event_type == POINTER_RELATIVE,
+            x: body.i32()?,
+            y: body.i32()?,
+            z: body.i32()?,
+            wheel: body.i32()?,
+        },
+        BUTTON_PRESS | BUTTON_RELEASE => InjectEvent::Button {
+            origin,
+            down: event_type == BUTTON_PRESS,
+            button: body.u32()?,
+        },
+        _ => {
+            let first = body.u32()?;
+            let count = body.u32()? as usize;
+            if size != 16 + 4 * count {
+                return Err(Error::Unexpected("gii event size"));
+            }
+            let mut values = Vec::new();
+            for _ in 0..count {
+                values.push(body.i32()?);
+            }
+            InjectEvent::Valuators {
+                origin,
+                relative: event_type == VALUATOR_RELATIVE,
+                first,
+                values,
+            }
+        }
+    })
+}
+
+fn write_event(body: &mut Vec<u8>, event: &InjectEvent) -> Result<()> {
+    let (event_type, origin, fields): (u8, u32, Vec<u32>) = match event {
+        InjectEvent::Key {
+            origin,
+            action,
+            modifiers,
+            symbol,
+            label,
+            button,
+        } => {
+            let event_type = match action {
+                KeyAction::Press => KEY_PRESS,
+                KeyAction::Release => KEY_RELEASE,
+                KeyAction::Repeat => KEY_REPEAT,
+            };
+            (
+                event_type,
+                *origin,
+                [*modifiers, *symbol, *label, *button].to_vec(),
+            )
+        }
+        InjectEvent::PointerMove {
+            origin,
+            relative,
+            x,
+            y,
+            z,
+            wheel,
+        } => {
+            let event_type = match relative {
+                true => POINTER_RELATIVE,
+                false => POINTER_ABSOLUTE,
+            };
+            let fields = [*x, *y, *z, *wheel].map(|value| value as u32);
+            (event_type, *origin, fields.to_vec())
+        }
+        InjectEvent::Button {
+            origin,
+            down,
+            button,
+        } => {
+            let event_type = match down {
+                true => BUTTON_PRESS,
+                false => BUTTON_RELEASE,
+            };
+            (event_type, *origin, [*button].to_vec())
+        }
+        InjectEvent::Valuators {
+            origin,
+            relative,
+            first,
+            ref values,
+        } => {
+            if values.len() > MAX_VALUATOR_VALUES {
+                return Err(Error::Unexpected("too many values in a gii event"));
+            }
+            let event_type = match relative {
+                true => VALUATOR_RELATIVE,
+                false => VALUATOR_ABSOLUTE,
+            };
+            let mut fields = [*first, values.len() as u32].to_vec();
+            fields.extend(values.iter().map(|&value| value as u32));
+            (event_type, *origin, fields)
+        }
+    };
+    body.push(8 + 4 * fields.len() as u8);
+    body.push(event_type);
+    body.extend_from_slice(&[0; 2]);
+    put_u32(body, origin);
+    for field in fields {
+        put_u32(body, field);
+    }
+    Ok(())
+}
+
+impl crate::protocol::Message for ServerMessage {
+    fn read_from<R: Read>(reader: &mut R) -> Result<ServerMessage> {
+        let mut data = Vec::new();
+        let (sub_type, big_endian) = read_body(reader, &mut data)?;
+        let mut body = Body {
+            data: &data,
+            big_endian,
+        };
+        let message = match sub_type {
+            VERSION => ServerMessage::Version {
+                maximum: body.u16()?,
+                minimum: body.u16()?,
+            },
+            DEVICE_CREATION => ServerMessage::DeviceCreated(body.u32()?),
+            _ => return Err(Error::Unexpected("gii message type")),
+        };
+        body.finish()?;
+        Ok(message)
+    }
+
+    fn write_to<W: Write>(&self, writer: &mut W) -> Result<()> {
+        let mut body = Vec::new();
+        let sub_type = match *self {
+            ServerMessage::Version { maximum, minimum } => {
+                body.extend_from_slice(&maximum.to_be_bytes());
+                body.extend_from_slice(&minimum.to_be_bytes());
+                VERSION
+            }
+            ServerMessage::DeviceCreated(origin) => {
+                put_u32(&mut body, origin);
+                DEVICE_CREATION
+            }
+        };
+        write_message(writer, sub_type, &body)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::Message;
+
+    #[test]
+    fn test_little_endian() {
+        // A version message and a device creation response, as a
+        // little-endian server sends them.
+        let data = [1, 4, 0, 1, 0, 1, 0, 2, 4, 0, 0x78, 0x56, 0x34, 0x12];
+        let mut reader = &data[..];
+        assert_eq!(
+            ServerMessage::read_from(&mut reader).unwrap(),
+            ServerMessage::Version {
+                maximum: 1,
+                minimum: 1
+            }
+        );
+        assert_eq!(
+            ServerMessage::read_from(&mut reader).unwrap(),
+            ServerMessage::DeviceCreated(0x12345678)
+        );
+    }
+
+    #[test]
+    fn test_device_creation_length() {
+        let valuator = Valuator {
+            index: 0,
+            long_name: String::from("Pressure"),
+            short_name: String::from("p"),
+            range_min: 0,
+            range_center: 0,
+            range_max: 1023,
+            si_unit: 0,
+            si_add: 0,
+            si_mul: 1,
+            si_div: 1,
+            si_shift: 0,
+        };
+        let device = Device {
+            name: String::from("stylus"),
+            vendor_id: 0,
+            product_id: 0,
+            can_generate: VALUATOR_ABSOLUTE_MASK | BUTTON_PRESS_MASK,
+            num_registers: 0,
+            num_buttons: 1,
+            valuators: [
+                valuator.clone(),
+                Valuator {
+                    index: 1,
+                    ..valuator
+                },
+            ]
+            .to_vec(),
+        };
+        let mut buffer = Vec::new();
+        ClientMessage::CreateDevice(device.clone())
+            .write_to(&mut buffer)
+            .unwrap();
+        assert_eq!(buffer.len(), 3 + 56 + 2 * 116);
+        assert_eq!(
+            ClientMessage::read_from(&mut &buffer[..]).unwrap(),
+            ClientMessage::CreateDevice(device.clone())
+        );
+
+        let name = String::from("a name of thirty-two bytes long!");
+        let device = Device { name, ..device };
+        assert!(ClientMessage::CreateDevice(device)
+            .write_to(&mut Vec::new())
+            .is_err());
+    }
+}
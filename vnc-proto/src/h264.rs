@@ -0,0 +1,174 @@
+use crate::io::{Read, ReadBytesExt};
+use crate::{pixels, protocol, Error, Rect, Result};
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use byteorder::BigEndian;
+
+/// An H.264 decoder, as used by the Open H.264 encoding. None is included in
+/// this crate; wrap a codec library such as OpenH264 or FFmpeg.
+pub trait H264 {
+    /// Decodes `data`, part of an H.264 byte stream in Annex B format. If that
+    /// completes a picture, appends it to `rgb` as three bytes, red, green and
+    /// blue, per pixel and returns `true`.
+    fn decode(&mut self, data: &[u8], rgb: &mut Vec<u8>) -> Result<bool>;
+}
+
+/// Creates an H.264 decoder for every stream the server starts.
+pub type Backend = Box<dyn FnMut() -> Box<dyn H264 + Send> + Send>;
+
+// Flags sent with every rectangle.
+const RESET_CONTEXT: u32 = 1 << 0;
+const RESET_ALL_CONTEXTS: u32 = 1 << 1;
+
+/// How many streams are kept at once; the least recently started is dropped to
+/// make room for another. This is the limit TigerVNC servers work with.
+const MAX_CONTEXTS: usize = 64;
+
+/// A decoder for the Open H.264 encoding. The server runs one H.264 stream per
+/// rectangle position and size, each decoded by a context of its own.
+pub struct Decoder {
+    backend: Backend,
+    contexts: Vec<(Rect, Box<dyn H264 + Send>)>,
+    // Kept across rectangles so that decoding does not allocate once they have
+    // grown to the largest picture seen.
+    data: Vec<u8>,
+    rgb: Vec<u8>,
+    pixels: Vec<u8>,
+}
+
+impl Decoder {
+    pub fn new(backend: Backend) -> Decoder {
+        Decoder {
+            backend,
+            contexts: Vec::new(),
+            data: Vec::new(),
+            rgb: Vec::new(),
+            pixels: Vec::new(),
+        }
+    }
+
+    /// Reads a rectangle covering `rect` from `reader` and returns its pixels,
+    /// or `None` if its data did not complete a picture.
+    pub fn decode<R: Read>(
+        &mut self,
+        reader: &mut R,
+        format: protocol::PixelFormat,
+        rect: Rect,
+    ) -> Result<Option<&[u8]>> {
+        let length = reader.read_u32::<BigEndian>()? as usize;
+        let flags = reader.read_u32::<BigEndian>()?;
+        self.data.resize(length, 0);
+        reader.read_exact(&mut self.data)?;
+
+        if flags & RESET_ALL_CONTEXTS != 0 {
+            self.contexts.clear();
+        } else if flags & RESET_CONTEXT != 0 {
+            self.contexts
+                .retain(|(context_rect, _)| *context_rect != rect);
+        }
+        if length == 0 {
+            return Ok(None);
+        }
+        if !format.true_colour {
+            return Err(Error::Unexpected("Open H.264 rectangle with a colour map"));
+        }
+
+        let index = match self
+            .contexts
+            .iter()
+            .position(|(context_rect, _)| *context_rect == rect)
+        {
+            Some(index) => index,
+            None => {
+                if self.contexts.len() == MAX_CONTEXTS {
+                    self.contexts.remove(0);
+                }
+                self.contexts.push((rect, (self.backend)()));
+                self.contexts.len() - 1
+            }
+        };
+
+        self.rgb.clear();
+        if !self.contexts[index].1.decode(&self.data, &mut self.rgb)? {
+            return Ok(None);
+        }
+        if self.rgb.len() != rect.area() * 3 {
+            return Err(Error::Unexpected("Open H.264 picture size"));
+        }
+        self.pixels.clear();
+        pixels::pack_rgb(format, &self.rgb, &mut self.pixels);
+        Ok(Some(&self.pixels))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Decoder, H264, RESET_ALL_CONTEXTS, RESET_CONTEXT};
+    use crate::{PixelFormat, Rect, Result};
+    use alloc::boxed::Box;
+    use alloc::vec::Vec;
+
+    const FORMAT: PixelFormat = PixelFormat {
+        bits_per_pixel: 8,
+        depth: 8,
+        big_endian: false,
+        true_colour: true,
+        red_max: 7,
+        green_max: 7,
+        blue_max: 3,
+        red_shift: 0,
+        green_shift: 3,
+        blue_shift: 6,
+    };
+
+    // Completes a picture of 1x1 pixels with every byte it is fed, whose red is
+    // how many bytes this context has been fed so far.
+    struct Counter(u8);
+
+    impl H264 for Counter {
+        fn decode(&mut self, data: &[u8], rgb: &mut Vec<u8>) -> Result<bool> {
+            self.0 += data.len() as u8;
+            rgb.extend_from_slice(&[(self.0 as u32 * 255 / 7) as u8, 0, 0]);
+            Ok(true)
+        }
+    }
+
+    fn rectangle(flags: u32, data: &[u8]) -> Vec<u8> {
+        let mut rectangle = (data.len() as u32).to_be_bytes().to_vec();
+        rectangle.extend_from_slice(&flags.to_be_bytes());
+        rectangle.extend_from_slice(data);
+        rectangle
+    }
+
+    #[test]
+    fn test_contexts() {
+        let mut decoder = Decoder::new(Box::new(|| Box::new(Counter(0))));
+        let mut decode = |rect: Rect, flags: u32, data: &[u8]| {
+            decoder
+                .decode(&mut &rectangle(flags, data)[..], FORMAT, rect)
+                .unwrap()
+                .map(|pixels| pixels.to_vec())
+        };
+        let first = Rect::new(0, 0, 1, 1);
+        let second = Rect::new(1, 0, 1, 1);
+
+        assert_eq!(decode(first, 0, &[0]), Some(vec![1]));
+        assert_eq!(decode(second, 0, &[0, 0]), Some(vec![2]));
+        assert_eq!(decode(first, 0, &[0]), Some(vec![2]));
+        // Resetting one context leaves the other.
+        assert_eq!(decode(first, RESET_CONTEXT, &[0]), Some(vec![1]));
+        assert_eq!(decode(second, 0, &[0]), Some(vec![3]));
+        // A reset without data decodes nothing.
+        assert_eq!(decode(first, RESET_ALL_CONTEXTS, &[]), None);
+        assert_eq!(decode(second, 0, &[0]), Some(vec![1]));
+    }
+
+    #[test]
+    fn test_picture_size() {
+        let mut decoder = Decoder::new(Box::new(|| Box::new(Counter(0))));
+        let data = rectangle(0, &[0]);
+        assert!(decoder
+            .decode(&mut &data[..], FORMAT, Rect::new(0, 0, 2, 1))
+            .is_err());
+    }
+}
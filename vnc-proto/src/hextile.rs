@@ -0,0 +1,372 @@
+use crate::io::{Read, ReadBytesExt};
+use crate::tile::copy_tile;
+use crate::{protocol, Error, Rect, Result};
+use alloc::vec::Vec;
+
+// Tile subencoding bits.
+pub(crate) const RAW: u8 = 1 << 0;
+const BACKGROUND_SPECIFIED: u8 = 1 << 1;
+const FOREGROUND_SPECIFIED: u8 = 1 << 2;
+const ANY_SUBRECTS: u8 = 1 << 3;
+const SUBRECTS_COLOURED: u8 = 1 << 4;
+
+/// A decoder for the Hextile encoding. Unlike ZRLE, Hextile data is not
+/// length-prefixed, so it is decoded as it is read from the connection.
+#[derive(Debug, Default)]
+pub struct Decoder {
+    // Both colours carry over from one tile to the next, and across rectangles.
+    background: [u8; 4],
+    foreground: [u8; 4],
+    // Scratch buffer for the tile being decoded.
+    pub(crate) pixels: Vec<u8>,
+}
+
+impl Decoder {
+    pub fn new() -> Decoder {
+        Decoder::default()
+    }
+
+    /// Reads the tiles of `rect` from `reader` and calls `callback` with the
+    /// pixels of each one, stopping early if it returns `false`.
+    pub fn decode<R, F>(
+        &mut self,
+        reader: &mut R,
+        format: protocol::PixelFormat,
+        rect: Rect,
+        mut callback: F,
+    ) -> Result<bool>
+    where
+        R: Read,
+        F: FnMut(Rect, &[u8]) -> Result<bool>,
+    {
+        let bpp = format.bits_per_pixel as usize / 8;
+        for tile in rect.tiles(16) {
+            let subencoding = reader.read_u8()?;
+            if subencoding & RAW != 0 {
+                self.read_raw(reader, bpp, tile)?
+            } else {
+                self.read_tile(reader, bpp, tile, subencoding)?
+            }
+
+            if !callback(tile, &self.pixels)? {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    pub(crate) fn read_raw(&mut self, reader: &mut dyn Read, bpp: usize, tile: Rect) -> Result<()> {
+        self.pixels.resize(tile.area() * bpp, 0);
+        reader.read_exact(&mut self.pixels)?;
+        Ok(())
+    }
+
+    /// Reads what follows the subencoding of a tile that is not raw.
+    pub(crate) fn read_tile(
+        &mut self,
+        reader: &mut dyn Read,
+        bpp: usize,
+        tile: Rect,
+        subencoding: u8,
+    ) -> Result<()> {
+        if subencoding & BACKGROUND_SPECIFIED != 0 {
+            reader.read_exact(&mut self.background[..bpp])?
+        }
+        if subencoding & FOREGROUND_SPECIFIED != 0 {
+            reader.read_exact(&mut self.foreground[..bpp])?
+        }
+
+        self.pixels.clear();
+        for _ in 0..tile.area() {
+            self.pixels.extend_from_slice(&self.background[..bpp])
+        }
+        if subencoding & ANY_SUBRECTS == 0 {
+            return Ok(());
+        }
+
+        let count = reader.read_u8()?;
+        let mut colour = self.foreground;
+        for _ in 0..count {
+            if subencoding & SUBRECTS_COLOURED != 0 {
+                reader.read_exact(&mut colour[..bpp])?
+            }
+            let position = reader.read_u8()?;
+            let size = reader.read_u8()?;
+            let subrect = Rect::new(
+                (position >> 4) as u16,
+                (position & 0xf) as u16,
+                (size >> 4) as u16 + 1,
+                (size & 0xf) as u16 + 1,
+            );
+            if !Rect::with_size(tile.width, tile.height).contains_rect(&subrect) {
+                return Err(Error::Unexpected("Hextile subrectangle out of bounds"));
+            }
+            for y in subrect.top..subrect.top + subrect.height {
+                let start = (y as usize * tile.width as usize + subrect.left as usize) * bpp;
+                let row = &mut self.pixels[start..start + subrect.width as usize * bpp];
+                for pixel in row.chunks_exact_mut(bpp) {
+                    pixel.copy_from_slice(&colour[..bpp])
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// An encoder for the Hextile encoding, for servers. Each tile is sent as a
+/// background with subrectangles of other colours, or raw if that is no
+/// smaller. Colours carry over from one tile to the next, but not across
+/// rectangles.
+#[derive(Debug, Default)]
+pub struct Encoder {
+    // Scratch buffers for the tile being encoded, its pixels as the bytes of
+    // the pixel in a little-endian u32, and which of them subrectangles cover.
+    tile: Vec<u8>,
+    colours: Vec<u32>,
+    covered: Vec<bool>,
+    subrects: Vec<(u32, Rect)>,
+}
+
+impl Encoder {
+    pub fn new() -> Encoder {
+        Encoder::default()
+    }
+
+    /// Encodes `rect`, made of rows of packed `pixels` in `format`, appending
+    /// the tiles to `output`.
+    pub fn encode(
+        &mut self,
+        format: protocol::PixelFormat,
+        rect: Rect,
+        pixels: &[u8],
+        output: &mut Vec<u8>,
+    ) -> Result<()> {
+        let bpp = format.bits_per_pixel as usize / 8;
+        if pixels.len() != rect.area() * bpp {
+            return Err(Error::Unexpected("pixel data length"));
+        }
+        let (mut background, mut foreground) = (None, None);
+        for tile in rect.tiles(16) {
+            self.tile.clear();
+            copy_tile(pixels, rect, bpp, tile, &mut self.tile);
+            self.colours.clear();
+            self.colours
+                .extend(self.tile.chunks_exact(bpp).map(|pixel| {
+                    let mut bytes = [0; 4];
+                    bytes[..bpp].copy_from_slice(pixel);
+                    u32::from_le_bytes(bytes)
+                }));
+
+            let tile_background = self.find_subrects(tile);
+            // All the subrectangles have the same colour, unless it varies.
+            let tile_foreground = self.subrects.first().map(|&(colour, _)| colour);
+            let coloured = self
+                .subrects
+                .iter()
+                .any(|&(colour, _)| Some(colour) != tile_foreground);
+
+            let mut subencoding = 0;
+            let mut size = 1;
+            if background != Some(tile_background) {
+                subencoding |= BACKGROUND_SPECIFIED;
+                size += bpp;
+            }
+            if !self.subrects.is_empty() {
+                subencoding |= ANY_SUBRECTS;
+                size += 1 + self.subrects.len() * 2;
+                if coloured {
+                    subencoding |= SUBRECTS_COLOURED;
+                    size += self.subrects.len() * bpp;
+                } else if foreground != tile_foreground {
+                    subencoding |= FOREGROUND_SPECIFIED;
+                    size += bpp;
+                }
+            }
+
+            if self.subrects.len() > 255 || size > 1 + self.tile.len() {
+                // The colours are undefined after a raw tile.
+                output.push(RAW);
+                output.extend_from_slice(&self.tile);
+                (background, foreground) = (None, None);
+                continue;
+            }
+            let write_colour = |colour: u32, output: &mut Vec<u8>| {
+                output.extend_from_slice(&colour.to_le_bytes()[..bpp])
+            };
+            output.push(subencoding);
+            if subencoding & BACKGROUND_SPECIFIED != 0 {
+                write_colour(tile_background, output);
+                background = Some(tile_background);
+            }
+            if subencoding & FOREGROUND_SPECIFIED != 0 {
+                write_colour(tile_foreground.unwrap(), output);
+                foreground = tile_foreground;
+            }
+            if subencoding & ANY_SUBRECTS != 0 {
+                output.push(self.subrects.len() as u8);
+                for &(colour, subrect) in &self.subrects {
+                    if coloured {
+                        write_colour(colour, output);
+                    }
+                    output.push((subrect.left as u8) << 4 | subrect.top as u8);
+                    output.push((subrect.width as u8 - 1) << 4 | (subrect.height as u8 - 1));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Picks the most common colour of the tile as its background, and covers
+    /// the other pixels with `subrects`, each grown right and then down as far
+    /// as its colour goes.
+    fn find_subrects(&mut self, tile: Rect) -> u32 {
+        let Encoder {
+            colours,
+            covered,
+            subrects,
+            ..
+        } = self;
+        let background = colours
+            .iter()
+            .max_by_key(|&&colour| colours.iter().filter(|&&other| other == colour).count())
+            .copied()
+            .unwrap_or(0);
+
+        let (width, height) = (tile.width as usize, tile.height as usize);
+        covered.clear();
+        covered.extend(colours.iter().map(|&colour| colour == background));
+        subrects.clear();
+        for y in 0..height {
+            for x in 0..width {
+                if covered[y * width + x] {
+                    continue;
+                }
+                let colour = colours[y * width + x];
+                let fits = |x: usize, y: usize| {
+                    !covered[y * width + x] && colours[y * width + x] == colour
+                };
+                let right = (x..width).take_while(|&x| fits(x, y)).last().unwrap() + 1;
+                let bottom = (y..height)
+                    .take_while(|&y| (x..right).all(|x| fits(x, y)))
+                    .last()
+                    .unwrap()
+                    + 1;
+                for row in covered[y * width..bottom * width].chunks_mut(width) {
+                    row[x..right].fill(true)
+                }
+                let subrect =
+                    Rect::new(x as u16, y as u16, (right - x) as u16, (bottom - y) as u16);
+                subrects.push((colour, subrect));
+            }
+        }
+        background
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Decoder, Encoder};
+    use crate::{PixelFormat, Rect};
+    use alloc::vec::Vec;
+
+    const FORMAT: PixelFormat = PixelFormat {
+        bits_per_pixel: 8,
+        depth: 8,
+        big_endian: false,
+        true_colour: true,
+        red_max: 7,
+        green_max: 7,
+        blue_max: 3,
+        red_shift: 0,
+        green_shift: 3,
+        blue_shift: 6,
+    };
+
+    fn decode(rect: Rect, data: &[u8]) -> Vec<(Rect, Vec<u8>)> {
+        let mut tiles = Vec::new();
+        let mut reader = data;
+        Decoder::new()
+            .decode(&mut reader, FORMAT, rect, |tile, pixels| {
+                tiles.push((tile, pixels.to_vec()));
+                Ok(true)
+            })
+            .unwrap();
+        assert!(reader.is_empty());
+        tiles
+    }
+
+    #[test]
+    fn test_decode() {
+        // A raw tile; a tile of background 5 with a subrectangle of foreground 9
+        // at (1, 0); and a tile keeping that background, with a subrectangle
+        // of its own colour.
+        let mut data = vec![0x01];
+        data.extend(0..16);
+        data.extend([0x0e, 5, 9, 1, 0x10, 0x00]);
+        data.extend([0x18, 1, 7, 0x00, 0x00]);
+
+        let mut second = vec![5; 16];
+        second[1] = 9;
+        assert_eq!(
+            decode(Rect::new(0, 0, 34, 1), &data),
+            [
+                (Rect::new(0, 0, 16, 1), (0..16).collect()),
+                (Rect::new(16, 0, 16, 1), second),
+                (Rect::new(32, 0, 2, 1), vec![7, 5]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_subrect_out_of_bounds() {
+        // A 2x2 subrectangle at (1, 1) of a 2x2 tile.
+        let data = [0x08, 1, 0x11, 0x11];
+        let result =
+            Decoder::new().decode(&mut &data[..], FORMAT, Rect::new(0, 0, 2, 2), |_, _| {
+                Ok(true)
+            });
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_round_trip() {
+        // Tiles that are solid, solid like the one before, two-coloured, with
+        // subrectangles of many colours, and noise that only goes raw.
+        let rect = Rect::new(3, 4, 80, 20);
+        let pixel = |x: u16, y: u16| -> u8 {
+            match x / 16 {
+                0 => 3,
+                1 => 3,
+                2 => [3, 200][(x.is_multiple_of(5) || y == 7) as usize],
+                3 => (x / 4 + y / 4 * 4) as u8,
+                _ => (x as u8).wrapping_mul(97) ^ (y as u8).wrapping_mul(31),
+            }
+        };
+        let pixels = (0..rect.height)
+            .flat_map(|y| (0..rect.width).map(move |x| pixel(x, y)))
+            .collect::<Vec<_>>();
+
+        let mut encoded = Vec::new();
+        let mut encoder = Encoder::new();
+        encoder.encode(FORMAT, rect, &pixels, &mut encoded).unwrap();
+        assert!(encoded.len() < pixels.len());
+
+        let mut decoded = vec![0; pixels.len()];
+        for (tile, tile_pixels) in decode(rect, &encoded) {
+            for (y, row) in tile_pixels.chunks(tile.width as usize).enumerate() {
+                let offset = (tile.top - rect.top) as usize * rect.width as usize
+                    + y * rect.width as usize
+                    + (tile.left - rect.left) as usize;
+                decoded[offset..offset + row.len()].copy_from_slice(row);
+            }
+        }
+        assert_eq!(decoded, pixels);
+
+        // The second tile only needs its subencoding.
+        let mut solid = Vec::new();
+        encoder
+            .encode(FORMAT, Rect::new(0, 0, 32, 1), &[3; 32], &mut solid)
+            .unwrap();
+        assert_eq!(solid, [0x02, 3, 0x00]);
+    }
+}
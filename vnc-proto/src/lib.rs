@@ -0,0 +1,99 @@
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+use alloc::string::String;
+
+pub mod des;
+pub mod fbs;
+pub mod gii;
+pub mod h264;
+pub mod hextile;
+pub mod io;
+#[cfg(feature = "lzo")]
+pub mod lzo;
+pub mod pixels;
+#[cfg(feature = "png")]
+pub mod png;
+pub mod protocol;
+mod rect;
+pub mod tight;
+#[cfg(feature = "png")]
+pub mod tightpng;
+mod tile;
+pub mod trle;
+pub mod ultra;
+pub mod zlib;
+pub mod zlibhex;
+pub mod zrle;
+
+pub use protocol::{Colour, Encoding, Fence, PixelFormat, Screen, SecurityType, Version};
+pub use rect::{Damage, Rect, Tiles};
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    Unexpected(&'static str),
+    UnexpectedMessageType(u8),
+    Server(String),
+    AuthenticationUnavailable,
+    AuthenticationFailure(String),
+    Disconnected,
+    /// The server's framebuffer is, or was resized to, larger than the caller allows.
+    FramebufferTooLarge(u16, u16),
+    /// Clipboard text of this many characters is longer than the caller allows.
+    ClipboardTooLarge(usize),
+    /// Reading from or writing to the connection took longer than its timeout.
+    Timeout,
+}
+
+impl core::fmt::Display for Error {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::result::Result<(), core::fmt::Error> {
+        match self {
+            Error::Io(ref inner) => inner.fmt(f),
+            Error::Unexpected(ref descr) => write!(f, "unexpected {}", descr),
+            Error::UnexpectedMessageType(message_type) => {
+                write!(f, "unexpected message type {}", message_type)
+            }
+            Error::Server(ref descr) => write!(f, "server error: {}", descr),
+            Error::AuthenticationFailure(ref descr) => {
+                write!(f, "authentication failure: {}", descr)
+            }
+            Error::AuthenticationUnavailable => write!(f, "authentication unavailable"),
+            Error::Disconnected => write!(f, "disconnected"),
+            Error::FramebufferTooLarge(width, height) => {
+                write!(f, "framebuffer size {}x{} exceeds the limit", width, height)
+            }
+            Error::ClipboardTooLarge(length) => {
+                write!(
+                    f,
+                    "clipboard text of {} characters exceeds the limit",
+                    length
+                )
+            }
+            Error::Timeout => write!(f, "timed out"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for Error {
+    fn cause(&self) -> Option<&dyn std::error::Error> {
+        match self {
+            Error::Io(ref inner) => Some(inner),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Error {
+        match error.kind() {
+            #[cfg(feature = "std")]
+            io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock => Error::Timeout,
+            _ => Error::Io(error),
+        }
+    }
+}
+
+pub type Result<T> = core::result::Result<T, Error>;
@@ -0,0 +1,206 @@
+//! A decompressor for LZO1X, the compression used by the Ultra encoding.
+//! It checks every length and distance against its buffers, so corrupt data
+//! is an error rather than a panic.
+
+use crate::ultra::Lzo;
+use crate::{Error, Result};
+
+/// The LZO1X backend for the Ultra decoder.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Lzo1x;
+
+impl Lzo for Lzo1x {
+    fn decompress(&mut self, input: &[u8], output: &mut [u8]) -> Result<usize> {
+        decompress(input, output)
+    }
+}
+
+struct Stream<'a> {
+    input: &'a [u8],
+    output: &'a mut [u8],
+    position: usize,
+}
+
+impl<'a> Stream<'a> {
+    fn byte(&mut self) -> Result<u8> {
+        let (&byte, rest) = self.input.split_first().ok_or(truncated())?;
+        self.input = rest;
+        Ok(byte)
+    }
+
+    fn le16(&mut self) -> Result<usize> {
+        Ok(self.byte()? as usize | (self.byte()? as usize) << 8)
+    }
+
+    /// Decodes a length field: `bits` of the instruction if they are not
+    /// zero, or else `max` plus the bytes that follow, each zero adding 255.
+    fn length(&mut self, bits: u8, max: usize) -> Result<usize> {
+        if bits != 0 {
+            return Ok(bits as usize);
+        }
+        let mut length = max;
+        loop {
+            match self.byte()? {
+                0 => length += 255,
+                byte => return Ok(length + byte as usize),
+            }
+        }
+    }
+
+    fn copy_literals(&mut self, count: usize) -> Result<()> {
+        if count > self.input.len() {
+            return Err(truncated());
+        }
+        let (literals, rest) = self.input.split_at(count);
+        self.output
+            .get_mut(self.position..self.position + count)
+            .ok_or(overrun())?
+            .copy_from_slice(literals);
+        self.input = rest;
+        self.position += count;
+        Ok(())
+    }
+
+    fn copy_match(&mut self, distance: usize, length: usize) -> Result<()> {
+        if distance > self.position {
+            return Err(Error::Unexpected("LZO match distance"));
+        }
+        if self.position + length > self.output.len() {
+            return Err(overrun());
+        }
+        // Byte by byte, as the match may overlap what it produces.
+        for _ in 0..length {
+            self.output[self.position] = self.output[self.position - distance];
+            self.position += 1;
+        }
+        Ok(())
+    }
+}
+
+fn truncated() -> Error {
+    Error::Unexpected("end of LZO data")
+}
+
+fn overrun() -> Error {
+    Error::Unexpected("LZO output overrun")
+}
+
+/// Decompresses the LZO1X block `input` into `output`, returning how many
+/// bytes it produced. All of `input` has to be one block.
+pub fn decompress(input: &[u8], output: &mut [u8]) -> Result<usize> {
+    let mut stream = Stream {
+        input,
+        output,
+        position: 0,
+    };
+
+    // How many literals the previous instruction copied, with 4 standing for
+    // a long run; it selects the meaning of instructions 0 to 15.
+    let mut state = 0;
+    if let Some(&first) = input.first() {
+        if first > 17 {
+            stream.byte()?;
+            let count = (first - 17) as usize;
+            stream.copy_literals(count)?;
+            state = count.min(4);
+        }
+    }
+
+    loop {
+        let instruction = stream.byte()?;
+        let (length, distance, literals) = match instruction {
+            0..=15 if state == 0 => {
+                let count = 3 + stream.length(instruction, 15)?;
+                stream.copy_literals(count)?;
+                state = 4;
+                continue;
+            }
+            0..=15 => {
+                let low = (instruction >> 2) as usize;
+                let distance = (stream.byte()? as usize) << 2 | low;
+                if state == 4 {
+                    (3, distance + 2049, instruction & 3)
+                } else {
+                    (2, distance + 1, instruction & 3)
+                }
+            }
+            16..=31 => {
+                let length = 2 + stream.length(instruction & 7, 7)?;
+                let bits = stream.le16()?;
+                let distance = 16384 + ((instruction as usize & 8) << 11) + (bits >> 2);
+                if distance == 16384 {
+                    break;
+                }
+                (length, distance, bits as u8 & 3)
+            }
+            32..=63 => {
+                let length = 2 + stream.length(instruction & 31, 31)?;
+                let bits = stream.le16()?;
+                (length, (bits >> 2) + 1, bits as u8 & 3)
+            }
+            64..=255 => {
+                let length = if instruction < 128 {
+                    3 + (instruction as usize >> 5 & 1)
+                } else {
+                    5 + (instruction as usize >> 5 & 3)
+                };
+                let low = (instruction >> 2 & 7) as usize;
+                let distance = (stream.byte()? as usize) << 3 | low;
+                (length, distance + 1, instruction & 3)
+            }
+        };
+        stream.copy_match(distance, length)?;
+        stream.copy_literals(literals as usize)?;
+        state = literals as usize;
+    }
+
+    if !stream.input.is_empty() {
+        return Err(Error::Unexpected("leftover LZO data"));
+    }
+    Ok(stream.position)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::decompress;
+
+    // The end of stream instruction.
+    const END: [u8; 3] = [0x11, 0x00, 0x00];
+
+    #[test]
+    fn test_decompress() {
+        // Three literals, then a match of nine bytes three back.
+        let mut data = vec![20, b'a', b'b', b'c', 39, 0x08, 0x00];
+        data.extend(END);
+        let mut output = [0; 16];
+        assert_eq!(decompress(&data, &mut output).unwrap(), 12);
+        assert_eq!(&output[..12], b"abcabcabcabc");
+
+        // A run of 3 + 15 + 255 + 2 literals, then a match of four bytes
+        // two back, followed by one literal.
+        let mut data = vec![0, 0, 2];
+        data.extend((0..275).map(|n| n as u8));
+        data.extend([0x65, 0x00, b'z']);
+        data.extend(END);
+        let mut output = [0; 280];
+        assert_eq!(decompress(&data, &mut output).unwrap(), 280);
+        assert_eq!(
+            &output[..275],
+            &(0..275).map(|n| n as u8).collect::<Vec<_>>()
+        );
+        assert_eq!(&output[275..], [17, 18, 17, 18, b'z']);
+    }
+
+    #[test]
+    fn test_errors() {
+        let mut output = [0; 8];
+        // A match reaching back before the start.
+        assert!(decompress(&[20, 1, 2, 3, 39, 0x10, 0x00], &mut output).is_err());
+        // More than fits in the output.
+        assert!(decompress(&[20, 1, 2, 3, 39, 0x08, 0x00, 0x11, 0, 0], &mut output).is_err());
+        // No end of stream.
+        assert!(decompress(&[20, 1, 2, 3], &mut output).is_err());
+        // Data after it.
+        assert!(decompress(&[20, 1, 2, 3, 0x11, 0, 0, 0], &mut output).is_err());
+    }
+}
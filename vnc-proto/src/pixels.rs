@@ -0,0 +1,352 @@
+use crate::{Colour, PixelFormat, Rect};
+use alloc::vec;
+use alloc::vec::Vec;
+
+/// Copies the `src` rectangle of a packed pixel buffer onto `dst`, as a CopyRect
+/// rectangle does. Overlapping rectangles are handled like `memmove`: the result
+/// is as if `src` was read completely before `dst` was written.
+///
+/// `pixels` holds rows of `stride` pixels of `bytes_per_pixel` bytes each. `dst`
+/// is positioned by its origin and has the size of `src`.
+///
+/// # Panics
+///
+/// Panics if either rectangle lies outside the buffer.
+pub fn copy_rect(pixels: &mut [u8], stride: usize, bytes_per_pixel: usize, src: Rect, dst: Rect) {
+    let row_length = src.width as usize * bytes_per_pixel;
+    let offset = |x: u16, y: usize| (y * stride + x as usize) * bytes_per_pixel;
+    assert!(src.right() as usize <= stride && dst.left as usize + src.width as usize <= stride);
+
+    let mut copy_row = |row: usize| {
+        let from = offset(src.left, src.top as usize + row);
+        let to = offset(dst.left, dst.top as usize + row);
+        pixels.copy_within(from..from + row_length, to);
+    };
+    // Copying downwards has to start from the bottom so that no source row is
+    // overwritten before it is read; within a row, `copy_within` takes care of it.
+    if dst.top > src.top {
+        (0..src.height as usize).rev().for_each(&mut copy_row)
+    } else {
+        (0..src.height as usize).for_each(&mut copy_row)
+    }
+}
+
+/// Resizes a packed framebuffer from `old` to `new`, both given as width and
+/// height, keeping the pixels of the region the two sizes have in common. The
+/// rest of the new framebuffer is zeroed; the rectangles covering it are
+/// returned alongside, as they have to be refreshed from the server.
+pub fn resize(
+    pixels: &[u8],
+    bytes_per_pixel: usize,
+    old: (u16, u16),
+    new: (u16, u16),
+) -> (Vec<u8>, Vec<Rect>) {
+    let (width, height) = (old.0.min(new.0), old.1.min(new.1));
+    let row_length = width as usize * bytes_per_pixel;
+    let mut resized = vec![0; new.0 as usize * new.1 as usize * bytes_per_pixel];
+    for y in 0..height as usize {
+        let from = y * old.0 as usize * bytes_per_pixel;
+        let to = y * new.0 as usize * bytes_per_pixel;
+        resized[to..to + row_length].copy_from_slice(&pixels[from..from + row_length]);
+    }
+
+    let exposed = [
+        Rect::new(width, 0, new.0 - width, height),
+        Rect::new(0, height, new.0, new.1 - height),
+    ];
+    let exposed = exposed
+        .into_iter()
+        .filter(|rect| !rect.is_empty())
+        .collect();
+    (resized, exposed)
+}
+
+/// Converts packed pixels in `format` to the byte order of the host in place, and
+/// returns the format they are in afterwards. Pixel data is handed out in the
+/// format of the session, which is the server's unless the client asked for
+/// another one; a big-endian server therefore produces byte-swapped pixels on a
+/// little-endian host and vice versa.
+pub fn to_native_endian(format: PixelFormat, pixels: &mut [u8]) -> PixelFormat {
+    let native = cfg!(target_endian = "big");
+    if format.big_endian != native {
+        match format.bits_per_pixel {
+            16 => pixels.chunks_exact_mut(2).for_each(|pixel| pixel.reverse()),
+            32 => pixels.chunks_exact_mut(4).for_each(|pixel| pixel.reverse()),
+            _ => (),
+        }
+    }
+    PixelFormat {
+        big_endian: native,
+        ..format
+    }
+}
+
+/// Packs triples of 8-bit red, green and blue into pixels in the true colour
+/// `format`, appending them to `pixels`.
+pub fn pack_rgb(format: PixelFormat, rgb: &[u8], pixels: &mut Vec<u8>) {
+    let scale = |value: u8, max: u16| (value as u32 * max as u32 + 127) / 255;
+    for colour in rgb.chunks_exact(3) {
+        let value = scale(colour[0], format.red_max) << format.red_shift
+            | scale(colour[1], format.green_max) << format.green_shift
+            | scale(colour[2], format.blue_max) << format.blue_shift;
+        let bytes = match format.big_endian {
+            true => value.to_be_bytes(),
+            false => value.to_le_bytes(),
+        };
+        match (format.bits_per_pixel, format.big_endian) {
+            (8, _) => pixels.push(value as u8),
+            (16, true) => pixels.extend_from_slice(&bytes[2..]),
+            (16, false) => pixels.extend_from_slice(&bytes[..2]),
+            _ => pixels.extend_from_slice(&bytes),
+        }
+    }
+}
+
+/// Unpacks pixels in the true colour `format` into triples of 8-bit red, green
+/// and blue, appending them to `rgb`; the reverse of `pack_rgb`.
+pub fn unpack_rgb(format: PixelFormat, pixels: &[u8], rgb: &mut Vec<u8>) {
+    let scale = |value: u32, max: u16, shift: u8| {
+        let max = (max as u32).max(1);
+        (((value >> shift) & max) * 255 + max / 2) / max
+    };
+    for pixel in pixels.chunks_exact(format.bits_per_pixel as usize / 8) {
+        let value =
+            pixel
+                .iter()
+                .enumerate()
+                .fold(0u32, |value, (i, &byte)| match format.big_endian {
+                    true => value << 8 | byte as u32,
+                    false => value | (byte as u32) << (8 * i),
+                });
+        rgb.extend_from_slice(&[
+            scale(value, format.red_max, format.red_shift) as u8,
+            scale(value, format.green_max, format.green_shift) as u8,
+            scale(value, format.blue_max, format.blue_shift) as u8,
+        ]);
+    }
+}
+
+/// Looks up pixels in the colour-mapped `format` in `colour_map`, appending
+/// them to `rgb` as triples of 8-bit red, green and blue. Pixels beyond the end
+/// of the colour map come out black.
+pub fn expand_indexed(
+    format: PixelFormat,
+    colour_map: &[Colour],
+    pixels: &[u8],
+    rgb: &mut Vec<u8>,
+) {
+    let bytes_per_pixel = format.bits_per_pixel as usize / 8;
+    for pixel in pixels.chunks_exact(bytes_per_pixel) {
+        let index = match (pixel, format.big_endian) {
+            (&[index], _) => index as usize,
+            (&[high, low], true) | (&[low, high], false) => (high as usize) << 8 | low as usize,
+            _ => usize::MAX,
+        };
+        match colour_map.get(index) {
+            Some(colour) => rgb.extend_from_slice(&[
+                (colour.red >> 8) as u8,
+                (colour.green >> 8) as u8,
+                (colour.blue >> 8) as u8,
+            ]),
+            None => rgb.extend_from_slice(&[0; 3]),
+        }
+    }
+}
+
+/// Turns quadruples of 8-bit red, green, blue and alpha with the colours
+/// premultiplied by alpha, as CursorWithAlpha sends them, into straight ones.
+pub fn unpremultiply_alpha(rgba: &mut [u8]) {
+    for pixel in rgba.chunks_exact_mut(4) {
+        let alpha = pixel[3] as u32;
+        if alpha == 0 {
+            continue;
+        }
+        for channel in &mut pixel[..3] {
+            *channel = ((*channel as u32 * 255 + alpha / 2) / alpha).min(255) as u8;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        copy_rect, expand_indexed, pack_rgb, resize, to_native_endian, unpack_rgb,
+        unpremultiply_alpha,
+    };
+    use crate::{Colour, PixelFormat, Rect};
+    use alloc::vec::Vec;
+
+    fn copy(src: Rect, dst: Rect) -> [u8; 16] {
+        let mut pixels = [0; 16];
+        for (i, pixel) in pixels.iter_mut().enumerate() {
+            *pixel = i as u8;
+        }
+        copy_rect(&mut pixels, 4, 1, src, dst);
+        pixels
+    }
+
+    #[test]
+    fn test_copy_rect_overlap() {
+        assert_eq!(
+            copy(Rect::new(0, 0, 3, 3), Rect::new(1, 1, 3, 3)),
+            [0, 1, 2, 3, 4, 0, 1, 2, 8, 4, 5, 6, 12, 8, 9, 10]
+        );
+        assert_eq!(
+            copy(Rect::new(1, 1, 3, 3), Rect::new(0, 0, 3, 3)),
+            [5, 6, 7, 3, 9, 10, 11, 7, 13, 14, 15, 11, 12, 13, 14, 15]
+        );
+        assert_eq!(
+            copy(Rect::new(0, 0, 4, 1), Rect::new(0, 3, 4, 1)),
+            [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 0, 1, 2, 3]
+        );
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_copy_rect_out_of_bounds() {
+        copy(Rect::new(0, 0, 2, 2), Rect::new(3, 0, 2, 2));
+    }
+
+    #[test]
+    fn test_resize() {
+        let (pixels, exposed) = resize(&[1, 2, 3, 4], 1, (2, 2), (3, 1));
+        assert_eq!(pixels, [1, 2, 0]);
+        assert_eq!(exposed, [Rect::new(2, 0, 1, 1)]);
+
+        let (pixels, exposed) = resize(&[1, 2, 3, 4], 1, (2, 2), (1, 3));
+        assert_eq!(pixels, [1, 3, 0]);
+        assert_eq!(exposed, [Rect::new(0, 2, 1, 1)]);
+
+        let (pixels, exposed) = resize(&[1, 2, 3, 4], 1, (2, 2), (2, 2));
+        assert_eq!(pixels, [1, 2, 3, 4]);
+        assert!(exposed.is_empty());
+    }
+
+    #[test]
+    fn test_to_native_endian() {
+        let format = PixelFormat {
+            bits_per_pixel: 32,
+            depth: 24,
+            big_endian: true,
+            true_colour: true,
+            red_max: 255,
+            green_max: 255,
+            blue_max: 255,
+            red_shift: 16,
+            green_shift: 8,
+            blue_shift: 0,
+        };
+        let mut pixels = [0x00, 0x11, 0x22, 0x33, 0x00, 0x44, 0x55, 0x66];
+        let native = to_native_endian(format, &mut pixels);
+        assert_eq!(
+            u32::from_ne_bytes(pixels[4..].try_into().unwrap()),
+            0x00445566
+        );
+        assert_eq!(native.big_endian, cfg!(target_endian = "big"));
+        assert_eq!(to_native_endian(native, &mut pixels), native);
+        assert_eq!(
+            u32::from_ne_bytes(pixels[..4].try_into().unwrap()),
+            0x00112233
+        );
+    }
+
+    #[test]
+    fn test_pack_rgb() {
+        let format = PixelFormat {
+            bits_per_pixel: 16,
+            depth: 16,
+            big_endian: true,
+            true_colour: true,
+            red_max: 31,
+            green_max: 63,
+            blue_max: 31,
+            red_shift: 11,
+            green_shift: 5,
+            blue_shift: 0,
+        };
+        let mut pixels = Vec::new();
+        pack_rgb(
+            format,
+            &[255, 0, 0, 0, 255, 255, 128, 128, 128],
+            &mut pixels,
+        );
+        assert_eq!(pixels, [0xf8, 0x00, 0x07, 0xff, 0x84, 0x10]);
+    }
+
+    #[test]
+    fn test_unpack_rgb() {
+        let format = PixelFormat {
+            bits_per_pixel: 16,
+            depth: 16,
+            big_endian: true,
+            true_colour: true,
+            red_max: 31,
+            green_max: 63,
+            blue_max: 31,
+            red_shift: 11,
+            green_shift: 5,
+            blue_shift: 0,
+        };
+        let rgb = [0xff, 0x00, 0x00, 0x00, 0x82, 0xff, 0x10, 0x20, 0x30];
+        let mut pixels = Vec::new();
+        pack_rgb(format, &rgb, &mut pixels);
+        let mut unpacked = Vec::new();
+        unpack_rgb(format, &pixels, &mut unpacked);
+        assert_eq!(
+            unpacked,
+            [0xff, 0x00, 0x00, 0x00, 0x82, 0xff, 0x10, 0x20, 0x31]
+        );
+
+        let format = PixelFormat {
+            big_endian: false,
+            ..format
+        };
+        unpacked.clear();
+        unpack_rgb(format, &[0x00, 0xf8], &mut unpacked);
+        assert_eq!(unpacked, [0xff, 0x00, 0x00]);
+    }
+
+    #[test]
+    fn test_expand_indexed() {
+        let format = PixelFormat {
+            bits_per_pixel: 16,
+            depth: 16,
+            big_endian: true,
+            true_colour: false,
+            red_max: 0,
+            green_max: 0,
+            blue_max: 0,
+            red_shift: 0,
+            green_shift: 0,
+            blue_shift: 0,
+        };
+        let colour = |red, green, blue| Colour { red, green, blue };
+        let mut colour_map = Vec::new();
+        colour_map.resize(0x101, colour(0, 0, 0));
+        colour_map[1] = colour(0xffff, 0x8000, 0x00ff);
+        colour_map[0x100] = colour(0x1234, 0x5678, 0x9abc);
+
+        let mut rgb = Vec::new();
+        expand_indexed(format, &colour_map, &[0, 1, 1, 0, 0xff, 0xff], &mut rgb);
+        assert_eq!(rgb, [0xff, 0x80, 0x00, 0x12, 0x56, 0x9a, 0, 0, 0]);
+
+        let format = PixelFormat {
+            bits_per_pixel: 8,
+            depth: 8,
+            ..format
+        };
+        rgb.clear();
+        expand_indexed(format, &colour_map, &[1], &mut rgb);
+        assert_eq!(rgb, [0xff, 0x80, 0x00]);
+    }
+
+    #[test]
+    fn test_unpremultiply_alpha() {
+        let mut rgba = [0, 0, 0, 0, 255, 128, 0, 255, 64, 32, 0, 128, 200, 0, 0, 100];
+        unpremultiply_alpha(&mut rgba);
+        assert_eq!(
+            rgba,
+            [0, 0, 0, 0, 255, 128, 0, 255, 128, 64, 0, 128, 255, 0, 0, 100]
+        );
+    }
+}
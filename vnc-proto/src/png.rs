@@ -0,0 +1,289 @@
+//! PNG images. The decoder is for the TightPng encoding, and handles the
+//! images servers produce for it: 8-bit greyscale, RGB and their alpha
+//! variants, and palettes of up to 8 bits per index, none of them interlaced.
+//! Alpha is dropped, as the framebuffer has none. The encoder writes 8-bit
+//! RGBA images, unfiltered.
+
+use crate::{Error, Result};
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use std::io::{self, Read, Write};
+
+const SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+struct Header {
+    width: u32,
+    height: u32,
+    bit_depth: u8,
+    colour_type: u8,
+}
+
+impl Header {
+    fn channels(&self) -> usize {
+        match self.colour_type {
+            0 | 3 => 1,
+            2 => 3,
+            4 => 2,
+            _ => 4,
+        }
+    }
+
+    fn stride(&self) -> usize {
+        (self.width as usize * self.channels() * self.bit_depth as usize).div_ceil(8)
+    }
+}
+
+fn be32(data: &[u8]) -> u32 {
+    u32::from_be_bytes([data[0], data[1], data[2], data[3]])
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = flate2::Crc::new();
+    crc.update(data);
+    crc.sum()
+}
+
+/// Writes `rgba`, four bytes, red, green, blue and alpha, per pixel, as a PNG
+/// file of `width` by `height` pixels.
+pub fn encode<W: Write>(writer: &mut W, width: u32, height: u32, rgba: &[u8]) -> io::Result<()> {
+    writer.write_all(SIGNATURE)?;
+    let mut chunk = |kind: &[u8], data: &[u8]| -> io::Result<()> {
+        let mut body = kind.to_vec();
+        body.extend_from_slice(data);
+        writer.write_all(&(data.len() as u32).to_be_bytes())?;
+        writer.write_all(&body)?;
+        writer.write_all(&crc32(&body).to_be_bytes())
+    };
+
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&width.to_be_bytes());
+    header.extend_from_slice(&height.to_be_bytes());
+    header.extend_from_slice(&[8, 6, 0, 0, 0]);
+    chunk(b"IHDR", &header)?;
+
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    for row in rgba.chunks(width as usize * 4) {
+        encoder.write_all(&[0])?;
+        encoder.write_all(row)?;
+    }
+    chunk(b"IDAT", &encoder.finish()?)?;
+    chunk(b"IEND", &[])
+}
+
+/// Decodes `data`, a whole PNG file of `width` by `height` pixels, into three
+/// bytes, red, green and blue, per pixel. An image of any other size is an
+/// error, caught before its data is inflated.
+pub fn decode(mut data: &[u8], width: u32, height: u32) -> Result<Vec<u8>> {
+    data = data
+        .strip_prefix(SIGNATURE)
+        .ok_or(Error::Unexpected("PNG signature"))?;
+
+    let mut header = None;
+    let mut palette: &[u8] = &[];
+    let mut compressed = Vec::new();
+    loop {
+        if data.len() < 12 {
+            return Err(Error::Unexpected("end of PNG data"));
+        }
+        let length = be32(data) as usize;
+        if data.len() - 12 < length {
+            return Err(Error::Unexpected("end of PNG data"));
+        }
+        let (body, rest) = data[4..].split_at(4 + length);
+        if crc32(body) != be32(rest) {
+            return Err(Error::Unexpected("PNG checksum"));
+        }
+        data = &rest[4..];
+
+        let (kind, body) = body.split_at(4);
+        match kind {
+            b"IHDR" if body.len() == 13 => {
+                let valid = matches!((body[8], body[9]), (8, 0 | 2 | 4 | 6) | (1 | 2 | 4 | 8, 3));
+                if !valid || body[12] != 0 {
+                    return Err(Error::Unexpected("PNG image type"));
+                }
+                header = Some(Header {
+                    width: be32(&body[0..4]),
+                    height: be32(&body[4..8]),
+                    bit_depth: body[8],
+                    colour_type: body[9],
+                });
+            }
+            b"PLTE" => palette = body,
+            b"IDAT" => compressed.extend_from_slice(body),
+            b"IEND" => break,
+            _ => (),
+        }
+    }
+    let header = header.ok_or(Error::Unexpected("PNG without header"))?;
+    if (header.width, header.height) != (width, height) {
+        return Err(Error::Unexpected("PNG image size"));
+    }
+
+    let stride = header.stride();
+    let length = (stride + 1) * header.height as usize;
+    let mut filtered = Vec::new();
+    // Read one byte more than needed, so that too much data is caught without
+    // inflating all of it.
+    ZlibDecoder::new(&compressed[..])
+        .take(length as u64 + 1)
+        .read_to_end(&mut filtered)?;
+    if filtered.len() != length {
+        return Err(Error::Unexpected("PNG image data length"));
+    }
+
+    // Filters work on whole pixels, or whole bytes if pixels are smaller.
+    let distance = (header.channels() * header.bit_depth as usize).div_ceil(8);
+    let mut lines = vec![0u8; stride * header.height as usize];
+    for (y, line) in filtered.chunks_exact(stride + 1).enumerate() {
+        let (previous, current) = lines.split_at_mut(y * stride);
+        let above = previous.get(previous.len().wrapping_sub(stride)..);
+        let current = &mut current[..stride];
+        for x in 0..stride {
+            let a = if x >= distance {
+                current[x - distance]
+            } else {
+                0
+            };
+            let b = above.map_or(0, |above| above[x]);
+            let c = match above {
+                Some(above) if x >= distance => above[x - distance],
+                _ => 0,
+            };
+            let predictor = match line[0] {
+                0 => 0,
+                1 => a,
+                2 => b,
+                3 => ((a as u16 + b as u16) / 2) as u8,
+                4 => {
+                    let p = a as i16 + b as i16 - c as i16;
+                    let (pa, pb, pc) = (
+                        (p - a as i16).abs(),
+                        (p - b as i16).abs(),
+                        (p - c as i16).abs(),
+                    );
+                    if pa <= pb && pa <= pc {
+                        a
+                    } else if pb <= pc {
+                        b
+                    } else {
+                        c
+                    }
+                }
+                _ => return Err(Error::Unexpected("PNG filter")),
+            };
+            current[x] = line[1 + x].wrapping_add(predictor);
+        }
+    }
+
+    let mut rgb = Vec::with_capacity(header.width as usize * header.height as usize * 3);
+    for line in lines.chunks_exact(stride.max(1)) {
+        match header.colour_type {
+            0 | 4 => {
+                for pixel in line.chunks_exact(header.channels()) {
+                    rgb.extend_from_slice(&[pixel[0]; 3])
+                }
+            }
+            2 | 6 => {
+                for pixel in line.chunks_exact(header.channels()) {
+                    rgb.extend_from_slice(&pixel[..3])
+                }
+            }
+            _ => {
+                let bits = header.bit_depth as usize;
+                for x in 0..header.width as usize {
+                    let byte = line[x * bits / 8];
+                    let shift = 8 - bits - x * bits % 8;
+                    let index = (byte >> shift) as usize & ((1 << bits) - 1);
+                    let colour = palette
+                        .get(index * 3..index * 3 + 3)
+                        .ok_or(Error::Unexpected("PNG palette index"))?;
+                    rgb.extend_from_slice(colour)
+                }
+            }
+        }
+    }
+    Ok(rgb)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{crc32, decode};
+    use flate2::write::ZlibEncoder;
+    use flate2::Compression;
+    use std::io::Write;
+
+    /// A PNG file with the given header, which `super::encode` cannot write.
+    fn encode(header: [u8; 13], palette: &[u8], filtered: &[u8]) -> Vec<u8> {
+        let mut png = super::SIGNATURE.to_vec();
+        let mut chunk = |kind: &[u8], data: &[u8]| {
+            let mut body = kind.to_vec();
+            body.extend_from_slice(data);
+            png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+            png.extend_from_slice(&body);
+            png.extend_from_slice(&crc32(&body).to_be_bytes());
+        };
+        chunk(b"IHDR", &header);
+        if !palette.is_empty() {
+            chunk(b"PLTE", palette);
+        }
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(filtered).unwrap();
+        chunk(b"IDAT", &encoder.finish().unwrap());
+        chunk(b"IEND", &[]);
+        png
+    }
+
+    #[test]
+    fn test_rgba() {
+        // Two rows of two pixels: the first with the Sub filter, the second
+        // with Paeth.
+        let header = [0, 0, 0, 2, 0, 0, 0, 2, 8, 6, 0, 0, 0];
+        let filtered = [
+            1, 10, 20, 30, 255, 1, 1, 1, 0, //
+            4, 5, 5, 5, 0, 1, 1, 1, 0,
+        ];
+        assert_eq!(
+            decode(&encode(header, &[], &filtered), 2, 2).unwrap(),
+            [10, 20, 30, 11, 21, 31, 15, 25, 35, 16, 26, 36]
+        );
+    }
+
+    #[test]
+    fn test_encode() {
+        let rgba = [1, 2, 3, 255, 4, 5, 6, 0, 7, 8, 9, 128];
+        let mut png = Vec::new();
+        super::encode(&mut png, 1, 3, &rgba).unwrap();
+        assert_eq!(decode(&png, 1, 3).unwrap(), [1, 2, 3, 4, 5, 6, 7, 8, 9]);
+    }
+
+    #[test]
+    fn test_palette() {
+        // Three pixels of 2 bits each, in a single row.
+        let header = [0, 0, 0, 3, 0, 0, 0, 1, 2, 3, 0, 0, 0];
+        let palette = [1, 2, 3, 4, 5, 6, 7, 8, 9];
+        let rgb = decode(&encode(header, &palette, &[0, 0b10_01_00_00]), 3, 1).unwrap();
+        assert_eq!(rgb, [7, 8, 9, 4, 5, 6, 1, 2, 3]);
+
+        // An index past the end of the palette.
+        assert!(decode(&encode(header, &palette, &[0, 0b11_00_00_00]), 3, 1).is_err());
+    }
+
+    #[test]
+    fn test_errors() {
+        let header = [0, 0, 0, 1, 0, 0, 0, 1, 8, 2, 0, 0, 0];
+        let png = encode(header, &[], &[0, 1, 2, 3]);
+        assert!(decode(&png, 1, 1).is_ok());
+        // An image of another size.
+        assert!(decode(&png, 1, 2).is_err());
+        // Too much image data.
+        assert!(decode(&encode(header, &[], &[0, 1, 2, 3, 4]), 1, 1).is_err());
+        // A corrupt chunk.
+        let mut corrupt = png.clone();
+        corrupt[20] ^= 1;
+        assert!(decode(&corrupt, 1, 1).is_err());
+        // A truncated file.
+        assert!(decode(&png[..png.len() - 1], 1, 1).is_err());
+    }
+}
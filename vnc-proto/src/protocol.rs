@@ -0,0 +1,1748 @@
+use crate::io::{BigEndian, ErrorKind as IoErrorKind, Read, ReadBytesExt, Write, WriteBytesExt};
+use crate::{gii, Error, Result};
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+
+pub trait Message {
+    fn read_from<R: Read>(reader: &mut R) -> Result<Self>
+    where
+        Self: Sized;
+    fn write_to<W: Write>(&self, writer: &mut W) -> Result<()>;
+}
+
+// Length-prefixed data is read in pieces of this size, so that a bogus length
+// only makes us allocate as much memory as the peer actually sends.
+const READ_CHUNK: usize = 64 * 1024;
+
+fn read_bytes<R: Read>(reader: &mut R, length: usize) -> Result<Vec<u8>> {
+    let mut buffer = Vec::new();
+    while buffer.len() < length {
+        let start = buffer.len();
+        buffer.resize(start + (length - start).min(READ_CHUNK), 0);
+        reader.read_exact(&mut buffer[start..])?;
+    }
+    Ok(buffer)
+}
+
+fn skip_bytes<R: Read>(reader: &mut R, mut length: usize) -> Result<()> {
+    let mut buffer = vec![0; length.min(READ_CHUNK)];
+    while length > 0 {
+        let chunk = length.min(READ_CHUNK);
+        reader.read_exact(&mut buffer[..chunk])?;
+        length -= chunk;
+    }
+    Ok(())
+}
+
+impl Message for Vec<u8> {
+    fn read_from<R: Read>(reader: &mut R) -> Result<Vec<u8>> {
+        let length = reader.read_u32::<BigEndian>()?;
+        read_bytes(reader, length as usize)
+    }
+
+    fn write_to<W: Write>(&self, writer: &mut W) -> Result<()> {
+        let length = self.len() as u32; // TODO: check?
+        writer.write_u32::<BigEndian>(length)?;
+        writer.write_all(self)?;
+        Ok(())
+    }
+}
+
+/* All strings in VNC are either ASCII or Latin-1, both of which
+are embedded in Unicode. */
+impl Message for String {
+    fn read_from<R: Read>(reader: &mut R) -> Result<String> {
+        let length = reader.read_u32::<BigEndian>()?;
+        let string = read_bytes(reader, length as usize)?;
+        Ok(string.iter().map(|c| *c as char).collect())
+    }
+
+    // Strings are Latin-1 on the wire. Every byte is a valid character when reading;
+    // characters outside of Latin-1 are written as `?`.
+    fn write_to<W: Write>(&self, writer: &mut W) -> Result<()> {
+        let string = self
+            .chars()
+            .map(|c| u8::try_from(c).unwrap_or(b'?'))
+            .collect::<Vec<u8>>();
+        writer.write_u32::<BigEndian>(string.len() as u32)?;
+        writer.write_all(&string)?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Version {
+    Rfb33,
+    Rfb37,
+    Rfb38,
+}
+
+impl Message for Version {
+    fn read_from<R: Read>(reader: &mut R) -> Result<Version> {
+        let mut buf = [0; 12];
+        reader.read_exact(&mut buf)?;
+        match &buf {
+            b"RFB 003.003\n" => Ok(Version::Rfb33),
+            b"RFB 003.007\n" => Ok(Version::Rfb37),
+            b"RFB 003.008\n" => Ok(Version::Rfb38),
+            // Apple remote desktop
+            b"RFB 003.889\n" => Ok(Version::Rfb38),
+            _ => Err(Error::Unexpected("protocol version")),
+        }
+    }
+
+    fn write_to<W: Write>(&self, writer: &mut W) -> Result<()> {
+        match self {
+            Version::Rfb33 => writer.write_all(b"RFB 003.003\n"),
+            Version::Rfb37 => writer.write_all(b"RFB 003.007\n"),
+            Version::Rfb38 => writer.write_all(b"RFB 003.008\n"),
+        }?;
+        Ok(())
+    }
+}
+
+/// Whom an UltraVNC repeater should pair a connection with. It is sent as a
+/// NUL-padded string before the RFB handshake: by a server right after
+/// connecting, and by a viewer after the repeater's `GREETING`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RepeaterId {
+    /// Mode II: the viewer and the server meet under the same number.
+    Id(u32),
+    /// Mode I: the repeater connects the viewer to the server at `HOST:PORT`.
+    Server(String),
+}
+
+impl RepeaterId {
+    /// What a repeater sends a viewer first, in place of an RFB version.
+    pub const GREETING: [u8; 12] = *b"RFB 000.000\n";
+    pub const LENGTH: usize = 250;
+}
+
+impl Message for RepeaterId {
+    fn read_from<R: Read>(reader: &mut R) -> Result<RepeaterId> {
+        let mut buffer = [0; RepeaterId::LENGTH];
+        reader.read_exact(&mut buffer)?;
+        let length = buffer.iter().position(|&c| c == 0).unwrap_or(buffer.len());
+        let string = core::str::from_utf8(&buffer[..length])
+            .map_err(|_| Error::Unexpected("repeater ID"))?;
+        match string.strip_prefix("ID:") {
+            Some(id) => id
+                .parse()
+                .map(RepeaterId::Id)
+                .map_err(|_| Error::Unexpected("repeater ID")),
+            None => Ok(RepeaterId::Server(String::from(string))),
+        }
+    }
+
+    fn write_to<W: Write>(&self, writer: &mut W) -> Result<()> {
+        let string = match self {
+            RepeaterId::Id(id) => alloc::format!("ID:{}", id),
+            RepeaterId::Server(address) => address.clone(),
+        };
+        // At least one NUL has to end the string.
+        if string.len() >= RepeaterId::LENGTH || string.contains('\0') {
+            return Err(Error::Unexpected("repeater ID length"));
+        }
+        let mut buffer = [0; RepeaterId::LENGTH];
+        buffer[..string.len()].copy_from_slice(string.as_bytes());
+        writer.write_all(&buffer)?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SecurityType {
+    Unknown(u8),
+    // core spec
+    Invalid,
+    None,
+    VncAuthentication,
+    // extensions
+    Tight,
+    VeNCrypt,
+    Sasl,
+    AppleRemoteDesktop,
+}
+
+impl Message for SecurityType {
+    fn read_from<R: Read>(reader: &mut R) -> Result<SecurityType> {
+        let security_type = reader.read_u8()?;
+        match security_type {
+            0 => Ok(SecurityType::Invalid),
+            1 => Ok(SecurityType::None),
+            2 => Ok(SecurityType::VncAuthentication),
+            16 => Ok(SecurityType::Tight),
+            19 => Ok(SecurityType::VeNCrypt),
+            20 => Ok(SecurityType::Sasl),
+            30 => Ok(SecurityType::AppleRemoteDesktop),
+            n => Ok(SecurityType::Unknown(n)),
+        }
+    }
+
+    fn write_to<W: Write>(&self, writer: &mut W) -> Result<()> {
+        let security_type = match self {
+            SecurityType::Invalid => 0,
+            SecurityType::None => 1,
+            SecurityType::VncAuthentication => 2,
+            SecurityType::Tight => 16,
+            SecurityType::VeNCrypt => 19,
+            SecurityType::Sasl => 20,
+            SecurityType::AppleRemoteDesktop => 30,
+            SecurityType::Unknown(n) => *n,
+        };
+        writer.write_u8(security_type)?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SecurityTypes(pub Vec<SecurityType>);
+
+impl Message for SecurityTypes {
+    fn read_from<R: Read>(reader: &mut R) -> Result<SecurityTypes> {
+        let count = reader.read_u8()?;
+        let mut security_types = Vec::new();
+        for _ in 0..count {
+            security_types.push(SecurityType::read_from(reader)?)
+        }
+        Ok(SecurityTypes(security_types))
+    }
+
+    fn write_to<W: Write>(&self, writer: &mut W) -> Result<()> {
+        let count = self.0.len() as u8; // TODO: check?
+        writer.write_u8(count)?;
+        for security_type in &self.0 {
+            security_type.write_to(writer)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SecurityResult {
+    Succeeded,
+    Failed,
+}
+
+impl Message for SecurityResult {
+    fn read_from<R: Read>(reader: &mut R) -> Result<SecurityResult> {
+        let result = reader.read_u32::<BigEndian>()?;
+        match result {
+            0 => Ok(SecurityResult::Succeeded),
+            1 => Ok(SecurityResult::Failed),
+            _ => Err(Error::Unexpected("security result")),
+        }
+    }
+
+    fn write_to<W: Write>(&self, writer: &mut W) -> Result<()> {
+        let result = match self {
+            SecurityResult::Succeeded => 0,
+            SecurityResult::Failed => 1,
+        };
+        writer.write_u32::<BigEndian>(result)?;
+        Ok(())
+    }
+}
+
+/// The VeNCrypt version, offered by the server and then picked by the client.
+/// The server answers the client's with a byte, 0 if it accepts it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct VeNCryptVersion {
+    pub major: u8,
+    pub minor: u8,
+}
+
+impl VeNCryptVersion {
+    /// The version with 32-bit sub-types, the only one in use.
+    pub const V0_2: VeNCryptVersion = VeNCryptVersion { major: 0, minor: 2 };
+}
+
+impl Message for VeNCryptVersion {
+    fn read_from<R: Read>(reader: &mut R) -> Result<VeNCryptVersion> {
+        Ok(VeNCryptVersion {
+            major: reader.read_u8()?,
+            minor: reader.read_u8()?,
+        })
+    }
+
+    fn write_to<W: Write>(&self, writer: &mut W) -> Result<()> {
+        writer.write_u8(self.major)?;
+        writer.write_u8(self.minor)?;
+        Ok(())
+    }
+}
+
+/// An authentication scheme within VeNCrypt: a plain security type, or one
+/// run over a TLS channel that is either anonymous or, for the X509 ones,
+/// authenticated with a server certificate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VeNCryptSubType {
+    Unknown(u32),
+    None,
+    VncAuthentication,
+    Plain,
+    TlsNone,
+    TlsVnc,
+    TlsPlain,
+    X509None,
+    X509Vnc,
+    X509Plain,
+}
+
+impl VeNCryptSubType {
+    /// Whether the channel has to be upgraded to TLS before authenticating.
+    pub fn uses_tls(&self) -> bool {
+        !matches!(
+            self,
+            VeNCryptSubType::None
+                | VeNCryptSubType::VncAuthentication
+                | VeNCryptSubType::Plain
+                | VeNCryptSubType::Unknown(_)
+        )
+    }
+}
+
+impl Message for VeNCryptSubType {
+    fn read_from<R: Read>(reader: &mut R) -> Result<VeNCryptSubType> {
+        let sub_type = reader.read_u32::<BigEndian>()?;
+        match sub_type {
+            1 => Ok(VeNCryptSubType::None),
+            2 => Ok(VeNCryptSubType::VncAuthentication),
+            256 => Ok(VeNCryptSubType::Plain),
+            257 => Ok(VeNCryptSubType::TlsNone),
+            258 => Ok(VeNCryptSubType::TlsVnc),
+            259 => Ok(VeNCryptSubType::TlsPlain),
+            260 => Ok(VeNCryptSubType::X509None),
+            261 => Ok(VeNCryptSubType::X509Vnc),
+            262 => Ok(VeNCryptSubType::X509Plain),
+            n => Ok(VeNCryptSubType::Unknown(n)),
+        }
+    }
+
+    fn write_to<W: Write>(&self, writer: &mut W) -> Result<()> {
+        let sub_type = match self {
+            VeNCryptSubType::None => 1,
+            VeNCryptSubType::VncAuthentication => 2,
+            VeNCryptSubType::Plain => 256,
+            VeNCryptSubType::TlsNone => 257,
+            VeNCryptSubType::TlsVnc => 258,
+            VeNCryptSubType::TlsPlain => 259,
+            VeNCryptSubType::X509None => 260,
+            VeNCryptSubType::X509Vnc => 261,
+            VeNCryptSubType::X509Plain => 262,
+            VeNCryptSubType::Unknown(n) => *n,
+        };
+        writer.write_u32::<BigEndian>(sub_type)?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VeNCryptSubTypes(pub Vec<VeNCryptSubType>);
+
+impl Message for VeNCryptSubTypes {
+    fn read_from<R: Read>(reader: &mut R) -> Result<VeNCryptSubTypes> {
+        let count = reader.read_u8()?;
+        let mut sub_types = Vec::new();
+        for _ in 0..count {
+            sub_types.push(VeNCryptSubType::read_from(reader)?)
+        }
+        Ok(VeNCryptSubTypes(sub_types))
+    }
+
+    fn write_to<W: Write>(&self, writer: &mut W) -> Result<()> {
+        if self.0.len() > u8::MAX as usize {
+            return Err(Error::Unexpected("more than 255 VeNCrypt sub-types"));
+        }
+        writer.write_u8(self.0.len() as u8)?;
+        for sub_type in &self.0 {
+            sub_type.write_to(writer)?;
+        }
+        Ok(())
+    }
+}
+
+/// The credentials of the VeNCrypt Plain sub-types, as UTF-8. They are only
+/// protected if the sub-type uses TLS.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VeNCryptPlain {
+    pub username: String,
+    pub password: String,
+}
+
+impl Message for VeNCryptPlain {
+    fn read_from<R: Read>(reader: &mut R) -> Result<VeNCryptPlain> {
+        let username_length = reader.read_u32::<BigEndian>()?;
+        let password_length = reader.read_u32::<BigEndian>()?;
+        let username = read_bytes(reader, username_length as usize)?;
+        let password = read_bytes(reader, password_length as usize)?;
+        Ok(VeNCryptPlain {
+            username: String::from_utf8_lossy(&username).into_owned(),
+            password: String::from_utf8_lossy(&password).into_owned(),
+        })
+    }
+
+    fn write_to<W: Write>(&self, writer: &mut W) -> Result<()> {
+        writer.write_u32::<BigEndian>(self.username.len() as u32)?;
+        writer.write_u32::<BigEndian>(self.password.len() as u32)?;
+        writer.write_all(self.username.as_bytes())?;
+        writer.write_all(self.password.as_bytes())?;
+        Ok(())
+    }
+}
+
+/// Something a TightVNC server or client supports: a tunnel, an
+/// authentication scheme, a message type or an encoding, identified by its
+/// number and by a vendor and name, e.g. `STDV` and `NOAUTH__`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TightCapability {
+    pub code: i32,
+    pub vendor: [u8; 4],
+    pub name: [u8; 8],
+}
+
+impl TightCapability {
+    /// The tunnel that leaves the connection as it is.
+    pub const NO_TUNNEL: i32 = 0;
+    pub const AUTH_NONE: i32 = 1;
+    pub const AUTH_VNC: i32 = 2;
+}
+
+impl Message for TightCapability {
+    fn read_from<R: Read>(reader: &mut R) -> Result<TightCapability> {
+        let code = reader.read_i32::<BigEndian>()?;
+        let mut vendor = [0; 4];
+        reader.read_exact(&mut vendor)?;
+        let mut name = [0; 8];
+        reader.read_exact(&mut name)?;
+        Ok(TightCapability { code, vendor, name })
+    }
+
+    fn write_to<W: Write>(&self, writer: &mut W) -> Result<()> {
+        writer.write_i32::<BigEndian>(self.code)?;
+        writer.write_all(&self.vendor)?;
+        writer.write_all(&self.name)?;
+        Ok(())
+    }
+}
+
+/// The tunnels or authentication schemes the server offers within the Tight
+/// security type. The client answers a non-empty list with the code it picked.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TightCapabilities(pub Vec<TightCapability>);
+
+impl Message for TightCapabilities {
+    fn read_from<R: Read>(reader: &mut R) -> Result<TightCapabilities> {
+        let count = reader.read_u32::<BigEndian>()?;
+        let mut capabilities = Vec::new();
+        for _ in 0..count {
+            capabilities.push(TightCapability::read_from(reader)?)
+        }
+        Ok(TightCapabilities(capabilities))
+    }
+
+    fn write_to<W: Write>(&self, writer: &mut W) -> Result<()> {
+        writer.write_u32::<BigEndian>(self.0.len() as u32)?;
+        for capability in &self.0 {
+            capability.write_to(writer)?;
+        }
+        Ok(())
+    }
+}
+
+/// The messages and encodings a server supports, which it sends right after
+/// ServerInit if the Tight security type was used.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TightInteraction {
+    pub server_messages: Vec<TightCapability>,
+    pub client_messages: Vec<TightCapability>,
+    pub encodings: Vec<TightCapability>,
+}
+
+impl Message for TightInteraction {
+    fn read_from<R: Read>(reader: &mut R) -> Result<TightInteraction> {
+        let server_messages = reader.read_u16::<BigEndian>()?;
+        let client_messages = reader.read_u16::<BigEndian>()?;
+        let encodings = reader.read_u16::<BigEndian>()?;
+        reader.read_exact(&mut [0u8; 2])?;
+        let mut read_list = |count| -> Result<Vec<TightCapability>> {
+            (0..count)
+                .map(|_| TightCapability::read_from(reader))
+                .collect()
+        };
+        Ok(TightInteraction {
+            server_messages: read_list(server_messages)?,
+            client_messages: read_list(client_messages)?,
+            encodings: read_list(encodings)?,
+        })
+    }
+
+    fn write_to<W: Write>(&self, writer: &mut W) -> Result<()> {
+        let lists = [
+            &self.server_messages,
+            &self.client_messages,
+            &self.encodings,
+        ];
+        for list in lists {
+            if list.len() > u16::MAX as usize {
+                return Err(Error::Unexpected("more than 65535 Tight capabilities"));
+            }
+            writer.write_u16::<BigEndian>(list.len() as u16)?;
+        }
+        writer.write_all(&[0u8; 2])?;
+        for capability in lists.into_iter().flatten() {
+            capability.write_to(writer)?;
+        }
+        Ok(())
+    }
+}
+
+#[allow(dead_code)]
+#[derive(Debug)]
+pub struct AppleAuthHandshake {
+    #[allow(dead_code)]
+    pub generator: u16,
+    #[allow(dead_code)]
+    pub prime: Vec<u8>,
+    #[allow(dead_code)]
+    pub peer_key: Vec<u8>,
+}
+
+impl Message for AppleAuthHandshake {
+    fn read_from<R: Read>(reader: &mut R) -> Result<Self> {
+        let generator = reader.read_u16::<BigEndian>()?;
+        let key_length = reader.read_u16::<BigEndian>()?;
+
+        let mut prime = vec![0; key_length as usize];
+        reader.read_exact(&mut prime)?;
+
+        let mut peer_key = vec![0; key_length as usize];
+        reader.read_exact(&mut peer_key)?;
+
+        Ok(AppleAuthHandshake {
+            generator,
+            prime,
+            peer_key,
+        })
+    }
+
+    fn write_to<W: Write>(&self, _writer: &mut W) -> Result<()> {
+        unreachable!()
+    }
+}
+
+#[allow(dead_code)]
+pub struct AppleAuthResponse {
+    pub ciphertext: [u8; 128],
+    pub pub_key: Vec<u8>,
+}
+
+impl Message for AppleAuthResponse {
+    fn read_from<R: Read>(_reader: &mut R) -> Result<Self> {
+        unreachable!()
+    }
+
+    fn write_to<W: Write>(&self, writer: &mut W) -> Result<()> {
+        writer.write_all(&self.ciphertext)?;
+        writer.write_all(&self.pub_key)?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientInit {
+    pub shared: bool,
+}
+
+impl Message for ClientInit {
+    fn read_from<R: Read>(reader: &mut R) -> Result<ClientInit> {
+        Ok(ClientInit {
+            shared: reader.read_u8()? != 0,
+        })
+    }
+
+    fn write_to<W: Write>(&self, writer: &mut W) -> Result<()> {
+        writer.write_u8(if self.shared { 1 } else { 0 })?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PixelFormat {
+    pub bits_per_pixel: u8,
+    pub depth: u8,
+    pub big_endian: bool,
+    pub true_colour: bool,
+    pub red_max: u16,
+    pub green_max: u16,
+    pub blue_max: u16,
+    pub red_shift: u8,
+    pub green_shift: u8,
+    pub blue_shift: u8,
+}
+
+impl Message for PixelFormat {
+    fn read_from<R: Read>(reader: &mut R) -> Result<PixelFormat> {
+        let pixel_format = PixelFormat {
+            bits_per_pixel: reader.read_u8()?,
+            depth: reader.read_u8()?,
+            big_endian: reader.read_u8()? != 0,
+            true_colour: reader.read_u8()? != 0,
+            red_max: reader.read_u16::<BigEndian>()?,
+            green_max: reader.read_u16::<BigEndian>()?,
+            blue_max: reader.read_u16::<BigEndian>()?,
+            red_shift: reader.read_u8()?,
+            green_shift: reader.read_u8()?,
+            blue_shift: reader.read_u8()?,
+        };
+        reader.read_exact(&mut [0u8; 3])?;
+        Ok(pixel_format)
+    }
+
+    fn write_to<W: Write>(&self, writer: &mut W) -> Result<()> {
+        writer.write_u8(self.bits_per_pixel)?;
+        writer.write_u8(self.depth)?;
+        writer.write_u8(if self.big_endian { 1 } else { 0 })?;
+        writer.write_u8(if self.true_colour { 1 } else { 0 })?;
+        writer.write_u16::<BigEndian>(self.red_max)?;
+        writer.write_u16::<BigEndian>(self.green_max)?;
+        writer.write_u16::<BigEndian>(self.blue_max)?;
+        writer.write_u8(self.red_shift)?;
+        writer.write_u8(self.green_shift)?;
+        writer.write_u8(self.blue_shift)?;
+        writer.write_all(&[0u8; 3])?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServerInit {
+    pub framebuffer_width: u16,
+    pub framebuffer_height: u16,
+    pub pixel_format: PixelFormat,
+    pub name: String,
+}
+
+impl Message for ServerInit {
+    fn read_from<R: Read>(reader: &mut R) -> Result<ServerInit> {
+        Ok(ServerInit {
+            framebuffer_width: reader.read_u16::<BigEndian>()?,
+            framebuffer_height: reader.read_u16::<BigEndian>()?,
+            pixel_format: PixelFormat::read_from(reader)?,
+            name: String::read_from(reader)?,
+        })
+    }
+
+    fn write_to<W: Write>(&self, writer: &mut W) -> Result<()> {
+        writer.write_u16::<BigEndian>(self.framebuffer_width)?;
+        writer.write_u16::<BigEndian>(self.framebuffer_height)?;
+        PixelFormat::write_to(&self.pixel_format, writer)?;
+        String::write_to(&self.name, writer)?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CopyRect {
+    pub src_x_position: u16,
+    pub src_y_position: u16,
+}
+
+impl Message for CopyRect {
+    fn read_from<R: Read>(reader: &mut R) -> Result<CopyRect> {
+        Ok(CopyRect {
+            src_x_position: reader.read_u16::<BigEndian>()?,
+            src_y_position: reader.read_u16::<BigEndian>()?,
+        })
+    }
+
+    fn write_to<W: Write>(&self, writer: &mut W) -> Result<()> {
+        writer.write_u16::<BigEndian>(self.src_x_position)?;
+        writer.write_u16::<BigEndian>(self.src_y_position)?;
+        Ok(())
+    }
+}
+
+/// One monitor of a multi-head framebuffer, as described by the
+/// ExtendedDesktopSize extension.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Screen {
+    pub id: u32,
+    pub x_position: u16,
+    pub y_position: u16,
+    pub width: u16,
+    pub height: u16,
+    pub flags: u32,
+}
+
+impl Message for Screen {
+    fn read_from<R: Read>(reader: &mut R) -> Result<Screen> {
+        Ok(Screen {
+            id: reader.read_u32::<BigEndian>()?,
+            x_position: reader.read_u16::<BigEndian>()?,
+            y_position: reader.read_u16::<BigEndian>()?,
+            width: reader.read_u16::<BigEndian>()?,
+            height: reader.read_u16::<BigEndian>()?,
+            flags: reader.read_u32::<BigEndian>()?,
+        })
+    }
+
+    fn write_to<W: Write>(&self, writer: &mut W) -> Result<()> {
+        writer.write_u32::<BigEndian>(self.id)?;
+        writer.write_u16::<BigEndian>(self.x_position)?;
+        writer.write_u16::<BigEndian>(self.y_position)?;
+        writer.write_u16::<BigEndian>(self.width)?;
+        writer.write_u16::<BigEndian>(self.height)?;
+        writer.write_u32::<BigEndian>(self.flags)?;
+        Ok(())
+    }
+}
+
+/// The screens following an `Encoding::ExtendedDesktopSize` rectangle.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScreenLayout(pub Vec<Screen>);
+
+impl Message for ScreenLayout {
+    fn read_from<R: Read>(reader: &mut R) -> Result<ScreenLayout> {
+        let count = reader.read_u8()?;
+        let mut padding = [0; 3];
+        reader.read_exact(&mut padding)?;
+        let mut screens = Vec::new();
+        for _ in 0..count {
+            screens.push(Screen::read_from(reader)?)
+        }
+        Ok(ScreenLayout(screens))
+    }
+
+    fn write_to<W: Write>(&self, writer: &mut W) -> Result<()> {
+        if self.0.len() > u8::MAX as usize {
+            return Err(Error::Unexpected("more than 255 screens"));
+        }
+        writer.write_u8(self.0.len() as u8)?;
+        writer.write_all(&[0; 3])?;
+        for screen in &self.0 {
+            screen.write_to(writer)?;
+        }
+        Ok(())
+    }
+}
+
+/// The new desktop name following an `Encoding::DesktopName` rectangle.
+/// Unlike the name in ServerInit, it is UTF-8; invalid sequences are replaced
+/// when reading.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DesktopName(pub String);
+
+impl Message for DesktopName {
+    fn read_from<R: Read>(reader: &mut R) -> Result<DesktopName> {
+        let length = reader.read_u32::<BigEndian>()?;
+        let name = read_bytes(reader, length as usize)?;
+        Ok(DesktopName(String::from_utf8_lossy(&name).into_owned()))
+    }
+
+    fn write_to<W: Write>(&self, writer: &mut W) -> Result<()> {
+        writer.write_u32::<BigEndian>(self.0.len() as u32)?;
+        writer.write_all(self.0.as_bytes())?;
+        Ok(())
+    }
+}
+
+/// A Fence message, sent in either direction once the server has answered
+/// `Encoding::Fence` with one. The side receiving a request answers it with
+/// the same payload, the flags it understood and `REQUEST` cleared.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fence {
+    pub flags: u32,
+    pub payload: Vec<u8>,
+}
+
+impl Fence {
+    /// Messages before the fence are handled before it.
+    pub const BLOCK_BEFORE: u32 = 1 << 0;
+    /// Messages after the fence are handled only after it has been answered.
+    pub const BLOCK_AFTER: u32 = 1 << 1;
+    /// The message following the fence is handled along with its answer.
+    pub const SYNC_NEXT: u32 = 1 << 2;
+    /// The fence asks for an answer; answers have this flag cleared.
+    pub const REQUEST: u32 = 1 << 31;
+    /// The longest payload a fence can carry.
+    pub const MAX_PAYLOAD: usize = 64;
+}
+
+impl Message for Fence {
+    fn read_from<R: Read>(reader: &mut R) -> Result<Fence> {
+        reader.read_exact(&mut [0u8; 3])?;
+        let flags = reader.read_u32::<BigEndian>()?;
+        let length = reader.read_u8()? as usize;
+        if length > Fence::MAX_PAYLOAD {
+            return Err(Error::Unexpected("fence payload longer than 64 bytes"));
+        }
+        let payload = read_bytes(reader, length)?;
+        Ok(Fence { flags, payload })
+    }
+
+    fn write_to<W: Write>(&self, writer: &mut W) -> Result<()> {
+        if self.payload.len() > Fence::MAX_PAYLOAD {
+            return Err(Error::Unexpected("fence payload longer than 64 bytes"));
+        }
+        writer.write_all(&[0u8; 3])?;
+        writer.write_u32::<BigEndian>(self.flags)?;
+        writer.write_u8(self.payload.len() as u8)?;
+        writer.write_all(&self.payload)?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Unknown(i32),
+    // core spec
+    Raw,
+    CopyRect,
+    Rre,
+    Hextile,
+    Zrle,
+    Cursor,
+    DesktopSize,
+    // extensions
+    LastRect,
+    Zlib,
+    ZlibHex,
+    Trle,
+    Ultra,
+    Tight,
+    TightPng,
+    OpenH264,
+    LedState,
+    QemuPointerMotionChange,
+    /// Asks QEMU to accept `C2S::QemuExtendedKeyEvent`, which it acknowledges
+    /// with an empty rectangle of this encoding.
+    QemuExtendedKeyEvent,
+    ExtendedDesktopSize,
+    DesktopName,
+    Fence,
+    /// Asks for the gii extension, which the server confirms with a
+    /// `gii::ServerMessage::Version`.
+    Gii,
+    /// A cursor image with 8 bits of alpha per pixel, replacing the bit mask
+    /// of `Cursor`.
+    CursorWithAlpha,
+    /// Asks for compression level 0 (fastest) to 9 (smallest) from encodings
+    /// that have a choice, such as Zlib, ZRLE and the Tight family.
+    CompressionLevel(u8),
+}
+
+impl Message for Encoding {
+    fn read_from<R: Read>(reader: &mut R) -> Result<Encoding> {
+        let encoding = reader.read_i32::<BigEndian>()?;
+        match encoding {
+            0 => Ok(Encoding::Raw),
+            1 => Ok(Encoding::CopyRect),
+            2 => Ok(Encoding::Rre),
+            5 => Ok(Encoding::Hextile),
+            6 => Ok(Encoding::Zlib),
+            7 => Ok(Encoding::Tight),
+            8 => Ok(Encoding::ZlibHex),
+            9 => Ok(Encoding::Ultra),
+            15 => Ok(Encoding::Trle),
+            16 => Ok(Encoding::Zrle),
+            50 => Ok(Encoding::OpenH264),
+            -260 => Ok(Encoding::TightPng),
+            -239 => Ok(Encoding::Cursor),
+            -223 => Ok(Encoding::DesktopSize),
+            -224 => Ok(Encoding::LastRect),
+            -261 => Ok(Encoding::LedState),
+            -257 => Ok(Encoding::QemuPointerMotionChange),
+            -258 => Ok(Encoding::QemuExtendedKeyEvent),
+            -308 => Ok(Encoding::ExtendedDesktopSize),
+            -307 => Ok(Encoding::DesktopName),
+            -312 => Ok(Encoding::Fence),
+            -305 => Ok(Encoding::Gii),
+            -314 => Ok(Encoding::CursorWithAlpha),
+            -256..=-247 => Ok(Encoding::CompressionLevel((encoding + 256) as u8)),
+            n => Ok(Encoding::Unknown(n)),
+        }
+    }
+
+    fn write_to<W: Write>(&self, writer: &mut W) -> Result<()> {
+        let encoding = match self {
+            Encoding::Raw => 0,
+            Encoding::CopyRect => 1,
+            Encoding::Rre => 2,
+            Encoding::Hextile => 5,
+            Encoding::Zlib => 6,
+            Encoding::Tight => 7,
+            Encoding::ZlibHex => 8,
+            Encoding::Ultra => 9,
+            Encoding::Trle => 15,
+            Encoding::Zrle => 16,
+            Encoding::OpenH264 => 50,
+            Encoding::TightPng => -260,
+            Encoding::Cursor => -239,
+            Encoding::DesktopSize => -223,
+            Encoding::LastRect => -224,
+            Encoding::LedState => -261,
+            Encoding::QemuPointerMotionChange => -257,
+            Encoding::QemuExtendedKeyEvent => -258,
+            Encoding::ExtendedDesktopSize => -308,
+            Encoding::DesktopName => -307,
+            Encoding::Fence => -312,
+            Encoding::Gii => -305,
+            Encoding::CursorWithAlpha => -314,
+            Encoding::CompressionLevel(level @ 0..=9) => -256 + *level as i32,
+            Encoding::CompressionLevel(_) => return Err(Error::Unexpected("compression level")),
+            Encoding::Unknown(n) => *n,
+        };
+        writer.write_i32::<BigEndian>(encoding)?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum C2S {
+    // core spec
+    SetPixelFormat(PixelFormat),
+    SetEncodings(Vec<Encoding>),
+    FramebufferUpdateRequest {
+        incremental: bool,
+        x_position: u16,
+        y_position: u16,
+        width: u16,
+        height: u16,
+    },
+    KeyEvent {
+        down: bool,
+        key: u32,
+    },
+    PointerEvent {
+        button_mask: u8,
+        x_position: u16,
+        y_position: u16,
+    },
+    CutText(String),
+    // extensions
+    /// Asks the server for a new framebuffer size and screen layout; it answers
+    /// with an `Encoding::ExtendedDesktopSize` rectangle.
+    SetDesktopSize {
+        width: u16,
+        height: u16,
+        screens: Vec<Screen>,
+    },
+    Fence(Fence),
+    /// A key event carrying the XT scancode of the key besides its keysym.
+    /// Scancodes with an 0xe0 prefix have the high bit set instead, e.g. 0xc8
+    /// for 0xe0 0x48.
+    QemuExtendedKeyEvent {
+        down: bool,
+        keysym: u32,
+        keycode: u32,
+    },
+    Gii(gii::ClientMessage),
+}
+
+impl Message for C2S {
+    fn read_from<R: Read>(reader: &mut R) -> Result<C2S> {
+        let message_type = match reader.read_u8() {
+            Err(ref e) if e.kind() == IoErrorKind::UnexpectedEof => {
+                return Err(Error::Disconnected)
+            }
+            result => result?,
+        };
+        match message_type {
+            0 => {
+                reader.read_exact(&mut [0u8; 3])?;
+                Ok(C2S::SetPixelFormat(PixelFormat::read_from(reader)?))
+            }
+            2 => {
+                reader.read_exact(&mut [0u8; 1])?;
+                let count = reader.read_u16::<BigEndian>()?;
+                let mut encodings = Vec::new();
+                for _ in 0..count {
+                    encodings.push(Encoding::read_from(reader)?);
+                }
+                Ok(C2S::SetEncodings(encodings))
+            }
+            3 => Ok(C2S::FramebufferUpdateRequest {
+                incremental: reader.read_u8()? != 0,
+                x_position: reader.read_u16::<BigEndian>()?,
+                y_position: reader.read_u16::<BigEndian>()?,
+                width: reader.read_u16::<BigEndian>()?,
+                height: reader.read_u16::<BigEndian>()?,
+            }),
+            4 => {
+                let down = reader.read_u8()? != 0;
+                reader.read_exact(&mut [0u8; 2])?;
+                let key = reader.read_u32::<BigEndian>()?;
+                Ok(C2S::KeyEvent { down, key })
+            }
+            5 => Ok(C2S::PointerEvent {
+                button_mask: reader.read_u8()?,
+                x_position: reader.read_u16::<BigEndian>()?,
+                y_position: reader.read_u16::<BigEndian>()?,
+            }),
+            6 => {
+                reader.read_exact(&mut [0u8; 3])?;
+                Ok(C2S::CutText(String::read_from(reader)?))
+            }
+            251 => {
+                reader.read_exact(&mut [0u8; 1])?;
+                let width = reader.read_u16::<BigEndian>()?;
+                let height = reader.read_u16::<BigEndian>()?;
+                let count = reader.read_u8()?;
+                reader.read_exact(&mut [0u8; 1])?;
+                let mut screens = Vec::new();
+                for _ in 0..count {
+                    screens.push(Screen::read_from(reader)?);
+                }
+                Ok(C2S::SetDesktopSize {
+                    width,
+                    height,
+                    screens,
+                })
+            }
+            248 => Ok(C2S::Fence(Fence::read_from(reader)?)),
+            253 => Ok(C2S::Gii(gii::ClientMessage::read_from(reader)?)),
+            255 => match reader.read_u8()? {
+                0 => Ok(C2S::QemuExtendedKeyEvent {
+                    down: reader.read_u16::<BigEndian>()? != 0,
+                    keysym: reader.read_u32::<BigEndian>()?,
+                    keycode: reader.read_u32::<BigEndian>()?,
+                }),
+                _ => Err(Error::Unexpected("QEMU client message type")),
+            },
+            n => Err(Error::UnexpectedMessageType(n)),
+        }
+    }
+    fn write_to<W: Write>(&self, writer: &mut W) -> Result<()> {
+        match self {
+            C2S::SetPixelFormat(ref pixel_format) => {
+                writer.write_u8(0)?;
+                writer.write_all(&[0u8; 3])?;
+                PixelFormat::write_to(pixel_format, writer)?;
+            }
+            C2S::SetEncodings(ref encodings) => {
+                writer.write_u8(2)?;
+                writer.write_all(&[0u8; 1])?;
+                writer.write_u16::<BigEndian>(encodings.len() as u16)?; // TODO: check?
+                for encoding in encodings {
+                    Encoding::write_to(encoding, writer)?;
+                }
+            }
+            C2S::FramebufferUpdateRequest {
+                incremental,
+                x_position,
+                y_position,
+                width,
+                height,
+            } => {
+                writer.write_u8(3)?;
+                writer.write_u8(if *incremental { 1 } else { 0 })?;
+                writer.write_u16::<BigEndian>(*x_position)?;
+                writer.write_u16::<BigEndian>(*y_position)?;
+                writer.write_u16::<BigEndian>(*width)?;
+                writer.write_u16::<BigEndian>(*height)?;
+            }
+            C2S::KeyEvent { down, key } => {
+                writer.write_u8(4)?;
+                writer.write_u8(if *down { 1 } else { 0 })?;
+                writer.write_all(&[0u8; 2])?;
+                writer.write_u32::<BigEndian>(*key)?;
+            }
+            C2S::PointerEvent {
+                button_mask,
+                x_position,
+                y_position,
+            } => {
+                writer.write_u8(5)?;
+                writer.write_u8(*button_mask)?;
+                writer.write_u16::<BigEndian>(*x_position)?;
+                writer.write_u16::<BigEndian>(*y_position)?;
+            }
+            C2S::CutText(ref text) => {
+                writer.write_u8(6)?;
+                writer.write_all(&[0u8; 3])?;
+                String::write_to(text, writer)?;
+            }
+            C2S::SetDesktopSize {
+                width,
+                height,
+                ref screens,
+            } => {
+                if screens.len() > u8::MAX as usize {
+                    return Err(Error::Unexpected("more than 255 screens"));
+                }
+                writer.write_u8(251)?;
+                writer.write_all(&[0u8; 1])?;
+                writer.write_u16::<BigEndian>(*width)?;
+                writer.write_u16::<BigEndian>(*height)?;
+                writer.write_u8(screens.len() as u8)?;
+                writer.write_all(&[0u8; 1])?;
+                for screen in screens {
+                    screen.write_to(writer)?;
+                }
+            }
+            C2S::Fence(ref fence) => {
+                writer.write_u8(248)?;
+                fence.write_to(writer)?;
+            }
+            C2S::QemuExtendedKeyEvent {
+                down,
+                keysym,
+                keycode,
+            } => {
+                writer.write_u8(255)?;
+                writer.write_u8(0)?;
+                writer.write_u16::<BigEndian>(if *down { 1 } else { 0 })?;
+                writer.write_u32::<BigEndian>(*keysym)?;
+                writer.write_u32::<BigEndian>(*keycode)?;
+            }
+            C2S::Gii(ref message) => {
+                writer.write_u8(253)?;
+                message.write_to(writer)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rectangle {
+    pub x_position: u16,
+    pub y_position: u16,
+    pub width: u16,
+    pub height: u16,
+    pub encoding: Encoding,
+}
+
+impl Message for Rectangle {
+    fn read_from<R: Read>(reader: &mut R) -> Result<Rectangle> {
+        Ok(Rectangle {
+            x_position: reader.read_u16::<BigEndian>()?,
+            y_position: reader.read_u16::<BigEndian>()?,
+            width: reader.read_u16::<BigEndian>()?,
+            height: reader.read_u16::<BigEndian>()?,
+            encoding: Encoding::read_from(reader)?,
+        })
+    }
+
+    fn write_to<W: Write>(&self, writer: &mut W) -> Result<()> {
+        writer.write_u16::<BigEndian>(self.x_position)?;
+        writer.write_u16::<BigEndian>(self.y_position)?;
+        writer.write_u16::<BigEndian>(self.width)?;
+        writer.write_u16::<BigEndian>(self.height)?;
+        Encoding::write_to(&self.encoding, writer)?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Colour {
+    pub red: u16,
+    pub green: u16,
+    pub blue: u16,
+}
+
+impl Message for Colour {
+    fn read_from<R: Read>(reader: &mut R) -> Result<Colour> {
+        Ok(Colour {
+            red: reader.read_u16::<BigEndian>()?,
+            green: reader.read_u16::<BigEndian>()?,
+            blue: reader.read_u16::<BigEndian>()?,
+        })
+    }
+
+    fn write_to<W: Write>(&self, writer: &mut W) -> Result<()> {
+        writer.write_u16::<BigEndian>(self.red)?;
+        writer.write_u16::<BigEndian>(self.green)?;
+        writer.write_u16::<BigEndian>(self.blue)?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum S2C {
+    // core spec
+    FramebufferUpdate {
+        count: u16,
+        /* Vec<Rectangle> has to be read out manually */
+    },
+    SetColourMapEntries {
+        first_colour: u16,
+        colours: Vec<Colour>,
+    },
+    Bell,
+    CutText(String),
+    // extensions
+    Fence(Fence),
+    Gii(gii::ServerMessage),
+}
+
+impl S2C {
+    pub fn message_type(&self) -> u8 {
+        match self {
+            S2C::FramebufferUpdate { .. } => 0,
+            S2C::SetColourMapEntries { .. } => 1,
+            S2C::Bell => 2,
+            S2C::CutText(_) => 3,
+            S2C::Fence(_) => 248,
+            S2C::Gii(_) => 253,
+        }
+    }
+
+    /// Checks that the message is allowed in a session using pixel format `format`.
+    /// This only covers the message itself; the rectangles of a framebuffer update
+    /// follow it on the wire.
+    pub fn validate(&self, format: &PixelFormat) -> Result<()> {
+        match self {
+            S2C::SetColourMapEntries {
+                first_colour,
+                ref colours,
+            } => {
+                // A colour map only exists in colour-mapped pixel formats, and only
+                // has as many entries as a pixel can index.
+                if format.true_colour {
+                    return Err(Error::Unexpected("SetColourMapEntries in true-colour mode"));
+                }
+                if *first_colour as usize + colours.len() > 1 << format.bits_per_pixel.min(16) {
+                    return Err(Error::Unexpected("colour map entry outside the colour map"));
+                }
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Reads a message like `read_from`, except that ServerCutText longer than
+    /// `max_cut_text` bytes is skipped without being kept in memory, and `None`
+    /// is returned for it.
+    pub fn read_from_limited<R: Read>(reader: &mut R, max_cut_text: usize) -> Result<Option<S2C>> {
+        let message_type = match reader.read_u8() {
+            Err(ref e) if e.kind() == IoErrorKind::UnexpectedEof => {
+                return Err(Error::Disconnected)
+            }
+            result => result?,
+        };
+        match message_type {
+            0 => {
+                reader.read_exact(&mut [0u8; 1])?;
+                Ok(Some(S2C::FramebufferUpdate {
+                    count: reader.read_u16::<BigEndian>()?,
+                }))
+            }
+            1 => {
+                reader.read_exact(&mut [0u8; 1])?;
+                let first_colour = reader.read_u16::<BigEndian>()?;
+                let count = reader.read_u16::<BigEndian>()?;
+                let mut colours = Vec::new();
+                for _ in 0..count {
+                    colours.push(Colour::read_from(reader)?);
+                }
+                Ok(Some(S2C::SetColourMapEntries {
+                    first_colour,
+                    colours,
+                }))
+            }
+            2 => Ok(Some(S2C::Bell)),
+            3 => {
+                reader.read_exact(&mut [0u8; 3])?;
+                let length = reader.read_u32::<BigEndian>()? as usize;
+                if length > max_cut_text {
+                    skip_bytes(reader, length)?;
+                    return Ok(None);
+                }
+                let text = read_bytes(reader, length)?;
+                Ok(Some(S2C::CutText(
+                    text.iter().map(|c| *c as char).collect(),
+                )))
+            }
+            248 => Ok(Some(S2C::Fence(Fence::read_from(reader)?))),
+            253 => Ok(Some(S2C::Gii(gii::ServerMessage::read_from(reader)?))),
+            n => Err(Error::UnexpectedMessageType(n)),
+        }
+    }
+}
+
+impl Message for S2C {
+    fn read_from<R: Read>(reader: &mut R) -> Result<S2C> {
+        Ok(S2C::read_from_limited(reader, usize::MAX)?.unwrap())
+    }
+
+    fn write_to<W: Write>(&self, writer: &mut W) -> Result<()> {
+        match self {
+            S2C::FramebufferUpdate { count } => {
+                writer.write_u8(0)?;
+                writer.write_all(&[0u8; 1])?;
+                writer.write_u16::<BigEndian>(*count)?;
+            }
+            S2C::SetColourMapEntries {
+                first_colour,
+                ref colours,
+            } => {
+                writer.write_u8(1)?;
+                writer.write_all(&[0u8; 1])?;
+                writer.write_u16::<BigEndian>(*first_colour)?;
+                writer.write_u16::<BigEndian>(colours.len() as u16)?;
+                for colour in colours {
+                    Colour::write_to(colour, writer)?;
+                }
+            }
+            S2C::Bell => {
+                writer.write_u8(2)?;
+            }
+            S2C::CutText(ref text) => {
+                writer.write_u8(3)?;
+                writer.write_all(&[0u8; 3])?;
+                String::write_to(text, writer)?;
+            }
+            S2C::Fence(ref fence) => {
+                writer.write_u8(248)?;
+                fence.write_to(writer)?;
+            }
+            S2C::Gii(ref message) => {
+                writer.write_u8(253)?;
+                message.write_to(writer)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::fmt::Debug;
+
+    #[test]
+    fn test_cut_text_latin1() {
+        let mut buffer = Vec::new();
+        C2S::CutText(String::from("caf\u{e9} \u{20ac}5"))
+            .write_to(&mut buffer)
+            .unwrap();
+        assert_eq!(buffer, b"\x06\0\0\0\0\0\0\x07caf\xe9 ?5");
+
+        match C2S::read_from(&mut &buffer[..]).unwrap() {
+            C2S::CutText(text) => assert_eq!(text, "caf\u{e9} ?5"),
+            message => panic!("unexpected {:?}", message),
+        }
+    }
+
+    #[test]
+    fn test_compression_level() {
+        let mut buffer = Vec::new();
+        Encoding::CompressionLevel(9).write_to(&mut buffer).unwrap();
+        assert_eq!(buffer, (-247i32).to_be_bytes());
+        assert_eq!(
+            Encoding::read_from(&mut &(-256i32).to_be_bytes()[..]).unwrap(),
+            Encoding::CompressionLevel(0)
+        );
+        assert!(Encoding::CompressionLevel(10)
+            .write_to(&mut Vec::new())
+            .is_err());
+    }
+
+    #[test]
+    fn test_cut_text_limit() {
+        let mut buffer = Vec::new();
+        S2C::CutText(String::from("x").repeat(100_000))
+            .write_to(&mut buffer)
+            .unwrap();
+        S2C::Bell.write_to(&mut buffer).unwrap();
+
+        let mut reader = &buffer[..];
+        assert_eq!(S2C::read_from_limited(&mut reader, 99_999).unwrap(), None);
+        assert_eq!(
+            S2C::read_from_limited(&mut reader, 99_999).unwrap(),
+            Some(S2C::Bell)
+        );
+
+        let mut reader = &buffer[..];
+        match S2C::read_from_limited(&mut reader, 100_000).unwrap() {
+            Some(S2C::CutText(text)) => assert_eq!(text.len(), 100_000),
+            message => panic!("unexpected {:?}", message),
+        }
+
+        // A length with nothing behind it fails instead of allocating it all.
+        let truncated = b"\x03\0\0\0\xff\xff\xff\xffxyz";
+        assert!(S2C::read_from(&mut &truncated[..]).is_err());
+    }
+
+    // A deterministic stand-in for a property testing framework: each round trip
+    // below is checked against a few thousand pseudo-random values.
+    struct Gen(u64);
+
+    impl Gen {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn u8(&mut self) -> u8 {
+            self.next() as u8
+        }
+
+        fn u16(&mut self) -> u16 {
+            self.next() as u16
+        }
+
+        fn u32(&mut self) -> u32 {
+            self.next() as u32
+        }
+
+        fn bool(&mut self) -> bool {
+            self.next() & 1 != 0
+        }
+
+        fn below(&mut self, n: usize) -> usize {
+            (self.next() % n as u64) as usize
+        }
+
+        fn vec<T>(&mut self, max: usize, mut f: impl FnMut(&mut Gen) -> T) -> Vec<T> {
+            (0..self.below(max + 1)).map(|_| f(self)).collect()
+        }
+
+        // Only Latin-1 survives the trip through the wire.
+        fn string(&mut self) -> String {
+            self.vec(20, |gen| gen.u8() as char).into_iter().collect()
+        }
+
+        fn pixel_format(&mut self) -> PixelFormat {
+            PixelFormat {
+                bits_per_pixel: self.u8(),
+                depth: self.u8(),
+                big_endian: self.bool(),
+                true_colour: self.bool(),
+                red_max: self.u16(),
+                green_max: self.u16(),
+                blue_max: self.u16(),
+                red_shift: self.u8(),
+                green_shift: self.u8(),
+                blue_shift: self.u8(),
+            }
+        }
+
+        // Values that have a variant of their own are never generated as `Unknown`;
+        // those are expected to decode to the named variant.
+        fn encoding(&mut self) -> Encoding {
+            let known = [
+                Encoding::Raw,
+                Encoding::CopyRect,
+                Encoding::Rre,
+                Encoding::Hextile,
+                Encoding::Zlib,
+                Encoding::ZlibHex,
+                Encoding::Trle,
+                Encoding::Ultra,
+                Encoding::Tight,
+                Encoding::TightPng,
+                Encoding::Zrle,
+                Encoding::OpenH264,
+                Encoding::Cursor,
+                Encoding::DesktopSize,
+                Encoding::LastRect,
+                Encoding::LedState,
+                Encoding::QemuPointerMotionChange,
+                Encoding::QemuExtendedKeyEvent,
+                Encoding::ExtendedDesktopSize,
+                Encoding::DesktopName,
+                Encoding::Fence,
+                Encoding::Gii,
+                Encoding::CursorWithAlpha,
+                Encoding::CompressionLevel(self.below(10) as u8),
+            ];
+            if self.bool() {
+                return known[self.below(known.len())];
+            }
+            let mut buffer = Vec::new();
+            Encoding::Unknown(self.u32() as i32)
+                .write_to(&mut buffer)
+                .unwrap();
+            Encoding::read_from(&mut &buffer[..]).unwrap()
+        }
+
+        fn security_type(&mut self) -> SecurityType {
+            SecurityType::read_from(&mut &[self.u8()][..]).unwrap()
+        }
+
+        fn vencrypt_sub_type(&mut self) -> VeNCryptSubType {
+            let known = [1, 2, 256, 257, 258, 259, 260, 261, 262];
+            let sub_type = match self.bool() {
+                true => known[self.below(known.len())],
+                false => self.u32(),
+            };
+            VeNCryptSubType::read_from(&mut &sub_type.to_be_bytes()[..]).unwrap()
+        }
+
+        fn tight_capability(&mut self) -> TightCapability {
+            TightCapability {
+                code: self.u32() as i32,
+                vendor: [self.u8(), self.u8(), self.u8(), self.u8()],
+                name: [0; 8].map(|_: u8| self.u8()),
+            }
+        }
+
+        fn rectangle(&mut self) -> Rectangle {
+            Rectangle {
+                x_position: self.u16(),
+                y_position: self.u16(),
+                width: self.u16(),
+                height: self.u16(),
+                encoding: self.encoding(),
+            }
+        }
+
+        fn screen(&mut self) -> Screen {
+            Screen {
+                id: self.u32(),
+                x_position: self.u16(),
+                y_position: self.u16(),
+                width: self.u16(),
+                height: self.u16(),
+                flags: self.u32(),
+            }
+        }
+
+        fn fence(&mut self) -> Fence {
+            Fence {
+                flags: self.u32(),
+                payload: self.vec(Fence::MAX_PAYLOAD, Gen::u8),
+            }
+        }
+
+        // Names are ASCII, as the fixed-size fields would cut other strings
+        // at arbitrary bytes.
+        fn name(&mut self, max: usize) -> String {
+            self.vec(max, |gen| (b'a' + gen.below(26) as u8) as char)
+                .into_iter()
+                .collect()
+        }
+
+        fn gii_event(&mut self) -> gii::InjectEvent {
+            let origin = self.u32();
+            match self.below(4) {
+                0 => gii::InjectEvent::Key {
+                    origin,
+                    action: [
+                        gii::KeyAction::Press,
+                        gii::KeyAction::Release,
+                        gii::KeyAction::Repeat,
+                    ][self.below(3)],
+                    modifiers: self.u32(),
+                    symbol: self.u32(),
+                    label: self.u32(),
+                    button: self.u32(),
+                },
+                1 => gii::InjectEvent::PointerMove {
+                    origin,
+                    relative: self.bool(),
+                    x: self.u32() as i32,
+                    y: self.u32() as i32,
+                    z: self.u32() as i32,
+                    wheel: self.u32() as i32,
+                },
+                2 => gii::InjectEvent::Button {
+                    origin,
+                    down: self.bool(),
+                    button: self.u32(),
+                },
+                _ => gii::InjectEvent::Valuators {
+                    origin,
+                    relative: self.bool(),
+                    first: self.u32(),
+                    values: self.vec(gii::MAX_VALUATOR_VALUES, |gen| gen.u32() as i32),
+                },
+            }
+        }
+
+        fn gii_valuator(&mut self) -> gii::Valuator {
+            gii::Valuator {
+                index: self.u32(),
+                long_name: self.name(74),
+                short_name: self.name(4),
+                range_min: self.u32() as i32,
+                range_center: self.u32() as i32,
+                range_max: self.u32() as i32,
+                si_unit: self.u32(),
+                si_add: self.u32() as i32,
+                si_mul: self.u32() as i32,
+                si_div: self.u32() as i32,
+                si_shift: self.u32() as i32,
+            }
+        }
+
+        fn gii_client_message(&mut self) -> gii::ClientMessage {
+            match self.below(4) {
+                0 => gii::ClientMessage::InjectEvents(self.vec(10, Gen::gii_event)),
+                1 => gii::ClientMessage::Version(self.u16()),
+                2 => gii::ClientMessage::CreateDevice(gii::Device {
+                    name: self.name(31),
+                    vendor_id: self.u32(),
+                    product_id: self.u32(),
+                    can_generate: self.u32(),
+                    num_registers: self.u32(),
+                    num_buttons: self.u32(),
+                    valuators: self.vec(4, Gen::gii_valuator),
+                }),
+                _ => gii::ClientMessage::DestroyDevice(self.u32()),
+            }
+        }
+
+        fn colour(&mut self) -> Colour {
+            Colour {
+                red: self.u16(),
+                green: self.u16(),
+                blue: self.u16(),
+            }
+        }
+
+        fn c2s(&mut self) -> C2S {
+            match self.below(10) {
+                0 => C2S::SetPixelFormat(self.pixel_format()),
+                1 => C2S::SetEncodings(self.vec(10, Gen::encoding)),
+                2 => C2S::FramebufferUpdateRequest {
+                    incremental: self.bool(),
+                    x_position: self.u16(),
+                    y_position: self.u16(),
+                    width: self.u16(),
+                    height: self.u16(),
+                },
+                3 => C2S::KeyEvent {
+                    down: self.bool(),
+                    key: self.u32(),
+                },
+                4 => C2S::PointerEvent {
+                    button_mask: self.u8(),
+                    x_position: self.u16(),
+                    y_position: self.u16(),
+                },
+                5 => C2S::CutText(self.string()),
+                6 => C2S::SetDesktopSize {
+                    width: self.u16(),
+                    height: self.u16(),
+                    screens: self.vec(10, Gen::screen),
+                },
+                7 => C2S::Fence(self.fence()),
+                8 => C2S::QemuExtendedKeyEvent {
+                    down: self.bool(),
+                    keysym: self.u32(),
+                    keycode: self.u32(),
+                },
+                _ => C2S::Gii(self.gii_client_message()),
+            }
+        }
+
+        fn s2c(&mut self) -> S2C {
+            match self.below(6) {
+                0 => S2C::FramebufferUpdate { count: self.u16() },
+                1 => S2C::SetColourMapEntries {
+                    first_colour: self.u16(),
+                    colours: self.vec(10, Gen::colour),
+                },
+                2 => S2C::Bell,
+                3 => S2C::CutText(self.string()),
+                4 => S2C::Fence(self.fence()),
+                _ => S2C::Gii(match self.bool() {
+                    true => gii::ServerMessage::Version {
+                        maximum: self.u16(),
+                        minimum: self.u16(),
+                    },
+                    false => gii::ServerMessage::DeviceCreated(self.u32()),
+                }),
+            }
+        }
+    }
+
+    fn check_round_trip<T, F>(mut generate: F)
+    where
+        T: Message + PartialEq + Debug,
+        F: FnMut(&mut Gen) -> T,
+    {
+        let mut gen = Gen(0x2545f4914f6cdd1d);
+        for _ in 0..2000 {
+            let message = generate(&mut gen);
+            let mut buffer = Vec::new();
+            message.write_to(&mut buffer).unwrap();
+            let mut reader = &buffer[..];
+            match T::read_from(&mut reader) {
+                Ok(decoded) => assert_eq!(decoded, message),
+                Err(error) => panic!("{:?} does not decode: {}", message, error),
+            }
+            assert!(reader.is_empty(), "{:?} left {:?} unread", message, reader);
+        }
+    }
+
+    #[test]
+    fn test_desktop_name_utf8() {
+        let mut buffer = Vec::new();
+        DesktopName(String::from("caf\u{e9} \u{20ac}5"))
+            .write_to(&mut buffer)
+            .unwrap();
+        assert_eq!(buffer, b"\0\0\0\x0acaf\xc3\xa9 \xe2\x82\xac5");
+
+        let data = b"\0\0\0\x02a\xff";
+        assert_eq!(
+            DesktopName::read_from(&mut &data[..]).unwrap(),
+            DesktopName(String::from("a\u{fffd}"))
+        );
+    }
+
+    #[test]
+    fn test_fence_payload_limit() {
+        let fence = Fence {
+            flags: Fence::REQUEST,
+            payload: vec![0; Fence::MAX_PAYLOAD + 1],
+        };
+        assert!(C2S::Fence(fence).write_to(&mut Vec::new()).is_err());
+        let data = b"\xf8\0\0\0\x80\0\0\0\x41";
+        assert!(S2C::read_from(&mut &data[..]).is_err());
+    }
+
+    #[test]
+    fn test_round_trip() {
+        check_round_trip(Gen::pixel_format);
+        check_round_trip(Gen::encoding);
+        check_round_trip(Gen::security_type);
+        check_round_trip(|gen| SecurityTypes(gen.vec(10, Gen::security_type)));
+        check_round_trip(|gen| VeNCryptVersion {
+            major: gen.u8(),
+            minor: gen.u8(),
+        });
+        check_round_trip(|gen| VeNCryptSubTypes(gen.vec(10, Gen::vencrypt_sub_type)));
+        check_round_trip(|gen| match gen.bool() {
+            true => RepeaterId::Id(gen.u32()),
+            false => RepeaterId::Server(alloc::format!("host{}:{}", gen.u8(), gen.u16())),
+        });
+        check_round_trip(|gen| VeNCryptPlain {
+            username: gen.string(),
+            password: gen.string(),
+        });
+        check_round_trip(|gen| TightCapabilities(gen.vec(10, Gen::tight_capability)));
+        check_round_trip(|gen| TightInteraction {
+            server_messages: gen.vec(10, Gen::tight_capability),
+            client_messages: gen.vec(10, Gen::tight_capability),
+            encodings: gen.vec(10, Gen::tight_capability),
+        });
+        check_round_trip(|gen| ClientInit { shared: gen.bool() });
+        check_round_trip(|gen| ServerInit {
+            framebuffer_width: gen.u16(),
+            framebuffer_height: gen.u16(),
+            pixel_format: gen.pixel_format(),
+            name: gen.string(),
+        });
+        check_round_trip(Gen::rectangle);
+        check_round_trip(|gen| CopyRect {
+            src_x_position: gen.u16(),
+            src_y_position: gen.u16(),
+        });
+        check_round_trip(|gen| ScreenLayout(gen.vec(10, Gen::screen)));
+        check_round_trip(|gen| DesktopName(gen.string()));
+        check_round_trip(Gen::colour);
+        check_round_trip(Gen::fence);
+        check_round_trip(Gen::c2s);
+        check_round_trip(Gen::s2c);
+        check_round_trip(|gen| gen.vec(100, Gen::u8));
+        check_round_trip(Gen::string);
+    }
+}
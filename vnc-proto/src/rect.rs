@@ -0,0 +1,282 @@
+use alloc::vec::Vec;
+use core::cmp::{max, min};
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Rect {
+    pub left: u16,
+    pub top: u16,
+    pub width: u16,
+    pub height: u16,
+}
+
+impl Rect {
+    pub const fn new(left: u16, top: u16, width: u16, height: u16) -> Rect {
+        Rect {
+            left,
+            top,
+            width,
+            height,
+        }
+    }
+
+    /// The rectangle at the origin covering a `width` by `height` framebuffer.
+    pub const fn with_size(width: u16, height: u16) -> Rect {
+        Rect::new(0, 0, width, height)
+    }
+
+    // The far edges can exceed u16 for rectangles near the end of the
+    // coordinate space, so they are computed in u32.
+    pub fn right(&self) -> u32 {
+        self.left as u32 + self.width as u32
+    }
+
+    pub fn bottom(&self) -> u32 {
+        self.top as u32 + self.height as u32
+    }
+
+    pub fn area(&self) -> usize {
+        self.width as usize * self.height as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.width == 0 || self.height == 0
+    }
+
+    pub fn contains(&self, x: u16, y: u16) -> bool {
+        x >= self.left && (x as u32) < self.right() && y >= self.top && (y as u32) < self.bottom()
+    }
+
+    pub fn contains_rect(&self, other: &Rect) -> bool {
+        other.left >= self.left
+            && other.top >= self.top
+            && other.right() <= self.right()
+            && other.bottom() <= self.bottom()
+    }
+
+    /// Returns the overlapping part of both rectangles, or `None` if they don't overlap.
+    pub fn intersection(&self, other: &Rect) -> Option<Rect> {
+        let left = max(self.left, other.left);
+        let top = max(self.top, other.top);
+        let right = min(self.right(), other.right());
+        let bottom = min(self.bottom(), other.bottom());
+        if right > left as u32 && bottom > top as u32 {
+            Some(Rect::new(
+                left,
+                top,
+                (right - left as u32) as u16,
+                (bottom - top as u32) as u16,
+            ))
+        } else {
+            None
+        }
+    }
+
+    /// Returns the smallest rectangle covering both rectangles. Empty rectangles are
+    /// ignored; the size saturates at the end of the coordinate space.
+    pub fn union(&self, other: &Rect) -> Rect {
+        if other.is_empty() {
+            return *self;
+        } else if self.is_empty() {
+            return *other;
+        }
+        let left = min(self.left, other.left);
+        let top = min(self.top, other.top);
+        let right = max(self.right(), other.right());
+        let bottom = max(self.bottom(), other.bottom());
+        Rect::new(
+            left,
+            top,
+            min(right - left as u32, u16::MAX as u32) as u16,
+            min(bottom - top as u32, u16::MAX as u32) as u16,
+        )
+    }
+
+    /// Returns the rectangle moved by `(dx, dy)`, or `None` if its origin would leave
+    /// the coordinate space.
+    pub fn translate(&self, dx: i32, dy: i32) -> Option<Rect> {
+        let left = u16::try_from(self.left as i32 + dx).ok()?;
+        let top = u16::try_from(self.top as i32 + dy).ok()?;
+        Some(Rect::new(left, top, self.width, self.height))
+    }
+
+    /// Returns the part of the rectangle that lies within a `width` by `height`
+    /// framebuffer, or `None` if there is none.
+    pub fn clip_to(&self, width: u16, height: u16) -> Option<Rect> {
+        self.intersection(&Rect::with_size(width, height))
+    }
+
+    /// Iterates over `size` by `size` tiles covering the rectangle, left to right and
+    /// top to bottom, with the tiles at the right and bottom edges cut short, as used
+    /// by the Hextile, TRLE and ZRLE encodings.
+    pub fn tiles(&self, size: u16) -> Tiles {
+        assert!(size > 0);
+        Tiles {
+            rect: *self,
+            size,
+            x: 0,
+            y: 0,
+        }
+    }
+}
+
+pub struct Tiles {
+    rect: Rect,
+    size: u16,
+    x: u16,
+    y: u16,
+}
+
+impl Iterator for Tiles {
+    type Item = Rect;
+
+    fn next(&mut self) -> Option<Rect> {
+        if self.rect.is_empty() || self.y >= self.rect.height {
+            return None;
+        }
+
+        let width = min(self.size, self.rect.width - self.x);
+        let height = min(self.size, self.rect.height - self.y);
+        let tile = Rect::new(
+            self.rect.left + self.x,
+            self.rect.top + self.y,
+            width,
+            height,
+        );
+
+        self.x += width;
+        if self.x >= self.rect.width {
+            self.x = 0;
+            self.y += height;
+        }
+        Some(tile)
+    }
+}
+
+/// A set of damaged rectangles, such as the ones making up one framebuffer update.
+///
+/// Rectangles are merged as they are added whenever their union covers no more
+/// than the two of them do, so rows of adjacent tiles collapse into strips and
+/// strips into blocks, while changes far apart stay separate.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Damage {
+    rects: Vec<Rect>,
+}
+
+impl Damage {
+    /// Beyond this many separate rectangles, everything is merged into one.
+    const MAX_RECTS: usize = 32;
+
+    pub fn new() -> Damage {
+        Damage::default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rects.is_empty()
+    }
+
+    pub fn rects(&self) -> &[Rect] {
+        &self.rects
+    }
+
+    /// Returns the smallest rectangle covering all the damage, if there is any.
+    pub fn bounds(&self) -> Option<Rect> {
+        self.rects.iter().copied().reduce(|a, b| a.union(&b))
+    }
+
+    pub fn add(&mut self, rect: Rect) {
+        if rect.is_empty() {
+            return;
+        }
+
+        let mut rect = rect;
+        while let Some(index) = self
+            .rects
+            .iter()
+            .position(|other| other.union(&rect).area() <= other.area() + rect.area())
+        {
+            rect = rect.union(&self.rects.swap_remove(index));
+        }
+        self.rects.push(rect);
+
+        if self.rects.len() > Damage::MAX_RECTS {
+            let bounds = self.bounds().unwrap();
+            self.rects.clear();
+            self.rects.push(bounds);
+        }
+    }
+
+    /// Removes and returns all the damage.
+    pub fn take(&mut self) -> Vec<Rect> {
+        core::mem::take(&mut self.rects)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Damage, Rect};
+    use alloc::vec::Vec;
+
+    #[test]
+    fn test_intersection_union() {
+        let a = Rect::new(10, 10, 20, 20);
+        let b = Rect::new(20, 25, 20, 20);
+        assert_eq!(a.intersection(&b), Some(Rect::new(20, 25, 10, 5)));
+        assert_eq!(a.union(&b), Rect::new(10, 10, 30, 35));
+        assert_eq!(a.intersection(&Rect::new(30, 10, 5, 5)), None);
+        assert_eq!(a.union(&Rect::new(0, 0, 0, 0)), a);
+        assert_eq!(
+            Rect::new(65000, 0, 535, 1).union(&Rect::new(0, 0, 1, 1)),
+            Rect::new(0, 0, 65535, 1)
+        );
+    }
+
+    #[test]
+    fn test_contains_translate_clip() {
+        let a = Rect::new(10, 10, 20, 20);
+        assert!(a.contains(10, 29) && !a.contains(30, 10));
+        assert!(a.contains_rect(&Rect::new(15, 15, 15, 15)));
+        assert!(!a.contains_rect(&Rect::new(15, 15, 16, 15)));
+        assert_eq!(a.translate(-10, 5), Some(Rect::new(0, 15, 20, 20)));
+        assert_eq!(a.translate(-11, 0), None);
+        assert_eq!(a.clip_to(25, 100), Some(Rect::new(10, 10, 15, 20)));
+        assert_eq!(a.clip_to(10, 10), None);
+    }
+
+    #[test]
+    fn test_tiles() {
+        let tiles = Rect::new(5, 5, 100, 70).tiles(64).collect::<Vec<_>>();
+        assert_eq!(
+            tiles,
+            [
+                Rect::new(5, 5, 64, 64),
+                Rect::new(69, 5, 36, 64),
+                Rect::new(5, 69, 64, 6),
+                Rect::new(69, 69, 36, 6),
+            ]
+        );
+        assert_eq!(Rect::new(0, 0, 0, 10).tiles(64).count(), 0);
+        assert_eq!(Rect::with_size(128, 128).tiles(64).count(), 4);
+    }
+
+    #[test]
+    fn test_damage() {
+        let mut damage = Damage::new();
+        for tile in Rect::new(10, 10, 300, 200).tiles(64) {
+            damage.add(tile);
+        }
+        assert_eq!(damage.rects(), [Rect::new(10, 10, 300, 200)]);
+
+        damage.add(Rect::new(1000, 1000, 10, 10));
+        damage.add(Rect::new(20, 20, 10, 10));
+        assert_eq!(damage.rects().len(), 2);
+        assert_eq!(damage.bounds(), Some(Rect::new(10, 10, 1000, 1000)));
+
+        assert_eq!(damage.take().len(), 2);
+        assert!(damage.is_empty());
+        for i in 0..100 {
+            damage.add(Rect::new(i * 10, i * 10, 1, 1));
+        }
+        assert!(damage.rects().len() <= Damage::MAX_RECTS);
+        assert_eq!(damage.bounds(), Some(Rect::new(0, 0, 991, 991)));
+    }
+}
@@ -0,0 +1,389 @@
+//! Pieces shared by the Tight family of encodings, and an encoder for Tight
+//! itself.
+
+use crate::io::{Read, ReadBytesExt};
+use crate::zrle::Deflate;
+use crate::{pixels, protocol, Error, Rect, Result};
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+
+// Compression types, in the high four bits of the control byte that starts a
+// rectangle. The types below `FILL` are basic compression; the low four bits
+// tell the decoder to reset zlib streams.
+pub const FILL: u8 = 0x8;
+pub const JPEG: u8 = 0x9;
+pub const PNG: u8 = 0xa;
+/// Set in the compression type of basic compression when a filter id follows.
+pub const EXPLICIT_FILTER: u8 = 0x4;
+
+// Filters of basic compression.
+pub const FILTER_COPY: u8 = 0;
+pub const FILTER_PALETTE: u8 = 1;
+pub const FILTER_GRADIENT: u8 = 2;
+
+/// Data after filtering that is shorter than this is sent without zlib.
+pub const MIN_TO_COMPRESS: usize = 12;
+
+/// The most pixels a Tight rectangle may have, and the widest it may be.
+/// Larger rectangles have to be split.
+pub const MAX_AREA: usize = 65536;
+pub const MAX_WIDTH: u16 = 2048;
+
+/// Reads a length in the compact representation: seven bits per byte, least
+/// significant first and the high bit marking that more follow, with the
+/// third byte contributing all eight.
+pub fn read_compact_length<R: Read>(reader: &mut R) -> Result<usize> {
+    let mut length = 0;
+    for shift in [0, 7] {
+        let byte = reader.read_u8()?;
+        length |= (byte as usize & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(length);
+        }
+    }
+    Ok(length | (reader.read_u8()? as usize) << 14)
+}
+
+/// Appends `length` in the compact representation; see `read_compact_length`.
+pub fn write_compact_length(length: usize, output: &mut Vec<u8>) {
+    if length < 1 << 7 {
+        output.push(length as u8)
+    } else if length < 1 << 14 {
+        output.extend_from_slice(&[length as u8 | 0x80, (length >> 7) as u8])
+    } else {
+        output.extend_from_slice(&[
+            length as u8 | 0x80,
+            (length >> 7) as u8 | 0x80,
+            (length >> 14) as u8,
+        ])
+    }
+}
+
+/// Whether pixels of `format` travel as TPIXELs of three bytes, red, green and
+/// blue, rather than as whole pixels.
+pub fn has_rgb_tpixels(format: protocol::PixelFormat) -> bool {
+    format.true_colour
+        && format.bits_per_pixel == 32
+        && format.depth == 24
+        && (format.red_max, format.green_max, format.blue_max) == (255, 255, 255)
+}
+
+/// Reads one TPIXEL, appending it to `pixels` as a pixel in `format`.
+pub fn read_tpixel<R: Read>(
+    reader: &mut R,
+    format: protocol::PixelFormat,
+    pixels: &mut Vec<u8>,
+) -> Result<()> {
+    let mut buf = [0; 4];
+    if has_rgb_tpixels(format) {
+        reader.read_exact(&mut buf[..3])?;
+        pixels::pack_rgb(format, &buf[..3], pixels);
+    } else {
+        let bpp = format.bits_per_pixel as usize / 8;
+        reader.read_exact(&mut buf[..bpp])?;
+        pixels.extend_from_slice(&buf[..bpp]);
+    }
+    Ok(())
+}
+
+/// Appends one `pixel` in `format` as a TPIXEL; the reverse of `read_tpixel`.
+pub fn write_tpixel(format: protocol::PixelFormat, pixel: &[u8], output: &mut Vec<u8>) {
+    if has_rgb_tpixels(format) {
+        pixels::unpack_rgb(format, pixel, output)
+    } else {
+        output.extend_from_slice(pixel)
+    }
+}
+
+// The zlib streams of the encoder, one for each kind of data as in TightVNC.
+const STREAM_FULL_COLOUR: usize = 0;
+const STREAM_MONO: usize = 1;
+const STREAM_INDEXED: usize = 2;
+
+/// Encodes rectangles as Tight, for servers: solid rectangles as fills, those
+/// of up to 256 colours with the palette filter and the others as they are,
+/// each kind of data in a zlib stream of its own that lasts the session. JPEG
+/// and the gradient filter are not used.
+pub struct Encoder {
+    streams: [Box<dyn Deflate + Send>; 3],
+    // Scratch buffers, as in the decoders. Pixels are kept as the bytes of the
+    // pixel in a little-endian u32.
+    palette: Vec<u32>,
+    indices: Vec<u8>,
+    data: Vec<u8>,
+    compressed: Vec<u8>,
+}
+
+#[cfg(feature = "std")]
+impl Default for Encoder {
+    fn default() -> Encoder {
+        Encoder::new()
+    }
+}
+
+impl Encoder {
+    #[cfg(feature = "std")]
+    pub fn new() -> Encoder {
+        Encoder::with_deflaters(core::array::from_fn(|_| {
+            Box::new(flate2::Compress::new(
+                flate2::Compression::default(),
+                /*zlib_header*/ true,
+            )) as Box<dyn Deflate + Send>
+        }))
+    }
+
+    /// Uses `streams` for full colour, two-colour and indexed data, in that
+    /// order.
+    pub fn with_deflaters(streams: [Box<dyn Deflate + Send>; 3]) -> Encoder {
+        Encoder {
+            streams,
+            palette: Vec::new(),
+            indices: Vec::new(),
+            data: Vec::new(),
+            compressed: Vec::new(),
+        }
+    }
+
+    /// Encodes `rect`, made of rows of packed `pixels` in the true colour
+    /// `format`, appending what follows the rectangle header to `output`. The
+    /// rectangle must be within `MAX_AREA` and `MAX_WIDTH`.
+    pub fn encode(
+        &mut self,
+        format: protocol::PixelFormat,
+        rect: Rect,
+        pixels: &[u8],
+        output: &mut Vec<u8>,
+    ) -> Result<()> {
+        let bpp = format.bits_per_pixel as usize / 8;
+        if pixels.len() != rect.area() * bpp {
+            return Err(Error::Unexpected("pixel data length"));
+        }
+        if rect.area() > MAX_AREA || rect.width > MAX_WIDTH {
+            return Err(Error::Unexpected("Tight rectangle size"));
+        }
+
+        let Encoder {
+            palette, indices, ..
+        } = self;
+        palette.clear();
+        indices.clear();
+        for pixel in pixels.chunks_exact(bpp) {
+            let mut bytes = [0; 4];
+            bytes[..bpp].copy_from_slice(pixel);
+            let pixel = u32::from_le_bytes(bytes);
+            let index = match palette.iter().position(|&colour| colour == pixel) {
+                Some(index) => index,
+                None if palette.len() < 256 => {
+                    palette.push(pixel);
+                    palette.len() - 1
+                }
+                None => {
+                    palette.clear();
+                    break;
+                }
+            };
+            indices.push(index as u8);
+        }
+
+        self.data.clear();
+        let stream = match self.palette.len() {
+            1 => {
+                output.push(FILL << 4);
+                write_tpixel(format, &pixels[..bpp], output);
+                return Ok(());
+            }
+            0 => {
+                output.push((STREAM_FULL_COLOUR as u8) << 4);
+                for pixel in pixels.chunks_exact(bpp) {
+                    write_tpixel(format, pixel, &mut self.data)
+                }
+                STREAM_FULL_COLOUR
+            }
+            colours => {
+                let stream = match colours {
+                    2 => STREAM_MONO,
+                    _ => STREAM_INDEXED,
+                };
+                output.push((EXPLICIT_FILTER | stream as u8) << 4);
+                output.push(FILTER_PALETTE);
+                output.push((colours - 1) as u8);
+                for colour in &self.palette {
+                    write_tpixel(format, &colour.to_le_bytes()[..bpp], output)
+                }
+                match colours {
+                    2 => {
+                        for row in self.indices.chunks(rect.width as usize) {
+                            for bits in row.chunks(8) {
+                                let byte = bits
+                                    .iter()
+                                    .enumerate()
+                                    .fold(0, |byte, (i, &bit)| byte | bit << (7 - i));
+                                self.data.push(byte)
+                            }
+                        }
+                    }
+                    _ => self.data.extend_from_slice(&self.indices),
+                }
+                stream
+            }
+        };
+
+        if self.data.len() < MIN_TO_COMPRESS {
+            output.extend_from_slice(&self.data);
+        } else {
+            self.compressed.clear();
+            self.streams[stream].deflate(&self.data, &mut self.compressed)?;
+            write_compact_length(self.compressed.len(), output);
+            output.extend_from_slice(&self.compressed);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        read_compact_length, read_tpixel, write_compact_length, Encoder, EXPLICIT_FILTER, FILL,
+        FILTER_PALETTE, MIN_TO_COMPRESS,
+    };
+    use crate::io::ReadBytesExt;
+    use crate::protocol::PixelFormat;
+    use crate::zrle::ZlibReader;
+    use crate::Rect;
+    use alloc::vec::Vec;
+
+    #[test]
+    fn test_compact_length() {
+        let read = |data: &[u8]| {
+            let mut reader = data;
+            let length = read_compact_length(&mut reader).unwrap();
+            assert!(reader.is_empty());
+            length
+        };
+        assert_eq!(read(&[0x05]), 5);
+        assert_eq!(read(&[0x90, 0x4e]), 10000);
+        assert_eq!(read(&[0xff, 0xff, 0xff]), 4194303);
+
+        for length in [5, 10000, 4194303] {
+            let mut output = Vec::new();
+            write_compact_length(length, &mut output);
+            assert_eq!(read(&output), length);
+        }
+    }
+
+    /// Decodes what `Encoder` produces, with one decompressor per stream.
+    fn decode(
+        streams: &mut [flate2::Decompress],
+        format: PixelFormat,
+        rect: Rect,
+        reader: &mut &[u8],
+    ) -> Vec<u8> {
+        let control = reader.read_u8().unwrap();
+        let mut pixels = Vec::new();
+        if control >> 4 == FILL {
+            read_tpixel(reader, format, &mut pixels).unwrap();
+            return pixels.repeat(rect.area());
+        }
+        let stream = (control >> 4 & 3) as usize;
+        let mut palette = Vec::new();
+        if control >> 4 & EXPLICIT_FILTER != 0 {
+            assert_eq!(reader.read_u8().unwrap(), FILTER_PALETTE);
+            for _ in 0..reader.read_u8().unwrap() as usize + 1 {
+                read_tpixel(reader, format, &mut palette).unwrap();
+            }
+        }
+        let bpp = format.bits_per_pixel as usize / 8;
+        let length = match palette.len() / bpp {
+            0 => rect.area() * 3,
+            2 => (rect.width as usize).div_ceil(8) * rect.height as usize,
+            _ => rect.area(),
+        };
+        let mut data = Vec::new();
+        if length < MIN_TO_COMPRESS {
+            data.extend_from_slice(&reader[..length]);
+            *reader = &reader[length..];
+        } else {
+            let compressed_length = read_compact_length(reader).unwrap();
+            ZlibReader::new(&mut streams[stream], &reader[..compressed_length])
+                .read_to_end(&mut data)
+                .unwrap();
+            *reader = &reader[compressed_length..];
+        }
+        assert_eq!(data.len(), length);
+
+        let colour = |index: usize| &palette[index * bpp..(index + 1) * bpp];
+        match palette.len() / bpp {
+            0 => data
+                .chunks(3)
+                .for_each(|rgb| crate::pixels::pack_rgb(format, rgb, &mut pixels)),
+            2 => {
+                for row in data.chunks((rect.width as usize).div_ceil(8)) {
+                    for x in 0..rect.width as usize {
+                        let bit = row[x / 8] >> (7 - x % 8) & 1;
+                        pixels.extend_from_slice(colour(bit as usize))
+                    }
+                }
+            }
+            _ => data
+                .iter()
+                .for_each(|&index| pixels.extend_from_slice(colour(index as usize))),
+        }
+        pixels
+    }
+
+    #[test]
+    fn test_encoder() {
+        let format = PixelFormat {
+            bits_per_pixel: 32,
+            depth: 24,
+            big_endian: false,
+            true_colour: true,
+            red_max: 255,
+            green_max: 255,
+            blue_max: 255,
+            red_shift: 16,
+            green_shift: 8,
+            blue_shift: 0,
+        };
+        let rect = Rect::new(5, 5, 100, 30);
+        let pixels_of = |pixel: &dyn Fn(u32, u32) -> u32| {
+            (0..rect.height as u32)
+                .flat_map(|y| (0..rect.width as u32).map(move |x| (x, y)))
+                .flat_map(|(x, y)| (pixel(x, y) & 0xffffff).to_le_bytes())
+                .collect::<Vec<_>>()
+        };
+        let cases = [
+            pixels_of(&|_, _| 0x123456),
+            pixels_of(&|x, y| [0, 0xffffff][((x / 3 + y) % 2) as usize]),
+            pixels_of(&|x, y| (x / 10 + y) * 0x030507),
+            pixels_of(&|x, y| x.wrapping_mul(2654435761) ^ y.wrapping_mul(40503)),
+            // Too small to be worth compressing.
+            [0x11, 0x22, 0x33, 0, 0x44, 0x55, 0x66, 0].to_vec(),
+        ];
+
+        let mut encoder = Encoder::new();
+        let mut streams = [(); 3].map(|_| flate2::Decompress::new(true));
+        for pixels in &cases {
+            let rect = match pixels.len() {
+                8 => Rect::new(0, 0, 2, 1),
+                _ => rect,
+            };
+            // Twice, as the streams carry on from one rectangle to the next.
+            for _ in 0..2 {
+                let mut encoded = Vec::new();
+                encoder.encode(format, rect, pixels, &mut encoded).unwrap();
+                let mut reader = &encoded[..];
+                assert_eq!(&decode(&mut streams, format, rect, &mut reader), pixels);
+                assert!(reader.is_empty());
+            }
+        }
+        assert!(encoder
+            .encode(
+                format,
+                Rect::new(0, 0, 4096, 1),
+                &[0; 4096 * 4],
+                &mut Vec::new()
+            )
+            .is_err());
+    }
+}
@@ -0,0 +1,121 @@
+use crate::io::{Read, ReadBytesExt};
+use crate::{pixels, png, protocol, tight, Error, Rect, Result};
+
+/// A decoder for the TightPng encoding: Tight rectangles that are either a
+/// solid fill or a PNG image. JPEG and basic compression are not supported.
+#[derive(Debug, Default)]
+pub struct Decoder {
+    // Kept across rectangles so that decoding does not allocate once it has
+    // grown to the largest rectangle seen.
+    pixels: Vec<u8>,
+}
+
+impl Decoder {
+    pub fn new() -> Decoder {
+        Decoder::default()
+    }
+
+    /// Reads a rectangle covering `rect` from `reader` and returns its pixels.
+    pub fn decode<R: Read>(
+        &mut self,
+        reader: &mut R,
+        format: protocol::PixelFormat,
+        rect: Rect,
+    ) -> Result<&[u8]> {
+        // TightPng rectangles use no zlib streams, so the requests to reset
+        // them in the low bits need no handling.
+        let control = reader.read_u8()?;
+        self.pixels.clear();
+        match control >> 4 {
+            tight::FILL => {
+                tight::read_tpixel(reader, format, &mut self.pixels)?;
+                let bpp = self.pixels.len();
+                for _ in 1..rect.area() {
+                    self.pixels.extend_from_within(..bpp)
+                }
+            }
+            tight::PNG => {
+                if !format.true_colour {
+                    return Err(Error::Unexpected("TightPng image with a colour map"));
+                }
+                let length = tight::read_compact_length(reader)?;
+                let mut data = vec![0; length];
+                reader.read_exact(&mut data)?;
+                let rgb = png::decode(&data, rect.width as u32, rect.height as u32)?;
+                pixels::pack_rgb(format, &rgb, &mut self.pixels);
+            }
+            tight::JPEG => return Err(Error::Unexpected("TightPng JPEG rectangle")),
+            _ => return Err(Error::Unexpected("TightPng basic compression")),
+        }
+        Ok(&self.pixels)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Decoder;
+    use crate::{PixelFormat, Rect};
+
+    const FORMAT: PixelFormat = PixelFormat {
+        bits_per_pixel: 32,
+        depth: 24,
+        big_endian: false,
+        true_colour: true,
+        red_max: 255,
+        green_max: 255,
+        blue_max: 255,
+        red_shift: 16,
+        green_shift: 8,
+        blue_shift: 0,
+    };
+
+    fn png(width: u8, rgb: &[u8]) -> Vec<u8> {
+        let rgba = rgb
+            .chunks_exact(3)
+            .flat_map(|pixel| [pixel[0], pixel[1], pixel[2], 0xff])
+            .collect::<Vec<_>>();
+        let height = rgb.len() / 3 / width as usize;
+        let mut png = Vec::new();
+        crate::png::encode(&mut png, width as u32, height as u32, &rgba).unwrap();
+        png
+    }
+
+    #[test]
+    fn test_decode() {
+        let mut decoder = Decoder::new();
+
+        // A fill with a three byte TPIXEL.
+        let data = [0x80, 0x11, 0x22, 0x33];
+        assert_eq!(
+            decoder
+                .decode(&mut &data[..], FORMAT, Rect::new(0, 0, 2, 1))
+                .unwrap(),
+            [0x33, 0x22, 0x11, 0, 0x33, 0x22, 0x11, 0]
+        );
+
+        // A PNG image, with its length in two bytes of compact length.
+        let image = png(50, &[0x44; 50 * 3 * 3]);
+        let mut data = vec![
+            0xa0,
+            0x80 | image.len() as u8 & 0x7f,
+            (image.len() >> 7) as u8,
+        ];
+        data.extend_from_slice(&image);
+        let mut reader = &data[..];
+        let pixels = decoder
+            .decode(&mut reader, FORMAT, Rect::new(0, 0, 50, 3))
+            .unwrap();
+        assert!(reader.is_empty());
+        assert_eq!(pixels, [0x44, 0x44, 0x44, 0].repeat(150));
+
+        // A PNG image of another size than its rectangle.
+        assert!(decoder
+            .decode(&mut &data[..], FORMAT, Rect::new(0, 0, 50, 2))
+            .is_err());
+
+        // JPEG.
+        assert!(decoder
+            .decode(&mut &[0x90, 0][..], FORMAT, Rect::new(0, 0, 1, 1))
+            .is_err());
+    }
+}
@@ -0,0 +1,425 @@
+//! The tile format shared by the TRLE and ZRLE encodings: each tile starts with
+//! a subencoding byte, and holds raw, solid, packed palette or run-length
+//! encoded pixels, all made of compressed pixels (CPIXELs). Tiles are decoded
+//! by `TileDecoder` and encoded by `TileEncoder`.
+
+use crate::io::{self, ErrorKind as IoErrorKind, Read, ReadBytesExt};
+use crate::{protocol, Error, Rect, Result};
+use alloc::vec::Vec;
+
+pub(crate) struct BitReader<T: Read> {
+    reader: T,
+    buffer: u8,
+    position: usize,
+}
+
+impl<T: Read> BitReader<T> {
+    pub fn new(reader: T) -> BitReader<T> {
+        BitReader {
+            reader,
+            buffer: 0,
+            position: 8,
+        }
+    }
+
+    pub fn into_inner(self) -> Result<T> {
+        if self.position == 8 {
+            Ok(self.reader)
+        } else {
+            Err(Error::Unexpected("leftover tile bit data"))
+        }
+    }
+
+    fn read_bits(&mut self, count: usize) -> io::Result<u8> {
+        assert!(count > 0 && count <= 8);
+
+        if self.position == 8 {
+            self.buffer = self.reader.read_u8()?;
+            self.position = 0;
+        }
+
+        if self.position + count <= 8 {
+            let shift = 8 - (count + self.position);
+            let mask = (1 << count) - 1;
+            let result = (self.buffer >> shift) & mask;
+            self.position += count;
+            Ok(result)
+        } else {
+            Err(invalid_data("unaligned tile bit read"))
+        }
+    }
+
+    fn align(&mut self) {
+        self.position = 8;
+    }
+}
+
+impl<T: Read> Read for BitReader<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.position == 8 {
+            self.reader.read(buf)
+        } else {
+            Err(invalid_data("unaligned tile byte read"))
+        }
+    }
+}
+
+/// How pixels of a format are packed into CPIXELs.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct PixelLayout {
+    bpp: usize,
+    compressed_bpp: usize,
+    // Whether the byte a CPIXEL leaves out is the first of the pixel.
+    pad: bool,
+}
+
+impl PixelLayout {
+    pub fn new(format: protocol::PixelFormat) -> PixelLayout {
+        let bpp = format.bits_per_pixel as usize / 8;
+        let pixel_mask = (format.red_max as u32) << format.red_shift
+            | (format.green_max as u32) << format.green_shift
+            | (format.blue_max as u32) << format.blue_shift;
+
+        let (compressed_bpp, pad) =
+            if format.bits_per_pixel == 32 && format.true_colour && format.depth <= 24 {
+                if pixel_mask & 0x000000ff == 0 {
+                    (3, !format.big_endian)
+                } else if pixel_mask & 0xff000000 == 0 {
+                    (3, format.big_endian)
+                } else {
+                    (4, false)
+                }
+            } else {
+                (bpp, false)
+            };
+        PixelLayout {
+            bpp,
+            compressed_bpp,
+            pad,
+        }
+    }
+}
+
+/// Decodes tiles one after another. The palette is kept from one tile to the
+/// next, as TRLE tiles can refer back to it.
+#[derive(Debug, Default)]
+pub(crate) struct TileDecoder {
+    // Scratch buffers, kept across tiles and rectangles so that decoding does
+    // not allocate once they have grown to the largest tile seen.
+    palette: Vec<u8>,
+    pub pixels: Vec<u8>,
+}
+
+impl TileDecoder {
+    /// Decodes one tile into `pixels`. Subencodings 127 and 129, which reuse the
+    /// palette of the previous tile, are only accepted with `reuse_palette`.
+    pub fn decode<R: Read>(
+        &mut self,
+        reader: &mut BitReader<R>,
+        layout: PixelLayout,
+        tile: Rect,
+        reuse_palette: bool,
+    ) -> Result<()> {
+        fn read_run_length(reader: &mut dyn Read) -> Result<usize> {
+            let mut run_length_part = reader.read_u8()?;
+            let mut run_length = 1 + run_length_part as usize;
+            while run_length_part == 255 {
+                run_length_part = reader.read_u8()?;
+                run_length += run_length_part as usize;
+            }
+            Ok(run_length)
+        }
+
+        fn copy_true_color(
+            reader: &mut dyn Read,
+            pixels: &mut Vec<u8>,
+            layout: PixelLayout,
+        ) -> Result<()> {
+            let mut buf = [0; 4];
+            let start = layout.pad as usize;
+            reader.read_exact(&mut buf[start..start + layout.compressed_bpp])?;
+            pixels.extend_from_slice(&buf[..layout.bpp]);
+            Ok(())
+        }
+
+        fn copy_indexed(palette: &[u8], pixels: &mut Vec<u8>, bpp: usize, index: u8) -> Result<()> {
+            let start = index as usize * bpp;
+            let colour = palette
+                .get(start..start + bpp)
+                .ok_or(Error::Unexpected("tile palette index"))?;
+            pixels.extend_from_slice(colour);
+            Ok(())
+        }
+
+        let TileDecoder { palette, pixels } = self;
+        let bpp = layout.bpp;
+        let pixel_count = tile.area();
+
+        let subencoding = reader.read_u8()?;
+        match subencoding {
+            127 | 129 if reuse_palette => (),
+            127 | 129 => return Err(Error::Unexpected("tile subencoding")),
+            _ => {
+                palette.clear();
+                for _ in 0..subencoding & 0x7f {
+                    copy_true_color(reader, palette, layout)?
+                }
+            }
+        }
+        let palette_size = palette.len() / bpp;
+
+        pixels.clear();
+        match subencoding {
+            0 => {
+                // True Color pixels
+                for _ in 0..pixel_count {
+                    copy_true_color(reader, pixels, layout)?
+                }
+            }
+            1 => {
+                // Color fill
+                for _ in 0..pixel_count {
+                    copy_indexed(palette, pixels, bpp, 0)?
+                }
+            }
+            2..=16 | 127 => {
+                // Indexed pixels
+                let bits_per_index = match palette_size {
+                    2 => 1,
+                    3..=4 => 2,
+                    5..=16 => 4,
+                    _ => return Err(Error::Unexpected("tile palette size")),
+                };
+                for _ in 0..tile.height {
+                    for _ in 0..tile.width {
+                        let index = reader.read_bits(bits_per_index)?;
+                        copy_indexed(palette, pixels, bpp, index)?
+                    }
+                    reader.align();
+                }
+            }
+            128 => {
+                // True Color RLE
+                let mut count = 0;
+                while count < pixel_count {
+                    let start = pixels.len();
+                    copy_true_color(reader, pixels, layout)?;
+                    let run_length = read_run_length(reader)?;
+                    for _ in 1..run_length {
+                        pixels.extend_from_within(start..start + bpp)
+                    }
+                    count += run_length;
+                }
+            }
+            129..=255 => {
+                // Indexed RLE
+                let mut count = 0;
+                while count < pixel_count {
+                    let longer_than_one = reader.read_bits(1)? != 0;
+                    let index = reader.read_bits(7)?;
+                    let run_length = if longer_than_one {
+                        read_run_length(reader)?
+                    } else {
+                        1
+                    };
+                    for _ in 0..run_length {
+                        copy_indexed(palette, pixels, bpp, index)?;
+                    }
+                    count += run_length;
+                }
+            }
+            _ => return Err(Error::Unexpected("tile subencoding")),
+        }
+        // A run may not spill over into the next tile.
+        if pixels.len() != pixel_count * bpp {
+            return Err(Error::Unexpected("tile run length"));
+        }
+        Ok(())
+    }
+}
+
+impl PixelLayout {
+    fn write_cpixel(&self, pixel: u32, output: &mut Vec<u8>) {
+        let start = self.pad as usize;
+        output.extend_from_slice(&pixel.to_le_bytes()[start..start + self.compressed_bpp])
+    }
+}
+
+/// Appends the pixels of `tile` to `output`, taking them from `pixels`, rows of
+/// `rect` packed at `bpp` bytes each. Encoders cut rectangles into tiles with
+/// this and `Rect::tiles`.
+pub(crate) fn copy_tile(pixels: &[u8], rect: Rect, bpp: usize, tile: Rect, output: &mut Vec<u8>) {
+    let stride = rect.width as usize * bpp;
+    let left = (tile.left - rect.left) as usize * bpp;
+    for y in tile.top - rect.top..tile.top - rect.top + tile.height {
+        let offset = y as usize * stride + left;
+        output.extend_from_slice(&pixels[offset..offset + tile.width as usize * bpp]);
+    }
+}
+
+/// The number of bytes `read_run_length` takes for a run of `run_length`.
+fn run_length_size(run_length: usize) -> usize {
+    (run_length - 1) / 255 + 1
+}
+
+fn write_run_length(run_length: usize, output: &mut Vec<u8>) {
+    let mut rest = run_length - 1;
+    while rest >= 255 {
+        output.push(255);
+        rest -= 255;
+    }
+    output.push(rest as u8);
+}
+
+/// Encodes tiles in whichever subencoding comes out smallest. Palettes are not
+/// reused from one tile to the next, so the tiles are valid in ZRLE as well
+/// as TRLE.
+#[derive(Debug, Default)]
+pub(crate) struct TileEncoder {
+    // Scratch buffers, as in `TileDecoder`. Pixels are kept as the bytes of
+    // the pixel in a little-endian u32, which is only compared and written out.
+    palette: Vec<u32>,
+    indices: Vec<u8>,
+    runs: Vec<(u32, usize)>,
+}
+
+impl TileEncoder {
+    /// Appends the tile made of rows of packed `pixels` to `output`.
+    pub fn encode(&mut self, layout: PixelLayout, tile: Rect, pixels: &[u8], output: &mut Vec<u8>) {
+        let TileEncoder {
+            palette,
+            indices,
+            runs,
+        } = self;
+        palette.clear();
+        indices.clear();
+        runs.clear();
+        // Beyond 127 colours, only the runs are of use.
+        let mut many_colours = false;
+        for pixel in pixels.chunks_exact(layout.bpp) {
+            let mut bytes = [0; 4];
+            bytes[..layout.bpp].copy_from_slice(pixel);
+            let pixel = u32::from_le_bytes(bytes);
+
+            if !many_colours {
+                let index = match palette.iter().position(|&colour| colour == pixel) {
+                    Some(index) => index,
+                    None if palette.len() < 127 => {
+                        palette.push(pixel);
+                        palette.len() - 1
+                    }
+                    None => {
+                        many_colours = true;
+                        0
+                    }
+                };
+                indices.push(index as u8);
+            }
+            match runs.last_mut() {
+                Some((colour, run_length)) if *colour == pixel => *run_length += 1,
+                _ => runs.push((pixel, 1)),
+            }
+        }
+
+        if !many_colours && palette.len() == 1 {
+            output.push(1);
+            layout.write_cpixel(palette[0], output);
+            return;
+        }
+
+        let cpixel_size = layout.compressed_bpp;
+        let bits_per_index = match palette.len() {
+            2 => 1,
+            3..=4 => 2,
+            _ => 4,
+        };
+        let raw_size = tile.area() * cpixel_size;
+        let packed_size = match many_colours || palette.len() > 16 {
+            true => usize::MAX,
+            false => {
+                let row_size = (tile.width as usize * bits_per_index).div_ceil(8);
+                palette.len() * cpixel_size + row_size * tile.height as usize
+            }
+        };
+        let rle_size = runs
+            .iter()
+            .map(|&(_, run_length)| cpixel_size + run_length_size(run_length))
+            .sum::<usize>();
+        let palette_rle_size = match many_colours {
+            true => usize::MAX,
+            false => {
+                palette.len() * cpixel_size
+                    + runs
+                        .iter()
+                        .map(|&(_, run_length)| match run_length {
+                            1 => 1,
+                            _ => 1 + run_length_size(run_length),
+                        })
+                        .sum::<usize>()
+            }
+        };
+
+        let smallest = raw_size
+            .min(packed_size)
+            .min(rle_size)
+            .min(palette_rle_size);
+        if smallest == packed_size {
+            output.push(palette.len() as u8);
+            for &colour in palette.iter() {
+                layout.write_cpixel(colour, output)
+            }
+            for row in indices.chunks(tile.width as usize) {
+                let (mut byte, mut position) = (0u8, 0);
+                for &index in row {
+                    position += bits_per_index;
+                    byte |= index << (8 - position);
+                    if position == 8 {
+                        output.push(byte);
+                        (byte, position) = (0, 0);
+                    }
+                }
+                if position != 0 {
+                    output.push(byte)
+                }
+            }
+        } else if smallest == palette_rle_size {
+            output.push(128 | palette.len() as u8);
+            for &colour in palette.iter() {
+                layout.write_cpixel(colour, output)
+            }
+            for &(colour, run_length) in runs.iter() {
+                let index = palette.iter().position(|&other| other == colour).unwrap() as u8;
+                match run_length {
+                    1 => output.push(index),
+                    _ => {
+                        output.push(0x80 | index);
+                        write_run_length(run_length, output)
+                    }
+                }
+            }
+        } else if smallest == rle_size {
+            output.push(128);
+            for &(colour, run_length) in runs.iter() {
+                layout.write_cpixel(colour, output);
+                write_run_length(run_length, output)
+            }
+        } else {
+            output.push(0);
+            for pixel in pixels.chunks_exact(layout.bpp) {
+                let mut bytes = [0; 4];
+                bytes[..layout.bpp].copy_from_slice(pixel);
+                layout.write_cpixel(u32::from_le_bytes(bytes), output)
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+fn invalid_data(descr: &'static str) -> io::Error {
+    io::Error::new(IoErrorKind::InvalidData, descr)
+}
+
+#[cfg(not(feature = "std"))]
+fn invalid_data(_descr: &'static str) -> io::Error {
+    IoErrorKind::InvalidData.into()
+}
@@ -0,0 +1,109 @@
+use crate::io::Read;
+use crate::tile::{BitReader, PixelLayout, TileDecoder};
+use crate::{protocol, Rect, Result};
+
+/// A decoder for the TRLE encoding: the tiles of ZRLE, 16 pixels on a side and
+/// without zlib, read from the connection as they arrive. Tiles may reuse the
+/// palette of the tile before them.
+#[derive(Debug, Default)]
+pub struct Decoder {
+    tiles: TileDecoder,
+}
+
+impl Decoder {
+    pub fn new() -> Decoder {
+        Decoder::default()
+    }
+
+    /// Reads the tiles of `rect` from `reader` and calls `callback` with the
+    /// pixels of each one, stopping early if it returns `false`.
+    pub fn decode<R, F>(
+        &mut self,
+        reader: &mut R,
+        format: protocol::PixelFormat,
+        rect: Rect,
+        mut callback: F,
+    ) -> Result<bool>
+    where
+        R: Read,
+        F: FnMut(Rect, &[u8]) -> Result<bool>,
+    {
+        let layout = PixelLayout::new(format);
+        let mut reader = BitReader::new(reader);
+        for tile in rect.tiles(16) {
+            self.tiles
+                .decode(&mut reader, layout, tile, /*reuse_palette*/ true)?;
+            if !callback(tile, &self.tiles.pixels)? {
+                return Ok(false);
+            }
+        }
+        reader.into_inner()?;
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Decoder;
+    use crate::{PixelFormat, Rect};
+    use alloc::vec::Vec;
+
+    const FORMAT: PixelFormat = PixelFormat {
+        bits_per_pixel: 8,
+        depth: 8,
+        big_endian: false,
+        true_colour: true,
+        red_max: 7,
+        green_max: 7,
+        blue_max: 3,
+        red_shift: 0,
+        green_shift: 3,
+        blue_shift: 6,
+    };
+
+    fn decode(rect: Rect, data: &[u8]) -> crate::Result<Vec<(Rect, Vec<u8>)>> {
+        let mut tiles = Vec::new();
+        let mut reader = data;
+        Decoder::new().decode(&mut reader, FORMAT, rect, |tile, pixels| {
+            tiles.push((tile, pixels.to_vec()));
+            Ok(true)
+        })?;
+        assert!(reader.is_empty());
+        Ok(tiles)
+    }
+
+    #[test]
+    fn test_decode() {
+        // A tile packed from a palette of 3 and 4; a tile reusing that palette;
+        // a run-length encoded tile from a new palette of 5 and 6; and one
+        // reusing it for a single run.
+        let mut data = vec![2, 3, 4, 0b0110_1001, 0b0110_1001];
+        data.extend([127, 0b1001_0110, 0b1001_0110]);
+        data.extend([130, 5, 6, 0x80, 14, 0x01]);
+        data.extend([129, 0x80, 15]);
+
+        let first = [3, 4, 4, 3, 4, 3, 3, 4, 3, 4, 4, 3, 4, 3, 3, 4].to_vec();
+        let second = first.iter().map(|colour| 7 - colour).collect();
+        let mut third = vec![5; 15];
+        third.push(6);
+        assert_eq!(
+            decode(Rect::new(0, 0, 64, 1), &data).unwrap(),
+            [
+                (Rect::new(0, 0, 16, 1), first),
+                (Rect::new(16, 0, 16, 1), second),
+                (Rect::new(32, 0, 16, 1), third),
+                (Rect::new(48, 0, 16, 1), vec![5; 16]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        // Reusing a palette before any tile has one.
+        assert!(decode(Rect::new(0, 0, 2, 1), &[127, 0]).is_err());
+        // An index past the end of the palette.
+        assert!(decode(Rect::new(0, 0, 2, 1), &[3, 1, 2, 3, 0b1100_0000]).is_err());
+        // A run spilling over into the next tile.
+        assert!(decode(Rect::new(0, 0, 2, 1), &[128, 1, 2]).is_err());
+    }
+}
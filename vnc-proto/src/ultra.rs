@@ -0,0 +1,94 @@
+use crate::{protocol, Error, Rect, Result};
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+
+/// An LZO1X decompressor, as used by the Ultra encoding.
+///
+/// With the `lzo` feature this is implemented by [`crate::lzo::Lzo1x`]; without
+/// it, users have to supply their own.
+pub trait Lzo {
+    /// Decompress all of `input`, one LZO1X block, into `output`, returning
+    /// the number of bytes produced.
+    fn decompress(&mut self, input: &[u8], output: &mut [u8]) -> Result<usize>;
+}
+
+/// A decoder for UltraVNC's Ultra encoding, which is Raw pixels compressed
+/// with LZO1X, one block per rectangle.
+pub struct Decoder {
+    decompressor: Box<dyn Lzo + Send>,
+    // Kept across rectangles so that decoding does not allocate once it has
+    // grown to the largest rectangle seen.
+    pixels: Vec<u8>,
+}
+
+#[cfg(feature = "lzo")]
+impl Default for Decoder {
+    fn default() -> Decoder {
+        Decoder::new()
+    }
+}
+
+impl Decoder {
+    #[cfg(feature = "lzo")]
+    pub fn new() -> Decoder {
+        Decoder::with_decompressor(Box::new(crate::lzo::Lzo1x))
+    }
+
+    pub fn with_decompressor(decompressor: Box<dyn Lzo + Send>) -> Decoder {
+        Decoder {
+            decompressor,
+            pixels: Vec::new(),
+        }
+    }
+
+    /// Decodes `input`, the compressed data of a rectangle covering `rect`,
+    /// and returns its pixels.
+    pub fn decode(
+        &mut self,
+        format: protocol::PixelFormat,
+        rect: Rect,
+        input: &[u8],
+    ) -> Result<&[u8]> {
+        let length = rect.area() * (format.bits_per_pixel as usize / 8);
+        self.pixels.resize(length, 0);
+        if self.decompressor.decompress(input, &mut self.pixels)? != length {
+            return Err(Error::Unexpected("Ultra data length"));
+        }
+        Ok(&self.pixels)
+    }
+}
+
+#[cfg(all(test, feature = "lzo"))]
+mod tests {
+    use super::Decoder;
+    use crate::{PixelFormat, Rect};
+
+    #[test]
+    fn test_decode() {
+        let format = PixelFormat {
+            bits_per_pixel: 16,
+            depth: 16,
+            big_endian: false,
+            true_colour: true,
+            red_max: 31,
+            green_max: 63,
+            blue_max: 31,
+            red_shift: 11,
+            green_shift: 5,
+            blue_shift: 0,
+        };
+        // Two literal pixels, repeated to fill a 3x2 rectangle.
+        let data = [21, 1, 2, 3, 4, 38, 0x0c, 0x00, 0x11, 0x00, 0x00];
+        let mut decoder = Decoder::new();
+        assert_eq!(
+            decoder
+                .decode(format, Rect::new(1, 1, 3, 2), &data)
+                .unwrap(),
+            [1, 2, 3, 4, 1, 2, 3, 4, 1, 2, 3, 4]
+        );
+        // The same data for a larger rectangle.
+        assert!(decoder
+            .decode(format, Rect::new(0, 0, 4, 2), &data)
+            .is_err());
+    }
+}
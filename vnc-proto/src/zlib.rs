@@ -0,0 +1,107 @@
+use crate::io::Read;
+use crate::zrle::{Inflate, ZlibReader};
+use crate::{protocol, Rect, Result};
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+
+/// A decoder for the Zlib encoding, which is Raw pixels sent through a zlib
+/// stream that lasts as long as the connection.
+pub struct Decoder {
+    decompressor: Box<dyn Inflate + Send>,
+    // Kept across rectangles so that decoding does not allocate once it has
+    // grown to the largest rectangle seen.
+    pixels: Vec<u8>,
+}
+
+#[cfg(feature = "std")]
+impl Default for Decoder {
+    fn default() -> Decoder {
+        Decoder::new()
+    }
+}
+
+impl Decoder {
+    #[cfg(feature = "std")]
+    pub fn new() -> Decoder {
+        Decoder::with_inflater(Box::new(flate2::Decompress::new(/*zlib_header*/ true)))
+    }
+
+    pub fn with_inflater(decompressor: Box<dyn Inflate + Send>) -> Decoder {
+        Decoder {
+            decompressor,
+            pixels: Vec::new(),
+        }
+    }
+
+    /// Decodes `input`, the compressed data of a rectangle covering `rect`,
+    /// and returns its pixels.
+    pub fn decode(
+        &mut self,
+        format: protocol::PixelFormat,
+        rect: Rect,
+        input: &[u8],
+    ) -> Result<&[u8]> {
+        let length = rect.area() * (format.bits_per_pixel as usize / 8);
+        self.pixels.resize(length, 0);
+        let mut reader = ZlibReader::new(&mut *self.decompressor, input);
+        reader.read_exact(&mut self.pixels)?;
+        reader.finish()?;
+        Ok(&self.pixels)
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::Decoder;
+    use crate::{PixelFormat, Rect};
+    use flate2::{Compress, Compression, FlushCompress};
+
+    #[test]
+    fn test_decode() {
+        let format = PixelFormat {
+            bits_per_pixel: 16,
+            depth: 16,
+            big_endian: false,
+            true_colour: true,
+            red_max: 31,
+            green_max: 63,
+            blue_max: 31,
+            red_shift: 11,
+            green_shift: 5,
+            blue_shift: 0,
+        };
+        // Like a server, compress both rectangles with one stream, flushing
+        // after each.
+        let mut compress = Compress::new(Compression::default(), /*zlib_header*/ true);
+        let mut deflate = |pixels: &[u8]| {
+            let mut output = Vec::with_capacity(pixels.len() + 64);
+            compress
+                .compress_vec(pixels, &mut output, FlushCompress::Sync)
+                .unwrap();
+            output
+        };
+        let first = (0..2 * 3 * 2).collect::<Vec<u8>>();
+        let second = [0x55; 4 * 4 * 2];
+        let first_data = deflate(&first);
+        let second_data = deflate(&second);
+
+        let mut decoder = Decoder::new();
+        assert_eq!(
+            decoder
+                .decode(format, Rect::new(1, 1, 2, 3), &first_data)
+                .unwrap(),
+            &first[..]
+        );
+        assert_eq!(
+            decoder
+                .decode(format, Rect::new(0, 0, 4, 4), &second_data)
+                .unwrap(),
+            &second[..]
+        );
+        // Data for a smaller rectangle than the one it arrives with.
+        let third_data = deflate(&[0; 2]);
+        assert!(decoder
+            .decode(format, Rect::new(0, 0, 2, 2), &third_data)
+            .is_err());
+    }
+}
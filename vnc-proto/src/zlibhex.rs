@@ -0,0 +1,176 @@
+use crate::hextile::{self, RAW};
+use crate::io::{BigEndian, Read, ReadBytesExt};
+use crate::zrle::{Inflate, ZlibReader};
+use crate::{protocol, Error, Rect, Result};
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+
+// Tile subencoding bits on top of those of Hextile.
+const ZLIB_RAW: u8 = 1 << 5;
+const ZLIB_HEX: u8 = 1 << 6;
+
+/// A decoder for the ZlibHex encoding, which is Hextile where a tile can also
+/// be sent compressed: either its raw pixels, through one zlib stream, or its
+/// Hextile data, through another. Both streams last as long as the connection.
+pub struct Decoder {
+    raw_decompressor: Box<dyn Inflate + Send>,
+    hex_decompressor: Box<dyn Inflate + Send>,
+    hextile: hextile::Decoder,
+    compressed: Vec<u8>,
+    inflated: Vec<u8>,
+}
+
+#[cfg(feature = "std")]
+impl Default for Decoder {
+    fn default() -> Decoder {
+        Decoder::new()
+    }
+}
+
+impl Decoder {
+    #[cfg(feature = "std")]
+    pub fn new() -> Decoder {
+        Decoder::with_inflaters(
+            Box::new(flate2::Decompress::new(/*zlib_header*/ true)),
+            Box::new(flate2::Decompress::new(/*zlib_header*/ true)),
+        )
+    }
+
+    /// Makes a decoder using `raw_decompressor` for compressed raw tiles and
+    /// `hex_decompressor` for compressed Hextile tiles.
+    pub fn with_inflaters(
+        raw_decompressor: Box<dyn Inflate + Send>,
+        hex_decompressor: Box<dyn Inflate + Send>,
+    ) -> Decoder {
+        Decoder {
+            raw_decompressor,
+            hex_decompressor,
+            hextile: hextile::Decoder::new(),
+            compressed: Vec::new(),
+            inflated: Vec::new(),
+        }
+    }
+
+    /// Reads the tiles of `rect` from `reader` and calls `callback` with the
+    /// pixels of each one, stopping early if it returns `false`.
+    pub fn decode<R, F>(
+        &mut self,
+        reader: &mut R,
+        format: protocol::PixelFormat,
+        rect: Rect,
+        mut callback: F,
+    ) -> Result<bool>
+    where
+        R: Read,
+        F: FnMut(Rect, &[u8]) -> Result<bool>,
+    {
+        let bpp = format.bits_per_pixel as usize / 8;
+        let Decoder {
+            raw_decompressor,
+            hex_decompressor,
+            hextile,
+            compressed,
+            inflated,
+        } = self;
+
+        fn read_compressed(reader: &mut dyn Read, compressed: &mut Vec<u8>) -> Result<()> {
+            let length = reader.read_u16::<BigEndian>()?;
+            compressed.resize(length as usize, 0);
+            reader.read_exact(compressed)?;
+            Ok(())
+        }
+
+        for tile in rect.tiles(16) {
+            let subencoding = reader.read_u8()?;
+            if subencoding & ZLIB_RAW != 0 {
+                read_compressed(reader, compressed)?;
+                let mut zlib_reader = ZlibReader::new(&mut **raw_decompressor, compressed);
+                hextile.read_raw(&mut zlib_reader, bpp, tile)?;
+                zlib_reader.finish()?
+            } else if subencoding & RAW != 0 {
+                hextile.read_raw(reader, bpp, tile)?
+            } else if subencoding & ZLIB_HEX != 0 {
+                read_compressed(reader, compressed)?;
+                inflated.clear();
+                ZlibReader::new(&mut **hex_decompressor, compressed).read_to_end(inflated)?;
+                let mut data = &inflated[..];
+                hextile.read_tile(&mut data, bpp, tile, subencoding)?;
+                if !data.is_empty() {
+                    return Err(Error::Unexpected("leftover ZlibHex tile data"));
+                }
+            } else {
+                hextile.read_tile(reader, bpp, tile, subencoding)?
+            }
+
+            if !callback(tile, &hextile.pixels)? {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::Decoder;
+    use crate::{PixelFormat, Rect};
+    use flate2::{Compress, Compression, FlushCompress};
+
+    #[test]
+    fn test_decode() {
+        let format = PixelFormat {
+            bits_per_pixel: 8,
+            depth: 8,
+            big_endian: false,
+            true_colour: true,
+            red_max: 7,
+            green_max: 7,
+            blue_max: 3,
+            red_shift: 0,
+            green_shift: 3,
+            blue_shift: 6,
+        };
+        let deflate = |compress: &mut Compress, data: &[u8]| {
+            let mut output = Vec::with_capacity(data.len() + 64);
+            compress
+                .compress_vec(data, &mut output, FlushCompress::Sync)
+                .unwrap();
+            let mut framed = (output.len() as u16).to_be_bytes().to_vec();
+            framed.extend(output);
+            framed
+        };
+        let mut raw_stream = Compress::new(Compression::default(), /*zlib_header*/ true);
+        let mut hex_stream = Compress::new(Compression::default(), /*zlib_header*/ true);
+
+        // A compressed raw tile, a compressed Hextile tile with background 3,
+        // and a plain Hextile tile keeping that background.
+        let mut data = vec![0x20];
+        data.extend(deflate(&mut raw_stream, &(0..16).collect::<Vec<u8>>()));
+        data.push(0x42);
+        data.extend(deflate(&mut hex_stream, &[3]));
+        data.push(0x00);
+
+        let mut tiles = Vec::new();
+        let mut reader = &data[..];
+        Decoder::new()
+            .decode(
+                &mut reader,
+                format,
+                Rect::new(0, 0, 36, 1),
+                |tile, pixels| {
+                    tiles.push((tile, pixels.to_vec()));
+                    Ok(true)
+                },
+            )
+            .unwrap();
+        assert!(reader.is_empty());
+        assert_eq!(
+            tiles,
+            [
+                (Rect::new(0, 0, 16, 1), (0..16).collect()),
+                (Rect::new(16, 0, 16, 1), vec![3; 16]),
+                (Rect::new(32, 0, 4, 1), vec![3; 4]),
+            ]
+        );
+    }
+}
@@ -0,0 +1,329 @@
+use crate::io::{self, Read};
+use crate::tile::{copy_tile, BitReader, PixelLayout, TileDecoder, TileEncoder};
+use crate::{protocol, Error, Rect, Result};
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+
+/// A zlib stream decompressor, as used by ZRLE and Zlib to carry state across
+/// rectangles.
+///
+/// With the `std` feature this is implemented for `flate2::Decompress`; without it,
+/// users have to supply their own.
+pub trait Inflate {
+    /// Decompress as much of `input` into `output` as possible, returning
+    /// the number of bytes consumed and produced.
+    fn inflate(&mut self, input: &[u8], output: &mut [u8]) -> io::Result<(usize, usize)>;
+}
+
+#[cfg(feature = "std")]
+impl Inflate for flate2::Decompress {
+    fn inflate(&mut self, input: &[u8], output: &mut [u8]) -> io::Result<(usize, usize)> {
+        let in_before = self.total_in();
+        let out_before = self.total_out();
+        let result = self.decompress(input, output, flate2::FlushDecompress::None);
+        let consumed = (self.total_in() - in_before) as usize;
+        let produced = (self.total_out() - out_before) as usize;
+
+        match result {
+            Ok(flate2::Status::Ok) => Ok((consumed, produced)),
+            Ok(flate2::Status::BufError) => Ok((consumed, 0)),
+            Err(error) => Err(io::Error::new(io::ErrorKind::InvalidData, error)),
+            Ok(flate2::Status::StreamEnd) => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "zlib stream end",
+            )),
+        }
+    }
+}
+
+/// A zlib stream compressor, the counterpart of `Inflate` for encoders.
+///
+/// With the `std` feature this is implemented for `flate2::Compress`; without it,
+/// users have to supply their own.
+pub trait Deflate {
+    /// Compresses all of `input`, appending it to `output`, and flushes the
+    /// stream so that everything so far can be decompressed.
+    fn deflate(&mut self, input: &[u8], output: &mut Vec<u8>) -> io::Result<()>;
+}
+
+#[cfg(feature = "std")]
+impl Deflate for flate2::Compress {
+    fn deflate(&mut self, mut input: &[u8], output: &mut Vec<u8>) -> io::Result<()> {
+        // The flush is complete once a call leaves room in the output.
+        loop {
+            output.reserve(input.len() + 64);
+            let in_before = self.total_in();
+            self.compress_vec(input, output, flate2::FlushCompress::Sync)
+                .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?;
+            input = &input[(self.total_in() - in_before) as usize..];
+            if input.is_empty() && output.len() < output.capacity() {
+                return Ok(());
+            }
+        }
+    }
+}
+
+pub(crate) struct ZlibReader<'a> {
+    decompressor: &'a mut dyn Inflate,
+    input: &'a [u8],
+}
+
+impl<'a> ZlibReader<'a> {
+    pub fn new(decompressor: &'a mut dyn Inflate, input: &'a [u8]) -> ZlibReader<'a> {
+        ZlibReader {
+            decompressor,
+            input,
+        }
+    }
+
+    /// Decompresses all of the input, appending it to `output`.
+    pub fn read_to_end(mut self, output: &mut Vec<u8>) -> Result<()> {
+        const CHUNK: usize = 1024;
+        loop {
+            let start = output.len();
+            output.resize(start + CHUNK, 0);
+            let (consumed, produced) = self
+                .decompressor
+                .inflate(self.input, &mut output[start..])?;
+            self.input = &self.input[consumed..];
+            output.truncate(start + produced);
+            if consumed == 0 && produced == 0 {
+                break;
+            }
+        }
+        self.finish()
+    }
+
+    pub fn finish(self) -> Result<()> {
+        if self.input.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::Unexpected("leftover zlib data"))
+        }
+    }
+}
+
+impl<'a> Read for ZlibReader<'a> {
+    fn read(&mut self, output: &mut [u8]) -> io::Result<usize> {
+        let (consumed, produced) = self.decompressor.inflate(self.input, output)?;
+        self.input = &self.input[consumed..];
+        Ok(produced)
+    }
+}
+
+pub struct Decoder {
+    decompressor: Box<dyn Inflate + Send>,
+    tiles: TileDecoder,
+}
+
+#[cfg(feature = "std")]
+impl Default for Decoder {
+    fn default() -> Decoder {
+        Decoder::new()
+    }
+}
+
+impl Decoder {
+    #[cfg(feature = "std")]
+    pub fn new() -> Decoder {
+        Decoder::with_inflater(Box::new(flate2::Decompress::new(/*zlib_header*/ true)))
+    }
+
+    pub fn with_inflater(decompressor: Box<dyn Inflate + Send>) -> Decoder {
+        Decoder {
+            decompressor,
+            tiles: TileDecoder::default(),
+        }
+    }
+
+    pub fn decode<F>(
+        &mut self,
+        format: protocol::PixelFormat,
+        rect: Rect,
+        input: &[u8],
+        mut callback: F,
+    ) -> Result<bool>
+    where
+        F: FnMut(Rect, &[u8]) -> Result<bool>,
+    {
+        let layout = PixelLayout::new(format);
+        let Decoder {
+            decompressor,
+            tiles,
+        } = self;
+        let mut reader = BitReader::new(ZlibReader::new(&mut **decompressor, input));
+
+        for tile in rect.tiles(64) {
+            tiles.decode(&mut reader, layout, tile, /*reuse_palette*/ false)?;
+            if !callback(tile, &tiles.pixels)? {
+                return Ok(false);
+            }
+        }
+
+        reader.into_inner()?.finish()?;
+        Ok(true)
+    }
+}
+
+/// Encodes rectangles as ZRLE, for servers. Like the decoder, it keeps one zlib
+/// stream for all the rectangles of a session.
+pub struct Encoder {
+    compressor: Box<dyn Deflate + Send>,
+    tiles: TileEncoder,
+    // Scratch buffers for the pixels of a tile and its encoding.
+    pixels: Vec<u8>,
+    encoded: Vec<u8>,
+}
+
+#[cfg(feature = "std")]
+impl Default for Encoder {
+    fn default() -> Encoder {
+        Encoder::new()
+    }
+}
+
+impl Encoder {
+    #[cfg(feature = "std")]
+    pub fn new() -> Encoder {
+        Encoder::with_deflater(Box::new(flate2::Compress::new(
+            flate2::Compression::default(),
+            /*zlib_header*/ true,
+        )))
+    }
+
+    pub fn with_deflater(compressor: Box<dyn Deflate + Send>) -> Encoder {
+        Encoder {
+            compressor,
+            tiles: TileEncoder::default(),
+            pixels: Vec::new(),
+            encoded: Vec::new(),
+        }
+    }
+
+    /// Encodes `rect`, made of rows of packed `pixels` in `format`, appending
+    /// the compressed data to `output`. On the wire, it follows its length.
+    pub fn encode(
+        &mut self,
+        format: protocol::PixelFormat,
+        rect: Rect,
+        pixels: &[u8],
+        output: &mut Vec<u8>,
+    ) -> Result<()> {
+        let layout = PixelLayout::new(format);
+        let bpp = format.bits_per_pixel as usize / 8;
+        if pixels.len() != rect.area() * bpp {
+            return Err(Error::Unexpected("pixel data length"));
+        }
+
+        self.encoded.clear();
+        for tile in rect.tiles(64) {
+            self.pixels.clear();
+            copy_tile(pixels, rect, bpp, tile, &mut self.pixels);
+            self.tiles
+                .encode(layout, tile, &self.pixels, &mut self.encoded);
+        }
+        self.compressor.deflate(&self.encoded, output)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Decoder, Encoder};
+    use crate::protocol::PixelFormat;
+    use crate::Rect;
+    use alloc::vec::Vec;
+
+    fn round_trip(
+        format: PixelFormat,
+        rect: Rect,
+        pixels: &[u8],
+        encoder: &mut Encoder,
+        decoder: &mut Decoder,
+    ) -> usize {
+        let mut encoded = Vec::new();
+        encoder.encode(format, rect, pixels, &mut encoded).unwrap();
+        let bpp = format.bits_per_pixel as usize / 8;
+        let mut decoded = vec![0; pixels.len()];
+        decoder
+            .decode(format, rect, &encoded, |tile, tile_pixels| {
+                let row_length = tile.width as usize * bpp;
+                for (y, row) in tile_pixels.chunks(row_length).enumerate() {
+                    let offset = ((tile.top - rect.top) as usize + y) * rect.width as usize * bpp
+                        + (tile.left - rect.left) as usize * bpp;
+                    decoded[offset..offset + row_length].copy_from_slice(row);
+                }
+                Ok(true)
+            })
+            .unwrap();
+        assert_eq!(decoded, pixels);
+        encoded.len()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let rgb888 = PixelFormat {
+            bits_per_pixel: 32,
+            depth: 24,
+            big_endian: false,
+            true_colour: true,
+            red_max: 255,
+            green_max: 255,
+            blue_max: 255,
+            red_shift: 16,
+            green_shift: 8,
+            blue_shift: 0,
+        };
+        let rgb565 = PixelFormat {
+            bits_per_pixel: 16,
+            depth: 16,
+            red_max: 31,
+            green_max: 63,
+            blue_max: 31,
+            red_shift: 11,
+            green_shift: 5,
+            ..rgb888
+        };
+        // Spanning several tiles, some partial, with every kind of content:
+        // solid, a few colours, long runs and noise.
+        let rect = Rect::new(10, 20, 150, 70);
+        let pattern = |x: u32, y: u32| match (x / 64, y / 64) {
+            (0, 0) => 0x123456,
+            (1, 0) => [0xff0000, 0x00ff00, 0x0000ff][((x + y) % 3) as usize],
+            (2, 0) => (y / 8) * 0x010101,
+            (0, 1) => [0, 0xffffff][(x % 2) as usize],
+            _ => x.wrapping_mul(2654435761) ^ y.wrapping_mul(40503),
+        };
+
+        let mut encoder = Encoder::new();
+        let mut decoder = Decoder::new();
+        for format in [rgb888, rgb565] {
+            let bpp = format.bits_per_pixel as usize / 8;
+            let mask = (1u32 << format.depth) - 1;
+            let pixels = (0..rect.height as u32)
+                .flat_map(|y| (0..rect.width as u32).map(move |x| (x, y)))
+                .flat_map(|(x, y)| (pattern(x, y) & mask).to_le_bytes()[..bpp].to_vec())
+                .collect::<Vec<_>>();
+            let size = round_trip(format, rect, &pixels, &mut encoder, &mut decoder);
+            assert!(size < pixels.len() / 2);
+        }
+
+        // The stream carries on from one rectangle to the next.
+        let solid = [0x11, 0x22, 0x33, 0].repeat(64 * 64);
+        let first = round_trip(
+            rgb888,
+            Rect::with_size(64, 64),
+            &solid,
+            &mut encoder,
+            &mut decoder,
+        );
+        let second = round_trip(
+            rgb888,
+            Rect::with_size(64, 64),
+            &solid,
+            &mut encoder,
+            &mut decoder,
+        );
+        assert!(second <= first);
+    }
+}
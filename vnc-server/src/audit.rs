@@ -0,0 +1,167 @@
+use log::error;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use vnc_proto::protocol;
+
+/// An append-only log of the input viewers send through a proxy.
+///
+/// Every line starts with the time, in seconds since the Unix epoch, and the
+/// session it belongs to:
+///
+/// ```text
+/// 1700000000.123456 session=3 started peer=192.0.2.1:50234
+/// 1700000000.234567 session=3 key down=1 keysym=0x0061
+/// 1700000000.345678 session=3 pointer buttons=0x01 x=120 y=45
+/// 1700000000.456789 session=3 ended
+/// ```
+///
+/// Session numbers count up from the point the log was opened, which is
+/// recorded in the log as well. The log is shared between sessions and can be
+/// cloned freely.
+#[derive(Clone)]
+pub struct AuditLog {
+    file: Arc<Mutex<File>>,
+    next_session: Arc<AtomicU64>,
+}
+
+impl AuditLog {
+    /// Opens the log at `path` for appending, creating it if necessary.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<AuditLog> {
+        let file = OpenOptions::new().append(true).create(true).open(path)?;
+        let log = AuditLog {
+            file: Arc::new(Mutex::new(file)),
+            next_session: Arc::new(AtomicU64::new(1)),
+        };
+        log.write(format_args!("opened"))?;
+        Ok(log)
+    }
+
+    /// Starts a new session with a viewer at `peer`.
+    pub fn session(&self, peer: &str) -> io::Result<AuditSession> {
+        let session = AuditSession {
+            log: self.clone(),
+            id: self.next_session.fetch_add(1, Ordering::SeqCst),
+        };
+        session.write(format_args!("started peer={}", peer))?;
+        Ok(session)
+    }
+
+    fn write(&self, line: std::fmt::Arguments) -> io::Result<()> {
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        // Format the line first so that it reaches the file in a single write,
+        // and lines of concurrent sessions do not interleave.
+        let line = format!("{}.{:06} {}\n", time.as_secs(), time.subsec_micros(), line);
+        let mut file = self.file.lock().unwrap();
+        file.write_all(line.as_bytes())?;
+        file.flush()
+    }
+}
+
+/// The part of an `AuditLog` belonging to one viewer. Dropping it records the
+/// end of the session.
+pub struct AuditSession {
+    log: AuditLog,
+    id: u64,
+}
+
+impl AuditSession {
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Records `message` if it is a key or pointer event.
+    pub fn record(&self, message: &protocol::C2S) -> io::Result<()> {
+        match *message {
+            protocol::C2S::KeyEvent { down, key } => {
+                self.write(format_args!("key down={} keysym={:#06x}", down as u8, key))
+            }
+            protocol::C2S::QemuExtendedKeyEvent {
+                down,
+                keysym,
+                keycode,
+            } => self.write(format_args!(
+                "key down={} keysym={:#06x} keycode={:#04x}",
+                down as u8, keysym, keycode
+            )),
+            protocol::C2S::PointerEvent {
+                button_mask,
+                x_position,
+                y_position,
+            } => self.write(format_args!(
+                "pointer buttons={:#04x} x={} y={}",
+                button_mask, x_position, y_position
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    fn write(&self, line: std::fmt::Arguments) -> io::Result<()> {
+        self.log.write(format_args!("session={} {}", self.id, line))
+    }
+}
+
+impl Drop for AuditSession {
+    fn drop(&mut self) {
+        if let Err(error) = self.write(format_args!("ended")) {
+            error!("cannot write to audit log: {}", error)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AuditLog;
+    use vnc_proto::protocol::C2S;
+
+    #[test]
+    fn test_audit_log() {
+        let path = std::env::temp_dir().join(format!("vnc-audit-{}.log", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        {
+            let log = AuditLog::open(&path).unwrap();
+            let session = log.session("192.0.2.1:50234").unwrap();
+            assert_eq!(session.id(), 1);
+            session
+                .record(&C2S::KeyEvent {
+                    down: true,
+                    key: 0x61,
+                })
+                .unwrap();
+            session
+                .record(&C2S::PointerEvent {
+                    button_mask: 1,
+                    x_position: 120,
+                    y_position: 45,
+                })
+                .unwrap();
+            session.record(&C2S::CutText("secret".to_owned())).unwrap();
+        }
+        // Reopening appends instead of truncating.
+        AuditLog::open(&path).unwrap();
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let lines = contents
+            .lines()
+            .map(|line| line.split_once(' ').unwrap().1)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            lines,
+            [
+                "opened",
+                "session=1 started peer=192.0.2.1:50234",
+                "session=1 key down=1 keysym=0x0061",
+                "session=1 pointer buttons=0x01 x=120 y=45",
+                "session=1 ended",
+                "opened",
+            ]
+        );
+    }
+}
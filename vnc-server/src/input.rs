@@ -0,0 +1,185 @@
+//! What a server lets through of a viewer's input.
+
+use std::collections::HashSet;
+use std::time::{Duration, Instant};
+
+use crate::ServerEvent;
+
+/// What a `Server` does with the input of viewers of one `Role`; see
+/// `Server::set_input_policy`. The default lets everything through as it is.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct InputPolicy {
+    pub block_keys: bool,
+    pub block_pointer: bool,
+    pub block_cut_text: bool,
+    /// Moves the pointer back onto the framebuffer when a viewer puts it
+    /// outside.
+    pub clamp_pointer: bool,
+    /// The most keys a viewer may press in a second; more are dropped.
+    pub max_keys_per_second: Option<u32>,
+    /// Keysyms that may not be held down together, such as Ctrl-Alt-Delete
+    /// (`[0xffe3, 0xffe9, 0xffff]`). The key that would complete one is
+    /// dropped, and so is its release. Keysyms are compared as they are, so
+    /// left and right modifiers need a chord each.
+    pub blocked_chords: Vec<Vec<u32>>,
+}
+
+impl InputPolicy {
+    /// Drops all input, for viewers that may only watch.
+    pub fn view_only() -> InputPolicy {
+        InputPolicy {
+            block_keys: true,
+            block_pointer: true,
+            block_cut_text: true,
+            ..InputPolicy::default()
+        }
+    }
+}
+
+/// Applies an `InputPolicy` to the input of one viewer, keeping track of the
+/// keys it holds and how fast it types.
+#[derive(Debug)]
+pub(crate) struct InputFilter {
+    held: HashSet<u32>,
+    /// Keys whose press was dropped, so that their release is as well.
+    dropped: HashSet<u32>,
+    second_start: Instant,
+    keys_this_second: u32,
+}
+
+impl InputFilter {
+    pub fn new() -> InputFilter {
+        InputFilter {
+            held: HashSet::new(),
+            dropped: HashSet::new(),
+            second_start: Instant::now(),
+            keys_this_second: 0,
+        }
+    }
+
+    /// Returns what of `event` gets through `policy`, for a framebuffer of
+    /// `size`.
+    pub fn filter(
+        &mut self,
+        policy: &InputPolicy,
+        size: (u16, u16),
+        event: ServerEvent,
+    ) -> Option<ServerEvent> {
+        match event {
+            ServerEvent::Key { down: true, key } => {
+                let rate_limited = match policy.max_keys_per_second {
+                    Some(max) => {
+                        if self.second_start.elapsed() >= Duration::from_secs(1) {
+                            self.second_start = Instant::now();
+                            self.keys_this_second = 0;
+                        }
+                        self.keys_this_second += 1;
+                        self.keys_this_second > max
+                    }
+                    None => false,
+                };
+                let chord = policy.blocked_chords.iter().any(|chord| {
+                    chord.contains(&key)
+                        && chord
+                            .iter()
+                            .all(|&other| other == key || self.held.contains(&other))
+                });
+                self.held.insert(key);
+                if policy.block_keys || rate_limited || chord {
+                    self.dropped.insert(key);
+                    return None;
+                }
+                self.dropped.remove(&key);
+                Some(event)
+            }
+            ServerEvent::Key { down: false, key } => {
+                self.held.remove(&key);
+                match policy.block_keys || self.dropped.remove(&key) {
+                    true => None,
+                    false => Some(event),
+                }
+            }
+            ServerEvent::Pointer { .. } if policy.block_pointer => None,
+            ServerEvent::Pointer { button_mask, x, y } if policy.clamp_pointer => {
+                Some(ServerEvent::Pointer {
+                    button_mask,
+                    x: x.min(size.0.saturating_sub(1)),
+                    y: y.min(size.1.saturating_sub(1)),
+                })
+            }
+            ServerEvent::CutText(_) if policy.block_cut_text => None,
+            event => Some(event),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{InputFilter, InputPolicy};
+    use crate::ServerEvent;
+
+    fn key(down: bool, key: u32) -> ServerEvent {
+        ServerEvent::Key { down, key }
+    }
+
+    #[test]
+    fn test_chords() {
+        let policy = InputPolicy {
+            blocked_chords: vec![vec![0xffe3, 0xffe9, 0xffff]],
+            ..InputPolicy::default()
+        };
+        let mut filter = InputFilter::new();
+        let mut passed = |event| filter.filter(&policy, (8, 8), event).is_some();
+        assert!(passed(key(true, 0xffe3)));
+        assert!(passed(key(true, 0xffe9)));
+        // Delete with Ctrl and Alt held is dropped, press and release.
+        assert!(!passed(key(true, 0xffff)));
+        assert!(!passed(key(false, 0xffff)));
+        assert!(passed(key(false, 0xffe9)));
+        // Without Alt, it gets through.
+        assert!(passed(key(true, 0xffff)));
+        assert!(passed(key(false, 0xffff)));
+    }
+
+    #[test]
+    fn test_rate_limit() {
+        let policy = InputPolicy {
+            max_keys_per_second: Some(2),
+            ..InputPolicy::default()
+        };
+        let mut filter = InputFilter::new();
+        let mut passed = |event| filter.filter(&policy, (8, 8), event).is_some();
+        for _ in 0..2 {
+            assert!(passed(key(true, 0x61)));
+            assert!(passed(key(false, 0x61)));
+        }
+        assert!(!passed(key(true, 0x61)));
+        assert!(!passed(key(false, 0x61)));
+    }
+
+    #[test]
+    fn test_pointer() {
+        let mut filter = InputFilter::new();
+        let pointer = |x, y| ServerEvent::Pointer {
+            button_mask: 1,
+            x,
+            y,
+        };
+        let clamp = InputPolicy {
+            clamp_pointer: true,
+            ..InputPolicy::default()
+        };
+        assert_eq!(
+            filter.filter(&clamp, (8, 4), pointer(20, 2)),
+            Some(pointer(7, 2))
+        );
+        assert_eq!(
+            filter.filter(&InputPolicy::default(), (8, 4), pointer(20, 2)),
+            Some(pointer(20, 2))
+        );
+        assert_eq!(
+            filter.filter(&InputPolicy::view_only(), (8, 4), pointer(1, 2)),
+            None
+        );
+    }
+}
@@ -0,0 +1,18 @@
+mod audit;
+mod input;
+mod playback;
+mod proxy;
+mod scroll;
+mod server;
+mod status;
+mod tap;
+
+pub use audit::{AuditLog, AuditSession};
+pub use input::InputPolicy;
+pub use playback::Playback;
+pub use proxy::{Proxy, ProxyStream};
+pub use server::{ClientId, Role, Server, ServerEvent};
+pub use status::{ClientStatus, Status};
+pub use tap::Frame;
+pub use vnc_proto::protocol::RepeaterId;
+pub use vnc_proto::{Error, Result};
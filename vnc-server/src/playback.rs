@@ -0,0 +1,232 @@
+use log::debug;
+use std::fs::File;
+use std::io::{self, BufReader, Read, Write};
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::ProxyStream;
+use vnc_proto::fbs::{self, Block};
+use vnc_proto::protocol::{self, Message};
+use vnc_proto::{Error, Result};
+
+/// A session recorded as an FBS file, e.g. by `Client::record`, rfbproxy or
+/// vncrec, that can be served to viewers as if it were a live server.
+///
+/// Only what the server sent is recorded, so the viewers have no say in it:
+/// they get the recorded pixel format and encodings whatever they ask for, and
+/// what they send is read and dropped. Recordings must start with an RFB 3.3
+/// handshake without authentication.
+pub struct Playback {
+    server_init: protocol::ServerInit,
+    /// What the server sent after the handshake.
+    blocks: Vec<Block>,
+    /// When the handshake ended, in milliseconds since the recording started.
+    start: u32,
+    speed: f64,
+}
+
+impl Playback {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Playback> {
+        Playback::read_from(&mut BufReader::new(File::open(path)?))
+    }
+
+    pub fn read_from<R: Read>(reader: &mut R) -> Result<Playback> {
+        fbs::read_magic(reader)?;
+        let mut file = Vec::new();
+        reader.read_to_end(&mut file)?;
+        let mut file = &file[..];
+        let mut blocks = Vec::new();
+        while !file.is_empty() {
+            blocks.push(Block::read_from(&mut file)?);
+        }
+
+        // The handshake may be spread over several blocks.
+        let data = blocks
+            .iter()
+            .flat_map(|block| block.data.iter().copied())
+            .collect::<Vec<_>>();
+        let mut handshake = &data[..];
+        if protocol::Version::read_from(&mut handshake)? != protocol::Version::Rfb33 {
+            return Err(Error::Unexpected("recorded version"));
+        }
+        let mut security_type = [0; 4];
+        handshake.read_exact(&mut security_type)?;
+        if u32::from_be_bytes(security_type) != 1 {
+            return Err(Error::Unexpected("recorded security type"));
+        }
+        let server_init = protocol::ServerInit::read_from(&mut handshake)?;
+
+        let mut handshake_length = data.len() - handshake.len();
+        let mut start = 0;
+        blocks.retain_mut(|block| {
+            if handshake_length == 0 {
+                return true;
+            }
+            let length = handshake_length.min(block.data.len());
+            block.data.drain(..length);
+            handshake_length -= length;
+            start = block.timestamp;
+            !block.data.is_empty()
+        });
+        Ok(Playback {
+            server_init,
+            blocks,
+            start,
+            speed: 1.0,
+        })
+    }
+
+    /// Sets how many times as fast as recorded to play back; with
+    /// `f64::INFINITY`, everything is sent right away.
+    pub fn set_speed(&mut self, speed: f64) {
+        assert!(speed > 0.0, "playback speed must be positive");
+        self.speed = speed
+    }
+
+    pub fn speed(&self) -> f64 {
+        self.speed
+    }
+
+    /// How long playing back takes, at the current speed.
+    pub fn duration(&self) -> Duration {
+        self.blocks
+            .last()
+            .map_or(Duration::ZERO, |block| self.delay(block.timestamp))
+    }
+
+    fn delay(&self, timestamp: u32) -> Duration {
+        let recorded = timestamp.saturating_sub(self.start) as f64 / 1000.0;
+        Duration::from_secs_f64(recorded / self.speed)
+    }
+
+    /// Plays the recording back to a viewer at the other end of `stream`. Once
+    /// all of it has been sent, the connection is kept open until the viewer
+    /// hangs up, as a server that has nothing more to show would.
+    pub fn serve<S: ProxyStream>(&self, mut stream: S) -> Result<()> {
+        let handshake = fbs::handshake(&self.server_init)?;
+        stream.write_all(&handshake[..12])?;
+        let version = protocol::Version::read_from(&mut stream)?;
+        debug!("c->! {:?}", version);
+        stream.write_all(&handshake[12..16])?;
+        let client_init = protocol::ClientInit::read_from(&mut stream)?;
+        debug!("c->! {:?}", client_init);
+        stream.write_all(&handshake[16..])?;
+
+        let mut client_stream = stream.try_clone()?;
+        let drain = thread::spawn(move || io::copy(&mut client_stream, &mut io::sink()));
+
+        let result = self.play(&mut stream);
+        if result.is_err() {
+            let _ = stream.shutdown();
+        }
+        let _ = drain.join();
+        result
+    }
+
+    fn play<W: Write>(&self, stream: &mut W) -> Result<()> {
+        let start = Instant::now();
+        for block in &self.blocks {
+            let due = start + self.delay(block.timestamp);
+            if let Some(wait) = due.checked_duration_since(Instant::now()) {
+                thread::sleep(wait)
+            }
+            stream.write_all(&block.data)?;
+        }
+        stream.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Playback;
+    use std::io::{Read, Write};
+    use std::net::{Shutdown, TcpListener, TcpStream};
+    use std::thread;
+    use std::time::{Duration, Instant};
+    use vnc_proto::fbs::{self, Block};
+    use vnc_proto::protocol::{self, Message, PixelFormat};
+
+    fn recording() -> Vec<u8> {
+        let server_init = protocol::ServerInit {
+            framebuffer_width: 2,
+            framebuffer_height: 1,
+            pixel_format: PixelFormat {
+                bits_per_pixel: 8,
+                depth: 8,
+                big_endian: false,
+                true_colour: true,
+                red_max: 7,
+                green_max: 7,
+                blue_max: 3,
+                red_shift: 0,
+                green_shift: 3,
+                blue_shift: 6,
+            },
+            name: String::from("fbs"),
+        };
+        let handshake = fbs::handshake(&server_init).unwrap();
+        let mut file = fbs::MAGIC.to_vec();
+        // Split the handshake, as rfbproxy does when it arrives in pieces.
+        for (data, timestamp) in [
+            (&handshake[..20], 0),
+            (&handshake[20..], 10),
+            (b"\0\0\0\x01\0\0\0\0\0\x02\0\x01\0\0\0\0\x12\x34", 210),
+        ] {
+            Block {
+                data: data.to_vec(),
+                timestamp,
+            }
+            .write_to(&mut file)
+            .unwrap();
+        }
+        file
+    }
+
+    #[test]
+    fn test_playback() {
+        let mut playback = Playback::read_from(&mut &recording()[..]).unwrap();
+        assert_eq!(playback.duration(), Duration::from_millis(200));
+        playback.set_speed(4.0);
+        assert_eq!(playback.duration(), Duration::from_millis(50));
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let server = thread::spawn(move || playback.serve(listener.accept().unwrap().0));
+
+        assert_eq!(
+            protocol::Version::read_from(&mut stream).unwrap(),
+            protocol::Version::Rfb33
+        );
+        protocol::Version::Rfb33.write_to(&mut stream).unwrap();
+        let mut security_type = [0; 4];
+        stream.read_exact(&mut security_type).unwrap();
+        assert_eq!(security_type, [0, 0, 0, 1]);
+        protocol::ClientInit { shared: true }
+            .write_to(&mut stream)
+            .unwrap();
+        let start = Instant::now();
+        let server_init = protocol::ServerInit::read_from(&mut stream).unwrap();
+        assert_eq!(server_init.name, "fbs");
+
+        // Requests make no difference, but must not get in the way either.
+        protocol::C2S::FramebufferUpdateRequest {
+            incremental: false,
+            x_position: 0,
+            y_position: 0,
+            width: 2,
+            height: 1,
+        }
+        .write_to(&mut stream)
+        .unwrap();
+        let mut update = [0; 18];
+        stream.read_exact(&mut update).unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(50));
+        assert_eq!(&update[16..], b"\x12\x34");
+
+        stream.flush().unwrap();
+        stream.shutdown(Shutdown::Both).unwrap();
+        server.join().unwrap().unwrap();
+    }
+}
@@ -0,0 +1,607 @@
+use log::{debug, warn};
+use std::io::{self, Cursor, Read, Write};
+use std::net::{Shutdown, TcpStream};
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+use std::thread;
+
+use crate::tap::{Observer, Tap};
+use crate::{AuditSession, Frame};
+use vnc_proto::protocol::{self, Message};
+use vnc_proto::{Error, Result};
+
+/// A connection the proxy can forward between. Each direction is forwarded
+/// on a thread of its own, with a clone of the connection.
+pub trait ProxyStream: Read + Write + Send + Sized + 'static {
+    fn try_clone(&self) -> io::Result<Self>;
+    /// Closes both directions, waking up any thread blocked on the connection.
+    fn shutdown(&self) -> io::Result<()>;
+}
+
+impl ProxyStream for TcpStream {
+    fn try_clone(&self) -> io::Result<TcpStream> {
+        TcpStream::try_clone(self)
+    }
+
+    fn shutdown(&self) -> io::Result<()> {
+        TcpStream::shutdown(self, Shutdown::Both)
+    }
+}
+
+#[cfg(unix)]
+impl ProxyStream for UnixStream {
+    fn try_clone(&self) -> io::Result<UnixStream> {
+        UnixStream::try_clone(self)
+    }
+
+    fn shutdown(&self) -> io::Result<()> {
+        UnixStream::shutdown(self, Shutdown::Both)
+    }
+}
+
+pub struct Proxy {
+    c2s_thread: thread::JoinHandle<Result<()>>,
+    s2c_thread: thread::JoinHandle<Result<()>>,
+}
+
+impl Proxy {
+    pub fn from_tcp_streams(server_stream: TcpStream, client_stream: TcpStream) -> Result<Proxy> {
+        Proxy::from_tcp_streams_with_audit(server_stream, client_stream, None)
+    }
+
+    /// Like `from_tcp_streams`, but records the viewer's key and pointer events
+    /// in `audit`, if given. An event is only forwarded once it has been
+    /// recorded; if that fails, the session ends.
+    pub fn from_tcp_streams_with_audit(
+        server_stream: TcpStream,
+        client_stream: TcpStream,
+        audit: Option<AuditSession>,
+    ) -> Result<Proxy> {
+        Proxy::from_streams(server_stream, client_stream, audit)
+    }
+
+    /// Like `from_tcp_streams_with_audit`, for any kind of connection on
+    /// either side, e.g. a Unix socket to a QEMU or libvirt domain.
+    pub fn from_streams<S: ProxyStream, C: ProxyStream>(
+        server_stream: S,
+        client_stream: C,
+        audit: Option<AuditSession>,
+    ) -> Result<Proxy> {
+        Proxy::start(server_stream, client_stream, audit, None)
+    }
+
+    /// Like `from_streams`, with `server_stream` connected to an UltraVNC
+    /// repeater, which pairs the proxy with the server `id` names.
+    pub fn from_repeater_streams<S: ProxyStream, C: ProxyStream>(
+        mut server_stream: S,
+        id: &protocol::RepeaterId,
+        client_stream: C,
+        audit: Option<AuditSession>,
+    ) -> Result<Proxy> {
+        let mut greeting = [0; 12];
+        server_stream.read_exact(&mut greeting)?;
+        if greeting != protocol::RepeaterId::GREETING {
+            return Err(Error::Unexpected("repeater greeting"));
+        }
+        debug!("-> {:?}", id);
+        id.write_to(&mut server_stream)?;
+        Proxy::from_streams(server_stream, client_stream, audit)
+    }
+
+    /// Like `from_streams`, but also decodes the framebuffer updates passing
+    /// through, and calls `tap` with the framebuffer after every one of them,
+    /// e.g. to make thumbnails or live previews of the session. This costs the
+    /// proxy as much work as a viewer would do. The viewer gets the updates
+    /// only after `tap` returns, so it should be quick; if an update cannot be
+    /// decoded, the tap is dropped and the session goes on without it.
+    pub fn from_streams_with_tap<S, C, F>(
+        server_stream: S,
+        client_stream: C,
+        audit: Option<AuditSession>,
+        tap: F,
+    ) -> Result<Proxy>
+    where
+        S: ProxyStream,
+        C: ProxyStream,
+        F: FnMut(&Frame) + Send + 'static,
+    {
+        Proxy::start(server_stream, client_stream, audit, Some(Box::new(tap)))
+    }
+
+    fn start<S: ProxyStream, C: ProxyStream>(
+        mut server_stream: S,
+        mut client_stream: C,
+        audit: Option<AuditSession>,
+        tap: Option<Observer>,
+    ) -> Result<Proxy> {
+        let server_version = protocol::Version::read_from(&mut server_stream)?;
+        debug!("c<-s {:?}", server_version);
+        protocol::Version::write_to(&server_version, &mut client_stream)?;
+
+        let client_version = protocol::Version::read_from(&mut client_stream)?;
+        debug!("c->s {:?}", client_version);
+        protocol::Version::write_to(&client_version, &mut server_stream)?;
+
+        fn security_type_supported(security_type: &protocol::SecurityType) -> bool {
+            match security_type {
+                &protocol::SecurityType::None => true,
+                security_type => {
+                    warn!("security type {:?} is not supported", security_type);
+                    false
+                }
+            }
+        }
+
+        let security_types = match client_version {
+            protocol::Version::Rfb33 => {
+                let mut security_type = protocol::SecurityType::read_from(&mut server_stream)?;
+                debug!("!<-s SecurityType::{:?}", security_type);
+
+                // Filter out security types we can't handle
+                if !security_type_supported(&security_type) {
+                    security_type = protocol::SecurityType::Invalid
+                }
+
+                debug!("c<-! SecurityType::{:?}", security_type);
+                protocol::SecurityType::write_to(&security_type, &mut client_stream)?;
+
+                if security_type == protocol::SecurityType::Invalid {
+                    vec![]
+                } else {
+                    vec![security_type]
+                }
+            }
+            _ => {
+                let mut security_types = protocol::SecurityTypes::read_from(&mut server_stream)?;
+                debug!("!<-s {:?}", security_types);
+
+                // Filter out security types we can't handle
+                security_types.0.retain(security_type_supported);
+
+                debug!("c<-! {:?}", security_types);
+                protocol::SecurityTypes::write_to(&security_types, &mut client_stream)?;
+
+                security_types.0
+            }
+        };
+
+        if security_types.is_empty() {
+            let reason = String::read_from(&mut server_stream)?;
+            debug!("c<-s {:?}", reason);
+            String::write_to(&reason, &mut client_stream)?;
+
+            return Err(Error::Server(reason));
+        }
+
+        let used_security_type = match client_version {
+            protocol::Version::Rfb33 => security_types[0],
+            _ => {
+                let used_security_type = protocol::SecurityType::read_from(&mut client_stream)?;
+                debug!("c->s SecurityType::{:?}", used_security_type);
+                protocol::SecurityType::write_to(&used_security_type, &mut server_stream)?;
+
+                used_security_type
+            }
+        };
+
+        let mut skip_security_result = false;
+        match &(used_security_type, client_version) {
+            &(protocol::SecurityType::None, protocol::Version::Rfb33)
+            | &(protocol::SecurityType::None, protocol::Version::Rfb37) => {
+                skip_security_result = true
+            }
+            _ => (),
+        }
+
+        if !skip_security_result {
+            let security_result = protocol::SecurityResult::read_from(&mut server_stream)?;
+            debug!("c<-s SecurityResult::{:?}", security_result);
+            protocol::SecurityResult::write_to(&security_result, &mut client_stream)?;
+
+            if security_result == protocol::SecurityResult::Failed {
+                match client_version {
+                    protocol::Version::Rfb33 | protocol::Version::Rfb37 => {
+                        return Err(Error::AuthenticationFailure(String::from("")))
+                    }
+                    protocol::Version::Rfb38 => {
+                        let reason = String::read_from(&mut server_stream)?;
+                        debug!("c<-s {:?}", reason);
+                        String::write_to(&reason, &mut client_stream)?;
+                        return Err(Error::AuthenticationFailure(reason));
+                    }
+                }
+            }
+        }
+
+        let client_init = protocol::ClientInit::read_from(&mut client_stream)?;
+        debug!("c->s {:?}", client_init);
+        protocol::ClientInit::write_to(&client_init, &mut server_stream)?;
+
+        let server_init = protocol::ServerInit::read_from(&mut server_stream)?;
+        debug!("c<-s {:?}", server_init);
+        protocol::ServerInit::write_to(&server_init, &mut client_stream)?;
+
+        let tap = tap.map(|observer| {
+            Tap::new(
+                observer,
+                server_init.pixel_format,
+                server_init.framebuffer_width,
+                server_init.framebuffer_height,
+            )
+        });
+
+        let (mut c2s_server_stream, mut c2s_client_stream) = (
+            server_stream.try_clone().unwrap(),
+            client_stream.try_clone().unwrap(),
+        );
+        let (mut s2c_server_stream, mut s2c_client_stream) = (
+            server_stream.try_clone().unwrap(),
+            client_stream.try_clone().unwrap(),
+        );
+
+        fn forward_c2s<S: Write, C: Read>(
+            server_stream: &mut S,
+            client_stream: &mut C,
+            audit: Option<&AuditSession>,
+        ) -> Result<()> {
+            fn encoding_supported(encoding: &protocol::Encoding) -> bool {
+                match encoding {
+                    &protocol::Encoding::Raw
+                    | &protocol::Encoding::CopyRect
+                    | &protocol::Encoding::Zrle
+                    | &protocol::Encoding::Cursor
+                    | &protocol::Encoding::DesktopSize
+                    // Only changes what the server does with ZRLE.
+                    | &protocol::Encoding::CompressionLevel(_) => true,
+                    encoding => {
+                        warn!("encoding {:?} is not supported", encoding);
+                        false
+                    }
+                }
+            }
+
+            loop {
+                let mut message = protocol::C2S::read_from(client_stream)?;
+                match message {
+                    protocol::C2S::SetEncodings(ref mut encodings) => {
+                        debug!("c->! SetEncodings({:?})", encodings);
+
+                        // Filter out encodings we can't handle
+                        encodings.retain(encoding_supported);
+
+                        debug!("!->s SetEncodings({:?})", encodings);
+                    }
+                    protocol::C2S::SetPixelFormat(_) => {
+                        // There is an inherent race condition in the VNC protocol (I think)
+                        // between SetPixelFormat and FramebufferUpdate and I've no idea
+                        // how to handle it properly, so defer for now.
+                        panic!("proxying SetPixelFormat is not implemented!")
+                    }
+                    ref message => debug!("c->s {:?}", message),
+                }
+                if let Some(audit) = audit {
+                    audit.record(&message)?
+                }
+                protocol::C2S::write_to(&message, server_stream)?
+            }
+        }
+
+        fn forward_s2c<S: Read, C: Write>(
+            server_stream: &mut S,
+            client_stream: &mut C,
+            format: protocol::PixelFormat,
+            mut tap: Option<Tap>,
+        ) -> Result<()> {
+            loop {
+                let mut buffer_stream = Cursor::new(Vec::new());
+
+                let message = protocol::S2C::read_from(server_stream)?;
+                debug!("c<-s {:?}", message);
+                message.validate(&format)?;
+                protocol::S2C::write_to(&message, &mut buffer_stream)?;
+
+                match message {
+                    protocol::S2C::FramebufferUpdate { count } => {
+                        for _ in 0..count {
+                            let rectangle = protocol::Rectangle::read_from(server_stream)?;
+                            debug!("c<-s {:?}", rectangle);
+                            protocol::Rectangle::write_to(&rectangle, &mut buffer_stream)?;
+                            let data_start = buffer_stream.position() as usize;
+
+                            match rectangle.encoding {
+                                protocol::Encoding::Raw => {
+                                    let mut pixels = vec![
+                                        0;
+                                        (rectangle.width as usize)
+                                            * (rectangle.height as usize)
+                                            * (format.bits_per_pixel as usize / 8)
+                                    ];
+                                    server_stream.read_exact(&mut pixels)?;
+                                    debug!("c<-s ...raw pixels");
+                                    buffer_stream.write_all(&pixels)?;
+                                }
+                                protocol::Encoding::CopyRect => {
+                                    let copy_rect = protocol::CopyRect::read_from(server_stream)?;
+                                    debug!("c<-s {:?}", copy_rect);
+                                    protocol::CopyRect::write_to(&copy_rect, &mut buffer_stream)?;
+                                }
+                                protocol::Encoding::Zrle => {
+                                    let zrle = Vec::<u8>::read_from(server_stream)?;
+                                    debug!("c<-s ...ZRLE pixels");
+                                    Vec::<u8>::write_to(&zrle, &mut buffer_stream)?;
+                                }
+                                protocol::Encoding::Cursor => {
+                                    let mut pixels = vec![
+                                        0;
+                                        (rectangle.width as usize)
+                                            * (rectangle.height as usize)
+                                            * (format.bits_per_pixel as usize / 8)
+                                    ];
+                                    server_stream.read_exact(&mut pixels)?;
+                                    buffer_stream.write_all(&pixels)?;
+                                    let mut mask_bits = vec![
+                                        0;
+                                        (rectangle.width as usize).div_ceil(8)
+                                            * (rectangle.height as usize)
+                                    ];
+                                    server_stream.read_exact(&mut mask_bits)?;
+                                    buffer_stream.write_all(&mask_bits)?;
+                                }
+                                protocol::Encoding::DesktopSize => (),
+                                _ => return Err(Error::Unexpected("encoding")),
+                            }
+
+                            if let Some(ref mut active_tap) = tap {
+                                let data = &buffer_stream.get_ref()[data_start..];
+                                if let Err(error) = active_tap.apply(&rectangle, data) {
+                                    warn!("cannot decode update for tap, dropping it: {}", error);
+                                    tap = None;
+                                }
+                            }
+                        }
+                        if let Some(ref mut tap) = tap {
+                            tap.end_of_frame()
+                        }
+                    }
+                    // Already buffered in full above.
+                    protocol::S2C::SetColourMapEntries { .. }
+                    | protocol::S2C::Bell
+                    | protocol::S2C::CutText(_)
+                    | protocol::S2C::Fence(_)
+                    | protocol::S2C::Gii(_) => (),
+                }
+
+                let buffer = buffer_stream.into_inner();
+                client_stream.write_all(&buffer)?;
+            }
+        }
+
+        Ok(Proxy {
+            c2s_thread: thread::spawn(move || {
+                let result = forward_c2s(
+                    &mut c2s_server_stream,
+                    &mut c2s_client_stream,
+                    audit.as_ref(),
+                );
+                let _ = c2s_server_stream.shutdown();
+                let _ = c2s_client_stream.shutdown();
+                result
+            }),
+            s2c_thread: thread::spawn(move || {
+                let result = forward_s2c(
+                    &mut s2c_server_stream,
+                    &mut s2c_client_stream,
+                    server_init.pixel_format,
+                    tap,
+                );
+                let _ = s2c_server_stream.shutdown();
+                let _ = s2c_client_stream.shutdown();
+                result
+            }),
+        })
+    }
+
+    pub fn join(self) -> Result<()> {
+        let c2s_result = self.c2s_thread.join().unwrap();
+        let s2c_result = self.s2c_thread.join().unwrap();
+        match c2s_result.and(s2c_result) {
+            Err(Error::Disconnected) => Ok(()),
+            result => result,
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::Proxy;
+    use std::os::unix::net::UnixStream;
+    use std::sync::mpsc::channel;
+    use std::thread;
+    use vnc_proto::protocol::{self, Message, RepeaterId};
+    use vnc_proto::{PixelFormat, Rect, Result};
+
+    const FORMAT: PixelFormat = PixelFormat {
+        bits_per_pixel: 32,
+        depth: 24,
+        big_endian: false,
+        true_colour: true,
+        red_max: 255,
+        green_max: 255,
+        blue_max: 255,
+        red_shift: 16,
+        green_shift: 8,
+        blue_shift: 0,
+    };
+
+    // Runs a handshake for a 2x2 framebuffer through the proxy `start` makes,
+    // and returns the proxy along with the server and viewer ends.
+    fn handshake<F>(start: F) -> (Proxy, UnixStream, UnixStream)
+    where
+        F: FnOnce(UnixStream, UnixStream) -> Result<Proxy> + Send + 'static,
+    {
+        handshake_via(None, start)
+    }
+
+    // Like `handshake`, with the server end playing an UltraVNC repeater that
+    // expects `repeater` first, if given.
+    fn handshake_via<F>(repeater: Option<RepeaterId>, start: F) -> (Proxy, UnixStream, UnixStream)
+    where
+        F: FnOnce(UnixStream, UnixStream) -> Result<Proxy> + Send + 'static,
+    {
+        let (mut server, server_end) = UnixStream::pair().unwrap();
+        let (mut viewer, viewer_end) = UnixStream::pair().unwrap();
+        let proxy = thread::spawn(move || start(server_end, viewer_end));
+        if let Some(id) = repeater {
+            std::io::Write::write_all(&mut server, &RepeaterId::GREETING).unwrap();
+            assert_eq!(RepeaterId::read_from(&mut server).unwrap(), id);
+        }
+
+        protocol::Version::Rfb38.write_to(&mut server).unwrap();
+        let version = protocol::Version::read_from(&mut viewer).unwrap();
+        version.write_to(&mut viewer).unwrap();
+        assert_eq!(
+            protocol::Version::read_from(&mut server).unwrap(),
+            protocol::Version::Rfb38
+        );
+
+        protocol::SecurityTypes(vec![protocol::SecurityType::None])
+            .write_to(&mut server)
+            .unwrap();
+        protocol::SecurityTypes::read_from(&mut viewer).unwrap();
+        protocol::SecurityType::None.write_to(&mut viewer).unwrap();
+        protocol::SecurityType::read_from(&mut server).unwrap();
+        protocol::SecurityResult::Succeeded
+            .write_to(&mut server)
+            .unwrap();
+        protocol::SecurityResult::read_from(&mut viewer).unwrap();
+
+        protocol::ClientInit { shared: true }
+            .write_to(&mut viewer)
+            .unwrap();
+        protocol::ClientInit::read_from(&mut server).unwrap();
+        let server_init = protocol::ServerInit {
+            framebuffer_width: 2,
+            framebuffer_height: 2,
+            pixel_format: FORMAT,
+            name: "unix".to_owned(),
+        };
+        server_init.write_to(&mut server).unwrap();
+        assert_eq!(
+            protocol::ServerInit::read_from(&mut viewer).unwrap(),
+            server_init
+        );
+        (proxy.join().unwrap().unwrap(), server, viewer)
+    }
+
+    #[test]
+    fn test_unix_streams() {
+        let (proxy, mut server, mut viewer) =
+            handshake(|server_end, viewer_end| Proxy::from_streams(server_end, viewer_end, None));
+
+        protocol::S2C::Bell.write_to(&mut server).unwrap();
+        assert_eq!(
+            protocol::S2C::read_from(&mut viewer).unwrap(),
+            protocol::S2C::Bell
+        );
+        let key_event = protocol::C2S::KeyEvent {
+            down: true,
+            key: 0x61,
+        };
+        key_event.write_to(&mut viewer).unwrap();
+        assert_eq!(protocol::C2S::read_from(&mut server).unwrap(), key_event);
+
+        drop(server);
+        drop(viewer);
+        proxy.join().unwrap();
+    }
+
+    #[test]
+    fn test_repeater() {
+        let (proxy, server, viewer) =
+            handshake_via(Some(RepeaterId::Id(1234)), |server_end, viewer_end| {
+                Proxy::from_repeater_streams(server_end, &RepeaterId::Id(1234), viewer_end, None)
+            });
+        drop(server);
+        drop(viewer);
+        proxy.join().unwrap();
+    }
+
+    #[test]
+    fn test_tap() {
+        let (tx_frames, rx_frames) = channel();
+        let (proxy, mut server, mut viewer) = handshake(move |server_end, viewer_end| {
+            Proxy::from_streams_with_tap(server_end, viewer_end, None, move |frame| {
+                let frame = (
+                    frame.width,
+                    frame.height,
+                    frame.pixels.to_vec(),
+                    frame.damage.to_vec(),
+                );
+                tx_frames.send(frame).unwrap()
+            })
+        });
+
+        // A raw pixel in the top left corner, then copied to the bottom right.
+        protocol::S2C::FramebufferUpdate { count: 1 }
+            .write_to(&mut server)
+            .unwrap();
+        protocol::Rectangle {
+            x_position: 0,
+            y_position: 0,
+            width: 1,
+            height: 1,
+            encoding: protocol::Encoding::Raw,
+        }
+        .write_to(&mut server)
+        .unwrap();
+        std::io::Write::write_all(&mut server, &[1, 2, 3, 0]).unwrap();
+        protocol::S2C::FramebufferUpdate { count: 1 }
+            .write_to(&mut server)
+            .unwrap();
+        protocol::Rectangle {
+            x_position: 1,
+            y_position: 1,
+            width: 1,
+            height: 1,
+            encoding: protocol::Encoding::CopyRect,
+        }
+        .write_to(&mut server)
+        .unwrap();
+        protocol::CopyRect {
+            src_x_position: 0,
+            src_y_position: 0,
+        }
+        .write_to(&mut server)
+        .unwrap();
+
+        let pixel = [1, 2, 3, 0];
+        let blank = [0; 4];
+        assert_eq!(
+            rx_frames.recv().unwrap(),
+            (
+                2,
+                2,
+                [pixel, blank, blank, blank].concat(),
+                vec![Rect::new(0, 0, 1, 1)]
+            )
+        );
+        assert_eq!(
+            rx_frames.recv().unwrap(),
+            (
+                2,
+                2,
+                [pixel, blank, blank, pixel].concat(),
+                vec![Rect::new(1, 1, 1, 1)]
+            )
+        );
+
+        // The viewer gets the updates all the same.
+        let mut forwarded = [0; 4 + 12 + 4 + 4 + 12 + 4];
+        std::io::Read::read_exact(&mut viewer, &mut forwarded).unwrap();
+        assert_eq!(forwarded[16..20], pixel);
+
+        drop(server);
+        drop(viewer);
+        proxy.join().unwrap();
+    }
+}
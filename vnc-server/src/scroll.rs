@@ -0,0 +1,187 @@
+//! Finding content that moved within a rectangle, so that viewers can be told
+//! to copy it instead of being sent it again.
+
+use std::collections::HashMap;
+use vnc_proto::Rect;
+
+/// Content of a rectangle that moved from `src` to `dst`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Scroll {
+    pub src: Rect,
+    pub dst: Rect,
+    /// What still has to be sent after the copy: the part of the rectangle
+    /// nothing moved into, and the rows of `dst` that changed besides.
+    pub damage: Vec<Rect>,
+}
+
+/// A hash of a row or column of pixels; FNV-1a.
+fn hash<'a, I: IntoIterator<Item = &'a u8>>(hash: u64, bytes: I) -> u64 {
+    bytes.into_iter().fold(hash, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+const HASH_START: u64 = 0xcbf29ce484222325;
+
+fn row(pixels: &[u8], row_length: usize, y: usize) -> &[u8] {
+    &pixels[y * row_length..(y + 1) * row_length]
+}
+
+/// The shift that moves the most lines of `old` to where they are in `new`.
+/// Lines that occur more than once in `old`, such as blank ones, do not tell
+/// where they came from and are left out.
+fn best_shift(old: &[u64], new: &[u64]) -> Option<i32> {
+    let mut positions = HashMap::new();
+    for (position, &line) in old.iter().enumerate() {
+        positions
+            .entry(line)
+            .and_modify(|unique: &mut Option<usize>| *unique = None)
+            .or_insert(Some(position));
+    }
+    let mut votes = HashMap::<i32, usize>::new();
+    for (position, line) in new.iter().enumerate() {
+        if let Some(&Some(old_position)) = positions.get(line) {
+            if old_position != position {
+                *votes
+                    .entry(position as i32 - old_position as i32)
+                    .or_default() += 1
+            }
+        }
+    }
+    votes
+        .into_iter()
+        .filter(|&(_, count)| count >= 2)
+        .max_by_key(|&(shift, count)| (count, -shift.abs()))
+        .map(|(shift, _)| shift)
+}
+
+/// Looks for content of `rect` that moved up, down, left or right between
+/// `old` and `new`, both rows of `rect` packed at `bpp` bytes per pixel. It
+/// counts as moved if at least half of the rows of the destination match.
+pub(crate) fn detect(rect: Rect, old: &[u8], new: &[u8], bpp: usize) -> Option<Scroll> {
+    let (width, height) = (rect.width as usize, rect.height as usize);
+    let row_length = width * bpp;
+    if width < 2 || height < 2 {
+        return None;
+    }
+    let rows = |pixels: &[u8]| -> Vec<u64> {
+        (0..height)
+            .map(|y| hash(HASH_START, row(pixels, row_length, y)))
+            .collect()
+    };
+    let columns = |pixels: &[u8]| -> Vec<u64> {
+        let mut columns = vec![HASH_START; width];
+        for y in 0..height {
+            for (x, pixel) in row(pixels, row_length, y).chunks_exact(bpp).enumerate() {
+                columns[x] = hash(columns[x], pixel)
+            }
+        }
+        columns
+    };
+    let (dx, dy) = match best_shift(&rows(old), &rows(new)) {
+        Some(dy) => (0, dy),
+        None => (best_shift(&columns(old), &columns(new))?, 0),
+    };
+
+    let (width, height) = (rect.width as i32 - dx.abs(), rect.height as i32 - dy.abs());
+    if width <= 0 || height <= 0 {
+        return None;
+    }
+    let moved = |dx: i32, dy: i32| {
+        let (left, top) = (dx.max(0) as u16, dy.max(0) as u16);
+        Rect::new(
+            rect.left + left,
+            rect.top + top,
+            width as u16,
+            height as u16,
+        )
+    };
+    let (src, dst) = (moved(-dx, -dy), moved(dx, dy));
+    let mut damage = Vec::new();
+    let mut matching = 0;
+    for y in 0..dst.height as usize {
+        let dst_y = (dst.top as usize - rect.top as usize) + y;
+        let src_y = (src.top as usize - rect.top as usize) + y;
+        let dst_x = (dst.left - rect.left) as usize * bpp;
+        let src_x = (src.left - rect.left) as usize * bpp;
+        let length = dst.width as usize * bpp;
+        if row(new, row_length, dst_y)[dst_x..dst_x + length]
+            == row(old, row_length, src_y)[src_x..src_x + length]
+        {
+            matching += 1
+        } else {
+            damage.push(Rect::new(dst.left, dst_y as u16 + rect.top, dst.width, 1))
+        }
+    }
+    if matching * 2 < dst.height as usize {
+        return None;
+    }
+
+    // Nothing moved into the strip the content moved away from.
+    let exposed = match (dx.signum(), dy.signum()) {
+        (0, 1) => Rect::new(rect.left, rect.top, rect.width, dy as u16),
+        (0, _) => Rect::new(rect.left, dst.bottom() as u16, rect.width, (-dy) as u16),
+        (1, _) => Rect::new(rect.left, rect.top, dx as u16, rect.height),
+        _ => Rect::new(dst.right() as u16, rect.top, (-dx) as u16, rect.height),
+    };
+    damage.push(exposed);
+    Some(Scroll { src, dst, damage })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{detect, Scroll};
+    use vnc_proto::Rect;
+
+    /// A screen of distinct lines of text, with blank lines in between, as
+    /// bytes of one pixel each.
+    fn text(width: u8, height: u8, first_line: u8) -> Vec<u8> {
+        (0..height)
+            .flat_map(|y| {
+                (0..width).map(move |x| match (y + first_line) % 3 {
+                    0 => 0,
+                    line => line.wrapping_mul(x ^ (y + first_line)),
+                })
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_vertical() {
+        let rect = Rect::new(10, 20, 16, 12);
+        let old = text(16, 12, 0);
+        // Scrolled up by 2 lines, with a new prompt at the bottom.
+        let mut new = text(16, 12, 2);
+        new[9 * 16..10 * 16].fill(7);
+        assert_eq!(
+            detect(rect, &old, &new, 1),
+            Some(Scroll {
+                src: Rect::new(10, 22, 16, 10),
+                dst: Rect::new(10, 20, 16, 10),
+                damage: vec![Rect::new(10, 29, 16, 1), Rect::new(10, 30, 16, 2)],
+            })
+        );
+        assert_eq!(detect(rect, &old, &old, 1), None);
+    }
+
+    #[test]
+    fn test_horizontal() {
+        let rect = Rect::new(0, 0, 12, 4);
+        let old = (0..48)
+            .map(|i| (i % 12) as u8 * 7 + (i / 12) as u8)
+            .collect::<Vec<_>>();
+        // Moved right by 3 columns.
+        let new = old
+            .chunks(12)
+            .flat_map(|row| [0, 0, 0].into_iter().chain(row[..9].iter().copied()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            detect(rect, &old, &new, 1),
+            Some(Scroll {
+                src: Rect::new(0, 0, 9, 4),
+                dst: Rect::new(3, 0, 9, 4),
+                damage: vec![Rect::new(0, 0, 3, 4)],
+            })
+        );
+    }
+}
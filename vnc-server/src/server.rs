@@ -0,0 +1,1269 @@
+use log::{debug, info, warn};
+use std::collections::hash_map::RandomState;
+use std::collections::{HashMap, VecDeque};
+use std::hash::BuildHasher;
+use std::io::{Read, Write};
+use std::net::TcpListener;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::input::{InputFilter, InputPolicy};
+use crate::scroll::{self, Scroll};
+use crate::status::{ClientStatus, Status};
+use crate::ProxyStream;
+use vnc_proto::protocol::{self, Message};
+use vnc_proto::{
+    des, hextile, pixels, tight, zrle, Damage, Encoding, Error, PixelFormat, Rect, Result,
+};
+
+/// Tells the viewers of a `Server` apart, for as long as it runs.
+pub type ClientId = u64;
+
+/// What a viewer of a `Server` did, as handed to its handler.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ServerEvent {
+    /// The viewer is through the handshake, logged in as `role`. Unless it
+    /// asked for a `shared` session, the other viewers have been disconnected.
+    Connected {
+        shared: bool,
+        role: Role,
+    },
+    Key {
+        down: bool,
+        key: u32,
+    },
+    Pointer {
+        button_mask: u8,
+        x: u16,
+        y: u16,
+    },
+    CutText(String),
+    Disconnected,
+}
+
+/// What a viewer may do, as given by the password it logged in with; see
+/// `Server::set_input_policy`. Without passwords, every viewer has full
+/// control.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Role {
+    /// By default, its input and clipboard text get through to the handler.
+    Full,
+    /// By default, only watches: its input and clipboard text are dropped, as
+    /// with UltraVNC's view-only password.
+    ViewOnly,
+}
+
+/// A password viewers can log in with.
+struct Password {
+    key: des::Key,
+    role: Role,
+    one_time: bool,
+}
+
+/// Truncates or pads `password` to the 8 bytes VNC authentication uses.
+fn password_key(password: &str) -> des::Key {
+    let mut key = [0; 8];
+    for (byte, &password_byte) in key.iter_mut().zip(password.as_bytes()) {
+        *byte = password_byte
+    }
+    key
+}
+
+/// A challenge for VNC authentication, from the random keys of `RandomState`
+/// and the time, which is hard enough to predict for a scheme this weak.
+fn challenge() -> [u8; 16] {
+    let mut challenge = [0; 16];
+    for chunk in challenge.chunks_mut(8) {
+        let random = RandomState::new().hash_one(SystemTime::now());
+        chunk.copy_from_slice(&random.to_le_bytes());
+    }
+    challenge
+}
+
+/// Checks that a viewer's `format` is one the encoders can produce: true
+/// colour, with whole bytes per pixel and colours that fit in them.
+fn check_format(format: &PixelFormat) -> Result<()> {
+    if !format.true_colour {
+        return Err(Error::Unexpected("colour-mapped pixel format"));
+    }
+    if !matches!(format.bits_per_pixel, 8 | 16 | 32) || format.depth > format.bits_per_pixel {
+        return Err(Error::Unexpected("bits per pixel"));
+    }
+    let fits = |max: u16, shift: u8| {
+        shift < format.bits_per_pixel && (max as u64) << shift < 1u64 << format.bits_per_pixel
+    };
+    if !(fits(format.red_max, format.red_shift)
+        && fits(format.green_max, format.green_shift)
+        && fits(format.blue_max, format.blue_shift))
+    {
+        return Err(Error::Unexpected("colour outside the pixel"));
+    }
+    Ok(())
+}
+
+type Handler = Box<dyn FnMut(ClientId, ServerEvent) + Send>;
+
+struct Desktop {
+    width: u16,
+    height: u16,
+    pixels: Vec<u8>,
+}
+
+/// What a viewer asked for and what it is due.
+struct ClientState {
+    format: PixelFormat,
+    encodings: Vec<Encoding>,
+    /// The size of the framebuffer as far as the viewer knows.
+    size: (u16, u16),
+    requested: bool,
+    damage: Damage,
+    /// Content to move within the viewer's framebuffer, from the first
+    /// rectangle to the second, before the damage is sent.
+    copy: Option<(Rect, Rect)>,
+    resized: bool,
+    messages: Vec<protocol::S2C>,
+    closed: bool,
+    role: Role,
+    connected_at: Instant,
+    updates_sent: u64,
+    bytes_sent: u64,
+}
+
+impl ClientState {
+    fn update_due(&self) -> bool {
+        self.requested && (self.resized || self.copy.is_some() || !self.damage.is_empty())
+    }
+
+    /// Adds content of `rect` that moved: copied, if the viewer can copy it
+    /// and has the source as it was, or damaged otherwise.
+    fn add_scroll(&mut self, rect: Rect, scroll: &Scroll) {
+        let bounds = Rect::with_size(self.size.0, self.size.1);
+        let copyable = self.encodings.contains(&Encoding::CopyRect)
+            && self.copy.is_none()
+            && bounds.contains_rect(&scroll.src)
+            && bounds.contains_rect(&scroll.dst)
+            && self
+                .damage
+                .rects()
+                .iter()
+                .all(|damage| damage.intersection(&scroll.src).is_none());
+        if !copyable {
+            self.damage.add(rect);
+            return;
+        }
+        self.copy = Some((scroll.src, scroll.dst));
+        for &damage in &scroll.damage {
+            self.damage.add(damage)
+        }
+    }
+
+    fn take_update(&mut self) -> Update {
+        self.requested = false;
+        // The first of the viewer's encodings the server can produce.
+        let encoding = self
+            .encodings
+            .iter()
+            .copied()
+            .find(|encoding| {
+                matches!(
+                    encoding,
+                    Encoding::Raw | Encoding::Hextile | Encoding::Zrle | Encoding::Tight
+                )
+            })
+            .unwrap_or(Encoding::Raw);
+        Update {
+            damage: self.damage.take(),
+            copy: self.copy.take(),
+            resized: std::mem::replace(&mut self.resized, false),
+            format: self.format,
+            size: self.size,
+            encoding,
+        }
+    }
+}
+
+/// An update a viewer is due, and how to write it for that viewer.
+struct Update {
+    damage: Vec<Rect>,
+    copy: Option<(Rect, Rect)>,
+    resized: bool,
+    format: PixelFormat,
+    size: (u16, u16),
+    encoding: Encoding,
+}
+
+/// The encoders of one viewer, whose compression streams span its session.
+struct Encoders {
+    hextile: hextile::Encoder,
+    zrle: zrle::Encoder,
+    tight: tight::Encoder,
+}
+
+impl Encoders {
+    /// Writes a rectangle of `pixels`, which are in the viewer's `format`.
+    fn write_rect(
+        &mut self,
+        encoding: Encoding,
+        format: PixelFormat,
+        rect: Rect,
+        pixels: &[u8],
+        buffer: &mut Vec<u8>,
+    ) -> Result<()> {
+        protocol::Rectangle {
+            x_position: rect.left,
+            y_position: rect.top,
+            width: rect.width,
+            height: rect.height,
+            encoding,
+        }
+        .write_to(buffer)?;
+        match encoding {
+            Encoding::Zrle => {
+                let mut data = Vec::new();
+                self.zrle.encode(format, rect, pixels, &mut data)?;
+                data.write_to(buffer)
+            }
+            Encoding::Hextile => self.hextile.encode(format, rect, pixels, buffer),
+            Encoding::Tight => self.tight.encode(format, rect, pixels, buffer),
+            _ => {
+                buffer.extend_from_slice(pixels);
+                Ok(())
+            }
+        }
+    }
+}
+
+struct Connection {
+    state: Mutex<ClientState>,
+    wake: Condvar,
+}
+
+impl Connection {
+    fn update<F: FnOnce(&mut ClientState)>(&self, f: F) {
+        f(&mut self.state.lock().unwrap());
+        self.wake.notify_one();
+    }
+}
+
+struct Shared {
+    name: String,
+    format: PixelFormat,
+    desktop: Mutex<Desktop>,
+    clients: Mutex<HashMap<ClientId, Arc<Connection>>>,
+    handler: Mutex<Handler>,
+    passwords: Mutex<Vec<Password>>,
+    policies: Mutex<HashMap<Role, InputPolicy>>,
+    next_id: AtomicU64,
+    started: Instant,
+    /// When something was sent to a viewer in the last `RATE_WINDOW`, how
+    /// many bytes and whether it was an update.
+    sent: Mutex<VecDeque<(Instant, usize, bool)>>,
+}
+
+impl Shared {
+    fn handle(&self, id: ClientId, event: ServerEvent) {
+        (self.handler.lock().unwrap())(id, event)
+    }
+
+    fn record_sent(&self, bytes: usize, update: bool) {
+        let now = Instant::now();
+        let mut sent = self.sent.lock().unwrap();
+        sent.push_back((now, bytes, update));
+        while sent
+            .front()
+            .is_some_and(|&(time, ..)| now - time > RATE_WINDOW)
+        {
+            sent.pop_front();
+        }
+    }
+
+    fn each_client<F: Fn(&mut ClientState)>(&self, f: F) {
+        for connection in self.clients.lock().unwrap().values() {
+            connection.update(&f)
+        }
+    }
+}
+
+/// How long the frame rate and bandwidth of a `Status` are averaged over.
+const RATE_WINDOW: Duration = Duration::from_secs(5);
+
+/// A VNC server for a framebuffer the application draws into, shared by any
+/// number of viewers. Each of them gets updates of what changed since it last
+/// asked, in the pixel format it asked for; what they do is handed to the
+/// handler given to `new`, on the thread of the viewer that did it, as far as
+/// the input policy of its role lets it through.
+///
+/// Viewers are let in without authentication unless passwords are added with
+/// `add_password`; even then, VNC authentication only keeps out those who do
+/// not know a password, so make sure only the right viewers can connect.
+/// Clones share the framebuffer, the viewers, the passwords and the input
+/// policies.
+#[derive(Clone)]
+pub struct Server {
+    shared: Arc<Shared>,
+}
+
+impl Server {
+    /// A black framebuffer of `width` by `height` pixels in the true colour
+    /// `format`, which is what viewers are offered and what `put_pixels`
+    /// takes.
+    pub fn new<F>(width: u16, height: u16, format: PixelFormat, name: &str, handler: F) -> Server
+    where
+        F: FnMut(ClientId, ServerEvent) + Send + 'static,
+    {
+        assert!(
+            format.true_colour,
+            "the server's pixel format must be true colour"
+        );
+        let length = width as usize * height as usize * (format.bits_per_pixel as usize / 8);
+        Server {
+            shared: Arc::new(Shared {
+                name: name.to_owned(),
+                format,
+                desktop: Mutex::new(Desktop {
+                    width,
+                    height,
+                    pixels: vec![0; length],
+                }),
+                clients: Mutex::new(HashMap::new()),
+                handler: Mutex::new(Box::new(handler)),
+                passwords: Mutex::new(Vec::new()),
+                policies: Mutex::new(HashMap::from([
+                    (Role::Full, InputPolicy::default()),
+                    (Role::ViewOnly, InputPolicy::view_only()),
+                ])),
+                next_id: AtomicU64::new(1),
+                started: Instant::now(),
+                sent: Mutex::new(VecDeque::new()),
+            }),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.shared.name
+    }
+
+    pub fn size(&self) -> (u16, u16) {
+        let desktop = self.shared.desktop.lock().unwrap();
+        (desktop.width, desktop.height)
+    }
+
+    pub fn format(&self) -> PixelFormat {
+        self.shared.format
+    }
+
+    /// The viewers connected now.
+    pub fn clients(&self) -> Vec<ClientId> {
+        self.shared
+            .clients
+            .lock()
+            .unwrap()
+            .keys()
+            .copied()
+            .collect()
+    }
+
+    /// Makes viewers that connect from now on log in with VNC authentication,
+    /// which lets in those who know `password` as `role`. Only the first 8
+    /// bytes of a password count. A `one_time` password is forgotten once a
+    /// viewer has logged in with it, e.g. to let someone in for one support
+    /// session.
+    pub fn add_password(&self, password: &str, role: Role, one_time: bool) {
+        let key = password_key(password);
+        let mut passwords = self.shared.passwords.lock().unwrap();
+        passwords.retain(|password| password.key != key);
+        passwords.push(Password {
+            key,
+            role,
+            one_time,
+        });
+    }
+
+    /// Forgets `password`. Once there are none left, viewers are let in
+    /// without authentication again; those logged in stay connected.
+    pub fn remove_password(&self, password: &str) {
+        let key = password_key(password);
+        let mut passwords = self.shared.passwords.lock().unwrap();
+        passwords.retain(|password| password.key != key);
+    }
+
+    /// The viewers connected now and what has been sent to them.
+    pub fn status(&self) -> Status {
+        let now = Instant::now();
+        let (mut bytes, mut updates) = (0, 0);
+        for &(time, length, update) in self.shared.sent.lock().unwrap().iter() {
+            if now - time <= RATE_WINDOW {
+                bytes += length;
+                updates += update as usize;
+            }
+        }
+        let uptime = now - self.shared.started;
+        let window = uptime.min(RATE_WINDOW).as_secs_f64();
+        let rate = |count: usize| match window > 0.0 {
+            true => count as f64 / window,
+            false => 0.0,
+        };
+        let mut clients = self
+            .shared
+            .clients
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(&id, connection)| {
+                let state = connection.state.lock().unwrap();
+                ClientStatus {
+                    id,
+                    role: state.role,
+                    connected: now - state.connected_at,
+                    updates: state.updates_sent,
+                    bytes: state.bytes_sent,
+                }
+            })
+            .collect::<Vec<_>>();
+        clients.sort_by_key(|client| client.id);
+        Status {
+            name: self.shared.name.clone(),
+            uptime,
+            frame_rate: rate(updates),
+            bandwidth: rate(bytes),
+            clients,
+        }
+    }
+
+    /// Sets what the server does with the input of viewers logged in as
+    /// `role`, from their next event on.
+    pub fn set_input_policy(&self, role: Role, policy: InputPolicy) {
+        self.shared.policies.lock().unwrap().insert(role, policy);
+    }
+
+    /// Accepts viewers on `listener` until accepting fails, shaking hands with
+    /// each of them on a thread of its own.
+    pub fn listen(&self, listener: &TcpListener) -> Result<()> {
+        loop {
+            let (stream, peer) = listener.accept()?;
+            let server = self.clone();
+            thread::spawn(move || match server.serve(stream) {
+                Ok(id) => info!("viewer {} connected from {}", id, peer),
+                Err(error) => warn!("cannot serve {}: {}", peer, error),
+            });
+        }
+    }
+
+    /// Shakes hands with the viewer at the other end of `stream`, then serves
+    /// it on threads of its own until either side hangs up.
+    pub fn serve<S: ProxyStream>(&self, mut stream: S) -> Result<ClientId> {
+        protocol::Version::Rfb38.write_to(&mut stream)?;
+        let version = protocol::Version::read_from(&mut stream)?;
+        debug!("c->! {:?}", version);
+        let role = self.authenticate(&mut stream, version)?;
+        let client_init = protocol::ClientInit::read_from(&mut stream)?;
+        debug!("c->! {:?}", client_init);
+        let (width, height) = self.size();
+        protocol::ServerInit {
+            framebuffer_width: width,
+            framebuffer_height: height,
+            pixel_format: self.shared.format,
+            name: self.shared.name.clone(),
+        }
+        .write_to(&mut stream)?;
+        stream.flush()?;
+
+        if !client_init.shared {
+            for id in self.clients() {
+                self.disconnect(id)
+            }
+        }
+        let mut write_stream = stream.try_clone()?;
+        let id = self.shared.next_id.fetch_add(1, Ordering::SeqCst);
+        let connection = Arc::new(Connection {
+            state: Mutex::new(ClientState {
+                format: self.shared.format,
+                encodings: Vec::new(),
+                size: (width, height),
+                requested: false,
+                damage: Damage::new(),
+                copy: None,
+                resized: false,
+                messages: Vec::new(),
+                closed: false,
+                role,
+                connected_at: Instant::now(),
+                updates_sent: 0,
+                bytes_sent: 0,
+            }),
+            wake: Condvar::new(),
+        });
+        self.shared
+            .clients
+            .lock()
+            .unwrap()
+            .insert(id, connection.clone());
+        self.shared.handle(
+            id,
+            ServerEvent::Connected {
+                shared: client_init.shared,
+                role,
+            },
+        );
+
+        let (shared, write_connection) = (self.shared.clone(), connection.clone());
+        thread::spawn(move || {
+            if let Err(error) = Server::write_updates(&shared, &write_connection, &mut write_stream)
+            {
+                debug!("viewer {}: {}", id, error);
+            }
+            write_connection.update(|state| state.closed = true);
+            let _ = write_stream.shutdown();
+        });
+
+        let shared = self.shared.clone();
+        thread::spawn(move || {
+            if let Err(error) = Server::read_messages(&shared, id, role, &connection, &mut stream) {
+                debug!("viewer {}: {}", id, error);
+            }
+            connection.update(|state| state.closed = true);
+            shared.clients.lock().unwrap().remove(&id);
+            shared.handle(id, ServerEvent::Disconnected);
+        });
+        Ok(id)
+    }
+
+    /// Offers VNC authentication if there are passwords, or none otherwise,
+    /// and returns the role of the password the viewer logged in with.
+    fn authenticate<S: Read + Write>(
+        &self,
+        stream: &mut S,
+        version: protocol::Version,
+    ) -> Result<Role> {
+        let security_type = match self.shared.passwords.lock().unwrap().is_empty() {
+            true => protocol::SecurityType::None,
+            false => protocol::SecurityType::VncAuthentication,
+        };
+        match version {
+            // Before 3.7, the server picks the security type, as a u32.
+            protocol::Version::Rfb33 => {
+                stream.write_all(&[0; 3])?;
+                security_type.write_to(stream)?;
+            }
+            _ => {
+                protocol::SecurityTypes(vec![security_type]).write_to(stream)?;
+                if protocol::SecurityType::read_from(stream)? != security_type {
+                    return Err(Error::Unexpected("security type"));
+                }
+            }
+        }
+        if security_type == protocol::SecurityType::None {
+            if version == protocol::Version::Rfb38 {
+                protocol::SecurityResult::Succeeded.write_to(stream)?;
+            }
+            return Ok(Role::Full);
+        }
+
+        let challenge = challenge();
+        stream.write_all(&challenge)?;
+        stream.flush()?;
+        let mut response = [0; 16];
+        stream.read_exact(&mut response)?;
+        let role = {
+            let mut passwords = self.shared.passwords.lock().unwrap();
+            let index = passwords
+                .iter()
+                .position(|password| des::vnc_auth_response(&challenge, &password.key) == response);
+            index.map(|index| match passwords[index].one_time {
+                true => passwords.remove(index).role,
+                false => passwords[index].role,
+            })
+        };
+        match role {
+            Some(role) => {
+                protocol::SecurityResult::Succeeded.write_to(stream)?;
+                Ok(role)
+            }
+            None => {
+                protocol::SecurityResult::Failed.write_to(stream)?;
+                if version == protocol::Version::Rfb38 {
+                    String::from("wrong password").write_to(stream)?;
+                }
+                stream.flush()?;
+                Err(Error::AuthenticationFailure(String::from("wrong password")))
+            }
+        }
+    }
+
+    fn read_messages<S: ProxyStream>(
+        shared: &Shared,
+        id: ClientId,
+        role: Role,
+        connection: &Connection,
+        stream: &mut S,
+    ) -> Result<()> {
+        let mut filter = InputFilter::new();
+        loop {
+            let message = protocol::C2S::read_from(stream)?;
+            debug!("c->! {:?}", message);
+            let event = match message {
+                protocol::C2S::SetPixelFormat(format) => {
+                    check_format(&format)?;
+                    connection.update(|state| {
+                        state.format = format;
+                        // Viewers may start over in the new format.
+                        if let Some((_, dst)) = state.copy.take() {
+                            state.damage.add(dst)
+                        }
+                    });
+                    continue;
+                }
+                protocol::C2S::SetEncodings(encodings) => {
+                    connection.update(|state| state.encodings = encodings);
+                    continue;
+                }
+                protocol::C2S::FramebufferUpdateRequest {
+                    incremental,
+                    x_position,
+                    y_position,
+                    width,
+                    height,
+                } => {
+                    let rect = Rect::new(x_position, y_position, width, height);
+                    let desktop = shared.desktop.lock().unwrap();
+                    let rect = rect.clip_to(desktop.width, desktop.height);
+                    drop(desktop);
+                    connection.update(|state| {
+                        state.requested = true;
+                        if let (false, Some(rect)) = (incremental, rect) {
+                            state.damage.add(rect)
+                        }
+                    });
+                    continue;
+                }
+                protocol::C2S::KeyEvent { down, key } => ServerEvent::Key { down, key },
+                protocol::C2S::QemuExtendedKeyEvent { down, keysym, .. } => {
+                    ServerEvent::Key { down, key: keysym }
+                }
+                protocol::C2S::PointerEvent {
+                    button_mask,
+                    x_position,
+                    y_position,
+                } => ServerEvent::Pointer {
+                    button_mask,
+                    x: x_position,
+                    y: y_position,
+                },
+                protocol::C2S::CutText(text) => ServerEvent::CutText(text),
+                // Nothing that would make viewers send these was announced.
+                _ => continue,
+            };
+            let desktop = shared.desktop.lock().unwrap();
+            let size = (desktop.width, desktop.height);
+            drop(desktop);
+            let policies = shared.policies.lock().unwrap();
+            let event = filter.filter(&policies[&role], size, event);
+            drop(policies);
+            if let Some(event) = event {
+                shared.handle(id, event)
+            }
+        }
+    }
+
+    fn write_updates<W: Write>(
+        shared: &Shared,
+        connection: &Connection,
+        stream: &mut W,
+    ) -> Result<()> {
+        let mut encoders = Encoders {
+            hextile: hextile::Encoder::new(),
+            zrle: zrle::Encoder::new(),
+            tight: tight::Encoder::new(),
+        };
+        loop {
+            let mut state = connection
+                .wake
+                .wait_while(connection.state.lock().unwrap(), |state| {
+                    !state.closed && state.messages.is_empty() && !state.update_due()
+                })
+                .unwrap();
+            if state.closed {
+                return Ok(());
+            }
+            let mut buffer = Vec::new();
+            for message in state.messages.drain(..) {
+                debug!("c<-! {:?}", message);
+                message.write_to(&mut buffer)?;
+            }
+            let update = state.update_due().then(|| state.take_update());
+            drop(state);
+            let updated = update.is_some();
+            if let Some(update) = update {
+                Server::write_update(shared, update, &mut encoders, &mut buffer)?;
+            }
+            {
+                let mut state = connection.state.lock().unwrap();
+                state.updates_sent += updated as u64;
+                state.bytes_sent += buffer.len() as u64;
+            }
+            shared.record_sent(buffer.len(), updated);
+            stream.write_all(&buffer)?;
+            stream.flush()?;
+        }
+    }
+
+    fn write_update(
+        shared: &Shared,
+        update: Update,
+        encoders: &mut Encoders,
+        buffer: &mut Vec<u8>,
+    ) -> Result<()> {
+        let Update {
+            damage,
+            copy,
+            resized,
+            format,
+            size,
+            encoding,
+        } = update;
+        // The pixels are copied out so that encoding them does not keep the
+        // application and the other viewers waiting.
+        let desktop = shared.desktop.lock().unwrap();
+        let desktop_size = (desktop.width, desktop.height);
+        let (width, height) = (desktop.width.min(size.0), desktop.height.min(size.1));
+        let rects = damage
+            .iter()
+            .filter_map(|rect| rect.clip_to(width, height))
+            // Squares of 256 pixels keep within the limits of Tight.
+            .flat_map(|rect| match encoding {
+                Encoding::Tight => rect.tiles(256).collect(),
+                _ => vec![rect],
+            })
+            .map(|rect| (rect, Server::rect_pixels(shared.format, &desktop, rect)))
+            .collect::<Vec<_>>();
+        drop(desktop);
+
+        // Updates have room for 65535 rectangles, so any more go in further
+        // updates; the first one also moves and resizes what needs it.
+        let mut rects = rects.into_iter().peekable();
+        let mut first = true;
+        while first || rects.peek().is_some() {
+            let extra = match first {
+                true => resized as usize + copy.is_some() as usize,
+                false => 0,
+            };
+            let batch = rects
+                .by_ref()
+                .take(u16::MAX as usize - extra)
+                .collect::<Vec<_>>();
+            let count = batch.len() + extra;
+            protocol::S2C::FramebufferUpdate {
+                count: count as u16,
+            }
+            .write_to(buffer)?;
+            if first && resized {
+                protocol::Rectangle {
+                    x_position: 0,
+                    y_position: 0,
+                    width: desktop_size.0,
+                    height: desktop_size.1,
+                    encoding: Encoding::DesktopSize,
+                }
+                .write_to(buffer)?;
+            }
+            if let (true, Some((src, dst))) = (first, copy) {
+                protocol::Rectangle {
+                    x_position: dst.left,
+                    y_position: dst.top,
+                    width: dst.width,
+                    height: dst.height,
+                    encoding: Encoding::CopyRect,
+                }
+                .write_to(buffer)?;
+                protocol::CopyRect {
+                    src_x_position: src.left,
+                    src_y_position: src.top,
+                }
+                .write_to(buffer)?;
+            }
+            for (rect, mut pixels) in batch {
+                if format != shared.format {
+                    let mut rgb = Vec::with_capacity(rect.area() * 3);
+                    pixels::unpack_rgb(shared.format, &pixels, &mut rgb);
+                    pixels.clear();
+                    pixels::pack_rgb(format, &rgb, &mut pixels);
+                }
+                encoders.write_rect(encoding, format, rect, &pixels, buffer)?;
+            }
+            debug!("c<-! FramebufferUpdate of {} rectangles", count);
+            first = false;
+        }
+        Ok(())
+    }
+
+    /// The pixels of `rect`, in rows, in the server's format.
+    fn rect_pixels(format: PixelFormat, desktop: &Desktop, rect: Rect) -> Vec<u8> {
+        let bytes_per_pixel = format.bits_per_pixel as usize / 8;
+        let row_length = rect.width as usize * bytes_per_pixel;
+        let mut pixels = Vec::with_capacity(rect.height as usize * row_length);
+        for y in rect.top as usize..rect.top as usize + rect.height as usize {
+            let offset = (y * desktop.width as usize + rect.left as usize) * bytes_per_pixel;
+            pixels.extend_from_slice(&desktop.pixels[offset..offset + row_length]);
+        }
+        pixels
+    }
+
+    /// Draws rows of packed `pixels` in the server's format into `rect`, and
+    /// lets the viewers know. Content of `rect` that moved up, down, left or
+    /// right, as when scrolling, is copied by viewers that support
+    /// `Encoding::CopyRect` instead of being sent again.
+    pub fn put_pixels(&self, rect: Rect, pixels: &[u8]) -> Result<()> {
+        let bytes_per_pixel = self.shared.format.bits_per_pixel as usize / 8;
+        let row_length = rect.width as usize * bytes_per_pixel;
+        let copyable = self
+            .shared
+            .clients
+            .lock()
+            .unwrap()
+            .values()
+            .any(|connection| {
+                let state = connection.state.lock().unwrap();
+                state.encodings.contains(&Encoding::CopyRect)
+            });
+        let scroll = {
+            let mut desktop = self.shared.desktop.lock().unwrap();
+            if !Rect::with_size(desktop.width, desktop.height).contains_rect(&rect) {
+                return Err(Error::Unexpected("rectangle out of bounds"));
+            }
+            if pixels.len() != rect.height as usize * row_length {
+                return Err(Error::Unexpected("pixel data length"));
+            }
+            if rect.is_empty() {
+                return Ok(());
+            }
+            let scroll = copyable
+                .then(|| {
+                    let old = Server::rect_pixels(self.shared.format, &desktop, rect);
+                    scroll::detect(rect, &old, pixels, bytes_per_pixel)
+                })
+                .flatten();
+            let stride = desktop.width as usize * bytes_per_pixel;
+            for (y, row) in pixels.chunks(row_length).enumerate() {
+                let offset =
+                    (rect.top as usize + y) * stride + rect.left as usize * bytes_per_pixel;
+                desktop.pixels[offset..offset + row_length].copy_from_slice(row);
+            }
+            scroll
+        };
+        match scroll {
+            Some(scroll) => self
+                .shared
+                .each_client(|state| state.add_scroll(rect, &scroll)),
+            None => self.shared.each_client(|state| state.damage.add(rect)),
+        }
+        Ok(())
+    }
+
+    /// Resizes the framebuffer, keeping what the old and new sizes have in
+    /// common. Viewers that support `Encoding::DesktopSize` are told; the
+    /// others go on with the size they started with, as far as it goes.
+    pub fn resize(&self, width: u16, height: u16) {
+        {
+            let mut desktop = self.shared.desktop.lock().unwrap();
+            let bytes_per_pixel = self.shared.format.bits_per_pixel as usize / 8;
+            (desktop.pixels, _) = pixels::resize(
+                &desktop.pixels,
+                bytes_per_pixel,
+                (desktop.width, desktop.height),
+                (width, height),
+            );
+            desktop.width = width;
+            desktop.height = height;
+        }
+        self.shared.each_client(|state| {
+            if state.encodings.contains(&Encoding::DesktopSize) {
+                state.resized = true;
+                state.size = (width, height);
+            }
+            state.damage.take();
+            state.copy = None;
+            state.damage.add(Rect::with_size(width, height));
+        });
+    }
+
+    /// Sends the clipboard text to every viewer.
+    pub fn set_cut_text(&self, text: &str) {
+        self.shared
+            .each_client(|state| state.messages.push(protocol::S2C::CutText(text.to_owned())));
+    }
+
+    /// Rings the bell of every viewer.
+    pub fn bell(&self) {
+        self.shared
+            .each_client(|state| state.messages.push(protocol::S2C::Bell));
+    }
+
+    /// Hangs up on a viewer; the handler gets `ServerEvent::Disconnected`.
+    pub fn disconnect(&self, id: ClientId) {
+        if let Some(connection) = self.shared.clients.lock().unwrap().get(&id) {
+            connection.update(|state| state.closed = true)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Role, Server, ServerEvent};
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::{Duration, Instant};
+    use vnc_client::{pixels, AuthChoice, Client, Encoding, Event, PixelFormat, Rect};
+
+    const FORMAT: PixelFormat = PixelFormat {
+        bits_per_pixel: 32,
+        depth: 24,
+        big_endian: false,
+        true_colour: true,
+        red_max: 255,
+        green_max: 255,
+        blue_max: 255,
+        red_shift: 16,
+        green_shift: 8,
+        blue_shift: 0,
+    };
+
+    const RGB565: PixelFormat = PixelFormat {
+        bits_per_pixel: 16,
+        depth: 16,
+        big_endian: false,
+        true_colour: true,
+        red_max: 31,
+        green_max: 63,
+        blue_max: 31,
+        red_shift: 11,
+        green_shift: 5,
+        blue_shift: 0,
+    };
+
+    fn connect(server: &Server) -> Client {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let server = server.clone();
+        let serving = thread::spawn(move || server.serve(listener.accept().unwrap().0));
+        let client = Client::from_tcp_stream(stream, true, |_| Some(AuthChoice::None)).unwrap();
+        serving.join().unwrap().unwrap();
+        client
+    }
+
+    /// Connects a viewer that logs in with `password`.
+    fn log_in(server: &Server, password: &str) -> vnc_client::Result<Client> {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let server = server.clone();
+        let serving = thread::spawn(move || server.serve(listener.accept().unwrap().0));
+        let mut key = [0; 8];
+        key[..password.len()].copy_from_slice(password.as_bytes());
+        let client = Client::from_tcp_stream(stream, true, |_| Some(AuthChoice::Password(key)));
+        assert_eq!(serving.join().unwrap().is_ok(), client.is_ok());
+        client
+    }
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    /// Hands the viewer's events to `f` until it returns true.
+    fn wait_for<F: FnMut(&Event) -> bool>(client: &mut Client, mut f: F) {
+        let deadline = Instant::now() + TIMEOUT;
+        while !client.poll_iter().any(|event| f(&event)) {
+            assert!(Instant::now() < deadline, "the viewer got no such event");
+            thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    /// Hands the viewer's events to `f` until its framebuffer holds `pixels`.
+    fn wait_for_pixels<F: FnMut(Event)>(client: &mut Client, pixels: &[u8], mut f: F) {
+        let deadline = Instant::now() + TIMEOUT;
+        loop {
+            client.poll_iter().for_each(&mut f);
+            if client.framebuffer().unwrap().pixels() == pixels {
+                return;
+            }
+            assert!(
+                Instant::now() < deadline,
+                "the viewer never showed the expected pixels"
+            );
+            thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    #[test]
+    fn test_server() {
+        let (tx, rx) = mpsc::channel();
+        let server = Server::new(4, 2, FORMAT, "test", move |id, event| {
+            tx.send((id, event)).unwrap()
+        });
+        let pixels = (0..8u32)
+            .flat_map(|i| (i * 0x200408).to_le_bytes())
+            .collect::<Vec<_>>();
+        server.put_pixels(Rect::with_size(4, 2), &pixels).unwrap();
+        assert!(server.put_pixels(Rect::new(3, 0, 2, 1), &[0; 8]).is_err());
+
+        // The server has to answer a request for the whole framebuffer in the
+        // old format before the new one takes effect.
+        let mut first = connect(&server);
+        first.set_format(RGB565).unwrap();
+        first
+            .set_encodings(&[Encoding::Hextile, Encoding::DesktopSize])
+            .unwrap();
+        first.enable_framebuffer();
+        first.request_update(Rect::with_size(4, 2), false).unwrap();
+        let rgb = (0..8u8)
+            .flat_map(|i| [i * 0x20, i * 0x04, i * 0x08])
+            .collect::<Vec<_>>();
+        let mut expected = Vec::new();
+        pixels::pack_rgb(RGB565, &rgb, &mut expected);
+        // The update that set_format waited for may still end in the events.
+        wait_for_pixels(&mut first, &expected, drop);
+
+        let mut second = connect(&server);
+        assert_eq!(server.clients().len(), 2);
+        second
+            .set_encodings(&[Encoding::Zrle, Encoding::Raw])
+            .unwrap();
+        second.enable_framebuffer();
+        second.request_update(Rect::with_size(4, 2), false).unwrap();
+        wait_for_pixels(&mut second, &pixels, drop);
+
+        second.send_key_event(true, 0x61).unwrap();
+        first.send_pointer_event(1, 2, 1).unwrap();
+        let mut events = (0..4)
+            .map(|_| rx.recv_timeout(TIMEOUT).unwrap())
+            .collect::<Vec<_>>();
+        events.sort_by_key(|(id, _)| *id);
+        let pointer = ServerEvent::Pointer {
+            button_mask: 1,
+            x: 2,
+            y: 1,
+        };
+        let key = ServerEvent::Key {
+            down: true,
+            key: 0x61,
+        };
+        assert_eq!(
+            events,
+            [
+                (
+                    1,
+                    ServerEvent::Connected {
+                        shared: true,
+                        role: Role::Full,
+                    }
+                ),
+                (1, pointer),
+                (
+                    2,
+                    ServerEvent::Connected {
+                        shared: true,
+                        role: Role::Full,
+                    }
+                ),
+                (2, key),
+            ]
+        );
+
+        // Only what changed is sent, and only to the viewers that asked.
+        first.request_update(Rect::with_size(4, 2), true).unwrap();
+        server
+            .put_pixels(Rect::new(1, 1, 1, 1), &[0xff; 4])
+            .unwrap();
+        wait_for(&mut first, |event| match event {
+            Event::PutPixels(rect, pixels) => {
+                assert_eq!(*rect, Rect::new(1, 1, 1, 1));
+                assert_eq!(pixels[..], [0xff; 2]);
+                true
+            }
+            _ => false,
+        });
+
+        first.request_update(Rect::with_size(4, 2), true).unwrap();
+        server.resize(3, 3);
+        wait_for(&mut first, |event| matches!(event, Event::Resize(3, 3)));
+        assert_eq!(first.size(), (3, 3));
+
+        second.disconnect().unwrap();
+        assert_eq!(
+            rx.recv_timeout(TIMEOUT).unwrap(),
+            (2, ServerEvent::Disconnected)
+        );
+        server.disconnect(1);
+        assert_eq!(
+            rx.recv_timeout(TIMEOUT).unwrap(),
+            (1, ServerEvent::Disconnected)
+        );
+        wait_for(&mut first, |event| matches!(event, Event::Disconnected(_)));
+        assert!(server.clients().is_empty());
+    }
+
+    #[test]
+    fn test_bad_format() {
+        let (tx, rx) = mpsc::channel();
+        let server = Server::new(4, 2, FORMAT, "test", move |id, event| {
+            tx.send((id, event)).unwrap()
+        });
+        let mut client = connect(&server);
+        assert_eq!(
+            rx.recv_timeout(TIMEOUT).unwrap(),
+            (
+                1,
+                ServerEvent::Connected {
+                    shared: true,
+                    role: Role::Full,
+                }
+            )
+        );
+        client.set_encodings(&[Encoding::Hextile]).unwrap();
+        client
+            .set_format(PixelFormat {
+                bits_per_pixel: 0,
+                ..FORMAT
+            })
+            .unwrap();
+        client.request_update(Rect::with_size(4, 2), false).unwrap();
+        // The viewer is hung up on, and the others are served as before.
+        assert_eq!(
+            rx.recv_timeout(TIMEOUT).unwrap(),
+            (1, ServerEvent::Disconnected)
+        );
+        server
+            .put_pixels(Rect::with_size(4, 2), &[0x55; 32])
+            .unwrap();
+        let mut client = connect(&server);
+        client.set_encodings(&[Encoding::Hextile]).unwrap();
+        client.enable_framebuffer();
+        client.request_update(Rect::with_size(4, 2), false).unwrap();
+        wait_for_pixels(&mut client, &[0x55; 32], drop);
+    }
+
+    #[test]
+    fn test_passwords() {
+        let (tx, rx) = mpsc::channel();
+        let server = Server::new(4, 2, FORMAT, "test", move |id, event| {
+            tx.send((id, event)).unwrap()
+        });
+        server.add_password("secret", Role::Full, false);
+        server.add_password("support", Role::ViewOnly, true);
+        assert!(log_in(&server, "wrong").is_err());
+        let mut full = log_in(&server, "secret").unwrap();
+        let mut viewer = log_in(&server, "support").unwrap();
+        // The one-time password is gone now.
+        assert!(log_in(&server, "support").is_err());
+        assert_eq!(
+            (0..2)
+                .map(|_| rx.recv_timeout(TIMEOUT).unwrap())
+                .collect::<Vec<_>>(),
+            [
+                (
+                    1,
+                    ServerEvent::Connected {
+                        shared: true,
+                        role: Role::Full,
+                    }
+                ),
+                (
+                    2,
+                    ServerEvent::Connected {
+                        shared: true,
+                        role: Role::ViewOnly,
+                    }
+                ),
+            ]
+        );
+
+        // The view-only viewer's input is dropped.
+        viewer.send_key_event(true, 0x61).unwrap();
+        viewer.disconnect().unwrap();
+        assert_eq!(
+            rx.recv_timeout(TIMEOUT).unwrap(),
+            (2, ServerEvent::Disconnected)
+        );
+        full.send_key_event(true, 0x62).unwrap();
+        assert_eq!(
+            rx.recv_timeout(TIMEOUT).unwrap(),
+            (
+                1,
+                ServerEvent::Key {
+                    down: true,
+                    key: 0x62
+                }
+            )
+        );
+
+        // Without passwords, viewers are let in as before.
+        server.remove_password("secret");
+        assert!(log_in(&server, "secret").is_err());
+        connect(&server);
+    }
+
+    #[test]
+    fn test_status() {
+        let server = Server::new(4, 2, FORMAT, "status", |_, _| {});
+        server
+            .put_pixels(Rect::with_size(4, 2), &[0x55; 32])
+            .unwrap();
+        let mut client = connect(&server);
+        client.set_encodings(&[Encoding::Raw]).unwrap();
+        client.enable_framebuffer();
+        client.request_update(Rect::with_size(4, 2), false).unwrap();
+        wait_for_pixels(&mut client, &[0x55; 32], drop);
+        let status = server.status();
+        assert_eq!(status.clients.len(), 1);
+        assert_eq!(status.clients[0].updates, 1);
+        assert!(status.clients[0].bytes > 32);
+        assert!(status.frame_rate > 0.0 && status.bandwidth > 0.0);
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let status_server = server.clone();
+        thread::spawn(move || status_server.listen_status(&listener));
+        let mut stream = TcpStream::connect(address).unwrap();
+        stream.write_all(b"GET / HTTP/1.0\r\n\r\n").unwrap();
+        let mut page = String::new();
+        stream.read_to_string(&mut page).unwrap();
+        assert!(page.starts_with("HTTP/1.0 200 OK\r\n"), "{}", page);
+        assert!(
+            page.contains("viewers: 1\n  viewer 1: full control"),
+            "{}",
+            page
+        );
+    }
+
+    #[test]
+    fn test_scroll() {
+        let server = Server::new(8, 8, FORMAT, "test", |_, _| {});
+        let lines = |first: u32| {
+            (first..first + 8)
+                .flat_map(|y| (0..8u32).flat_map(move |x| (y * 0x1001 + x).to_le_bytes()))
+                .collect::<Vec<_>>()
+        };
+        server.put_pixels(Rect::with_size(8, 8), &lines(0)).unwrap();
+        let mut client = connect(&server);
+        client
+            .set_encodings(&[Encoding::CopyRect, Encoding::Raw])
+            .unwrap();
+        client.enable_framebuffer();
+        client.request_update(Rect::with_size(8, 8), false).unwrap();
+        wait_for_pixels(&mut client, &lines(0), drop);
+
+        // Scrolled up by 3 lines: only the 3 new ones are sent.
+        client.request_update(Rect::with_size(8, 8), true).unwrap();
+        server.put_pixels(Rect::with_size(8, 8), &lines(3)).unwrap();
+        let mut copied = false;
+        wait_for_pixels(&mut client, &lines(3), |event| match event {
+            Event::CopyPixels { src, dst } => {
+                assert_eq!((src, dst), (Rect::new(0, 3, 8, 5), Rect::new(0, 0, 8, 5)));
+                copied = true
+            }
+            Event::PutPixels(rect, _) => assert_eq!(rect, Rect::new(0, 5, 8, 3)),
+            _ => {}
+        });
+        assert!(copied);
+    }
+}
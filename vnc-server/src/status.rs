@@ -0,0 +1,112 @@
+//! A status page for a `Server`, over HTTP.
+
+use log::debug;
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+use std::time::Duration;
+
+use crate::{ClientId, Result, Role, Server};
+
+/// What a `Server` is up to; see `Server::status`. Its `Display` form is the
+/// status page.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Status {
+    pub name: String,
+    pub uptime: Duration,
+    /// Updates sent per second, to all viewers together, over the last few
+    /// seconds.
+    pub frame_rate: f64,
+    /// Bytes sent per second, to all viewers together, over the last few
+    /// seconds.
+    pub bandwidth: f64,
+    pub clients: Vec<ClientStatus>,
+}
+
+/// A viewer connected to a `Server`, and what has been sent to it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientStatus {
+    pub id: ClientId,
+    pub role: Role,
+    /// How long ago it connected.
+    pub connected: Duration,
+    pub updates: u64,
+    pub bytes: u64,
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{}", self.name)?;
+        writeln!(f, "uptime: {}s", self.uptime.as_secs())?;
+        writeln!(f, "frame rate: {:.1} updates/s", self.frame_rate)?;
+        writeln!(f, "bandwidth: {:.0} bytes/s", self.bandwidth)?;
+        writeln!(f, "viewers: {}", self.clients.len())?;
+        for client in &self.clients {
+            let role = match client.role {
+                Role::Full => "full control",
+                Role::ViewOnly => "view-only",
+            };
+            writeln!(
+                f,
+                "  viewer {}: {}, connected {}s, {} updates, {} bytes",
+                client.id,
+                role,
+                client.connected.as_secs(),
+                client.updates,
+                client.bytes
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// How long a request for the status page may take to arrive.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+impl Server {
+    /// Serves the status page on `listener` until accepting fails, as plain
+    /// text to any GET request, e.g. from a browser or `curl`. Each request is
+    /// answered on a thread of its own. Anyone who can connect sees the page,
+    /// so `listener` is best bound to a local address.
+    pub fn listen_status(&self, listener: &TcpListener) -> Result<()> {
+        loop {
+            let (stream, peer) = listener.accept()?;
+            let server = self.clone();
+            thread::spawn(move || {
+                if let Err(error) = server.answer_status(stream) {
+                    debug!("status page for {}: {}", peer, error)
+                }
+            });
+        }
+    }
+
+    fn answer_status(&self, mut stream: TcpStream) -> io::Result<()> {
+        stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+        // The request line and the headers, which are skipped.
+        let mut reader = BufReader::new((&stream).take(8192));
+        let mut request = String::new();
+        reader.read_line(&mut request)?;
+        let mut header = String::new();
+        while reader.read_line(&mut header)? > 0 && !header.trim_end().is_empty() {
+            header.clear()
+        }
+        let (status_line, body) = match request.starts_with("GET ") {
+            true => ("200 OK", self.status().to_string()),
+            false => ("405 Method Not Allowed", String::new()),
+        };
+        write!(
+            stream,
+            "HTTP/1.0 {}\r\n\
+             Content-Type: text/plain; charset=utf-8\r\n\
+             Content-Length: {}\r\n\
+             Connection: close\r\n\
+             \r\n\
+             {}",
+            status_line,
+            body.len(),
+            body
+        )?;
+        stream.flush()
+    }
+}
@@ -0,0 +1,145 @@
+use vnc_proto::protocol::{self, Message};
+use vnc_proto::{pixels, zrle, Damage, Error, PixelFormat, Rect, Result};
+
+/// The framebuffer of a proxied session, as handed to a tap after every update.
+pub struct Frame<'a> {
+    pub width: u16,
+    pub height: u16,
+    /// The format of the session, which is the server's; pixels are in the
+    /// byte order it specifies.
+    pub format: PixelFormat,
+    /// Rows of `width` packed pixels. Regions the server has not sent yet are
+    /// zeroed.
+    pub pixels: &'a [u8],
+    /// The regions that changed since the previous frame.
+    pub damage: &'a [Rect],
+}
+
+pub(crate) type Observer = Box<dyn FnMut(&Frame) + Send>;
+
+/// Keeps a copy of the framebuffer by decoding the updates the proxy forwards.
+pub(crate) struct Tap {
+    observer: Observer,
+    format: PixelFormat,
+    size: (u16, u16),
+    pixels: Vec<u8>,
+    damage: Damage,
+    zrle_decoder: zrle::Decoder,
+}
+
+impl Tap {
+    pub fn new(observer: Observer, format: PixelFormat, width: u16, height: u16) -> Tap {
+        Tap {
+            observer,
+            format,
+            size: (width, height),
+            pixels: vec![0; width as usize * height as usize * Tap::bytes_per_pixel(format)],
+            damage: Damage::new(),
+            zrle_decoder: zrle::Decoder::new(),
+        }
+    }
+
+    fn bytes_per_pixel(format: PixelFormat) -> usize {
+        format.bits_per_pixel as usize / 8
+    }
+
+    fn check_bounds(&self, rect: Rect) -> Result<()> {
+        if !Rect::with_size(self.size.0, self.size.1).contains_rect(&rect) {
+            return Err(Error::Unexpected("rectangle out of bounds"));
+        }
+        Ok(())
+    }
+
+    fn put_pixels(&mut self, rect: Rect, pixels: &[u8]) -> Result<()> {
+        self.check_bounds(rect)?;
+        if rect.is_empty() {
+            return Ok(());
+        }
+        let bytes_per_pixel = Tap::bytes_per_pixel(self.format);
+        let row_length = rect.width as usize * bytes_per_pixel;
+        for (y, row) in pixels.chunks(row_length).enumerate() {
+            let offset = ((rect.top as usize + y) * self.size.0 as usize + rect.left as usize)
+                * bytes_per_pixel;
+            self.pixels[offset..offset + row_length].copy_from_slice(row);
+        }
+        self.damage.add(rect);
+        Ok(())
+    }
+
+    /// Applies one rectangle of an update; `data` is what followed its header.
+    pub fn apply(&mut self, rectangle: &protocol::Rectangle, data: &[u8]) -> Result<()> {
+        let rect = Rect {
+            left: rectangle.x_position,
+            top: rectangle.y_position,
+            width: rectangle.width,
+            height: rectangle.height,
+        };
+        match rectangle.encoding {
+            protocol::Encoding::Raw => self.put_pixels(rect, data),
+            protocol::Encoding::CopyRect => {
+                let copy_rect = protocol::CopyRect::read_from(&mut &data[..])?;
+                let src = Rect {
+                    left: copy_rect.src_x_position,
+                    top: copy_rect.src_y_position,
+                    ..rect
+                };
+                self.check_bounds(src)?;
+                self.check_bounds(rect)?;
+                pixels::copy_rect(
+                    &mut self.pixels,
+                    self.size.0 as usize,
+                    Tap::bytes_per_pixel(self.format),
+                    src,
+                    rect,
+                );
+                self.damage.add(rect);
+                Ok(())
+            }
+            protocol::Encoding::Zrle => {
+                let Tap {
+                    zrle_decoder,
+                    format,
+                    ..
+                } = self;
+                let mut tiles = Vec::new();
+                // Skip the length prefix; the proxy has already checked it.
+                zrle_decoder.decode(*format, rect, &data[4..], |tile, pixels| {
+                    tiles.push((tile, pixels.to_vec()));
+                    Ok(true)
+                })?;
+                for (tile, pixels) in tiles {
+                    self.put_pixels(tile, &pixels)?
+                }
+                Ok(())
+            }
+            protocol::Encoding::DesktopSize => {
+                let size = (rectangle.width, rectangle.height);
+                let (resized, _) = pixels::resize(
+                    &self.pixels,
+                    Tap::bytes_per_pixel(self.format),
+                    self.size,
+                    size,
+                );
+                self.pixels = resized;
+                self.size = size;
+                self.damage.take();
+                self.damage.add(Rect::with_size(size.0, size.1));
+                Ok(())
+            }
+            // The cursor is not part of the framebuffer.
+            _ => Ok(()),
+        }
+    }
+
+    /// Hands the framebuffer to the observer at the end of an update.
+    pub fn end_of_frame(&mut self) {
+        let damage = self.damage.take();
+        (self.observer)(&Frame {
+            width: self.size.0,
+            height: self.size.1,
+            format: self.format,
+            pixels: &self.pixels,
+            damage: &damage,
+        })
+    }
+}
@@ -0,0 +1,1238 @@
+use byteorder::{NativeEndian, ReadBytesExt, WriteBytesExt};
+use clap::{arg, value_parser, Arg, ArgAction, Command};
+use log::{debug, error, info, warn};
+use sdl2::{
+    pixels::{Color, PixelFormatEnum as SdlPixelFormat, PixelMasks},
+    rect::Rect as SdlRect,
+};
+use std::fs::File;
+use std::io::{BufWriter, Cursor, ErrorKind as IoErrorKind, Read, Result as IoResult, Write};
+
+use std::path::PathBuf;
+use std::time::Duration;
+
+mod connect;
+mod control;
+mod mouse;
+mod socks;
+mod via;
+
+// SDL's packed formats are in host byte order. Pixels in the other byte order are
+// swapped before they reach SDL, so formats match regardless of endianness.
+const BIG_ENDIAN: bool = cfg!(target_endian = "big");
+
+const FORMAT_MAP: [(SdlPixelFormat, vnc_client::PixelFormat); 5] = [
+    (
+        SdlPixelFormat::RGB888,
+        vnc_client::PixelFormat {
+            bits_per_pixel: 32,
+            depth: 24,
+            big_endian: BIG_ENDIAN,
+            true_colour: true,
+            red_max: 255,
+            green_max: 255,
+            blue_max: 255,
+            red_shift: 16,
+            green_shift: 8,
+            blue_shift: 0,
+        },
+    ),
+    (
+        SdlPixelFormat::BGR888,
+        vnc_client::PixelFormat {
+            bits_per_pixel: 32,
+            depth: 24,
+            big_endian: BIG_ENDIAN,
+            true_colour: true,
+            red_max: 255,
+            green_max: 255,
+            blue_max: 255,
+            red_shift: 0,
+            green_shift: 8,
+            blue_shift: 16,
+        },
+    ),
+    // these break x11vnc
+    // (SdlPixelFormat::RGB24, vnc_client::PixelFormat {
+    //     bits_per_pixel: 24, depth: 24, big_endian: false, true_colour: true,
+    //     red_max: 255,  green_max: 255, blue_max: 255,
+    //     red_shift: 16, green_shift: 8, blue_shift: 0
+    // }),
+    // (SdlPixelFormat::BGR24, vnc_client::PixelFormat {
+    //     bits_per_pixel: 24, depth: 24, big_endian: true, true_colour: true,
+    //     red_max: 255,  green_max: 255, blue_max: 255,
+    //     red_shift: 0, green_shift: 8, blue_shift: 16
+    // }),
+    (
+        SdlPixelFormat::RGB565,
+        vnc_client::PixelFormat {
+            bits_per_pixel: 16,
+            depth: 16,
+            big_endian: BIG_ENDIAN,
+            true_colour: true,
+            red_max: 32,
+            green_max: 64,
+            blue_max: 32,
+            red_shift: 11,
+            green_shift: 5,
+            blue_shift: 0,
+        },
+    ),
+    (
+        SdlPixelFormat::BGR565,
+        vnc_client::PixelFormat {
+            bits_per_pixel: 16,
+            depth: 16,
+            big_endian: BIG_ENDIAN,
+            true_colour: true,
+            red_max: 32,
+            green_max: 64,
+            blue_max: 32,
+            red_shift: 0,
+            green_shift: 5,
+            blue_shift: 11,
+        },
+    ),
+    (
+        SdlPixelFormat::RGB332,
+        vnc_client::PixelFormat {
+            bits_per_pixel: 8,
+            depth: 8,
+            big_endian: BIG_ENDIAN,
+            true_colour: true,
+            red_max: 8,
+            green_max: 8,
+            blue_max: 4,
+            red_shift: 5,
+            green_shift: 2,
+            blue_shift: 0,
+        },
+    ),
+];
+
+fn pixel_format_vnc_to_sdl(vnc_format: vnc_client::PixelFormat) -> Option<SdlPixelFormat> {
+    let vnc_format = vnc_client::PixelFormat {
+        big_endian: BIG_ENDIAN,
+        ..vnc_format
+    };
+    for format in &FORMAT_MAP {
+        if format.1 == vnc_format {
+            return Some(format.0);
+        }
+    }
+    None
+}
+
+fn pixel_format_sdl_to_vnc(sdl_format: SdlPixelFormat) -> Option<vnc_client::PixelFormat> {
+    for format in &FORMAT_MAP {
+        if format.0 == sdl_format {
+            return Some(format.1);
+        }
+    }
+    None
+}
+
+fn mask_cursor(
+    vnc_in_format: vnc_client::PixelFormat,
+    in_pixels: Vec<u8>,
+    mask_pixels: Vec<u8>,
+) -> (SdlPixelFormat, Vec<u8>) {
+    use sdl2::pixels::PixelFormatEnum::*;
+
+    let in_format = pixel_format_vnc_to_sdl(vnc_in_format).unwrap();
+    let out_format = match in_format {
+        RGB332 => ARGB4444, /* meh, close enough */
+        RGB444 => ARGB4444,
+        RGB555 | RGB565 => ARGB1555,
+        BGR555 | BGR565 => ABGR1555,
+        RGB24 | RGB888 | RGBX8888 => ARGB8888,
+        BGR24 | BGR888 | BGRX8888 => ABGR8888,
+        _ => panic!("cannot add alpha to {:?}", in_format),
+    };
+    let out_pixels = Vec::new();
+
+    let in_size = in_format.byte_size_per_pixel();
+    let in_masks = in_format.into_masks().unwrap();
+    let out_size = out_format.byte_size_per_pixel();
+    let out_masks = out_format.into_masks().unwrap();
+
+    let mut in_cursor = Cursor::new(in_pixels);
+    let mut out_cursor = Cursor::new(out_pixels);
+    let mut mask_cursor = Cursor::new(mask_pixels);
+
+    fn read_color<R: Read>(reader: &mut R, size: usize, masks: &PixelMasks) -> IoResult<Color> {
+        let packed = reader.read_uint::<NativeEndian>(size)?;
+        Ok(Color::RGB(
+            ((packed as u32 & masks.rmask) >> masks.rmask.trailing_zeros()) as u8,
+            ((packed as u32 & masks.gmask) >> masks.gmask.trailing_zeros()) as u8,
+            ((packed as u32 & masks.bmask) >> masks.bmask.trailing_zeros()) as u8,
+        ))
+    }
+
+    fn write_color<W: Write>(
+        writer: &mut W,
+        size: usize,
+        masks: &PixelMasks,
+        color: Color,
+    ) -> IoResult<()> {
+        let Color { r, g, b, a } = color;
+        let packed = (((r as u32) << masks.rmask.trailing_zeros()) & masks.rmask)
+            | (((g as u32) << masks.gmask.trailing_zeros()) & masks.gmask)
+            | (((b as u32) << masks.bmask.trailing_zeros()) & masks.bmask)
+            | (((a as u32) << masks.amask.trailing_zeros()) & masks.amask);
+        writer
+            .write_uint::<NativeEndian>(packed as u64, size)
+            .unwrap();
+        Ok(())
+    }
+
+    loop {
+        match read_color(&mut in_cursor, in_size, &in_masks) {
+            Err(ref e) if e.kind() == IoErrorKind::UnexpectedEof => break,
+            Err(_) => unreachable!(),
+            Ok(in_color) => {
+                let mask = mask_cursor.read_u8().unwrap();
+                let Color { r, g, b, .. } = in_color;
+                let out_color = Color::RGBA(r, g, b, if mask != 0 { 255 } else { 0 });
+                write_color(&mut out_cursor, out_size, &out_masks, out_color).unwrap();
+            }
+        }
+    }
+
+    (out_format, out_cursor.into_inner())
+}
+
+/// Sends a change of the pressed buttons, which in relative pointer mode is a
+/// movement of nothing rather than a position.
+fn send_buttons(
+    vnc: &mut vnc_client::Client,
+    buttons: u8,
+    x: u16,
+    y: u16,
+) -> vnc_client::Result<()> {
+    match vnc.pointer_motion_mode() {
+        vnc_client::PointerMotionMode::Relative => vnc.send_relative_pointer(0, 0, buttons),
+        vnc_client::PointerMotionMode::Absolute => vnc.send_pointer_event(buttons, x, y),
+    }
+}
+
+fn parse_size(size: &str) -> Result<(u16, u16), String> {
+    let (width, height) = size
+        .split_once('x')
+        .ok_or_else(|| format!("expected WIDTHxHEIGHT, got {}", size))?;
+    match (width.parse(), height.parse()) {
+        (Ok(width), Ok(height)) => Ok((width, height)),
+        _ => Err(format!("invalid size {}", size)),
+    }
+}
+
+fn main() {
+    env_logger::init();
+
+    let matches = Command::new("rvncclient")
+        .about("VNC client")
+        .arg(arg!(<HOST> "server hostname or IP"))
+        .arg(
+            arg!(<PORT> "server port").value_parser(value_parser!(u16)), // Arg::new("PORT").help("server port").required(true).index(2)
+        )
+        .arg(
+            Arg::new("VIA")
+                .help("connect through an SSH tunnel to GATEWAY, which resolves HOST")
+                .long("via")
+                .value_name("GATEWAY"),
+        )
+        .arg(
+            Arg::new("SOCKS5")
+                .help("connect through a SOCKS5 proxy, which resolves HOST")
+                .long("socks5")
+                .value_name("[USER:PASSWORD@]HOST:PORT")
+                .value_parser(socks::Socks5Proxy::parse)
+                .conflicts_with("VIA"),
+        )
+        .arg(
+            Arg::new("USERNAME")
+                .help("server username")
+                .long("username"), // .takes_value(true),
+        )
+        .arg(
+            Arg::new("PASSWORD")
+                .help("server password")
+                .long("password"), // .takes_value(true),
+        )
+        .arg(
+            Arg::new("EXCLUSIVE")
+                .help("request a non-shared session")
+                .long("exclusive")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("VIEW-ONLY")
+                .help("ignore any input")
+                .long("view-only")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("EMULATE-MIDDLE")
+                .help("press left and right together for the middle button")
+                .long("emulate-middle-button")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("QEMU-HACKS")
+                .help("hack around QEMU/XenHVM's braindead VNC server (automatic with QEMU)")
+                .long("heinous-qemu-hacks")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("REFRESH-INTERVAL")
+                .help("request a full update every N seconds")
+                .long("refresh-interval")
+                .value_parser(value_parser!(u64)),
+        )
+        .arg(
+            Arg::new("FPS-LIMIT")
+                .help("ask for at most N updates per second")
+                .long("fps-limit")
+                .value_name("N")
+                .value_parser(value_parser!(u32).range(1..)),
+        )
+        .arg(
+            Arg::new("COMPRESSION-LEVEL")
+                .help("ask for compression level N, from 0 (fastest) to 9 (smallest)")
+                .long("compression-level")
+                .value_name("N")
+                .value_parser(value_parser!(u8).range(0..=9)),
+        )
+        .arg(
+            Arg::new("NO-POWER-SAVING")
+                .help("keep updating at full rate while the window is unfocused or minimized")
+                .long("no-power-saving")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("CONTROL")
+                .help("accept commands on a Unix socket at PATH")
+                .long("control")
+                .value_name("PATH")
+                .value_parser(value_parser!(PathBuf)),
+        )
+        .arg(
+            Arg::new("MAX-SIZE")
+                .help("refuse framebuffers larger than WIDTHxHEIGHT")
+                .long("max-size")
+                .value_name("WIDTHxHEIGHT")
+                .value_parser(parse_size),
+        )
+        .get_matches();
+
+    let host = matches.get_one::<String>("HOST").unwrap();
+    let port = matches.get_one::<u16>("PORT").unwrap();
+    let via = matches.get_one::<String>("VIA");
+    let socks5 = matches.get_one::<socks::Socks5Proxy>("SOCKS5");
+    let username = matches.get_one::<String>("USERNAME");
+    let password = matches.get_one::<String>("PASSWORD");
+    let exclusive = matches.get_flag("EXCLUSIVE");
+    let mut view_only = matches.get_flag("VIEW-ONLY");
+    let emulate_middle = matches.get_flag("EMULATE-MIDDLE");
+    let mut qemu_hacks = matches.get_flag("QEMU-HACKS");
+    let refresh_interval = matches.get_one::<u64>("REFRESH-INTERVAL");
+    let fps_limit = matches.get_one::<u32>("FPS-LIMIT").copied();
+    let compression_level = matches.get_one::<u8>("COMPRESSION-LEVEL").copied();
+    let power_saving = !matches.get_flag("NO-POWER-SAVING");
+    let max_size = matches
+        .get_one::<(u16, u16)>("MAX-SIZE")
+        .map(|x| x.to_owned())
+        .unwrap_or((u16::MAX, u16::MAX));
+    let control = matches
+        .get_one::<PathBuf>("CONTROL")
+        .map(|path| match control::listen(path) {
+            Ok(requests) => requests,
+            Err(error) => {
+                error!("cannot listen on {}: {}", path.display(), error);
+                std::process::exit(1)
+            }
+        });
+
+    info!("connecting to {}:{}", host, port);
+    let connection = match (via, socks5) {
+        (Some(gateway), _) => {
+            info!("tunneling through {}", gateway);
+            match via::tunnel(gateway, host, *port) {
+                Ok(local_port) => {
+                    connect::connect("localhost", local_port).map(|(stream, _)| stream)
+                }
+                Err(error) => {
+                    error!("cannot tunnel through {}: {}", gateway, error);
+                    std::process::exit(1)
+                }
+            }
+        }
+        (None, Some(proxy)) => {
+            info!("connecting through SOCKS5 proxy {}", proxy);
+            proxy.connect(host, *port)
+        }
+        (None, None) => connect::connect(host, *port).map(|(stream, address)| {
+            info!("connected to {}", address);
+            stream
+        }),
+    };
+    let stream = match connection {
+        Ok(stream) => stream,
+        Err(error) => {
+            error!("cannot connect to {}:{}: {}", host, port, error);
+            std::process::exit(1)
+        }
+    };
+
+    let mut vnc = match vnc_client::Client::from_tcp_stream_with_max_size(
+        stream,
+        !exclusive,
+        max_size,
+        |methods| {
+            debug!("available authentication methods: {:?}", methods);
+            for method in methods {
+                match method {
+                    vnc_client::AuthMethod::None => return Some(vnc_client::AuthChoice::None),
+                    vnc_client::AuthMethod::Password => {
+                        return match password {
+                            None => None,
+                            Some(password) => {
+                                let mut key = [0; 8];
+                                for (i, byte) in password.bytes().enumerate() {
+                                    if i == 8 {
+                                        break;
+                                    }
+                                    key[i] = byte
+                                }
+                                Some(vnc_client::AuthChoice::Password(key))
+                            }
+                        }
+                    }
+                    vnc_client::AuthMethod::AppleRemoteDesktop => {
+                        if let (Some(username), Some(password)) = (username, password) {
+                            return Some(vnc_client::AuthChoice::AppleRemoteDesktop(
+                                username.to_owned(),
+                                password.to_owned(),
+                            ));
+                        }
+                    }
+                    vnc_client::AuthMethod::Sasl => {
+                        if let (Some(username), Some(password)) = (username, password) {
+                            return Some(vnc_client::AuthChoice::Sasl(
+                                username.to_owned(),
+                                password.to_owned(),
+                            ));
+                        }
+                    }
+                    vnc_client::AuthMethod::Plain => {
+                        if let (Some(username), Some(password)) = (username, password) {
+                            return Some(vnc_client::AuthChoice::Plain(
+                                username.to_owned(),
+                                password.to_owned(),
+                            ));
+                        }
+                    }
+                    _ => (),
+                }
+            }
+            None
+        },
+    ) {
+        Ok(vnc) => vnc,
+        Err(error) => {
+            error!("cannot initialize VNC session: {}", error);
+            std::process::exit(1)
+        }
+    };
+
+    let (mut width, mut height) = vnc.size();
+    info!(
+        "connected to \"{}\", {}x{} framebuffer",
+        vnc.name(),
+        width,
+        height
+    );
+
+    info!(
+        "negotiated {:?} with {:?} security",
+        vnc.version(),
+        vnc.security_type()
+    );
+
+    if vnc.server_kind() == vnc_client::ServerKind::Qemu && !qemu_hacks {
+        info!("server looks like QEMU, enabling QEMU workarounds");
+        qemu_hacks = true;
+    }
+
+    let mut vnc_format = vnc.format();
+    info!("received {:?}", vnc_format);
+
+    let sdl_context = sdl2::init().unwrap();
+    let sdl_video = sdl_context.video().unwrap();
+    let sdl_timer = sdl_context.timer().unwrap();
+    let mut sdl_events = sdl_context.event_pump().unwrap();
+
+    let sdl_format = match pixel_format_vnc_to_sdl(vnc_format) {
+        Some(format) => format,
+        None => {
+            let sdl_format = SdlPixelFormat::RGB888;
+            vnc_format = pixel_format_sdl_to_vnc(sdl_format).unwrap();
+            warn!(
+                "server's natural framebuffer format {:?} is not supported, \
+                       using {:?} instead",
+                vnc_format, sdl_format
+            );
+            vnc.set_format(vnc_format).unwrap();
+            sdl_format
+        }
+    };
+    info!("rendering to a {:?} texture", sdl_format);
+
+    vnc.set_refresh_interval(refresh_interval.map(|secs| Duration::from_secs(*secs)));
+    vnc.set_min_update_interval(update_interval(fps_limit, true));
+
+    let mut encodings = if qemu_hacks {
+        vec![
+            vnc_client::Encoding::Zrle,
+            vnc_client::Encoding::DesktopSize,
+            vnc_client::Encoding::QemuPointerMotionChange,
+        ]
+    } else {
+        vec![
+            vnc_client::Encoding::Zrle,
+            vnc_client::Encoding::Trle,
+            vnc_client::Encoding::Ultra,
+            vnc_client::Encoding::TightPng,
+            vnc_client::Encoding::Zlib,
+            vnc_client::Encoding::ZlibHex,
+            vnc_client::Encoding::Hextile,
+            vnc_client::Encoding::CopyRect,
+            vnc_client::Encoding::Raw,
+            vnc_client::Encoding::CursorWithAlpha,
+            vnc_client::Encoding::Cursor,
+            vnc_client::Encoding::DesktopSize,
+            vnc_client::Encoding::LastRect,
+            vnc_client::Encoding::LedState,
+            vnc_client::Encoding::DesktopName,
+        ]
+    };
+    if let Some(level) = compression_level {
+        encodings.push(vnc_client::Encoding::CompressionLevel(level))
+    }
+    vnc.set_encodings(&encodings).unwrap();
+    // Screenshots for the control socket are taken of the client's own copy of
+    // the framebuffer, in the format the server sends.
+    if control.is_some() {
+        vnc.enable_framebuffer();
+    }
+
+    let title = format!("{} - {}:{} - RVNC", vnc.name(), host, port);
+    let window = sdl_video
+        .window(&title, width as u32, height as u32)
+        .build()
+        .unwrap();
+    sdl_video.text_input().start();
+
+    let mut canvas = window.into_canvas().build().unwrap();
+    let renderer = canvas.texture_creator();
+    let mut screen = renderer
+        .create_texture_streaming(sdl_format, width as u32, height as u32)
+        .unwrap();
+    // Updates are applied to a copy of the framebuffer, which also makes CopyRect
+    // independent of reading back the canvas, and only the region they damaged is
+    // uploaded to the texture, once per frame.
+    let bytes_per_pixel = sdl_format.byte_size_per_pixel();
+    let mut framebuffer = vec![0u8; width as usize * height as usize * bytes_per_pixel];
+    let mut damage = vnc_client::Damage::new();
+
+    let mut cursor = None;
+    let (mut hotspot_x, mut hotspot_y) = (0u16, 0u16);
+
+    let mut mouse_buttons = 0u8;
+    let mut middle_emulation = emulate_middle.then(mouse::MiddleEmulation::default);
+    let (mut mouse_x, mut mouse_y) = (0u16, 0u16);
+
+    let mut key_ctrl = false;
+    let mut key_alt = false;
+    // In pass-through mode, the window grabs the keyboard, so that shortcuts
+    // like Alt-Tab reach the server instead of the local desktop.
+    let mut pass_through = false;
+    let mut remote_locks = None;
+    // With power saving, updates slow down while the window is in the
+    // background, and stop altogether while it cannot be seen.
+    let mut minimized = false;
+
+    canvas.clear();
+    vnc.request_update(vnc_client::Rect::with_size(width, height), false)
+        .unwrap();
+
+    let mut incremental = true;
+    let mut qemu_network_rtt = 1000;
+    let mut qemu_prev_update = sdl_timer.ticks();
+    let mut qemu_next_update = sdl_timer.ticks() + qemu_network_rtt / 2;
+    'running: loop {
+        const FRAME_MS: u32 = 1000 / 60;
+        const MINIMIZED_FRAME_MS: u32 = 250;
+        let ticks = sdl_timer.ticks();
+
+        let mut behind = false;
+        for event in vnc.poll_iter() {
+            use vnc_client::{DisconnectReason, Event};
+
+            match event {
+                Event::Disconnected(DisconnectReason::Closed) => break 'running,
+                Event::Disconnected(reason) => {
+                    error!("server disconnected: {}", reason);
+                    break 'running;
+                }
+                Event::Resize(new_width, new_height) => {
+                    // Keep showing what is still on screen until the server
+                    // refreshes it.
+                    (framebuffer, _) = vnc_client::pixels::resize(
+                        &framebuffer,
+                        bytes_per_pixel,
+                        (width, height),
+                        (new_width, new_height),
+                    );
+                    width = new_width;
+                    height = new_height;
+                    canvas
+                        .window_mut()
+                        .set_size(width as u32, height as u32)
+                        .expect("canvas resize window failed");
+                    screen = renderer
+                        .create_texture_streaming(sdl_format, width as u32, height as u32)
+                        .unwrap();
+                    damage.take();
+                    damage.add(vnc_client::Rect::with_size(width, height));
+                    incremental = false;
+                }
+                Event::PutPixels(vnc_rect, mut pixels) => {
+                    vnc_client::pixels::to_native_endian(vnc_format, &mut pixels);
+                    let row_length = vnc_rect.width as usize * bytes_per_pixel;
+                    for (y, row) in pixels.chunks(row_length).enumerate() {
+                        let offset = ((vnc_rect.top as usize + y) * width as usize
+                            + vnc_rect.left as usize)
+                            * bytes_per_pixel;
+                        framebuffer[offset..offset + row_length].copy_from_slice(row);
+                    }
+                    damage.add(vnc_rect);
+                    incremental |= vnc_rect == vnc_client::Rect::with_size(width, height);
+                }
+                Event::CopyPixels {
+                    src: vnc_src,
+                    dst: vnc_dst,
+                } => {
+                    vnc_client::pixels::copy_rect(
+                        &mut framebuffer,
+                        width as usize,
+                        bytes_per_pixel,
+                        vnc_src,
+                        vnc_dst,
+                    );
+                    damage.add(vnc_dst);
+                }
+                Event::EndOfFrame if qemu_hacks => {
+                    let network_rtt = sdl_timer.ticks() - qemu_prev_update;
+                    // qemu_network_rtt = network_rtt;
+                    qemu_network_rtt = qemu_network_rtt * 80 / 100 + network_rtt * 20 / 100;
+                    qemu_prev_update = sdl_timer.ticks();
+                    qemu_next_update = sdl_timer.ticks() + qemu_network_rtt / 2;
+                    debug!("network RTT: {} ms", qemu_network_rtt);
+                }
+                Event::Clipboard(ref text) => {
+                    let _ = sdl_video.clipboard().set_clipboard_text(text);
+                    // this returns a Result, but unwrapping it fails with "Invalid renderer",
+                    // even though the call to set_clipboard_text actually succeeds.
+                }
+                Event::SetCursor {
+                    size: (width, height),
+                    hotspot: (new_hotspot_x, new_hotspot_y),
+                    mut pixels,
+                    mask_bits,
+                } => {
+                    vnc_client::pixels::to_native_endian(vnc_format, &mut pixels);
+                    hotspot_x = new_hotspot_x;
+                    hotspot_y = new_hotspot_y;
+                    if width > 0 && height > 0 {
+                        let mut mask_pixels = Vec::new();
+                        let mask_stride = width.div_ceil(8);
+                        for y in 0..height {
+                            for x in 0..mask_stride {
+                                let mask_byte = mask_bits[(y * mask_stride + x) as usize];
+                                for w in 0..8 {
+                                    mask_pixels.push(mask_byte & (1 << (7 - w)))
+                                }
+                            }
+                        }
+                        let (sdl_cursor_format, cursor_pixels) =
+                            mask_cursor(vnc_format, pixels, mask_pixels);
+                        let mut new_cursor = renderer
+                            .create_texture_streaming(
+                                sdl_cursor_format,
+                                width as u32,
+                                height as u32,
+                            )
+                            .unwrap();
+                        new_cursor
+                            .update(
+                                None,
+                                &cursor_pixels,
+                                sdl_cursor_format.byte_size_of_pixels(width as usize),
+                            )
+                            .unwrap();
+                        new_cursor.set_blend_mode(sdl2::render::BlendMode::Blend);
+                        cursor = Some(new_cursor);
+                    } else {
+                        cursor = None
+                    }
+                }
+                Event::SetCursorAlpha {
+                    size: (width, height),
+                    hotspot: (new_hotspot_x, new_hotspot_y),
+                    rgba,
+                } => {
+                    hotspot_x = new_hotspot_x;
+                    hotspot_y = new_hotspot_y;
+                    if width > 0 && height > 0 {
+                        let mut new_cursor = renderer
+                            .create_texture_streaming(
+                                SdlPixelFormat::RGBA32,
+                                width as u32,
+                                height as u32,
+                            )
+                            .unwrap();
+                        new_cursor.update(None, &rgba, width as usize * 4).unwrap();
+                        new_cursor.set_blend_mode(sdl2::render::BlendMode::Blend);
+                        cursor = Some(new_cursor);
+                    } else {
+                        cursor = None
+                    }
+                }
+                Event::LedState {
+                    num_lock,
+                    caps_lock,
+                    ..
+                } => remote_locks = Some((num_lock, caps_lock)),
+                Event::PointerMotionMode(mode) => {
+                    // Guests that grab the pointer want movements, which SDL only
+                    // reports unclamped with the pointer confined to the window.
+                    let relative = mode == vnc_client::PointerMotionMode::Relative;
+                    sdl_context.mouse().set_relative_mouse_mode(relative);
+                }
+                Event::NameChange(ref name) => {
+                    let title = format!("{} - {}:{} - RVNC", name, host, port);
+                    let _ = canvas.window_mut().set_title(&title);
+                }
+                _ => (), /* ignore unsupported events */
+            }
+
+            if sdl_timer.ticks() - ticks > FRAME_MS {
+                behind = true;
+                break;
+            }
+        }
+
+        for rect in damage.take() {
+            let sdl_rect = SdlRect::new(
+                rect.left as i32,
+                rect.top as i32,
+                rect.width as u32,
+                rect.height as u32,
+            );
+            let row_length = rect.width as usize * bytes_per_pixel;
+            screen
+                .with_lock(Some(sdl_rect), |buffer, pitch| {
+                    for y in 0..rect.height as usize {
+                        let offset = ((rect.top as usize + y) * width as usize
+                            + rect.left as usize)
+                            * bytes_per_pixel;
+                        buffer[y * pitch..y * pitch + row_length]
+                            .copy_from_slice(&framebuffer[offset..offset + row_length]);
+                    }
+                })
+                .unwrap();
+        }
+        canvas
+            .copy(&screen, None, None)
+            .expect("canvas copy failed");
+
+        match cursor {
+            Some(ref cursor) => {
+                sdl_context.mouse().show_cursor(false);
+
+                let raw_cursor_rect = SdlRect::new(
+                    mouse_x as i32 - hotspot_x as i32,
+                    mouse_y as i32 - hotspot_y as i32,
+                    cursor.query().width,
+                    cursor.query().height,
+                );
+                let screen_rect = SdlRect::new(0, 0, width as u32, height as u32);
+                let clipped_cursor_rect = raw_cursor_rect & screen_rect;
+                if let Some(clipped_cursor_rect) = clipped_cursor_rect {
+                    let source_rect = SdlRect::new(
+                        clipped_cursor_rect.x() - raw_cursor_rect.x(),
+                        clipped_cursor_rect.y() - raw_cursor_rect.y(),
+                        clipped_cursor_rect.width(),
+                        clipped_cursor_rect.height(),
+                    );
+                    canvas
+                        .copy(cursor, Some(source_rect), Some(clipped_cursor_rect))
+                        .expect("canvas copy failed");
+                }
+            }
+            None => {
+                sdl_context.mouse().show_cursor(true);
+            }
+        }
+        canvas.present();
+
+        for request in control.iter().flat_map(|requests| requests.try_iter()) {
+            let result = match request.command {
+                control::Command::Screenshot(ref path) => match vnc.screenshot() {
+                    Some(screenshot) => File::create(path)
+                        .and_then(|file| {
+                            let mut writer = BufWriter::new(file);
+                            screenshot.write_ppm(&mut writer)?;
+                            writer.flush()
+                        })
+                        .map_err(|error| error.to_string()),
+                    None => Err("no framebuffer".to_owned()),
+                },
+                control::Command::ViewOnly(on) => {
+                    view_only = on;
+                    Ok(())
+                }
+                control::Command::Type(_) if view_only => Err("view-only".to_owned()),
+                control::Command::Type(ref text) => {
+                    use x11::keysym::{XK_Return, XK_Tab};
+
+                    let mut batch = vnc.batch();
+                    for chr in text.chars() {
+                        let keysym = match chr {
+                            '\n' => XK_Return,
+                            '\t' => XK_Tab,
+                            chr => 0x01000000 + chr as u32,
+                        };
+                        batch.send_key_event(true, keysym).unwrap();
+                        batch.send_key_event(false, keysym).unwrap();
+                    }
+                    batch.finish().unwrap();
+                    Ok(())
+                }
+                control::Command::Disconnect => {
+                    let _ = request.reply.send(Ok(()));
+                    break 'running;
+                }
+            };
+            let _ = request.reply.send(result);
+        }
+
+        // Still catching up with the server; don't ask it for more yet.
+        if behind {
+            continue 'running;
+        }
+
+        let frame_ms = if minimized {
+            MINIMIZED_FRAME_MS
+        } else {
+            FRAME_MS
+        };
+        for event in sdl_events.wait_timeout_iter(sdl_timer.ticks() - ticks + frame_ms) {
+            use sdl2::event::{Event, WindowEvent};
+
+            match event {
+                Event::Quit { .. } => break 'running,
+                Event::Window {
+                    win_event: win_event @ (WindowEvent::FocusGained | WindowEvent::FocusLost),
+                    ..
+                } if power_saving => {
+                    let focused = matches!(win_event, WindowEvent::FocusGained);
+                    debug!("window focused: {}", focused);
+                    vnc.set_min_update_interval(update_interval(fps_limit, focused))
+                }
+                Event::Window {
+                    win_event: WindowEvent::Minimized | WindowEvent::Hidden,
+                    ..
+                } if power_saving => {
+                    debug!("window minimized, pausing updates");
+                    minimized = true
+                }
+                Event::Window {
+                    win_event: WindowEvent::Restored | WindowEvent::Shown,
+                    ..
+                } if power_saving && minimized => {
+                    debug!("window restored, resuming updates");
+                    minimized = false;
+                    // Whatever changed in the meantime was never asked for.
+                    vnc.refresh().unwrap()
+                }
+                Event::Window {
+                    win_event: WindowEvent::SizeChanged(width, height),
+                    ..
+                } => {
+                    let screen_rect = SdlRect::new(0, 0, width as u32, height as u32);
+                    canvas
+                        .copy(&screen, None, Some(screen_rect))
+                        .expect("canvas copy failed");
+                    canvas.present()
+                }
+                _ => (),
+            }
+
+            if view_only {
+                continue;
+            }
+
+            match event {
+                Event::KeyDown {
+                    keycode: Some(keycode),
+                    ..
+                }
+                | Event::KeyUp {
+                    keycode: Some(keycode),
+                    ..
+                } => {
+                    use sdl2::keyboard::Keycode;
+                    let down = matches!(event, Event::KeyDown { .. });
+                    match keycode {
+                        Keycode::LCtrl | Keycode::RCtrl => key_ctrl = down,
+                        Keycode::LAlt | Keycode::RAlt => key_alt = down,
+                        _ => (),
+                    }
+                    if keycode == PASS_THROUGH_KEY && key_ctrl && key_alt {
+                        if down {
+                            pass_through = !pass_through;
+                            let window = canvas.window_mut();
+                            window.set_keyboard_grab(pass_through);
+                            if pass_through {
+                                info!("passing all keys through, Ctrl+Alt+G to stop");
+                                window
+                                    .set_title(&format!(
+                                        "{} [all keys go to the server, Ctrl+Alt+G to release]",
+                                        title
+                                    ))
+                                    .unwrap();
+                            } else {
+                                info!("no longer passing all keys through");
+                                window.set_title(&title).unwrap();
+                            }
+                        }
+                        continue;
+                    }
+                    if let Some(keysym) = map_special_key(key_ctrl, keycode) {
+                        vnc.send_key_event(down, keysym).unwrap();
+                    }
+                }
+                Event::Window {
+                    win_event: WindowEvent::FocusGained,
+                    ..
+                } => {
+                    // The lock keys may have been toggled while another window had focus;
+                    // tap the ones that disagree with the remote LEDs to resync them.
+                    if let Some((remote_num_lock, remote_caps_lock)) = remote_locks {
+                        use sdl2::keyboard::Mod;
+                        use x11::keysym::{XK_Caps_Lock, XK_Num_Lock};
+
+                        let mod_state = sdl_context.keyboard().mod_state();
+                        let local_num_lock = mod_state.contains(Mod::NUMMOD);
+                        let local_caps_lock = mod_state.contains(Mod::CAPSMOD);
+                        let mut batch = vnc.batch();
+                        for (local, remote, keysym) in [
+                            (local_num_lock, remote_num_lock, XK_Num_Lock),
+                            (local_caps_lock, remote_caps_lock, XK_Caps_Lock),
+                        ] {
+                            if local != remote {
+                                batch.send_key_event(true, keysym).unwrap();
+                                batch.send_key_event(false, keysym).unwrap();
+                            }
+                        }
+                        batch.finish().unwrap()
+                    }
+                }
+                Event::TextInput { text, .. } => {
+                    let chr = 0x01000000 + text.chars().next().unwrap() as u32;
+                    let mut batch = vnc.batch();
+                    batch.send_key_event(true, chr).unwrap();
+                    batch.send_key_event(false, chr).unwrap();
+                    batch.finish().unwrap()
+                }
+                Event::MouseMotion {
+                    x, y, xrel, yrel, ..
+                } => {
+                    mouse_x = x as u16;
+                    mouse_y = y as u16;
+                    if vnc.pointer_motion_mode() == vnc_client::PointerMotionMode::Relative {
+                        vnc.send_relative_pointer(xrel as i16, yrel as i16, mouse_buttons)
+                            .unwrap()
+                    } else if !qemu_hacks {
+                        vnc.send_pointer_event(mouse_buttons, mouse_x, mouse_y)
+                            .unwrap()
+                    }
+                }
+                Event::MouseButtonDown {
+                    x, y, mouse_btn, ..
+                }
+                | Event::MouseButtonUp {
+                    x, y, mouse_btn, ..
+                } => {
+                    use sdl2::mouse::MouseButton;
+                    mouse_x = x as u16;
+                    mouse_y = y as u16;
+                    let mouse_button = match mouse_btn {
+                        MouseButton::Left => 0x01,
+                        MouseButton::Middle => 0x02,
+                        MouseButton::Right => 0x04,
+                        MouseButton::X1 => 0x20,
+                        MouseButton::X2 => 0x40,
+                        MouseButton::Unknown => 0x00,
+                    };
+                    let masks = match (&mut middle_emulation, &event) {
+                        (Some(emulation), Event::MouseButtonDown { .. }) => {
+                            emulation.press(mouse_button, sdl_timer.ticks())
+                        }
+                        (Some(emulation), Event::MouseButtonUp { .. }) => {
+                            emulation.release(mouse_button)
+                        }
+                        (None, Event::MouseButtonDown { .. }) => {
+                            vec![mouse_buttons | mouse_button]
+                        }
+                        (None, Event::MouseButtonUp { .. }) => {
+                            vec![mouse_buttons & !mouse_button]
+                        }
+                        _ => unreachable!(),
+                    };
+                    let mut batch = vnc.batch();
+                    for mask in masks {
+                        mouse_buttons = mask;
+                        send_buttons(&mut batch, mouse_buttons, mouse_x, mouse_y).unwrap();
+                    }
+                    batch.finish().unwrap()
+                }
+                Event::MouseWheel { y, .. } => {
+                    let wheel_button = match y {
+                        1 => 0x08,
+                        -1 => 0x10,
+                        _ => continue,
+                    };
+                    let mut batch = vnc.batch();
+                    send_buttons(&mut batch, mouse_buttons | wheel_button, mouse_x, mouse_y)
+                        .unwrap();
+                    send_buttons(&mut batch, mouse_buttons, mouse_x, mouse_y).unwrap();
+                    batch.finish().unwrap()
+                }
+                Event::ClipboardUpdate { .. } => {
+                    let text = sdl_video.clipboard().clipboard_text().unwrap();
+                    match vnc.update_clipboard(&text) {
+                        Err(vnc_client::Error::ClipboardTooLarge(length)) => {
+                            warn!("not sending clipboard text of {} characters", length)
+                        }
+                        result => result.unwrap(),
+                    }
+                }
+                _ => (),
+            }
+        }
+
+        if let Some(ref mut emulation) = middle_emulation {
+            // A left or right press that did not turn into a chord.
+            for mask in emulation.tick(sdl_timer.ticks()) {
+                mouse_buttons = mask;
+                send_buttons(&mut vnc, mouse_buttons, mouse_x, mouse_y).unwrap()
+            }
+        }
+
+        if minimized {
+            // Nobody is looking; let the server idle.
+        } else if qemu_hacks && sdl_timer.ticks() > qemu_next_update {
+            // QEMU ignores incremental update requests and sends non-incremental ones,
+            // but does not update framebuffer in them. However, it does update framebuffer
+            // (and send it to us) if we change the pixel format, including not actually
+            // changing it.
+            vnc.poke_qemu().unwrap();
+            qemu_next_update = sdl_timer.ticks() + qemu_network_rtt / 2;
+        } else {
+            vnc.request_update(vnc_client::Rect::with_size(width, height), incremental)
+                .unwrap();
+        }
+    }
+}
+
+// How often to ask for updates while the window is unfocused, at most.
+const BACKGROUND_FPS: u32 = 2;
+
+fn update_interval(fps_limit: Option<u32>, focused: bool) -> Option<Duration> {
+    let fps = match (fps_limit, focused) {
+        (Some(fps), true) => fps,
+        (Some(fps), false) => fps.min(BACKGROUND_FPS),
+        (None, true) => return None,
+        (None, false) => BACKGROUND_FPS,
+    };
+    Some(Duration::from_secs(1) / fps)
+}
+
+// Pressed with Ctrl and Alt, toggles passing all keys through to the server.
+const PASS_THROUGH_KEY: sdl2::keyboard::Keycode = sdl2::keyboard::Keycode::G;
+
+fn map_special_key(alnum_ok: bool, keycode: sdl2::keyboard::Keycode) -> Option<u32> {
+    use sdl2::keyboard::Keycode::*;
+    use x11::keysym::*;
+
+    let x11code = match keycode {
+        Space => XK_space,
+        Exclaim => XK_exclam,
+        Quotedbl => XK_quotedbl,
+        Hash => XK_numbersign,
+        Dollar => XK_dollar,
+        Percent => XK_percent,
+        Ampersand => XK_ampersand,
+        Quote => XK_apostrophe,
+        LeftParen => XK_parenleft,
+        RightParen => XK_parenright,
+        Asterisk => XK_asterisk,
+        Plus => XK_plus,
+        Comma => XK_comma,
+        Minus => XK_minus,
+        Period => XK_period,
+        Slash => XK_slash,
+        Num0 => XK_0,
+        Num1 => XK_1,
+        Num2 => XK_2,
+        Num3 => XK_3,
+        Num4 => XK_4,
+        Num5 => XK_5,
+        Num6 => XK_6,
+        Num7 => XK_7,
+        Num8 => XK_8,
+        Num9 => XK_9,
+        Colon => XK_colon,
+        Semicolon => XK_semicolon,
+        Less => XK_less,
+        Equals => XK_equal,
+        Greater => XK_greater,
+        Question => XK_question,
+        At => XK_at,
+        LeftBracket => XK_bracketleft,
+        Backslash => XK_backslash,
+        RightBracket => XK_bracketright,
+        Caret => XK_caret,
+        Underscore => XK_underscore,
+        Backquote => XK_grave,
+        A => XK_a,
+        B => XK_b,
+        C => XK_c,
+        D => XK_d,
+        E => XK_e,
+        F => XK_f,
+        G => XK_g,
+        H => XK_h,
+        I => XK_i,
+        J => XK_j,
+        K => XK_k,
+        L => XK_l,
+        M => XK_m,
+        N => XK_n,
+        O => XK_o,
+        P => XK_p,
+        Q => XK_q,
+        R => XK_r,
+        S => XK_s,
+        T => XK_t,
+        U => XK_u,
+        V => XK_v,
+        W => XK_w,
+        X => XK_x,
+        Y => XK_y,
+        Z => XK_z,
+        _ => 0,
+    };
+    if x11code != 0 && alnum_ok {
+        return Some(x11code);
+    }
+
+    let x11code = match keycode {
+        Backspace => XK_BackSpace,
+        Tab => XK_Tab,
+        Return => XK_Return,
+        Escape => XK_Escape,
+        Delete => XK_Delete,
+        CapsLock => XK_Caps_Lock,
+        F1 => XK_F1,
+        F2 => XK_F2,
+        F3 => XK_F3,
+        F4 => XK_F4,
+        F5 => XK_F5,
+        F6 => XK_F6,
+        F7 => XK_F7,
+        F8 => XK_F8,
+        F9 => XK_F9,
+        F10 => XK_F10,
+        F11 => XK_F11,
+        F12 => XK_F12,
+        PrintScreen => XK_Print,
+        ScrollLock => XK_Scroll_Lock,
+        Pause => XK_Pause,
+        Insert => XK_Insert,
+        Home => XK_Home,
+        PageUp => XK_Page_Up,
+        End => XK_End,
+        PageDown => XK_Page_Down,
+        Right => XK_Right,
+        Left => XK_Left,
+        Down => XK_Down,
+        Up => XK_Up,
+        NumLockClear => XK_Num_Lock,
+        KpDivide => XK_KP_Divide,
+        KpMultiply => XK_KP_Multiply,
+        KpMinus => XK_KP_Subtract,
+        KpPlus => XK_KP_Add,
+        KpEnter => XK_KP_Enter,
+        Kp1 => XK_KP_1,
+        Kp2 => XK_KP_2,
+        Kp3 => XK_KP_3,
+        Kp4 => XK_KP_4,
+        Kp5 => XK_KP_5,
+        Kp6 => XK_KP_6,
+        Kp7 => XK_KP_7,
+        Kp8 => XK_KP_8,
+        Kp9 => XK_KP_9,
+        Kp0 => XK_KP_0,
+        KpPeriod => XK_KP_Separator,
+        F13 => XK_F13,
+        F14 => XK_F14,
+        F15 => XK_F15,
+        F16 => XK_F16,
+        F17 => XK_F17,
+        F18 => XK_F18,
+        F19 => XK_F19,
+        F20 => XK_F20,
+        F21 => XK_F21,
+        F22 => XK_F22,
+        F23 => XK_F23,
+        F24 => XK_F24,
+        Menu => XK_Menu,
+        Sysreq => XK_Sys_Req,
+        LCtrl => XK_Control_L,
+        LShift => XK_Shift_L,
+        LAlt => XK_Alt_L,
+        LGui => XK_Super_L,
+        RCtrl => XK_Control_R,
+        RShift => XK_Shift_R,
+        RAlt => XK_Alt_R,
+        RGui => XK_Super_R,
+        // XF86 multimedia keys
+        Mute | AudioMute => 0x1008ff12,
+        VolumeDown => 0x1008ff11,
+        VolumeUp => 0x1008ff13,
+        AudioPlay => 0x1008ff14,
+        AudioStop => 0x1008ff15,
+        AudioPrev => 0x1008ff16,
+        AudioNext => 0x1008ff17,
+        _ => 0,
+    };
+    if x11code != 0 {
+        Some(x11code)
+    } else {
+        None
+    }
+}
@@ -0,0 +1,64 @@
+//! Connecting to a host name with several addresses, as in RFC 8305 ("happy
+//! eyeballs"): IPv6 and IPv4 addresses are tried alternately, and while an
+//! attempt is pending, the next one starts after `STAGGER`, so that a
+//! black-holed address family does not hold up the connection.
+
+use log::debug;
+use std::io;
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::sync::mpsc::channel;
+use std::thread;
+use std::time::Duration;
+
+const STAGGER: Duration = Duration::from_millis(250);
+const TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Returns the first connection that succeeds, and the address it went to.
+pub fn connect(host: &str, port: u16) -> io::Result<(TcpStream, SocketAddr)> {
+    // Bracketed IPv6 literals, as in URLs, are accepted too.
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    let (ipv6, ipv4): (Vec<_>, Vec<_>) = (host, port)
+        .to_socket_addrs()?
+        .partition(SocketAddr::is_ipv6);
+    let (mut ipv6, mut ipv4) = (ipv6.into_iter(), ipv4.into_iter());
+    let mut candidates = Vec::new();
+    while ipv6.len() + ipv4.len() > 0 {
+        candidates.extend(ipv6.next());
+        candidates.extend(ipv4.next());
+    }
+    let mut candidates = candidates.into_iter();
+
+    let (tx_results, rx_results) = channel();
+    let mut pending = 0;
+    let mut last_error = None;
+    loop {
+        if let Some(address) = candidates.next() {
+            debug!("connecting to {}", address);
+            let tx_results = tx_results.clone();
+            thread::spawn(move || {
+                let _ = tx_results.send((address, TcpStream::connect_timeout(&address, TIMEOUT)));
+            });
+            pending += 1;
+        }
+        if pending == 0 {
+            break;
+        }
+
+        // Every attempt sends its result, so waiting for one cannot hang.
+        let result = match candidates.len() {
+            0 => rx_results.recv().ok(),
+            _ => rx_results.recv_timeout(STAGGER).ok(),
+        };
+        match result {
+            Some((address, Ok(stream))) => return Ok((stream, address)),
+            Some((address, Err(error))) => {
+                debug!("cannot connect to {}: {}", address, error);
+                last_error = Some(error);
+                pending -= 1;
+            }
+            None => (),
+        }
+    }
+    Err(last_error
+        .unwrap_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no addresses to connect to")))
+}
@@ -0,0 +1,123 @@
+//! A control socket for a running viewer.
+//!
+//! Clients connect to a Unix socket and send one command per line; each command
+//! is answered with a line reading `ok` or `error: <reason>`. The commands are:
+//!
+//!   * `screenshot PATH`, which saves the framebuffer as a binary PPM image;
+//!   * `view-only on` and `view-only off`;
+//!   * `type TEXT`, which types the rest of the line, with `\n`, `\t` and `\\`
+//!     standing for Return, Tab and a backslash;
+//!   * `disconnect`.
+//!
+//! Commands are handed to the main loop, which executes them between frames.
+
+use log::{debug, warn};
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::thread;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    Screenshot(PathBuf),
+    ViewOnly(bool),
+    Type(String),
+    Disconnect,
+}
+
+impl Command {
+    pub fn parse(line: &str) -> Result<Command, String> {
+        let (verb, argument) = match line.split_once(' ') {
+            Some((verb, argument)) => (verb, Some(argument)),
+            None => (line, None),
+        };
+        match (verb, argument) {
+            ("screenshot", Some(path)) if !path.is_empty() => {
+                Ok(Command::Screenshot(PathBuf::from(path)))
+            }
+            ("view-only", Some("on")) => Ok(Command::ViewOnly(true)),
+            ("view-only", Some("off")) => Ok(Command::ViewOnly(false)),
+            ("type", Some(text)) => {
+                let mut unescaped = String::new();
+                let mut chars = text.chars();
+                while let Some(chr) = chars.next() {
+                    if chr != '\\' {
+                        unescaped.push(chr);
+                        continue;
+                    }
+                    unescaped.push(match chars.next() {
+                        Some('n') => '\n',
+                        Some('t') => '\t',
+                        Some('\\') => '\\',
+                        _ => return Err(format!("invalid escape in {:?}", text)),
+                    })
+                }
+                Ok(Command::Type(unescaped))
+            }
+            ("disconnect", None) => Ok(Command::Disconnect),
+            _ => Err(format!("unknown command {:?}", line)),
+        }
+    }
+}
+
+pub struct Request {
+    pub command: Command,
+    /// Where the result of the command goes; the connection waits for it.
+    pub reply: Sender<Result<(), String>>,
+}
+
+/// Listens on `path` and returns the commands received by it. A socket left
+/// over from an earlier run is replaced; any other file at `path` is an error.
+pub fn listen(path: &Path) -> io::Result<Receiver<Request>> {
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => fs::remove_file(path)?,
+        _ => (),
+    }
+    let listener = UnixListener::bind(path)?;
+    let (tx_requests, rx_requests) = channel();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    let tx_requests = tx_requests.clone();
+                    thread::spawn(move || {
+                        if let Err(error) = serve(stream, tx_requests) {
+                            debug!("control connection failed: {}", error)
+                        }
+                    });
+                }
+                Err(error) => warn!("cannot accept control connection: {}", error),
+            }
+        }
+    });
+    Ok(rx_requests)
+}
+
+fn serve(stream: UnixStream, tx_requests: Sender<Request>) -> io::Result<()> {
+    let mut writer = stream.try_clone()?;
+    for line in BufReader::new(stream).lines() {
+        let line = line?;
+        debug!("control command {:?}", line);
+        let result = match Command::parse(line.trim_end_matches('\r')) {
+            Ok(command) => {
+                let (reply, rx_reply) = channel();
+                if tx_requests.send(Request { command, reply }).is_err() {
+                    // The viewer is shutting down.
+                    return Ok(());
+                }
+                rx_reply
+                    .recv()
+                    .unwrap_or_else(|_| Err("viewer has exited".to_owned()))
+            }
+            Err(error) => Err(error),
+        };
+        match result {
+            Ok(()) => writeln!(writer, "ok")?,
+            Err(error) => writeln!(writer, "error: {}", error)?,
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,92 @@
+//! Middle button emulation for two-button mice and touchpads: pressing the left
+//! and right buttons together sends a middle button press instead.
+//!
+//! A left or right press is held back for `CHORD_MS`, in case the other button
+//! follows; if it doesn't, the press is sent late. Once a chord has started,
+//! the middle button is released as soon as either button is, and the other one
+//! is ignored until it is released too.
+
+pub const LEFT: u8 = 0x01;
+pub const MIDDLE: u8 = 0x02;
+pub const RIGHT: u8 = 0x04;
+
+const CHORD_MS: u32 = 50;
+
+#[derive(Debug, Default)]
+pub struct MiddleEmulation {
+    buttons: u8,
+    // A left or right press not sent yet, and when it happened.
+    pending: Option<(u8, u32)>,
+    // The buttons of the chord that are still held.
+    chord: u8,
+}
+
+impl MiddleEmulation {
+    /// The button mask as the server should see it.
+    pub fn buttons(&self) -> u8 {
+        let mut buttons = self.buttons;
+        if let Some((button, _)) = self.pending {
+            buttons &= !button
+        }
+        if self.chord != 0 {
+            buttons &= !(LEFT | RIGHT);
+            if self.chord == LEFT | RIGHT {
+                buttons |= MIDDLE
+            }
+        }
+        buttons
+    }
+
+    /// Handles a button press at time `ticks`, in milliseconds, and returns the
+    /// button masks to send, in order.
+    pub fn press(&mut self, button: u8, ticks: u32) -> Vec<u8> {
+        let before = self.buttons();
+        if button & (LEFT | RIGHT) != 0 && self.chord == 0 {
+            match self.pending {
+                Some((other, _)) if other != button => {
+                    self.pending = None;
+                    self.chord = LEFT | RIGHT
+                }
+                // The other button has been sent already; this is no chord.
+                None if self.buttons & (LEFT | RIGHT) != 0 => (),
+                None => self.pending = Some((button, ticks)),
+                Some(_) => (),
+            }
+        }
+        self.buttons |= button;
+        self.changed(before)
+    }
+
+    /// Handles a button release; see `press`.
+    pub fn release(&mut self, button: u8) -> Vec<u8> {
+        let mut masks = Vec::new();
+        if matches!(self.pending, Some((pending, _)) if pending == button) {
+            // Too short to wait for the other button; click as it was.
+            self.pending = None;
+            masks.push(self.buttons())
+        }
+        let before = self.buttons();
+        self.buttons &= !button;
+        self.chord &= !button;
+        masks.extend(self.changed(before));
+        masks
+    }
+
+    /// Sends a held back press once it is clear that no chord follows.
+    pub fn tick(&mut self, ticks: u32) -> Vec<u8> {
+        let before = self.buttons();
+        if matches!(self.pending, Some((_, since)) if ticks.wrapping_sub(since) >= CHORD_MS) {
+            self.pending = None
+        }
+        self.changed(before)
+    }
+
+    fn changed(&self, before: u8) -> Vec<u8> {
+        let after = self.buttons();
+        if after != before {
+            vec![after]
+        } else {
+            vec![]
+        }
+    }
+}
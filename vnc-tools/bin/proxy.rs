@@ -0,0 +1,405 @@
+mod socket;
+mod socks;
+
+use clap::{value_parser, Arg, ArgAction, Command};
+use log::{error, info, warn};
+use socket::{Address, Connection, Listener};
+use std::io::Read;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, Receiver};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use vnc_server::{ProxyStream, RepeaterId};
+
+const HEALTH_CHECK_TIMEOUT: Duration = Duration::from_secs(3);
+
+struct Upstream {
+    address: Address,
+    healthy: AtomicBool,
+    sessions: AtomicUsize,
+}
+
+impl Upstream {
+    fn new(address: Address) -> Upstream {
+        Upstream {
+            address,
+            // Assume the best so that the first health check reports failures.
+            healthy: AtomicBool::new(true),
+            sessions: AtomicUsize::new(0),
+        }
+    }
+
+    // A server is considered healthy if it accepts a connection and greets us
+    // with an RFB version banner in time.
+    fn probe(&self) -> bool {
+        match self.address.connect_timeout(HEALTH_CHECK_TIMEOUT) {
+            Ok(mut stream) => {
+                let mut version = [0; 12];
+                stream.set_read_timeout(Some(HEALTH_CHECK_TIMEOUT)).is_ok()
+                    && stream.read_exact(&mut version).is_ok()
+                    && version.starts_with(b"RFB ")
+            }
+            Err(_) => false,
+        }
+    }
+
+    fn check_health(&self) {
+        let healthy = self.probe();
+        if self.healthy.swap(healthy, Ordering::SeqCst) != healthy {
+            if healthy {
+                info!("upstream {} is healthy", self.address)
+            } else {
+                warn!("upstream {} is unhealthy", self.address)
+            }
+        }
+    }
+}
+
+fn balance(
+    viewers: Receiver<Connection>,
+    pool: Vec<Upstream>,
+    health_interval: Duration,
+    audit: Option<vnc_server::AuditLog>,
+) {
+    let pool = Arc::new(pool);
+    for upstream in pool.iter() {
+        upstream.check_health()
+    }
+    {
+        let pool = pool.clone();
+        thread::spawn(move || loop {
+            thread::sleep(health_interval);
+            for upstream in pool.iter() {
+                upstream.check_health()
+            }
+        });
+    }
+
+    loop {
+        let client_stream = accept(&viewers, "viewer");
+
+        let index = (0..pool.len())
+            .filter(|&index| pool[index].healthy.load(Ordering::SeqCst))
+            .min_by_key(|&index| pool[index].sessions.load(Ordering::SeqCst));
+        let index = match index {
+            Some(index) => index,
+            None => {
+                error!("no healthy upstream for viewer");
+                let _ = client_stream.shutdown();
+                continue;
+            }
+        };
+
+        let upstream = &pool[index];
+        let sessions = upstream.sessions.fetch_add(1, Ordering::SeqCst) + 1;
+        info!(
+            "assigned viewer {} to {} ({} sessions)",
+            client_stream.peer(),
+            upstream.address,
+            sessions
+        );
+
+        let pool = pool.clone();
+        let audit = audit.clone();
+        thread::spawn(move || {
+            let upstream = &pool[index];
+            match upstream.address.connect() {
+                Ok(server_stream) => serve(server_stream, client_stream, audit.as_ref(), None),
+                Err(error) => {
+                    error!("cannot connect to {}: {}", upstream.address, error);
+                    upstream.healthy.store(false, Ordering::SeqCst);
+                    let _ = client_stream.shutdown();
+                }
+            }
+            let sessions = upstream.sessions.fetch_sub(1, Ordering::SeqCst) - 1;
+            info!("viewer left {} ({} sessions)", upstream.address, sessions);
+        });
+    }
+}
+
+fn listen(address: &Address) -> Listener {
+    info!("listening at {}", address);
+    match Listener::bind(address) {
+        Ok(listener) => listener,
+        Err(error) => {
+            error!("cannot listen at {}: {}", address, error);
+            std::process::exit(1)
+        }
+    }
+}
+
+// Accepts connections on all of `listeners` at once, in the order they come.
+fn accept_all(listeners: Vec<Listener>, what: &'static str) -> Receiver<Connection> {
+    let (tx_streams, rx_streams) = channel();
+    for listener in listeners {
+        let tx_streams = tx_streams.clone();
+        thread::spawn(move || loop {
+            match listener.accept() {
+                Ok(stream) => {
+                    if tx_streams.send(stream).is_err() {
+                        break;
+                    }
+                }
+                Err(error) => error!("incoming {} connection failed: {}", what, error),
+            }
+        });
+    }
+    rx_streams
+}
+
+fn accept(streams: &Receiver<Connection>, what: &str) -> Connection {
+    let stream = streams.recv().expect("all listeners have stopped");
+    info!("{} connected from {}", what, stream.peer());
+    stream
+}
+
+fn serve(
+    server_stream: Connection,
+    client_stream: Connection,
+    audit: Option<&vnc_server::AuditLog>,
+    repeater: Option<&RepeaterId>,
+) {
+    let audit = match audit {
+        Some(audit) => {
+            match audit.session(&client_stream.peer()) {
+                Ok(session) => {
+                    info!("auditing session {}", session.id());
+                    Some(session)
+                }
+                Err(error) => {
+                    // Input that cannot be recorded must not reach the server.
+                    error!("cannot write to audit log: {}", error);
+                    let _ = client_stream.shutdown();
+                    return;
+                }
+            }
+        }
+        None => None,
+    };
+
+    let proxy = match repeater {
+        Some(id) => {
+            vnc_server::Proxy::from_repeater_streams(server_stream, id, client_stream, audit)
+        }
+        None => vnc_server::Proxy::from_streams(server_stream, client_stream, audit),
+    };
+    let proxy = match proxy {
+        Ok(proxy) => proxy,
+        Err(error) => {
+            error!("handshake failed: {}", error);
+            return;
+        }
+    };
+
+    match proxy.join() {
+        Ok(()) => info!("session ended"),
+        Err(error) => error!("session failed: {}", error),
+    }
+}
+
+fn main() {
+    env_logger::init();
+
+    let matches = Command::new("rvncproxy")
+        .about("VNC proxy")
+        .arg(
+            Arg::new("CONNECT-HOST")
+                .help("server hostname or IP, or unix:PATH for a Unix socket")
+                .required(true)
+                .index(1),
+        )
+        .arg(
+            Arg::new("CONNECT-PORT")
+                .value_parser(value_parser!(u16))
+                .help("server port (default: 5900, or 5500 with --bridge)")
+                .index(2),
+        )
+        .arg(
+            Arg::new("LISTEN-HOST")
+                .help(
+                    "proxy hostname or IP, or unix:PATH for a Unix socket (default: localhost); \
+                     ignored when systemd passes in listening sockets",
+                )
+                .index(3),
+        )
+        .arg(
+            Arg::new("LISTEN-PORT")
+                .value_parser(value_parser!(u16))
+                .help("proxy port (default: server port plus one)")
+                .index(4),
+        )
+        .arg(
+            Arg::new("BRIDGE")
+                .help(
+                    "instead of connecting to the server, listen at CONNECT-HOST:CONNECT-PORT \
+                     for a reverse connection from it, and bridge it to the next viewer",
+                )
+                .long("bridge")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("BALANCE")
+                .help(
+                    "add another upstream server, or unix:PATH; viewers are spread across all healthy \
+                     upstreams, each going to the one with the fewest sessions",
+                )
+                .long("balance")
+                .value_name("HOST:PORT")
+                .action(ArgAction::Append)
+                .conflicts_with("BRIDGE"),
+        )
+        .arg(
+            Arg::new("HEALTH-INTERVAL")
+                .help("seconds between upstream health checks with --balance (default: 10)")
+                .long("health-interval")
+                .value_parser(value_parser!(u64).range(1..)),
+        )
+        .arg(
+            Arg::new("REPEATER")
+                .help(
+                    "CONNECT-HOST:CONNECT-PORT is an UltraVNC repeater; have it pair viewers with \
+                     the server registered as ID:NUMBER, or connect them to HOST:PORT",
+                )
+                .long("repeater")
+                .value_name("ID")
+                .conflicts_with_all(["BRIDGE", "BALANCE"]),
+        )
+        .arg(
+            Arg::new("SOCKS5")
+                .help("connect to TCP servers through a SOCKS5 proxy, which resolves their names")
+                .long("socks5")
+                .value_name("[USER:PASSWORD@]HOST:PORT")
+                .value_parser(socks::Socks5Proxy::parse)
+                .conflicts_with("BRIDGE"),
+        )
+        .arg(
+            Arg::new("AUDIT-LOG")
+                .help("append every key and pointer event viewers send to PATH")
+                .long("audit-log")
+                .value_name("PATH"),
+        )
+        .get_matches();
+
+    let bridge = matches.get_flag("BRIDGE");
+    let connect_host = matches.get_one::<String>("CONNECT-HOST").unwrap();
+    let default_port = if bridge { 5500 } else { 5900 };
+    let connect_port = matches
+        .get_one::<u16>("CONNECT-PORT")
+        .map(|x| x.to_owned())
+        .unwrap_or(default_port);
+    let listen_host = matches
+        .get_one::<String>("LISTEN-HOST")
+        .map(|x| x.to_owned())
+        .unwrap_or("localhost".to_owned());
+    let listen_port = matches
+        .get_one::<u16>("LISTEN-PORT")
+        .map(|x| x.to_owned())
+        .unwrap_or(connect_port + 1);
+    let address = |host: &str, port: u16| match host.strip_prefix("unix:") {
+        Some(path) => Address::Unix(path.into()),
+        None => Address::Tcp(host.to_owned(), port),
+    };
+    let socks5 = matches.get_one::<socks::Socks5Proxy>("SOCKS5");
+    let upstream = |address: Address| match socks5 {
+        Some(proxy) => address.via_socks5(proxy),
+        None => address,
+    };
+    let connect_address = upstream(address(connect_host, connect_port));
+    let inherited = Listener::from_systemd();
+    let viewer_listeners = if inherited.is_empty() {
+        vec![listen(&address(&listen_host, listen_port))]
+    } else {
+        for listener in &inherited {
+            info!("listening at {} (from systemd)", listener)
+        }
+        inherited
+    };
+
+    let audit =
+        matches
+            .get_one::<String>("AUDIT-LOG")
+            .map(|path| match vnc_server::AuditLog::open(path) {
+                Ok(audit) => audit,
+                Err(error) => {
+                    error!("cannot open audit log {}: {}", path, error);
+                    std::process::exit(1)
+                }
+            });
+
+    if bridge {
+        // Both sides connect to us; wait for a server first, then pair it
+        // with whichever viewer comes next. Viewers that arrive earlier simply
+        // wait in the listen backlog until a server shows up.
+        let servers = accept_all(vec![listen(&connect_address)], "server");
+        let viewers = accept_all(viewer_listeners, "viewer");
+        loop {
+            let server_stream = accept(&servers, "server");
+            let client_stream = accept(&viewers, "viewer");
+            serve(server_stream, client_stream, audit.as_ref(), None);
+        }
+    }
+
+    let balance_upstreams = matches
+        .get_many::<String>("BALANCE")
+        .map(|addresses| addresses.collect::<Vec<_>>())
+        .unwrap_or_default();
+    if !balance_upstreams.is_empty() {
+        let mut pool = vec![Upstream::new(connect_address)];
+        for address in balance_upstreams {
+            match Address::parse(address, 5900) {
+                Ok(address) => pool.push(Upstream::new(upstream(address))),
+                Err(error) => {
+                    error!("{}", error);
+                    std::process::exit(1)
+                }
+            }
+        }
+        let health_interval = matches
+            .get_one::<u64>("HEALTH-INTERVAL")
+            .map(|secs| Duration::from_secs(*secs))
+            .unwrap_or(Duration::from_secs(10));
+        balance(
+            accept_all(viewer_listeners, "viewer"),
+            pool,
+            health_interval,
+            audit,
+        );
+        return;
+    }
+
+    let repeater = matches
+        .get_one::<String>("REPEATER")
+        .map(|id| match id.strip_prefix("ID:") {
+            Some(number) => match number.parse() {
+                Ok(number) => RepeaterId::Id(number),
+                Err(_) => {
+                    error!("invalid repeater ID {}", id);
+                    std::process::exit(1)
+                }
+            },
+            None => RepeaterId::Server(id.to_owned()),
+        });
+
+    let viewers = accept_all(viewer_listeners, "viewer");
+    loop {
+        let client_stream = accept(&viewers, "viewer");
+
+        info!("connecting to {}", connect_address);
+        let server_stream = match connect_address.connect() {
+            Ok(stream) => stream,
+            Err(error) => {
+                error!("cannot connect to {}: {}", connect_address, error);
+                let _ = client_stream.shutdown();
+                continue;
+            }
+        };
+
+        serve(
+            server_stream,
+            client_stream,
+            audit.as_ref(),
+            repeater.as_ref(),
+        );
+    }
+}
@@ -0,0 +1,247 @@
+//! TCP and Unix domain sockets behind one interface, so that either end of
+//! the proxy can be a Unix socket, e.g. the VNC socket of a QEMU or libvirt
+//! domain. Addresses of the form `unix:PATH` name a Unix socket; anything else
+//! is `HOST` or `HOST:PORT`.
+//!
+//! Listening sockets can also be inherited through systemd socket activation,
+//! and TCP addresses can be reached through a SOCKS5 proxy.
+
+use std::fmt;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream, ToSocketAddrs};
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::io::{FromRawFd, IntoRawFd, RawFd};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::socks::Socks5Proxy;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Address {
+    Tcp(String, u16),
+    Unix(PathBuf),
+    /// A TCP address, as the SOCKS5 proxy resolves it. It can only be connected to.
+    Socks5(Socks5Proxy, String, u16),
+}
+
+impl Address {
+    pub fn parse(address: &str, default_port: u16) -> Result<Address, String> {
+        if let Some(path) = address.strip_prefix("unix:") {
+            return Ok(Address::Unix(PathBuf::from(path)));
+        }
+        match address.rsplit_once(':') {
+            Some((host, port)) => match port.parse() {
+                Ok(port) => Ok(Address::Tcp(host.to_owned(), port)),
+                Err(_) => Err(format!("invalid port in {}", address)),
+            },
+            None => Ok(Address::Tcp(address.to_owned(), default_port)),
+        }
+    }
+
+    /// The same address, reached through `proxy` if it is a TCP one.
+    pub fn via_socks5(self, proxy: &Socks5Proxy) -> Address {
+        match self {
+            Address::Tcp(host, port) => Address::Socks5(proxy.clone(), host, port),
+            address => address,
+        }
+    }
+
+    pub fn connect(&self) -> io::Result<Connection> {
+        match self {
+            Address::Tcp(host, port) => {
+                Ok(Connection::Tcp(TcpStream::connect((host.as_str(), *port))?))
+            }
+            Address::Unix(path) => Ok(Connection::Unix(UnixStream::connect(path)?)),
+            Address::Socks5(proxy, host, port) => Ok(Connection::Tcp(proxy.connect(host, *port)?)),
+        }
+    }
+
+    /// Like `connect`, but gives up on TCP connections after `timeout`.
+    /// Connecting to a Unix socket does not block in the first place.
+    pub fn connect_timeout(&self, timeout: Duration) -> io::Result<Connection> {
+        match self {
+            Address::Tcp(host, port) => {
+                let mut last_error = None;
+                for address in (host.as_str(), *port).to_socket_addrs()? {
+                    match TcpStream::connect_timeout(&address, timeout) {
+                        Ok(stream) => return Ok(Connection::Tcp(stream)),
+                        Err(error) => last_error = Some(error),
+                    }
+                }
+                Err(last_error.unwrap_or_else(|| {
+                    io::Error::new(io::ErrorKind::NotFound, "no addresses to connect to")
+                }))
+            }
+            Address::Unix(_) | Address::Socks5(..) => self.connect(),
+        }
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Address::Tcp(host, port) => write!(f, "{}:{}", host, port),
+            Address::Unix(path) => write!(f, "unix:{}", path.display()),
+            Address::Socks5(proxy, host, port) => write!(f, "{}:{} via {}", host, port, proxy),
+        }
+    }
+}
+
+pub enum Listener {
+    Tcp(TcpListener),
+    Unix(UnixListener),
+}
+
+impl Listener {
+    /// Listens at `address`. A Unix socket left over from an earlier run is
+    /// replaced; any other file in its place is an error.
+    pub fn bind(address: &Address) -> io::Result<Listener> {
+        match address {
+            Address::Tcp(host, port) => {
+                Ok(Listener::Tcp(TcpListener::bind((host.as_str(), *port))?))
+            }
+            Address::Unix(path) => {
+                match fs::symlink_metadata(path) {
+                    Ok(metadata) if metadata.file_type().is_socket() => fs::remove_file(path)?,
+                    _ => (),
+                }
+                Ok(Listener::Unix(UnixListener::bind(path)?))
+            }
+            Address::Socks5(..) => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "cannot listen through a SOCKS5 proxy",
+            )),
+        }
+    }
+
+    /// Takes over the listening sockets passed by systemd socket activation,
+    /// if any were passed to this process.
+    pub fn from_systemd() -> Vec<Listener> {
+        // See sd_listen_fds(3); the sockets start right after stdio.
+        const SD_LISTEN_FDS_START: RawFd = 3;
+
+        let for_us = std::env::var("LISTEN_PID")
+            .ok()
+            .and_then(|pid| pid.parse::<u32>().ok())
+            == Some(std::process::id());
+        let count = std::env::var("LISTEN_FDS")
+            .ok()
+            .and_then(|count| count.parse::<RawFd>().ok())
+            .unwrap_or(0);
+        if !for_us {
+            return vec![];
+        }
+
+        (SD_LISTEN_FDS_START..SD_LISTEN_FDS_START + count)
+            .map(|fd| {
+                // Only TCP sockets have an address std can make sense of.
+                let listener = unsafe { TcpListener::from_raw_fd(fd) };
+                if listener.local_addr().is_ok() {
+                    Listener::Tcp(listener)
+                } else {
+                    Listener::Unix(unsafe { UnixListener::from_raw_fd(listener.into_raw_fd()) })
+                }
+            })
+            .collect()
+    }
+
+    pub fn accept(&self) -> io::Result<Connection> {
+        match self {
+            Listener::Tcp(listener) => Ok(Connection::Tcp(listener.accept()?.0)),
+            Listener::Unix(listener) => Ok(Connection::Unix(listener.accept()?.0)),
+        }
+    }
+}
+
+impl fmt::Display for Listener {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Listener::Tcp(listener) => match listener.local_addr() {
+                Ok(address) => write!(f, "{}", address),
+                Err(_) => write!(f, "TCP socket"),
+            },
+            Listener::Unix(listener) => match listener.local_addr() {
+                Ok(address) => match address.as_pathname() {
+                    Some(path) => write!(f, "unix:{}", path.display()),
+                    None => write!(f, "unnamed Unix socket"),
+                },
+                Err(_) => write!(f, "Unix socket"),
+            },
+        }
+    }
+}
+
+pub enum Connection {
+    Tcp(TcpStream),
+    Unix(UnixStream),
+}
+
+impl Connection {
+    /// Who is at the other end, for the logs.
+    pub fn peer(&self) -> String {
+        match self {
+            Connection::Tcp(stream) => stream
+                .peer_addr()
+                .map(|address| address.to_string())
+                .unwrap_or_default(),
+            // Clients of a Unix socket are rarely bound to a path.
+            Connection::Unix(stream) => match stream.peer_addr() {
+                Ok(address) => match address.as_pathname() {
+                    Some(path) => format!("unix:{}", path.display()),
+                    None => "unix".to_owned(),
+                },
+                Err(_) => "unix".to_owned(),
+            },
+        }
+    }
+
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        match self {
+            Connection::Tcp(stream) => stream.set_read_timeout(timeout),
+            Connection::Unix(stream) => stream.set_read_timeout(timeout),
+        }
+    }
+}
+
+impl Read for Connection {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Connection::Tcp(stream) => stream.read(buf),
+            Connection::Unix(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Connection {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Connection::Tcp(stream) => stream.write(buf),
+            Connection::Unix(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Connection::Tcp(stream) => stream.flush(),
+            Connection::Unix(stream) => stream.flush(),
+        }
+    }
+}
+
+impl vnc_server::ProxyStream for Connection {
+    fn try_clone(&self) -> io::Result<Connection> {
+        match self {
+            Connection::Tcp(stream) => Ok(Connection::Tcp(stream.try_clone()?)),
+            Connection::Unix(stream) => Ok(Connection::Unix(stream.try_clone()?)),
+        }
+    }
+
+    fn shutdown(&self) -> io::Result<()> {
+        match self {
+            Connection::Tcp(stream) => stream.shutdown(Shutdown::Both),
+            Connection::Unix(stream) => stream.shutdown(Shutdown::Both),
+        }
+    }
+}
@@ -0,0 +1,63 @@
+//! The `--socks5 [USER:PASSWORD@]HOST:PORT` option both tools have, for
+//! reaching servers through a SOCKS5 proxy such as `ssh -D PORT` opens.
+
+use std::fmt;
+use std::io;
+use std::net::TcpStream;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Socks5Proxy {
+    host: String,
+    port: u16,
+    credentials: Option<(String, String)>,
+}
+
+impl Socks5Proxy {
+    pub fn parse(proxy: &str) -> Result<Socks5Proxy, String> {
+        let (credentials, address) = match proxy.rsplit_once('@') {
+            Some((credentials, address)) => match credentials.split_once(':') {
+                Some((username, password)) => {
+                    (Some((username.to_owned(), password.to_owned())), address)
+                }
+                None => return Err(format!("expected USER:PASSWORD in {}", proxy)),
+            },
+            None => (None, proxy),
+        };
+        match address.rsplit_once(':') {
+            Some((host, port)) => match port.parse() {
+                Ok(port) => Ok(Socks5Proxy {
+                    host: host.to_owned(),
+                    port,
+                    credentials,
+                }),
+                Err(_) => Err(format!("invalid port in {}", address)),
+            },
+            None => Err(format!("expected HOST:PORT, got {}", address)),
+        }
+    }
+
+    /// Connects to `host:port` through the proxy, which resolves `host`.
+    pub fn connect(&self, host: &str, port: u16) -> io::Result<TcpStream> {
+        let proxy_host = self.host.trim_start_matches('[').trim_end_matches(']');
+        let mut stream = TcpStream::connect((proxy_host, self.port))?;
+        let credentials = self
+            .credentials
+            .as_ref()
+            .map(|(username, password)| (username.as_str(), password.as_str()));
+        match vnc_client::socks5::connect(&mut stream, host, port, credentials) {
+            Ok(()) => Ok(stream),
+            Err(vnc_client::Error::Io(error)) => Err(error),
+            Err(error) => Err(io::Error::other(error.to_string())),
+        }
+    }
+}
+
+impl fmt::Display for Socks5Proxy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // Leave the password out of the logs.
+        match self.credentials {
+            Some((ref username, _)) => write!(f, "{}@{}:{}", username, self.host, self.port),
+            None => write!(f, "{}:{}", self.host, self.port),
+        }
+    }
+}
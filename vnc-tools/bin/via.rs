@@ -0,0 +1,35 @@
+//! Reaching a server through an SSH gateway, as TigerVNC's `-via` does: the
+//! system `ssh` forwards a free local port to the server, as the gateway sees
+//! it, and stays in the background until the client has connected.
+
+use log::debug;
+use std::io;
+use std::net::TcpListener;
+use std::process::Command;
+
+/// Seconds ssh waits for the forwarded port to be used; it exits once the
+/// connection through it closes.
+const LINGER: &str = "20";
+
+/// Forwards a local port to `host:port` through `gateway`, e.g. `user@bastion`,
+/// and returns the port to connect to on localhost.
+pub fn tunnel(gateway: &str, host: &str, port: u16) -> io::Result<u16> {
+    let local_port = TcpListener::bind("127.0.0.1:0")?.local_addr()?.port();
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    let forward = match host.contains(':') {
+        true => format!("{}:[{}]:{}", local_port, host, port),
+        false => format!("{}:{}:{}", local_port, host, port),
+    };
+    debug!("ssh -L {} {}", forward, gateway);
+    let status = Command::new("ssh")
+        .args(["-f", "-o", "ExitOnForwardFailure=yes", "-L", &forward])
+        .args([gateway, "sleep", LINGER])
+        .status()?;
+    if !status.success() {
+        return Err(io::Error::other(format!(
+            "ssh to {} failed: {}",
+            gateway, status
+        )));
+    }
+    Ok(local_port)
+}